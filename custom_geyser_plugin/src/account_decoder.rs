@@ -0,0 +1,386 @@
+/// Decodes account data into queryable fields, selecting the decoder by the
+/// account's owner program.
+///
+/// Decoded fields are stored under `decoded` next to the raw `data`. When the
+/// data of a selected owner cannot be decoded the document gets a
+/// `decode_error` instead, and the raw bytes are kept or dropped according to
+/// the owner's `on_decode_failure` policy.
+use {
+    crate::{
+        geyser_plugin_mongodb::{GeyserPluginMongoDBConfig, GeyserPluginMongoDbError},
+        metrics,
+        mongodb_client::DbAccountInfo,
+    },
+    log::*,
+    mongodb::bson::{doc, Bson, Document},
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_metrics::datapoint::DataPoint,
+    solana_sdk::{bpf_loader_upgradeable, pubkey::Pubkey},
+    std::{collections::HashMap, str::FromStr, sync::Arc},
+};
+
+const SPL_TOKEN_PROGRAM_ID: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+const SPL_TOKEN_2022_PROGRAM_ID: &str = "TokenzQdBNbLqP5VEhdkAS6EPFLZ1VE8VMefKXY1xM";
+const SPL_ASSOCIATED_TOKEN_ACCOUNT_PROGRAM_ID: &str =
+    "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL";
+
+const SPL_TOKEN_ACCOUNT_LEN: usize = 165;
+const SPL_TOKEN_MINT_LEN: usize = 82;
+const SPL_TOKEN_MULTISIG_LEN: usize = 355;
+/// Token-2022 accounts with extensions store their type right after the base
+/// account layout.
+const SPL_TOKEN_2022_ACCOUNT_TYPE_MINT: u8 = 1;
+const SPL_TOKEN_2022_ACCOUNT_TYPE_ACCOUNT: u8 = 2;
+
+/// What to do with the raw account data when decoding fails.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DecodeFailurePolicy {
+    /// Store the raw bytes as if no decoder was configured.
+    #[default]
+    KeepRaw,
+    /// Store the account without its data.
+    DropRaw,
+}
+
+/// Per-owner decoder configuration.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct AccountDecoderConfig {
+    /// The owner program whose accounts are decoded.
+    pub owner: String,
+
+    /// The decoder to use, one of the built-in decoder names. When unset the
+    /// built-in decoder of the owner is used.
+    pub decoder: Option<String>,
+
+    /// The handling of accounts that fail to decode. The default is keep_raw.
+    #[serde(default)]
+    pub on_decode_failure: DecodeFailurePolicy,
+}
+
+pub trait AccountDecoder: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn decode(&self, data: &[u8]) -> Result<Document, String>;
+}
+
+/// Decodes SPL Token and Token-2022 mints, token accounts and multisigs.
+/// Token-2022 extensions are not decoded.
+pub struct SplTokenDecoder;
+
+fn read_pubkey(data: &[u8], offset: usize) -> String {
+    bs58::encode(&data[offset..offset + 32]).into_string()
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+/// Reads a `COption<Pubkey>`, a four byte tag followed by the key.
+fn read_optional_pubkey(data: &[u8], offset: usize) -> Bson {
+    match data[offset..offset + 4] {
+        [0, 0, 0, 0] => Bson::Null,
+        _ => Bson::String(read_pubkey(data, offset + 4)),
+    }
+}
+
+impl SplTokenDecoder {
+    // Token amounts may exceed i64, they are stored as strings like the RPC does.
+    fn decode_account(data: &[u8]) -> Result<Document, String> {
+        let state = match data[108] {
+            0 => return Err("Token account is not initialized".to_string()),
+            1 => "initialized",
+            2 => "frozen",
+            state => return Err(format!("Invalid token account state {}", state)),
+        };
+        let is_native = match data[109..113] {
+            [0, 0, 0, 0] => Bson::Null,
+            _ => Bson::String(read_u64(data, 113).to_string()),
+        };
+        Ok(doc! {
+            "type": "account",
+            "mint": read_pubkey(data, 0),
+            "owner": read_pubkey(data, 32),
+            "amount": read_u64(data, 64).to_string(),
+            "delegate": read_optional_pubkey(data, 72),
+            "state": state,
+            "is_native": is_native,
+            "delegated_amount": read_u64(data, 121).to_string(),
+            "close_authority": read_optional_pubkey(data, 129),
+        })
+    }
+
+    fn decode_mint(data: &[u8]) -> Result<Document, String> {
+        if data[45] == 0 {
+            return Err("Mint is not initialized".to_string());
+        }
+        Ok(doc! {
+            "type": "mint",
+            "mint_authority": read_optional_pubkey(data, 0),
+            "supply": read_u64(data, 36).to_string(),
+            "decimals": data[44] as i32,
+            "freeze_authority": read_optional_pubkey(data, 46),
+        })
+    }
+
+    fn decode_multisig(data: &[u8]) -> Result<Document, String> {
+        if data[2] == 0 {
+            return Err("Multisig is not initialized".to_string());
+        }
+        let signer_count = (data[1] as usize).min(11);
+        Ok(doc! {
+            "type": "multisig",
+            "m": data[0] as i32,
+            "n": data[1] as i32,
+            "signers": (0..signer_count)
+                .map(|index| read_pubkey(data, 3 + index * 32))
+                .collect::<Vec<String>>(),
+        })
+    }
+}
+
+impl AccountDecoder for SplTokenDecoder {
+    fn name(&self) -> &'static str {
+        "spl_token"
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Document, String> {
+        match data.len() {
+            SPL_TOKEN_ACCOUNT_LEN => Self::decode_account(data),
+            SPL_TOKEN_MINT_LEN => Self::decode_mint(data),
+            SPL_TOKEN_MULTISIG_LEN => Self::decode_multisig(data),
+            len if len > SPL_TOKEN_ACCOUNT_LEN => match data[SPL_TOKEN_ACCOUNT_LEN] {
+                SPL_TOKEN_2022_ACCOUNT_TYPE_ACCOUNT => Self::decode_account(data),
+                SPL_TOKEN_2022_ACCOUNT_TYPE_MINT => Self::decode_mint(data),
+                account_type => Err(format!("Unknown token account type {}", account_type)),
+            },
+            len => Err(format!("Unexpected token account length {}", len)),
+        }
+    }
+}
+
+// UpgradeableLoaderState::Buffer and UpgradeableLoaderState::ProgramData
+const BUFFER_TAG: [u8; 4] = [1, 0, 0, 0];
+const PROGRAM_DATA_TAG: [u8; 4] = [3, 0, 0, 0];
+
+/// Whether the account holds a program binary: an executable account, or the
+/// ProgramData or Buffer account of the upgradeable BPF loader.
+pub fn is_program_account(executable: bool, owner: &[u8], data: &[u8]) -> bool {
+    executable
+        || is_program_data_account(owner, data)
+        || (owner == bpf_loader_upgradeable::id().as_ref() && data.starts_with(&BUFFER_TAG))
+}
+
+/// Whether the account is the ProgramData account of an upgradeable program.
+pub fn is_program_data_account(owner: &[u8], data: &[u8]) -> bool {
+    owner == bpf_loader_upgradeable::id().as_ref() && data.starts_with(&PROGRAM_DATA_TAG)
+}
+
+pub fn is_spl_token_program(program_id: &str) -> bool {
+    program_id == SPL_TOKEN_PROGRAM_ID || program_id == SPL_TOKEN_2022_PROGRAM_ID
+}
+
+/// The position among its accounts of the mint an SPL Token or Token-2022 instruction
+/// operates on, None for instructions not naming a mint, e.g. unchecked transfers.
+pub fn spl_token_instruction_mint_position(data: &[u8]) -> Option<usize> {
+    match data.first()? {
+        // InitializeMint, MintTo, MintToChecked, InitializeMint2
+        0 | 7 | 14 | 20 => Some(0),
+        // InitializeAccount, Burn, FreezeAccount, ThawAccount, TransferChecked,
+        // ApproveChecked, BurnChecked, InitializeAccount2, InitializeAccount3
+        1 | 8 | 10 | 11 | 12 | 13 | 15 | 16 | 18 => Some(1),
+        _ => None,
+    }
+}
+
+/// The owner and mint of an SPL Token or Token-2022 token account, None for any other
+/// account.
+pub fn token_account_owner_and_mint(
+    owner_program: &[u8],
+    data: &[u8],
+) -> Option<(Pubkey, Pubkey)> {
+    let token_program = Pubkey::try_from(owner_program).ok()?;
+    if !is_spl_token_program(&token_program.to_string()) {
+        return None;
+    }
+    let is_token_account = data.len() == SPL_TOKEN_ACCOUNT_LEN
+        || (data.len() > SPL_TOKEN_ACCOUNT_LEN
+            && data[SPL_TOKEN_ACCOUNT_LEN] == SPL_TOKEN_2022_ACCOUNT_TYPE_ACCOUNT);
+    if !is_token_account {
+        return None;
+    }
+    let mint = Pubkey::try_from(&data[0..32]).ok()?;
+    let owner = Pubkey::try_from(&data[32..64]).ok()?;
+    Some((owner, mint))
+}
+
+/// The wallet owner and mint of a token account that is the associated token account
+/// of that owner and mint. None for other accounts, including token accounts at
+/// other addresses.
+pub fn associated_token_account_owner_and_mint(
+    pubkey: &[u8],
+    owner_program: &[u8],
+    data: &[u8],
+) -> Option<(Pubkey, Pubkey)> {
+    let token_program = Pubkey::try_from(owner_program).ok()?;
+    let (wallet, mint) = token_account_owner_and_mint(owner_program, data)?;
+    let (associated_token_account, _) = Pubkey::find_program_address(
+        &[wallet.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &Pubkey::from_str(SPL_ASSOCIATED_TOKEN_ACCOUNT_PROGRAM_ID).unwrap(),
+    );
+    (associated_token_account.as_ref() == pubkey).then_some((wallet, mint))
+}
+
+fn builtin_decoder(name: &str) -> Option<Arc<dyn AccountDecoder>> {
+    match name {
+        "spl_token" => Some(Arc::new(SplTokenDecoder)),
+        _ => None,
+    }
+}
+
+fn builtin_decoder_name(owner: &str) -> Option<&'static str> {
+    match owner {
+        SPL_TOKEN_PROGRAM_ID | SPL_TOKEN_2022_PROGRAM_ID => Some("spl_token"),
+        _ => None,
+    }
+}
+
+struct RegisteredDecoder {
+    decoder: Arc<dyn AccountDecoder>,
+    on_decode_failure: DecodeFailurePolicy,
+}
+
+/// The decoders by owner program. Each worker owns a registry, so the failure
+/// counters need no synchronization.
+#[derive(Default)]
+pub struct AccountDecoderRegistry {
+    decoders: HashMap<Vec<u8>, RegisteredDecoder>,
+    decode_failures: HashMap<Vec<u8>, u64>,
+}
+
+impl AccountDecoderRegistry {
+    /// Builds the registry from `decode_accounts`, which registers the built-in
+    /// decoders for their owners, and the per-owner `account_decoders` entries.
+    pub fn from_config(config: &GeyserPluginMongoDBConfig) -> Result<Self, GeyserPluginError> {
+        let to_error = |msg: String| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::ConfigurationError {
+                msg,
+            }))
+        };
+        let mut registry = Self::default();
+        if config.decode_accounts.unwrap_or_default() {
+            for owner in [SPL_TOKEN_PROGRAM_ID, SPL_TOKEN_2022_PROGRAM_ID] {
+                registry.register(
+                    bs58::decode(owner).into_vec().unwrap(),
+                    builtin_decoder("spl_token").unwrap(),
+                    DecodeFailurePolicy::default(),
+                );
+            }
+        }
+        for decoder_config in config.account_decoders.iter().flatten() {
+            let owner = bs58::decode(&decoder_config.owner)
+                .into_vec()
+                .map_err(|err| {
+                    to_error(format!(
+                        "Invalid account decoder owner {}: {}",
+                        decoder_config.owner, err
+                    ))
+                })?;
+            let name = decoder_config
+                .decoder
+                .as_deref()
+                .or_else(|| builtin_decoder_name(&decoder_config.owner))
+                .ok_or_else(|| {
+                    to_error(format!(
+                        "No decoder given for the account decoder owner {}",
+                        decoder_config.owner
+                    ))
+                })?;
+            let decoder = builtin_decoder(name)
+                .ok_or_else(|| to_error(format!("Unknown account decoder {}", name)))?;
+            registry.register(owner, decoder, decoder_config.on_decode_failure);
+        }
+        if !registry.decoders.is_empty() {
+            info!(
+                "Decoding the accounts of {} owner programs",
+                registry.decoders.len()
+            );
+        }
+        Ok(registry)
+    }
+
+    pub fn register(
+        &mut self,
+        owner: Vec<u8>,
+        decoder: Arc<dyn AccountDecoder>,
+        on_decode_failure: DecodeFailurePolicy,
+    ) {
+        self.decoders.insert(
+            owner,
+            RegisteredDecoder {
+                decoder,
+                on_decode_failure,
+            },
+        );
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.decoders.is_empty()
+    }
+
+    /// Decodes `data` with the decoder registered for `owner`, None when the owner
+    /// has no decoder.
+    pub fn decode(&self, owner: &[u8], data: &[u8]) -> Option<Result<Document, String>> {
+        self.decoders
+            .get(owner)
+            .map(|registered| registered.decoder.decode(data))
+    }
+
+    /// Adds the decoded fields of `account` to its `document`, or applies the
+    /// owner's failure policy when the data cannot be decoded.
+    pub fn decode_into(&mut self, account: &DbAccountInfo, document: &mut Document) {
+        let registered = match self.decoders.get(&account.owner) {
+            Some(registered) => registered,
+            None => return,
+        };
+        match registered.decoder.decode(&account.data) {
+            Ok(decoded) => {
+                document.insert("decoder", registered.decoder.name());
+                document.insert("decoded", decoded);
+            }
+            Err(err) => {
+                trace!(
+                    "{}",
+                    GeyserPluginMongoDbError::DecodeError {
+                        pubkey: bs58::encode(&account.pubkey).into_string(),
+                        owner: bs58::encode(&account.owner).into_string(),
+                        msg: format!("{}: {}", registered.decoder.name(), err),
+                    }
+                );
+                document.insert("decode_error", err);
+                if registered.on_decode_failure == DecodeFailurePolicy::DropRaw {
+                    document.remove("data");
+                }
+                *self
+                    .decode_failures
+                    .entry(account.owner.clone())
+                    .or_default() += 1;
+            }
+        }
+    }
+
+    /// Reports the decode failures per owner program and starts a new period.
+    pub fn report(&mut self) {
+        for (owner, failures) in self.decode_failures.drain() {
+            metrics::submit(
+                DataPoint::new("geyser-plugin-mongodb-decode-failures")
+                    .add_field_str("program", &bs58::encode(&owner).into_string())
+                    .add_field_i64("failures", failures as i64),
+                Level::Info,
+            );
+        }
+    }
+}
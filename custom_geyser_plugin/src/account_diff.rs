@@ -0,0 +1,187 @@
+/// Compares the state of accounts at two slots using the account history collection.
+///
+/// The state of an account at a slot is its newest version stored at or before that
+/// slot. Accounts without a version, or with zero lamports, do not exist at the slot.
+use {
+    crate::account_decoder::AccountDecoderRegistry,
+    futures::TryStreamExt,
+    mongodb::{
+        bson::{doc, Bson, Document},
+        error::Result,
+        Collection,
+    },
+    serde_derive::Serialize,
+    std::collections::{BTreeMap, BTreeSet},
+};
+
+/// The top level fields compared between two versions of an account.
+const COMPARED_FIELDS: [&str; 4] = ["owner", "lamports", "executable", "rent_epoch"];
+
+#[derive(Debug, Serialize)]
+pub struct FieldDiff {
+    /// The field name, decoded fields are prefixed with `decoded.`.
+    pub field: String,
+    pub before: Bson,
+    pub after: Bson,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ChangedAccount {
+    pub pubkey: String,
+    pub data_changed: bool,
+    pub fields: Vec<FieldDiff>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct AccountDiff {
+    pub from_slot: u64,
+    pub to_slot: u64,
+    pub appeared: Vec<String>,
+    pub disappeared: Vec<String>,
+    pub changed: Vec<ChangedAccount>,
+}
+
+/// The newest version at or before `slot` of every account in the history
+/// collection, restricted to `pubkeys` and `owners` when given, keyed by pubkey.
+pub async fn account_states_at_slot(
+    history: &Collection<Document>,
+    slot: u64,
+    pubkeys: &[String],
+    owners: &[String],
+) -> Result<BTreeMap<String, Document>> {
+    // Versions stored one per document have a slot, buckets a first_slot.
+    let mut history_filter = doc! { "$or": [
+        { "slot": { "$lte": slot as i64 } },
+        { "first_slot": { "$lte": slot as i64 } },
+    ]};
+    if !pubkeys.is_empty() {
+        history_filter.insert("pubkey", doc! { "$in": pubkeys });
+    }
+    let mut pipeline = vec![
+        doc! { "$match": history_filter },
+        doc! { "$unwind": { "path": "$versions", "preserveNullAndEmptyArrays": true } },
+        doc! { "$replaceRoot": {
+            "newRoot": { "$mergeObjects": [
+                { "$ifNull": ["$versions", "$$ROOT"] },
+                { "pubkey": "$pubkey" },
+            ]},
+        }},
+        doc! { "$match": { "slot": { "$lte": slot as i64 } } },
+        doc! { "$sort": { "pubkey": 1, "slot": -1, "write_version": -1 } },
+        doc! { "$group": { "_id": "$pubkey", "account": { "$first": "$$ROOT" } } },
+        doc! { "$replaceRoot": { "newRoot": "$account" } },
+    ];
+    if !owners.is_empty() {
+        pipeline.push(doc! { "$match": { "owner": { "$in": owners } } });
+    }
+
+    let states: Vec<Document> = history
+        .aggregate(pipeline)
+        .allow_disk_use(true)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(states
+        .into_iter()
+        .filter(|state| !matches!(state.get("lamports"), Some(Bson::Int64(0))))
+        .filter_map(|state| {
+            let pubkey = state.get_str("pubkey").ok()?.to_string();
+            Some((pubkey, state))
+        })
+        .collect())
+}
+
+fn decode_state(decoders: Option<&AccountDecoderRegistry>, state: &Document) -> Option<Document> {
+    let owner = bs58::decode(state.get_str("owner").ok()?).into_vec().ok()?;
+    let data = state.get_binary_generic("data").ok()?;
+    decoders?.decode(&owner, data)?.ok()
+}
+
+fn diff_fields(
+    prefix: &str,
+    fields: impl IntoIterator<Item = String>,
+    before: &Document,
+    after: &Document,
+    diffs: &mut Vec<FieldDiff>,
+) {
+    for field in fields {
+        let (old, new) = (
+            before.get(&field).cloned().unwrap_or(Bson::Null),
+            after.get(&field).cloned().unwrap_or(Bson::Null),
+        );
+        if old != new {
+            diffs.push(FieldDiff {
+                field: format!("{}{}", prefix, field),
+                before: old,
+                after: new,
+            });
+        }
+    }
+}
+
+/// Compares two account states. Returns None when nothing changed.
+pub fn diff_account_states(
+    pubkey: &str,
+    before: &Document,
+    after: &Document,
+    decoders: Option<&AccountDecoderRegistry>,
+) -> Option<ChangedAccount> {
+    let mut fields = Vec::default();
+    diff_fields(
+        "",
+        COMPARED_FIELDS.iter().map(|field| field.to_string()),
+        before,
+        after,
+        &mut fields,
+    );
+    let data_changed = before.get("data") != after.get("data");
+    if data_changed {
+        if let (Some(old), Some(new)) = (
+            decode_state(decoders, before),
+            decode_state(decoders, after),
+        ) {
+            let keys: BTreeSet<String> = old.keys().chain(new.keys()).cloned().collect();
+            diff_fields("decoded.", keys, &old, &new, &mut fields);
+        }
+    }
+    (data_changed || !fields.is_empty()).then(|| ChangedAccount {
+        pubkey: pubkey.to_string(),
+        data_changed,
+        fields,
+    })
+}
+
+/// Reports the accounts that appeared, disappeared or changed between `from_slot`
+/// and `to_slot`. Accounts of owners with a decoder in `decoders` get field level
+/// diffs of their decoded data.
+pub async fn diff_accounts_between_slots(
+    history: &Collection<Document>,
+    from_slot: u64,
+    to_slot: u64,
+    pubkeys: &[String],
+    owners: &[String],
+    decoders: Option<&AccountDecoderRegistry>,
+) -> Result<AccountDiff> {
+    let before = account_states_at_slot(history, from_slot, pubkeys, owners).await?;
+    let after = account_states_at_slot(history, to_slot, pubkeys, owners).await?;
+
+    let mut diff = AccountDiff {
+        from_slot,
+        to_slot,
+        ..AccountDiff::default()
+    };
+    for (pubkey, old) in &before {
+        match after.get(pubkey) {
+            Some(new) => diff
+                .changed
+                .extend(diff_account_states(pubkey, old, new, decoders)),
+            None => diff.disappeared.push(pubkey.clone()),
+        }
+    }
+    diff.appeared = after
+        .keys()
+        .filter(|pubkey| !before.contains_key(*pubkey))
+        .cloned()
+        .collect();
+    Ok(diff)
+}
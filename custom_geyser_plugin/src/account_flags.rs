@@ -0,0 +1,69 @@
+/// Derived flags stored on account documents, for filters that otherwise need client
+/// side math over the lamports, data length and address of every account:
+///
+/// - `rent_exempt`: the account holds the minimum balance for its data size.
+/// - `on_curve`: the address is on the ed25519 curve, so a keypair can sign for it.
+/// - `pda_likely`: the address is off the curve, as program derived addresses are,
+///   and not owned by the stake or vote program. Addresses created with a seed are
+///   off the curve about half of the time too, and are mostly stake and vote accounts.
+///
+/// Rent exemption follows the default rent, that of mainnet-beta, until a worker
+/// writes the rent sysvar, when selected, and then the rent it holds.
+use {
+    crate::mongodb_client::DbAccountInfo,
+    log::*,
+    mongodb::bson::Document,
+    solana_sdk::{pubkey::Pubkey, rent::Rent, stake, sysvar, vote},
+};
+
+/// The serialized size of the rent sysvar: lamports per byte-year, exemption
+/// threshold and burn percent.
+const RENT_SYSVAR_LEN: usize = 17;
+
+#[derive(Clone, Debug, Default)]
+pub struct AccountFlags {
+    rent: Rent,
+}
+
+impl AccountFlags {
+    /// Adds the flags of `account` to `document`, picking up the rent from the rent
+    /// sysvar when `account` is that sysvar.
+    pub fn flag(&mut self, account: &DbAccountInfo, document: &mut Document) {
+        if account.pubkey == sysvar::rent::id().as_ref() && account.redacted_data.is_none() {
+            self.update_rent(&account.data);
+        }
+        let data_len = account
+            .redacted_data
+            .as_ref()
+            .map_or(account.data.len(), |redacted| redacted.len);
+        let on_curve = Pubkey::try_from(account.pubkey.as_slice())
+            .map(|pubkey| pubkey.is_on_curve())
+            .unwrap_or(true);
+        let owner = Pubkey::try_from(account.owner.as_slice()).unwrap_or_default();
+        document.insert(
+            "rent_exempt",
+            self.rent.is_exempt(account.lamports as u64, data_len),
+        );
+        document.insert("on_curve", on_curve);
+        document.insert(
+            "pda_likely",
+            !on_curve && owner != stake::program::id() && owner != vote::program::id(),
+        );
+    }
+
+    fn update_rent(&mut self, data: &[u8]) {
+        if data.len() < RENT_SYSVAR_LEN {
+            warn!("Ignoring a rent sysvar of {} bytes", data.len());
+            return;
+        }
+        let rent = Rent {
+            lamports_per_byte_year: u64::from_le_bytes(data[0..8].try_into().unwrap()),
+            exemption_threshold: f64::from_le_bytes(data[8..16].try_into().unwrap()),
+            burn_percent: data[16],
+        };
+        if rent != self.rent {
+            info!("Rent exemption follows the rent sysvar: {:?}", rent);
+            self.rent = rent;
+        }
+    }
+}
@@ -1,11 +1,228 @@
 
-use {log::*, std::collections::HashSet};
+use {
+    crate::{
+        account_decoder::{is_program_data_account, token_account_owner_and_mint},
+        geyser_plugin_mongodb::to_config_error,
+        metrics::CounterHandles,
+    },
+    base64::{engine::general_purpose::STANDARD, Engine},
+    log::*,
+    serde_json::Value,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_sdk::{native_loader, pubkey::Pubkey, sysvar},
+    std::{collections::HashSet, str::FromStr},
+};
+
+/// Names accepted in place of the program ids of common owners.
+pub const OWNER_ALIASES: [(&str, &str); 14] = [
+    ("system", "11111111111111111111111111111111"),
+    ("spl-token", "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA"),
+    ("token-2022", "TokenzQdBNbLqP5VEhdkAS6EPFLZ1VE8VMefKXY1xM"),
+    ("associated-token", "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL"),
+    ("stake", "Stake11111111111111111111111111111111111111"),
+    ("vote", "Vote111111111111111111111111111111111111111"),
+    ("config", "Config1111111111111111111111111111111111111"),
+    ("bpf-loader", "BPFLoader2111111111111111111111111111111111"),
+    ("bpf-loader-upgradeable", "BPFLoaderUpgradeab1e11111111111111111111111"),
+    ("address-lookup-table", "AddressLookupTab1e1111111111111111111111111"),
+    ("compute-budget", "ComputeBudget111111111111111111111111111111"),
+    ("memo", "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr"),
+    ("sysvar", "Sysvar1111111111111111111111111111111111111"),
+    ("native-loader", "NativeLoader1111111111111111111111111111111"),
+];
+
+/// The program id of an owner alias, other keys as they are.
+pub fn resolve_owner_alias(key: &str) -> &str {
+    OWNER_ALIASES
+        .iter()
+        .find(|(alias, _)| *alias == key)
+        .map(|(_, program_id)| *program_id)
+        .unwrap_or(key)
+}
+
+fn owner_keys(owners: &[String]) -> HashSet<Vec<u8>> {
+    owners
+        .iter()
+        .map(|key| bs58::decode(resolve_owner_alias(key)).into_vec().unwrap())
+        .collect()
+}
+
+/// The owners of the sysvars and of the builtin programs, excluded from the
+/// selections not naming them unless `exclude_sysvars` is false.
+pub fn default_builtin_owners() -> Vec<String> {
+    vec![sysvar::id().to_string(), native_loader::id().to_string()]
+}
+
+/// A condition on the account data, like the getProgramAccounts filters of the RPC.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum AccountFilter {
+    /// The data holds `bytes` at `offset`.
+    Memcmp { offset: usize, bytes: Vec<u8> },
+    /// The data is exactly this many bytes long.
+    DataSize(usize),
+    /// The data is at least this many bytes long.
+    MinDataSize(usize),
+    /// The data is at most this many bytes long.
+    MaxDataSize(usize),
+}
+
+impl AccountFilter {
+    pub fn matches(&self, data: &[u8]) -> bool {
+        match self {
+            AccountFilter::Memcmp { offset, bytes } => data
+                .get(*offset..)
+                .map(|data| data.starts_with(bytes))
+                .unwrap_or(false),
+            AccountFilter::DataSize(size) => data.len() == *size,
+            AccountFilter::MinDataSize(size) => data.len() >= *size,
+            AccountFilter::MaxDataSize(size) => data.len() <= *size,
+        }
+    }
+}
+
+/// The counter of every `AccountSelection`, by discriminant.
+pub type AccountSelectionCounters = CounterHandles<{ AccountSelection::ALL.len() }>;
+
+/// The rule that selected or rejected an account, counted per rule as
+/// `geyser-plugin-mongodb-account-selector-<matched|rejected>-<rule>`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum AccountSelection {
+    /// Selected by `"*"`.
+    MatchedWildcard,
+    MatchedAccount,
+    MatchedOwner,
+    /// The Base58 key starts with a selected prefix.
+    MatchedPrefix,
+    /// Selected by data filters, `executable_only` or token mints without any key.
+    MatchedUnkeyed,
+    RejectedExcludedAccount,
+    RejectedExcludedOwner,
+    /// A sysvar or builtin program not selected by its key or owner.
+    RejectedBuiltin,
+    RejectedLamports,
+    RejectedExecutable,
+    /// Neither the key nor the owner is selected.
+    RejectedKey,
+    RejectedTokenMint,
+    RejectedDataFilter,
+    /// Selected by the first set of a difference and by the second.
+    RejectedDifference,
+}
+
+impl AccountSelection {
+    /// Every selection, in the order of declaration.
+    pub const ALL: [AccountSelection; 14] = [
+        AccountSelection::MatchedWildcard,
+        AccountSelection::MatchedAccount,
+        AccountSelection::MatchedOwner,
+        AccountSelection::MatchedPrefix,
+        AccountSelection::MatchedUnkeyed,
+        AccountSelection::RejectedExcludedAccount,
+        AccountSelection::RejectedExcludedOwner,
+        AccountSelection::RejectedBuiltin,
+        AccountSelection::RejectedLamports,
+        AccountSelection::RejectedExecutable,
+        AccountSelection::RejectedKey,
+        AccountSelection::RejectedTokenMint,
+        AccountSelection::RejectedDataFilter,
+        AccountSelection::RejectedDifference,
+    ];
+
+    /// Resolves the counters of every selection in the registry of the current instance.
+    pub fn counters() -> AccountSelectionCounters {
+        CounterHandles::new(Self::ALL.map(|selection| selection.counter_name()))
+    }
+
+    pub fn is_selected(&self) -> bool {
+        matches!(
+            self,
+            AccountSelection::MatchedWildcard
+                | AccountSelection::MatchedAccount
+                | AccountSelection::MatchedOwner
+                | AccountSelection::MatchedPrefix
+                | AccountSelection::MatchedUnkeyed
+        )
+    }
+
+    pub fn counter_name(&self) -> &'static str {
+        match self {
+            AccountSelection::MatchedWildcard => {
+                "geyser-plugin-mongodb-account-selector-matched-wildcard"
+            }
+            AccountSelection::MatchedAccount => {
+                "geyser-plugin-mongodb-account-selector-matched-account"
+            }
+            AccountSelection::MatchedOwner => {
+                "geyser-plugin-mongodb-account-selector-matched-owner"
+            }
+            AccountSelection::MatchedPrefix => {
+                "geyser-plugin-mongodb-account-selector-matched-prefix"
+            }
+            AccountSelection::MatchedUnkeyed => {
+                "geyser-plugin-mongodb-account-selector-matched-unkeyed"
+            }
+            AccountSelection::RejectedExcludedAccount => {
+                "geyser-plugin-mongodb-account-selector-rejected-excluded-account"
+            }
+            AccountSelection::RejectedExcludedOwner => {
+                "geyser-plugin-mongodb-account-selector-rejected-excluded-owner"
+            }
+            AccountSelection::RejectedBuiltin => {
+                "geyser-plugin-mongodb-account-selector-rejected-builtin"
+            }
+            AccountSelection::RejectedLamports => {
+                "geyser-plugin-mongodb-account-selector-rejected-lamports"
+            }
+            AccountSelection::RejectedExecutable => {
+                "geyser-plugin-mongodb-account-selector-rejected-executable"
+            }
+            AccountSelection::RejectedKey => {
+                "geyser-plugin-mongodb-account-selector-rejected-key"
+            }
+            AccountSelection::RejectedTokenMint => {
+                "geyser-plugin-mongodb-account-selector-rejected-token-mint"
+            }
+            AccountSelection::RejectedDataFilter => {
+                "geyser-plugin-mongodb-account-selector-rejected-data-filter"
+            }
+            AccountSelection::RejectedDifference => {
+                "geyser-plugin-mongodb-account-selector-rejected-difference"
+            }
+        }
+    }
+}
 
 #[derive(Debug, Default)]
-pub(crate) struct AccountsSelector {
+pub struct AccountsSelector {
     pub accounts: HashSet<Vec<u8>>,
     pub owners: HashSet<Vec<u8>>,
     pub select_all_accounts: bool,
+    /// All of them must match the data of an account selected by key or owner.
+    /// With no accounts or owners given, they select among all accounts.
+    pub filters: Vec<AccountFilter>,
+    /// Never selected, whatever the rules above say.
+    pub exclude_accounts: HashSet<Vec<u8>>,
+    /// Accounts of these owners are never selected.
+    pub exclude_owners: HashSet<Vec<u8>>,
+    /// Accounts holding fewer lamports are never selected.
+    pub min_lamports: Option<u64>,
+    /// Accounts holding more lamports are never selected.
+    pub max_lamports: Option<u64>,
+    /// Only executable accounts are selected. With no accounts or owners given,
+    /// selects all of them.
+    pub executable_only: bool,
+    /// With `executable_only`, the ProgramData accounts of upgradeable programs are
+    /// selected as well.
+    pub include_program_data: bool,
+    /// Only SPL Token and Token-2022 token accounts of these mints are selected. With
+    /// no accounts or owners given, selects among all token accounts.
+    pub token_mints: HashSet<Vec<u8>>,
+    /// Accounts whose Base58 key starts with one of these are selected. Sorted, and
+    /// without prefixes covered by a shorter one, see `with_account_prefixes`.
+    pub account_prefixes: Vec<String>,
+    /// Accounts of these owners are only selected by their key or owner, not by the
+    /// wildcard, prefixes or unkeyed rules. See `default_builtin_owners`.
+    pub builtin_owners: HashSet<Vec<u8>>,
 }
 
 
@@ -23,29 +240,546 @@ impl AccountsSelector {
                 accounts: HashSet::default(),
                 owners: HashSet::default(),
                 select_all_accounts,
+                ..AccountsSelector::default()
             };
         }
         let accounts = accounts
             .iter()
             .map(|key| bs58::decode(key).into_vec().unwrap())
             .collect();
-        let owners = owners
-            .iter()
-            .map(|key| bs58::decode(key).into_vec().unwrap())
-            .collect();
+        let owners = owner_keys(owners);
         AccountsSelector {
             accounts,
             owners,
             select_all_accounts,
+            ..AccountsSelector::default()
         }
     }
 
-   pub fn is_account_selected(&self, account: &[u8], owner: &[u8]) -> bool {
-        self.select_all_accounts || self.accounts.contains(account) || self.owners.contains(owner)
+    /// Excludes `accounts` and the accounts of `owners` from the selection, taking
+    /// precedence over every include rule.
+    pub fn with_exclusions(mut self, accounts: &[String], owners: &[String]) -> Self {
+        info!("Excluding accounts: {:?}, owners: {:?}", accounts, owners);
+        self.exclude_accounts = accounts
+            .iter()
+            .map(|key| bs58::decode(key).into_vec().unwrap())
+            .collect();
+        self.exclude_owners = owner_keys(owners);
+        self
+    }
+
+    /// Restricts the selection to accounts whose data matches all of `filters`.
+    pub fn with_filters(mut self, filters: Vec<AccountFilter>) -> Self {
+        info!("Filtering selected accounts by data: {:?}", filters);
+        self.filters = filters;
+        self
+    }
+
+    /// Restricts the selection to accounts holding between `min` and `max` lamports.
+    pub fn with_lamports(mut self, min: Option<u64>, max: Option<u64>) -> Self {
+        info!("Selecting accounts with lamports between {:?} and {:?}", min, max);
+        self.min_lamports = min;
+        self.max_lamports = max;
+        self
+    }
+
+    /// Restricts the selection to executable accounts, and ProgramData accounts when
+    /// `include_program_data` is set.
+    pub fn with_executable_only(mut self, include_program_data: bool) -> Self {
+        info!(
+            "Selecting executable accounts only, including ProgramData accounts: {}",
+            include_program_data
+        );
+        self.executable_only = true;
+        self.include_program_data = include_program_data;
+        self
+    }
+
+    /// Restricts the selection to the token accounts of `mints`.
+    pub fn with_token_mints(mut self, mints: &[String]) -> Self {
+        info!("Selecting the token accounts of mints: {:?}", mints);
+        self.token_mints = mints
+            .iter()
+            .map(|key| bs58::decode(key).into_vec().unwrap())
+            .collect();
+        self
+    }
+
+    /// Selects the accounts whose Base58 key starts with one of `prefixes`, e.g. the
+    /// vanity addresses of a program.
+    pub fn with_account_prefixes(mut self, prefixes: &[String]) -> Self {
+        info!("Selecting accounts with key prefixes: {:?}", prefixes);
+        let mut prefixes = prefixes.to_vec();
+        prefixes.sort();
+        // Sorted, a prefix comes right before the prefixes it covers.
+        prefixes.dedup_by(|covered, prefix| covered.starts_with(prefix.as_str()));
+        self.account_prefixes = prefixes;
+        self
+    }
+
+    /// Leaves the accounts of `owners`, e.g. the sysvars, out of the selections that
+    /// do not name them.
+    pub fn with_builtin_exclusions(mut self, owners: &[String]) -> Self {
+        info!("Excluding the builtin accounts of owners: {:?}", owners);
+        self.builtin_owners = owner_keys(owners);
+        self
+    }
+
+    /// Without covered prefixes only the greatest prefix not after the key can be a
+    /// prefix of it, so one binary search decides.
+    fn is_prefix_selected(&self, account: &[u8]) -> bool {
+        if self.account_prefixes.is_empty() {
+            return false;
+        }
+        let key = bs58::encode(account).into_string();
+        match self
+            .account_prefixes
+            .partition_point(|prefix| prefix.as_str() <= key.as_str())
+        {
+            0 => false,
+            index => key.starts_with(self.account_prefixes[index - 1].as_str()),
+        }
+    }
+
+    /// Reads no more of the data than the mint of a token account.
+    fn is_token_mint_selected(&self, owner: &[u8], data: &[u8]) -> bool {
+        self.token_mints.is_empty()
+            || token_account_owner_and_mint(owner, data)
+                .map(|(_, mint)| self.token_mints.contains(mint.as_ref()))
+                .unwrap_or(false)
+    }
+
+    fn is_executable_selected(&self, owner: &[u8], executable: bool, data: &[u8]) -> bool {
+        !self.executable_only
+            || executable
+            || (self.include_program_data && is_program_data_account(owner, data))
+    }
+
+    fn is_lamports_selected(&self, lamports: u64) -> bool {
+        self.min_lamports.map(|min| lamports >= min).unwrap_or(true)
+            && self.max_lamports.map(|max| lamports <= max).unwrap_or(true)
+    }
+
+    pub fn is_account_selected(
+        &self,
+        account: &[u8],
+        owner: &[u8],
+        lamports: u64,
+        executable: bool,
+        data: &[u8],
+    ) -> bool {
+        self.account_selection(account, owner, lamports, executable, data)
+            .is_selected()
+    }
+
+    /// The rule selecting or rejecting the account, the exclusions first.
+    pub fn account_selection(
+        &self,
+        account: &[u8],
+        owner: &[u8],
+        lamports: u64,
+        executable: bool,
+        data: &[u8],
+    ) -> AccountSelection {
+        if self.exclude_accounts.contains(account) {
+            return AccountSelection::RejectedExcludedAccount;
+        }
+        if self.exclude_owners.contains(owner) {
+            return AccountSelection::RejectedExcludedOwner;
+        }
+        if !self.is_lamports_selected(lamports) {
+            return AccountSelection::RejectedLamports;
+        }
+        if !self.is_executable_selected(owner, executable, data) {
+            return AccountSelection::RejectedExecutable;
+        }
+        let selection = if self.select_all_accounts {
+            AccountSelection::MatchedWildcard
+        } else if self.accounts.contains(account) {
+            AccountSelection::MatchedAccount
+        } else if self.owners.contains(owner) {
+            AccountSelection::MatchedOwner
+        } else if self.is_prefix_selected(account) {
+            AccountSelection::MatchedPrefix
+        } else if self.accounts.is_empty()
+            && self.owners.is_empty()
+            && self.account_prefixes.is_empty()
+            && (!self.filters.is_empty() || self.executable_only || !self.token_mints.is_empty())
+        {
+            AccountSelection::MatchedUnkeyed
+        } else {
+            return AccountSelection::RejectedKey;
+        };
+        let is_named = matches!(
+            selection,
+            AccountSelection::MatchedAccount | AccountSelection::MatchedOwner
+        );
+        if !is_named && self.builtin_owners.contains(owner) {
+            return AccountSelection::RejectedBuiltin;
+        }
+        if !self.is_token_mint_selected(owner, data) {
+            return AccountSelection::RejectedTokenMint;
+        }
+        if !self.filters.iter().all(|filter| filter.matches(data)) {
+            return AccountSelection::RejectedDataFilter;
+        }
+        selection
     }
 
     /// Check if any account is of interested at all
     pub fn is_enabled(&self) -> bool {
-        self.select_all_accounts || !self.accounts.is_empty() || !self.owners.is_empty()
+        self.select_all_accounts
+            || !self.accounts.is_empty()
+            || !self.owners.is_empty()
+            || !self.account_prefixes.is_empty()
+            || !self.filters.is_empty()
+            || self.executable_only
+            || !self.token_mints.is_empty()
+    }
+}
+
+/// A composition of selector groups. A group is an `AccountsSelector`, groups are
+/// combined with union, intersection and difference, e.g. the accounts of owner A
+/// except some of them:
+/// "accounts_selector" : {
+///     "difference" : \[
+///         { "owners" : \["owner-a"\] },
+///         { "accounts" : \["pubkey-1", "pubkey-2"\] }
+///     \]
+/// }
+#[derive(Debug)]
+pub enum SelectorSet {
+    Group(AccountsSelector),
+    /// Accounts selected by any of the sets.
+    Union(Vec<SelectorSet>),
+    /// Accounts selected by all of the sets.
+    Intersection(Vec<SelectorSet>),
+    /// Accounts selected by the first set but not by the second.
+    Difference(Box<SelectorSet>, Box<SelectorSet>),
+}
+
+impl Default for SelectorSet {
+    fn default() -> Self {
+        SelectorSet::Group(AccountsSelector::default())
+    }
+}
+
+impl From<AccountsSelector> for SelectorSet {
+    fn from(selector: AccountsSelector) -> Self {
+        SelectorSet::Group(selector)
+    }
+}
+
+fn keys_from_config(config: &Value, field: &str) -> Result<Vec<String>, GeyserPluginError> {
+    match &config[field] {
+        Value::Null => Ok(Vec::default()),
+        Value::Array(keys) => keys
+            .iter()
+            .map(|key| {
+                let key = key.as_str().ok_or_else(|| {
+                    to_config_error(format!("\"{}\" of a selector must hold strings", field))
+                })?;
+                if key != "*" {
+                    bs58::decode(key).into_vec().map_err(|err| {
+                        to_config_error(format!("Invalid key {} in a selector: {}", key, err))
+                    })?;
+                }
+                Ok(key.to_string())
+            })
+            .collect(),
+        _ => Err(to_config_error(format!(
+            "\"{}\" of a selector must be an array",
+            field
+        ))),
+    }
+}
+
+/// Reads owner keys, program ids or the names of `OWNER_ALIASES`.
+fn owner_keys_from_config(config: &Value, field: &str) -> Result<Vec<String>, GeyserPluginError> {
+    let owners = match &config[field] {
+        Value::Null => return Ok(Vec::default()),
+        Value::Array(owners) => owners,
+        _ => {
+            return Err(to_config_error(format!(
+                "\"{}\" of a selector must be an array",
+                field
+            )))
+        }
+    };
+    owners
+        .iter()
+        .map(|owner| {
+            let owner = owner.as_str().ok_or_else(|| {
+                to_config_error(format!("\"{}\" of a selector must hold strings", field))
+            })?;
+            if let Err(err) = Pubkey::from_str(resolve_owner_alias(owner)) {
+                let aliases: Vec<&str> = OWNER_ALIASES.iter().map(|(alias, _)| *alias).collect();
+                return Err(to_config_error(format!(
+                    "\"{}\" in \"{}\" is neither a pubkey ({}) nor one of the aliases {}",
+                    owner,
+                    field,
+                    err,
+                    aliases.join(", ")
+                )));
+            }
+            Ok(owner.to_string())
+        })
+        .collect()
+}
+
+fn filter_bytes(memcmp: &Value) -> Result<Vec<u8>, GeyserPluginError> {
+    let bytes = memcmp["bytes"]
+        .as_str()
+        .ok_or_else(|| to_config_error("A memcmp filter needs \"bytes\"".to_string()))?;
+    match memcmp["encoding"].as_str().unwrap_or("base58") {
+        "base58" => bs58::decode(bytes).into_vec().map_err(|err| err.to_string()),
+        "base64" => STANDARD.decode(bytes).map_err(|err| err.to_string()),
+        encoding => Err(format!("unknown encoding {}", encoding)),
+    }
+    .map_err(|err| to_config_error(format!("Invalid memcmp bytes {}: {}", bytes, err)))
+}
+
+/// Parses `filters`, e.g.
+/// \[{ "memcmp" : { "offset" : 32, "bytes" : "<base58>" } }, { "data_size" : 165 }\]
+/// The length can also be bounded with `min_data_size` and `max_data_size`.
+fn filters_from_config(config: &Value) -> Result<Vec<AccountFilter>, GeyserPluginError> {
+    match &config["filters"] {
+        Value::Null => Ok(Vec::default()),
+        Value::Array(filters) => filters
+            .iter()
+            .map(|filter| {
+                if let Some(size) = filter["data_size"].as_u64() {
+                    Ok(AccountFilter::DataSize(size as usize))
+                } else if let Some(size) = filter["min_data_size"].as_u64() {
+                    Ok(AccountFilter::MinDataSize(size as usize))
+                } else if let Some(size) = filter["max_data_size"].as_u64() {
+                    Ok(AccountFilter::MaxDataSize(size as usize))
+                } else if filter["memcmp"].is_object() {
+                    let memcmp = &filter["memcmp"];
+                    let offset = memcmp["offset"].as_u64().ok_or_else(|| {
+                        to_config_error("A memcmp filter needs an \"offset\"".to_string())
+                    })?;
+                    Ok(AccountFilter::Memcmp {
+                        offset: offset as usize,
+                        bytes: filter_bytes(memcmp)?,
+                    })
+                } else {
+                    Err(to_config_error(format!("Unknown account filter {}", filter)))
+                }
+            })
+            .collect(),
+        _ => Err(to_config_error(
+            "\"filters\" of a selector must be an array".to_string(),
+        )),
+    }
+}
+
+/// Parses the optional `min_lamports` and `max_lamports` bounds of a selector.
+fn lamports_from_config(
+    config: &Value,
+) -> Result<(Option<u64>, Option<u64>), GeyserPluginError> {
+    let bound = |field: &str| match &config[field] {
+        Value::Null => Ok(None),
+        value => value.as_u64().map(Some).ok_or_else(|| {
+            to_config_error(format!(
+                "\"{}\" of a selector must be a number of lamports",
+                field
+            ))
+        }),
+    };
+    let (min, max) = (bound("min_lamports")?, bound("max_lamports")?);
+    if let (Some(min), Some(max)) = (min, max) {
+        if min > max {
+            return Err(to_config_error(format!(
+                "\"min_lamports\" {} is above \"max_lamports\" {}",
+                min, max
+            )));
+        }
+    }
+    Ok((min, max))
+}
+
+impl SelectorSet {
+    pub fn union(self, other: SelectorSet) -> Self {
+        match self {
+            SelectorSet::Union(mut sets) => {
+                sets.push(other);
+                SelectorSet::Union(sets)
+            }
+            set => SelectorSet::Union(vec![set, other]),
+        }
+    }
+
+    pub fn intersection(self, other: SelectorSet) -> Self {
+        match self {
+            SelectorSet::Intersection(mut sets) => {
+                sets.push(other);
+                SelectorSet::Intersection(sets)
+            }
+            set => SelectorSet::Intersection(vec![set, other]),
+        }
+    }
+
+    pub fn difference(self, other: SelectorSet) -> Self {
+        SelectorSet::Difference(Box::new(self), Box::new(other))
+    }
+
+    /// Parses a selector from the `accounts_selector` config section. A section with
+    /// `union`, `intersection` or `difference` combines the nested selectors, any
+    /// other section is a group of `accounts`, `owners` and `account_prefixes`,
+    /// optionally narrowed by data `filters`, `min_lamports` / `max_lamports`,
+    /// `executable_only`, `select_token_mints` and `exclude_accounts` /
+    /// `exclude_owners`. The sysvars and builtin programs are left out unless named,
+    /// see `exclude_sysvars` and `builtin_owners`.
+    pub fn from_config(config: &Value) -> Result<Self, GeyserPluginError> {
+        let nested = |operands: &Value| -> Result<Vec<SelectorSet>, GeyserPluginError> {
+            operands
+                .as_array()
+                .ok_or_else(|| {
+                    to_config_error("Selector operations take an array of selectors".to_string())
+                })?
+                .iter()
+                .map(SelectorSet::from_config)
+                .collect()
+        };
+        if config.is_null() {
+            Ok(SelectorSet::default())
+        } else if !config["union"].is_null() {
+            Ok(SelectorSet::Union(nested(&config["union"])?))
+        } else if !config["intersection"].is_null() {
+            Ok(SelectorSet::Intersection(nested(&config["intersection"])?))
+        } else if !config["difference"].is_null() {
+            let mut operands = nested(&config["difference"])?.into_iter();
+            match (operands.next(), operands.next(), operands.next()) {
+                (Some(selected), Some(excluded), None) => Ok(selected.difference(excluded)),
+                _ => Err(to_config_error(
+                    "\"difference\" takes exactly two selectors".to_string(),
+                )),
+            }
+        } else {
+            let specific_keys = |field: &str| -> Result<Vec<String>, GeyserPluginError> {
+                let keys = keys_from_config(config, field)?;
+                if keys.iter().any(|key| key == "*") {
+                    return Err(to_config_error(format!("\"{}\" does not take \"*\"", field)));
+                }
+                Ok(keys)
+            };
+            let flag_or = |field: &str, default: bool| match &config[field] {
+                Value::Null => Ok(default),
+                value => value
+                    .as_bool()
+                    .ok_or_else(|| to_config_error(format!("\"{}\" must be a boolean", field))),
+            };
+            let flag = |field: &str| flag_or(field, false);
+            let exclude_sysvars = flag_or("exclude_sysvars", true)?;
+            let builtin_owners = match (exclude_sysvars, &config["builtin_owners"]) {
+                (false, _) => Vec::default(),
+                (true, Value::Null) => default_builtin_owners(),
+                (true, _) => owner_keys_from_config(config, "builtin_owners")?,
+            };
+            let (min_lamports, max_lamports) = lamports_from_config(config)?;
+            let mut selector = AccountsSelector::new(
+                &keys_from_config(config, "accounts")?,
+                &owner_keys_from_config(config, "owners")?,
+            )
+            .with_filters(filters_from_config(config)?)
+            .with_lamports(min_lamports, max_lamports)
+            .with_token_mints(&specific_keys("select_token_mints")?)
+            .with_account_prefixes(&specific_keys("account_prefixes")?)
+            .with_builtin_exclusions(&builtin_owners)
+            .with_exclusions(
+                &specific_keys("exclude_accounts")?,
+                &owner_keys_from_config(config, "exclude_owners")?,
+            );
+            if flag("executable_only")? {
+                selector = selector.with_executable_only(flag("include_program_data")?);
+            } else if flag("include_program_data")? {
+                return Err(to_config_error(
+                    "\"include_program_data\" requires \"executable_only\"".to_string(),
+                ));
+            }
+            Ok(SelectorSet::Group(selector))
+        }
+    }
+
+    /// Whether the account is selected, counting the rule that decided in `counters`.
+    pub fn is_account_selected(
+        &self,
+        counters: &AccountSelectionCounters,
+        account: &[u8],
+        owner: &[u8],
+        lamports: u64,
+        executable: bool,
+        data: &[u8],
+    ) -> bool {
+        let selection = self.account_selection(account, owner, lamports, executable, data);
+        counters.get(selection as usize).add(1);
+        selection.is_selected()
+    }
+
+    /// The rule that decided: the first selecting group of a union, the first
+    /// rejecting group of an intersection.
+    pub fn account_selection(
+        &self,
+        account: &[u8],
+        owner: &[u8],
+        lamports: u64,
+        executable: bool,
+        data: &[u8],
+    ) -> AccountSelection {
+        let selection = |set: &SelectorSet| {
+            set.account_selection(account, owner, lamports, executable, data)
+        };
+        match self {
+            SelectorSet::Group(selector) => {
+                selector.account_selection(account, owner, lamports, executable, data)
+            }
+            SelectorSet::Union(sets) => {
+                let mut rejected = AccountSelection::RejectedKey;
+                for set in sets {
+                    let set_selection = selection(set);
+                    if set_selection.is_selected() {
+                        return set_selection;
+                    }
+                    if rejected == AccountSelection::RejectedKey {
+                        rejected = set_selection;
+                    }
+                }
+                rejected
+            }
+            SelectorSet::Intersection(sets) => {
+                let mut selected = AccountSelection::RejectedKey;
+                for set in sets {
+                    let set_selection = selection(set);
+                    if !set_selection.is_selected() {
+                        return set_selection;
+                    }
+                    if !selected.is_selected() {
+                        selected = set_selection;
+                    }
+                }
+                selected
+            }
+            SelectorSet::Difference(selected, excluded) => {
+                let set_selection = selection(selected);
+                if set_selection.is_selected() && selection(excluded).is_selected() {
+                    AccountSelection::RejectedDifference
+                } else {
+                    set_selection
+                }
+            }
+        }
+    }
+
+    /// Check if any account may be of interest at all
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            SelectorSet::Group(selector) => selector.is_enabled(),
+            SelectorSet::Union(sets) => sets.iter().any(SelectorSet::is_enabled),
+            SelectorSet::Intersection(sets) => {
+                !sets.is_empty() && sets.iter().all(SelectorSet::is_enabled)
+            }
+            SelectorSet::Difference(selected, _) => selected.is_enabled(),
+        }
     }
-}
\ No newline at end of file
+}
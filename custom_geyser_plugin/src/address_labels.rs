@@ -0,0 +1,128 @@
+/// Human readable labels of addresses, stamped onto documents before they are written.
+///
+/// Labels map a pubkey to a name like "Raydium AMM v4". They are listed in the config,
+/// read from a collection of the plugin database when the plugin loads, or both, the
+/// config winning on conflicts. Every labeled address found in the address fields of a
+/// document is added to its labels field, e.g. the pubkey and owner of an account or
+/// the account keys of a transaction, so the database can be explored by name.
+use {
+    crate::{
+        geyser_plugin_mongodb::{
+            to_config_error, GeyserPluginMongoDBConfig, GeyserPluginMongoDbError,
+        },
+        mongodb_client::{plugin_database, SimpleMongoDbClient},
+    },
+    futures::TryStreamExt,
+    log::*,
+    mongodb::bson::{Bson, Document},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_sdk::pubkey::Pubkey,
+    std::{collections::HashMap, str::FromStr},
+};
+
+/// The top level fields holding addresses, either one or an array of them.
+const ADDRESS_FIELDS: [&str; 4] = ["pubkey", "owner", "account_keys", "program_ids"];
+
+pub const DEFAULT_LABELS_FIELD: &str = "labels";
+
+#[derive(Debug, Default)]
+pub struct AddressLabels {
+    labels: HashMap<String, String>,
+}
+
+impl AddressLabels {
+    /// Reads the labels of `collection`, documents `{ address, label }`, then adds the
+    /// labels of the config.
+    pub fn load(
+        config: &GeyserPluginMongoDBConfig,
+        labels: &HashMap<String, String>,
+        collection: Option<&str>,
+    ) -> Result<Self, GeyserPluginError> {
+        let mut address_labels = Self::default();
+        if let Some(collection) = collection {
+            for (address, label) in Self::read_collection(config, collection)? {
+                address_labels.insert(address, label)?;
+            }
+        }
+        for (address, label) in labels {
+            address_labels.insert(address.clone(), label.clone())?;
+        }
+        info!("Loaded {} address labels", address_labels.labels.len());
+        Ok(address_labels)
+    }
+
+    fn insert(&mut self, address: String, label: String) -> Result<(), GeyserPluginError> {
+        if let Err(err) = Pubkey::from_str(&address) {
+            return Err(to_config_error(format!(
+                "Invalid address {} labeled \"{}\": {}",
+                address, label, err
+            )));
+        }
+        self.labels.insert(address, label);
+        Ok(())
+    }
+
+    fn read_collection(
+        config: &GeyserPluginMongoDBConfig,
+        collection: &str,
+    ) -> Result<Vec<(String, String)>, GeyserPluginError> {
+        let to_error = |msg: String| {
+            GeyserPluginError::Custom(Box::new(
+                GeyserPluginMongoDbError::DataStoreConnectionError { msg },
+            ))
+        };
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| to_error(format!("Failed to create the tokio runtime: {}", err)))?;
+        let documents: Vec<Document> = runtime.block_on(async {
+            let client = SimpleMongoDbClient::connect_to_db(config).await?;
+            plugin_database(&client, config)
+                .collection::<Document>(collection)
+                .find(Document::new())
+                .await
+                .map_err(|err| to_error(format!("Failed to read the address labels: {}", err)))?
+                .try_collect()
+                .await
+                .map_err(|err| to_error(format!("Failed to read the address labels: {}", err)))
+        })?;
+        documents
+            .into_iter()
+            .map(|document| match (document.get_str("address"), document.get_str("label")) {
+                (Ok(address), Ok(label)) => Ok((address.to_string(), label.to_string())),
+                _ => Err(to_config_error(format!(
+                    "Address labels need a string address and label, got {}",
+                    document
+                ))),
+            })
+            .collect()
+    }
+
+    pub fn label(&self, address: &str) -> Option<&str> {
+        self.labels.get(address).map(String::as_str)
+    }
+
+    /// Sets `field` of `document` to the labels of its labeled addresses, keyed by
+    /// address. Documents without any are left as they are.
+    pub fn stamp(&self, document: &mut Document, field: &str) {
+        let mut stamped = Document::new();
+        for name in ADDRESS_FIELDS {
+            let addresses = match document.get(name) {
+                Some(Bson::String(address)) => vec![address.as_str()],
+                Some(Bson::Array(addresses)) => addresses
+                    .iter()
+                    .filter_map(|address| address.as_str())
+                    .collect(),
+                _ => continue,
+            };
+            for address in addresses {
+                if let Some(label) = self.label(address) {
+                    stamped.insert(address, label);
+                }
+            }
+        }
+        if !stamped.is_empty() {
+            document.insert(field, stamped);
+        }
+    }
+}
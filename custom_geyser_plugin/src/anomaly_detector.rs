@@ -0,0 +1,219 @@
+/// Flags sudden spikes or silences in the rate of account updates per owner program.
+///
+/// Updates of the watched owners are counted as they are notified. Once a minute the
+/// count is compared to the owner's baseline, either configured or learned as a moving
+/// average of past minutes. A spike is a minute with more than `spike_factor` times the
+/// baseline, a silence is `silence_minutes` minutes in a row without any update. Every
+/// minute is reported as a metric; the start of a spike or silence is also returned as
+/// an alert for the alerts collection.
+use {
+    crate::{geyser_plugin_mongodb::GeyserPluginMongoDbError, metrics},
+    log::Level,
+    mongodb::bson::{self, doc, Document},
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_metrics::datapoint::DataPoint,
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Mutex,
+        },
+    },
+};
+
+const DEFAULT_SPIKE_FACTOR: f64 = 5.0;
+const DEFAULT_SILENCE_MINUTES: u64 = 5;
+/// Minutes observed before a learned baseline is trusted.
+const BASELINE_WARMUP_MINUTES: u64 = 10;
+/// Weight of the latest minute in a learned baseline.
+const BASELINE_SMOOTHING: f64 = 0.1;
+/// Spikes below this many updates per minute are not reported, tiny baselines
+/// would otherwise flag every burst of a few updates.
+const MIN_SPIKE_UPDATES_PER_MINUTE: u64 = 10;
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct OwnerRateConfig {
+    /// The owner program whose update rate is watched.
+    pub owner: String,
+
+    /// The expected updates per minute. When unset the baseline is learned.
+    pub expected_updates_per_minute: Option<f64>,
+
+    /// A minute with more than this multiple of the baseline is a spike. The
+    /// default is 5.
+    pub spike_factor: Option<f64>,
+
+    /// This many minutes in a row without updates are a silence. The default is 5.
+    pub silence_minutes: Option<u64>,
+}
+
+impl Eq for OwnerRateConfig {}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum RateAnomalyKind {
+    Spike,
+    Silence,
+}
+
+impl RateAnomalyKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RateAnomalyKind::Spike => "spike",
+            RateAnomalyKind::Silence => "silence",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RateAnomaly {
+    pub owner: String,
+    pub kind: RateAnomalyKind,
+    pub updates_per_minute: u64,
+    pub baseline: f64,
+}
+
+impl RateAnomaly {
+    /// The document stored in the alerts collection.
+    pub fn to_document(&self) -> Document {
+        doc! {
+            "source": "rate_anomaly_detector",
+            "kind": self.kind.as_str(),
+            "owner": &self.owner,
+            "updates_per_minute": self.updates_per_minute as i64,
+            "baseline": self.baseline,
+            "detected_at": bson::DateTime::now(),
+        }
+    }
+}
+
+#[derive(Default)]
+struct OwnerRateState {
+    baseline: Option<f64>,
+    observed_minutes: u64,
+    silent_minutes: u64,
+    active_anomaly: Option<RateAnomalyKind>,
+}
+
+struct OwnerRate {
+    owner: String,
+    updates: AtomicU64,
+    expected_updates_per_minute: Option<f64>,
+    spike_factor: f64,
+    silence_minutes: u64,
+    state: Mutex<OwnerRateState>,
+}
+
+impl OwnerRate {
+    /// Folds the minute that just ended into the state, returning the anomaly it
+    /// starts, if any.
+    fn evaluate(&self) -> Option<RateAnomaly> {
+        let updates = self.updates.swap(0, Ordering::Relaxed);
+        let mut state = self.state.lock().unwrap();
+        state.observed_minutes += 1;
+        state.silent_minutes = if updates == 0 {
+            state.silent_minutes + 1
+        } else {
+            0
+        };
+
+        let baseline = self.expected_updates_per_minute.or_else(|| {
+            (state.observed_minutes > BASELINE_WARMUP_MINUTES)
+                .then_some(state.baseline)
+                .flatten()
+        });
+        let kind = baseline.and_then(|baseline| {
+            if updates >= MIN_SPIKE_UPDATES_PER_MINUTE
+                && updates as f64 > baseline * self.spike_factor
+            {
+                Some(RateAnomalyKind::Spike)
+            } else if baseline > 0.0 && state.silent_minutes >= self.silence_minutes {
+                Some(RateAnomalyKind::Silence)
+            } else {
+                None
+            }
+        });
+
+        // Anomalous minutes would drag the learned baseline towards the anomaly.
+        if kind.is_none() {
+            state.baseline = Some(match state.baseline {
+                Some(learned) => {
+                    learned + BASELINE_SMOOTHING * (updates as f64 - learned)
+                }
+                None => updates as f64,
+            });
+        }
+        metrics::submit(
+            DataPoint::new("geyser-plugin-mongodb-owner-update-rate")
+                .add_field_str("owner", &self.owner)
+                .add_field_i64("updates-per-minute", updates as i64)
+                .add_field_f64("baseline", baseline.unwrap_or_default()),
+            Level::Info,
+        );
+
+        let started = kind.filter(|kind| state.active_anomaly != Some(*kind));
+        state.active_anomaly = kind;
+        started.map(|kind| RateAnomaly {
+            owner: self.owner.clone(),
+            kind,
+            updates_per_minute: updates,
+            baseline: baseline.unwrap_or_default(),
+        })
+    }
+}
+
+pub struct RateAnomalyDetector {
+    owners: HashMap<Vec<u8>, OwnerRate>,
+}
+
+impl RateAnomalyDetector {
+    pub fn new(configs: &[OwnerRateConfig]) -> Result<Self, GeyserPluginError> {
+        let mut owners = HashMap::default();
+        for config in configs {
+            let key = bs58::decode(&config.owner).into_vec().map_err(|err| {
+                GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::ConfigurationError {
+                    msg: format!(
+                        "Invalid owner {} in \"rate_anomaly_detection\": {}",
+                        config.owner, err
+                    ),
+                }))
+            })?;
+            owners.insert(
+                key,
+                OwnerRate {
+                    owner: config.owner.clone(),
+                    updates: AtomicU64::default(),
+                    expected_updates_per_minute: config.expected_updates_per_minute,
+                    spike_factor: config.spike_factor.unwrap_or(DEFAULT_SPIKE_FACTOR),
+                    silence_minutes: config.silence_minutes.unwrap_or(DEFAULT_SILENCE_MINUTES),
+                    state: Mutex::default(),
+                },
+            );
+        }
+        Ok(Self { owners })
+    }
+
+    /// Counts one update of an account owned by `owner`.
+    pub fn record(&self, owner: &[u8]) {
+        if let Some(rate) = self.owners.get(owner) {
+            rate.updates.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Closes the current minute for every watched owner. Called once a minute.
+    pub fn evaluate(&self) -> Vec<RateAnomaly> {
+        let anomalies: Vec<RateAnomaly> =
+            self.owners.values().filter_map(OwnerRate::evaluate).collect();
+        for anomaly in &anomalies {
+            metrics::submit(
+                DataPoint::new("geyser-plugin-mongodb-rate-anomaly")
+                    .add_field_str("owner", &anomaly.owner)
+                    .add_field_str("kind", anomaly.kind.as_str())
+                    .add_field_i64("updates-per-minute", anomaly.updates_per_minute as i64)
+                    .add_field_f64("baseline", anomaly.baseline),
+                Level::Warn,
+            );
+        }
+        anomalies
+    }
+}
@@ -0,0 +1,114 @@
+/// How long account and transaction notifications may block the validator when the
+/// workers fall behind.
+///
+/// Notifications are queued for the workers in a bounded queue. By default a
+/// notification finding it full waits for room, holding up the validator thread
+/// that notified it: no data is lost, but a slow database slows the validator down.
+/// Per notification kind, `max_queue_len` refuses notifications as soon as the queue
+/// holds that many items, e.g. to keep room for transactions while accounts are
+/// refused, and `max_block_ms` bounds the wait for room. A refused notification is
+/// returned to the validator as an error and counted, so the data lost for the
+/// latency kept is visible. Startup accounts, and the accounts and transactions
+/// held back until a slot status or block, always wait.
+use {
+    crate::metrics::{self, Counter, Histogram},
+    crossbeam_channel::{SendTimeoutError, Sender, TrySendError},
+    serde_derive::{Deserialize, Serialize},
+    std::{
+        sync::Arc,
+        time::{Duration, Instant},
+    },
+};
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BackpressureThresholds {
+    /// Notifications are refused while the queue holds this many items or more. The
+    /// default is the queue capacity.
+    pub max_queue_len: Option<usize>,
+    /// Milliseconds a notification waits for room in a full queue before it is
+    /// refused, 0 never waits. Unset waits as long as it takes, the default.
+    pub max_block_ms: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct BackpressureConfig {
+    /// The items the queue holds at most. The default is 40960.
+    pub queue_capacity: Option<usize>,
+    /// The thresholds of steady-state account updates.
+    pub accounts: Option<BackpressureThresholds>,
+    /// The thresholds of transactions.
+    pub transactions: Option<BackpressureThresholds>,
+}
+
+/// Queues the notifications of one kind according to its thresholds.
+pub struct QueueGate {
+    max_queue_len: usize,
+    max_block: Option<Duration>,
+    refused: Arc<Counter>,
+    blocked_us: Arc<Histogram>,
+}
+
+impl QueueGate {
+    fn new(
+        thresholds: Option<&BackpressureThresholds>,
+        refused: &'static str,
+        blocked_us: &'static str,
+    ) -> Self {
+        let thresholds = thresholds.cloned().unwrap_or_default();
+        Self {
+            max_queue_len: thresholds.max_queue_len.unwrap_or(usize::MAX),
+            max_block: thresholds.max_block_ms.map(Duration::from_millis),
+            refused: metrics::registry().counter(refused),
+            blocked_us: metrics::registry().histogram(blocked_us),
+        }
+    }
+
+    pub fn accounts(config: &BackpressureConfig) -> Self {
+        Self::new(
+            config.accounts.as_ref(),
+            "geyser-plugin-mongodb-backpressure-accounts-refused",
+            "geyser-plugin-mongodb-backpressure-accounts-blocked-us",
+        )
+    }
+
+    pub fn transactions(config: &BackpressureConfig) -> Self {
+        Self::new(
+            config.transactions.as_ref(),
+            "geyser-plugin-mongodb-backpressure-transactions-refused",
+            "geyser-plugin-mongodb-backpressure-transactions-blocked-us",
+        )
+    }
+
+    /// Queues `item`, waiting for room within the thresholds. The error says why the
+    /// item was refused.
+    pub fn send<T>(&self, sender: &Sender<T>, item: T) -> Result<(), String> {
+        let queue_len = sender.len();
+        if queue_len >= self.max_queue_len {
+            self.refused.add(1);
+            return Err(format!(
+                "The queue holds {} items, the threshold is {}",
+                queue_len, self.max_queue_len
+            ));
+        }
+        let item = match sender.try_send(item) {
+            Ok(()) => return Ok(()),
+            Err(TrySendError::Full(item)) => item,
+            Err(TrySendError::Disconnected(_)) => return Err("The queue is closed".to_string()),
+        };
+        let blocked_at = Instant::now();
+        let result = match self.max_block {
+            None => sender
+                .send(item)
+                .map_err(|_| "The queue is closed".to_string()),
+            Some(max_block) => sender.send_timeout(item, max_block).map_err(|err| match err {
+                SendTimeoutError::Timeout(_) => {
+                    self.refused.add(1);
+                    format!("The queue stayed full for {:?}", max_block)
+                }
+                SendTimeoutError::Disconnected(_) => "The queue is closed".to_string(),
+            }),
+        };
+        self.blocked_us.record(blocked_at.elapsed().as_micros() as u64);
+        result
+    }
+}
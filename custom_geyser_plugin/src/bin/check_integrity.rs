@@ -0,0 +1,112 @@
+/// Verifies the invariants between the collections over a slot range.
+///
+/// Usage: check_integrity <plugin-config.json> <start-slot> <end-slot> [--sample <count>]
+///        [--examples <count>]
+///
+/// Every document of the range is checked unless `--sample` draws a random sample
+/// per collection. The report is printed and submitted to solana_metrics, see
+/// `integrity`. Exits with 2 when a violation is found.
+use {
+    custom_geyser_plugin::{
+        geyser_plugin_mongodb::GeyserPluginMongoDBConfig,
+        integrity::{check_integrity, IntegrityOptions},
+        mongodb_client::{plugin_database, SimpleMongoDbClient},
+    },
+    std::{env, fs, process::exit, str::FromStr},
+};
+
+const DEFAULT_MAX_EXAMPLES: usize = 10;
+
+fn parse<T: FromStr>(what: &str, value: &str) -> T
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse().unwrap_or_else(|err| {
+        eprintln!("Invalid {} {}: {}", what, value, err);
+        exit(1);
+    })
+}
+
+#[tokio::main]
+async fn main() {
+    solana_logger::setup_with_default("info");
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 4 {
+        eprintln!(
+            "Usage: {} <plugin-config.json> <start-slot> <end-slot> [--sample <count>] \
+             [--examples <count>]",
+            args[0]
+        );
+        exit(1);
+    }
+    let start_slot: u64 = parse("start slot", &args[2]);
+    let end_slot: u64 = parse("end slot", &args[3]);
+    let mut sample_size = None;
+    let mut max_examples = DEFAULT_MAX_EXAMPLES;
+    let mut options = args[4..].iter();
+    while let Some(option) = options.next() {
+        match (option.as_str(), options.next()) {
+            ("--sample", Some(count)) => sample_size = Some(parse("sample size", count)),
+            ("--examples", Some(count)) => max_examples = parse("example count", count),
+            _ => {
+                eprintln!("Unexpected argument {}", option);
+                exit(1);
+            }
+        }
+    }
+
+    let config = fs::read_to_string(&args[1])
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            serde_json::from_str::<GeyserPluginMongoDBConfig>(&contents)
+                .map_err(|err| err.to_string())
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to read the plugin config {}: {}", args[1], err);
+            exit(1);
+        });
+    let client = SimpleMongoDbClient::connect_to_db(&config)
+        .await
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to connect to MongoDB: {}", err);
+            exit(1);
+        });
+
+    let options = IntegrityOptions {
+        start_slot,
+        end_slot,
+        sample_size,
+        max_examples,
+        schema_profile: config.schema_profile.unwrap_or_default(),
+        bucketed_history: config.account_history_bucket_size.is_some(),
+    };
+    let report = check_integrity(&plugin_database(&client, &config), &options)
+        .await
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to check the integrity: {}", err);
+            exit(1);
+        });
+    println!(
+        "{:<24} {:<24} {:>14} {:>14}",
+        "check", "collection", "checked", "violations"
+    );
+    for check in &report.checks {
+        println!(
+            "{:<24} {:<24} {:>14} {:>14}",
+            check.check.as_str(),
+            check.collection,
+            check.checked,
+            check.violations
+        );
+    }
+    for check in &report.checks {
+        for example in &check.examples {
+            println!("{} {}: {}", check.check.as_str(), check.collection, example);
+        }
+    }
+    report.submit();
+    solana_metrics::flush();
+    if report.violations() > 0 {
+        exit(2);
+    }
+}
@@ -0,0 +1,189 @@
+/// Launches a local pipeline for evaluating the plugin in one command: a MongoDB
+/// container, a plugin config pointing at it and, optionally, a
+/// `solana-test-validator` loading the plugin.
+///
+/// Usage: devnet-stack [--config <path>] [--libpath <path>] [--mongo-tag <tag>]
+///            [--validator] [--ledger <dir>]
+///
+/// The config selects all accounts and transactions and is written to `--config`,
+/// `devnet-stack.json` by default. `--libpath` defaults to the plugin library next
+/// to this binary, i.e. the one built with it. Without `--validator` the stack runs
+/// until interrupted, for a validator started by hand with
+/// `--geyser-plugin-config <path>`. The container is removed on exit.
+use {
+    custom_geyser_plugin::{
+        geyser_plugin_mongodb::GeyserPluginMongoDBConfig, mongodb_client::SimpleMongoDbClient,
+    },
+    serde_json::json,
+    std::{env, fs, path::PathBuf, process::exit},
+    testcontainers::{
+        core::{IntoContainerPort, WaitFor},
+        runners::AsyncRunner,
+        GenericImage,
+    },
+    tokio::{process::Command, signal},
+};
+
+const DEFAULT_CONFIG_PATH: &str = "devnet-stack.json";
+const DEFAULT_LEDGER_DIR: &str = "devnet-stack-ledger";
+const DEFAULT_MONGO_TAG: &str = "7.0";
+const MONGO_PORT: u16 = 27017;
+const PLUGIN_LIBRARY: &str = "libcustom_geyser_plugin.so";
+
+struct Options {
+    config_path: PathBuf,
+    libpath: Option<PathBuf>,
+    mongo_tag: String,
+    validator: bool,
+    ledger_dir: PathBuf,
+}
+
+fn parse_options(args: &[String]) -> Result<Options, String> {
+    let mut options = Options {
+        config_path: PathBuf::from(DEFAULT_CONFIG_PATH),
+        libpath: None,
+        mongo_tag: DEFAULT_MONGO_TAG.to_string(),
+        validator: false,
+        ledger_dir: PathBuf::from(DEFAULT_LEDGER_DIR),
+    };
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--validator" {
+            options.validator = true;
+            continue;
+        }
+        let value = args
+            .next()
+            .ok_or_else(|| format!("Missing the value of {}", arg))?;
+        match arg.as_str() {
+            "--config" => options.config_path = PathBuf::from(value),
+            "--libpath" => options.libpath = Some(PathBuf::from(value)),
+            "--mongo-tag" => options.mongo_tag = value.clone(),
+            "--ledger" => options.ledger_dir = PathBuf::from(value),
+            _ => return Err(format!("Unexpected argument {}", arg)),
+        }
+    }
+    Ok(options)
+}
+
+/// The plugin library built alongside this binary.
+fn default_libpath() -> Option<PathBuf> {
+    let libpath = env::current_exe().ok()?.parent()?.join(PLUGIN_LIBRARY);
+    libpath.exists().then_some(libpath)
+}
+
+#[tokio::main]
+async fn main() {
+    solana_logger::setup_with_default("info");
+    let args: Vec<String> = env::args().collect();
+    let options = parse_options(&args[1..]).unwrap_or_else(|err| {
+        eprintln!("{}", err);
+        eprintln!(
+            "Usage: {} [--config <path>] [--libpath <path>] [--mongo-tag <tag>] \
+             [--validator] [--ledger <dir>]",
+            args[0]
+        );
+        exit(1);
+    });
+    let libpath = options.libpath.clone().or_else(default_libpath).unwrap_or_else(|| {
+        eprintln!(
+            "{} not found next to this binary, build the plugin or pass --libpath",
+            PLUGIN_LIBRARY
+        );
+        exit(1);
+    });
+
+    log::info!("Starting MongoDB {} in a container", options.mongo_tag);
+    let container = GenericImage::new("mongo", &options.mongo_tag)
+        .with_exposed_port(MONGO_PORT.tcp())
+        .with_wait_for(WaitFor::message_on_stdout("Waiting for connections"))
+        .start()
+        .await
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to start the MongoDB container, is Docker running? {}", err);
+            exit(1);
+        });
+    let host = container.get_host().await.map(|host| host.to_string());
+    let port = container.get_host_port_ipv4(MONGO_PORT).await;
+    let (host, port) = match (host, port) {
+        (Ok(host), Ok(port)) => (host, port),
+        (Err(err), _) | (_, Err(err)) => {
+            eprintln!("Failed to read the address of the MongoDB container: {}", err);
+            return;
+        }
+    };
+
+    let config = json!({
+        "libpath": libpath,
+        "host": host,
+        "port": port,
+        "threads": 4,
+        "batch_size": 10,
+        "accounts_selector": { "accounts": ["*"] },
+        "transaction_selector": { "mentions": ["*"] },
+    });
+    // Connecting checks the generated config the way the plugin will read it.
+    let connected = match serde_json::from_value::<GeyserPluginMongoDBConfig>(config.clone()) {
+        Ok(plugin_config) => SimpleMongoDbClient::connect_to_db(&plugin_config)
+            .await
+            .map(|_| ())
+            .map_err(|err| err.to_string()),
+        Err(err) => Err(err.to_string()),
+    };
+    if let Err(err) = connected {
+        eprintln!("Failed to connect to MongoDB at {}:{}: {}", host, port, err);
+        return;
+    }
+    let written = serde_json::to_string_pretty(&config)
+        .map_err(|err| err.to_string())
+        .and_then(|config| {
+            fs::write(&options.config_path, config).map_err(|err| err.to_string())
+        });
+    if let Err(err) = written {
+        eprintln!("Failed to write {}: {}", options.config_path.display(), err);
+        return;
+    }
+    log::info!(
+        "MongoDB is listening on {}:{}, plugin config written to {}",
+        host,
+        port,
+        options.config_path.display()
+    );
+
+    if !options.validator {
+        log::info!(
+            "Start a validator with --geyser-plugin-config {}, press Ctrl-C to stop",
+            options.config_path.display()
+        );
+        let _ = signal::ctrl_c().await;
+        return;
+    }
+
+    let mut validator = match Command::new("solana-test-validator")
+        .arg("--reset")
+        .arg("--ledger")
+        .arg(&options.ledger_dir)
+        .arg("--geyser-plugin-config")
+        .arg(&options.config_path)
+        .kill_on_drop(true)
+        .spawn()
+    {
+        Ok(validator) => validator,
+        Err(err) => {
+            eprintln!("Failed to start solana-test-validator, is it on PATH? {}", err);
+            return;
+        }
+    };
+    tokio::select! {
+        status = validator.wait() => match status {
+            Ok(status) => log::info!("solana-test-validator exited with {}", status),
+            Err(err) => eprintln!("Failed to wait for solana-test-validator: {}", err),
+        },
+        _ = signal::ctrl_c() => {
+            log::info!("Stopping solana-test-validator");
+            let _ = validator.kill().await;
+        }
+    }
+    // The container is removed when dropped, after the validator stopped writing.
+    drop(container);
+}
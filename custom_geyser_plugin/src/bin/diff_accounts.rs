@@ -0,0 +1,94 @@
+/// Reports which accounts appeared, disappeared or changed between two slots.
+///
+/// Usage: diff_accounts <plugin-config.json> <from-slot> <to-slot>
+///            [--account <pubkey>]... [--owner <pubkey>]...
+///
+/// The diff is printed as JSON. It is computed from the account history collection,
+/// so `store_account_historical_data` must have been enabled for the slots compared.
+/// Accounts of owners decoded by the plugin (see `decode_accounts`) get field level
+/// diffs of their decoded data.
+use {
+    custom_geyser_plugin::{
+        account_decoder::AccountDecoderRegistry,
+        account_diff::diff_accounts_between_slots,
+        geyser_plugin_mongodb::GeyserPluginMongoDBConfig,
+        mongodb_client::{plugin_database, SimpleMongoDbClient, ACCOUNT_HISTORY_COLLECTION},
+    },
+    mongodb::bson::Document,
+    std::{env, fs, process::exit},
+};
+
+#[tokio::main]
+async fn main() {
+    solana_logger::setup_with_default("info");
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 4 {
+        eprintln!(
+            "Usage: {} <plugin-config.json> <from-slot> <to-slot> [--account <pubkey>]... [--owner <pubkey>]...",
+            args[0]
+        );
+        exit(1);
+    }
+    let parse_slot = |value: &str| -> u64 {
+        value.parse().unwrap_or_else(|err| {
+            eprintln!("Invalid slot {}: {}", value, err);
+            exit(1);
+        })
+    };
+    let (from_slot, to_slot) = (parse_slot(&args[2]), parse_slot(&args[3]));
+    let mut pubkeys = Vec::default();
+    let mut owners = Vec::default();
+    let mut options = args[4..].iter();
+    while let Some(option) = options.next() {
+        match (option.as_str(), options.next()) {
+            ("--account", Some(pubkey)) => pubkeys.push(pubkey.clone()),
+            ("--owner", Some(owner)) => owners.push(owner.clone()),
+            _ => {
+                eprintln!("Unexpected argument {}", option);
+                exit(1);
+            }
+        }
+    }
+
+    let config = fs::read_to_string(&args[1])
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            serde_json::from_str::<GeyserPluginMongoDBConfig>(&contents)
+                .map_err(|err| err.to_string())
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to read the plugin config {}: {}", args[1], err);
+            exit(1);
+        });
+    let decoders = AccountDecoderRegistry::from_config(&config).unwrap_or_else(|err| {
+        eprintln!("Invalid account decoder config: {}", err);
+        exit(1);
+    });
+    let client = SimpleMongoDbClient::connect_to_db(&config)
+        .await
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to connect to MongoDB: {}", err);
+            exit(1);
+        });
+    let history = plugin_database(&client, &config).collection::<Document>(ACCOUNT_HISTORY_COLLECTION);
+
+    match diff_accounts_between_slots(
+        &history,
+        from_slot,
+        to_slot,
+        &pubkeys,
+        &owners,
+        (!decoders.is_empty()).then_some(&decoders),
+    )
+    .await
+    {
+        Ok(diff) => println!(
+            "{}",
+            serde_json::to_string_pretty(&diff).unwrap_or_default()
+        ),
+        Err(err) => {
+            eprintln!("Failed to diff the accounts: {}", err);
+            exit(1);
+        }
+    }
+}
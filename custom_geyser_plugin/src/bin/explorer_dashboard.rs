@@ -0,0 +1,201 @@
+/// A read-only web dashboard over the data stored by the plugin.
+///
+/// Usage: explorer_dashboard <plugin-config.json> [bind-address]
+///
+/// The MongoDB connection settings are read from the plugin config file, the
+/// dashboard listens on 127.0.0.1:8080 unless another address is given.
+use {
+    custom_geyser_plugin::{
+        explorer::{self, ExplorerSummary},
+        geyser_plugin_mongodb::GeyserPluginMongoDBConfig,
+        mongodb_client::{plugin_database, SimpleMongoDbClient},
+        schema::SchemaProfile,
+    },
+    mongodb::{
+        bson::{Bson, Document},
+        Database,
+    },
+    std::{env, fs, process::exit},
+    tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    },
+};
+
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:8080";
+const ROWS_PER_TABLE: i64 = 20;
+const REFRESH_INTERVAL_SECS: u64 = 10;
+
+#[tokio::main]
+async fn main() {
+    solana_logger::setup_with_default("info");
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <plugin-config.json> [bind-address]", args[0]);
+        exit(1);
+    }
+    let bind_address = args
+        .get(2)
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_BIND_ADDRESS);
+
+    let config = fs::read_to_string(&args[1])
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            serde_json::from_str::<GeyserPluginMongoDBConfig>(&contents)
+                .map_err(|err| err.to_string())
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to read the plugin config {}: {}", args[1], err);
+            exit(1);
+        });
+    let client = SimpleMongoDbClient::connect_to_db(&config)
+        .await
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to connect to MongoDB: {}", err);
+            exit(1);
+        });
+    let database = plugin_database(&client, &config);
+    let profile = config.schema_profile.unwrap_or_default();
+
+    let listener = TcpListener::bind(bind_address).await.unwrap_or_else(|err| {
+        eprintln!("Failed to bind {}: {}", bind_address, err);
+        exit(1);
+    });
+    log::info!("Explorer dashboard listening on http://{}", bind_address);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let database = database.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle_connection(stream, &database, profile).await {
+                        log::warn!("Dashboard request failed: {}", err);
+                    }
+                });
+            }
+            Err(err) => log::warn!("Failed to accept a dashboard connection: {}", err),
+        }
+    }
+}
+
+async fn handle_connection(
+    mut stream: TcpStream,
+    database: &Database,
+    profile: SchemaProfile,
+) -> std::io::Result<()> {
+    let mut buffer = [0u8; 4096];
+    let read = stream.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let path = request
+        .lines()
+        .next()
+        .and_then(|request_line| request_line.split_whitespace().nth(1))
+        .unwrap_or("/");
+
+    let (status, content_type, body) = match path {
+        "/" => match explorer::summary(database, profile, ROWS_PER_TABLE).await {
+            Ok(summary) => ("200 OK", "text/html; charset=utf-8", render_summary(&summary)),
+            Err(err) => (
+                "500 Internal Server Error",
+                "text/plain; charset=utf-8",
+                format!("Failed to query MongoDB: {}", err),
+            ),
+        },
+        _ => ("404 Not Found", "text/plain; charset=utf-8", "Not found".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+fn escape_html(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn lookup<'a>(document: &'a Document, field: &str) -> Option<&'a Bson> {
+    match field.split_once('.') {
+        Some((head, rest)) => lookup(document.get_document(head).ok()?, rest),
+        None => document.get(field),
+    }
+}
+
+/// Renders `documents` as a table with one column per entry of `fields`, nested
+/// fields are addressed with dots as in MongoDB queries.
+fn render_table(title: &str, fields: &[&str], documents: &[Document]) -> String {
+    let mut html = format!("<h2>{}</h2><table><tr>", escape_html(title));
+    for field in fields {
+        html.push_str(&format!("<th>{}</th>", escape_html(field)));
+    }
+    html.push_str("</tr>");
+    for document in documents {
+        html.push_str("<tr>");
+        for field in fields {
+            let value = lookup(document, field)
+                .map(|value| value.to_string())
+                .unwrap_or_default();
+            html.push_str(&format!("<td>{}</td>", escape_html(&value)));
+        }
+        html.push_str("</tr>");
+    }
+    html.push_str("</table>");
+    html
+}
+
+fn render_summary(summary: &ExplorerSummary) -> String {
+    let mut html = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+         <meta http-equiv=\"refresh\" content=\"{}\"><title>Geyser MongoDB explorer</title>\
+         <style>body{{font-family:monospace}}table{{border-collapse:collapse;margin-bottom:1em}}\
+         td,th{{border:1px solid #ccc;padding:2px 6px;text-align:left}}</style></head><body>\
+         <h1>Geyser MongoDB explorer</h1>",
+        REFRESH_INTERVAL_SECS
+    );
+    html.push_str(&format!(
+        "<p>Latest account slot: {}, last write {} seconds ago</p>",
+        summary
+            .latest_account_slot
+            .map(|slot| slot.to_string())
+            .unwrap_or_else(|| "-".to_string()),
+        summary
+            .seconds_since_last_write
+            .map(|lag| lag.to_string())
+            .unwrap_or_else(|| "-".to_string()),
+    ));
+    html.push_str(&render_table(
+        "Recent slots",
+        &["slot", "parent", "status", "updated_on"],
+        &summary.recent_slots,
+    ));
+    html.push_str(&render_table(
+        "Biggest accounts",
+        &["_id", "owner", "data_len", "slot"],
+        &summary.biggest_accounts,
+    ));
+    html.push_str(&render_table(
+        &format!(
+            "Top programs by writes (last {} slots)",
+            explorer::DEFAULT_TOP_PROGRAMS_SLOT_WINDOW
+        ),
+        &["_id", "writes"],
+        &summary.top_programs,
+    ));
+    html.push_str(&render_table(
+        "Recent failed transactions",
+        &["signature", "slot", "meta.error.error_code", "meta.error.error_detail"],
+        &summary.recent_failed_transactions,
+    ));
+    html.push_str("</body></html>");
+    html
+}
@@ -0,0 +1,124 @@
+/// Exports the current state of the stored accounts as a point-in-time BSON dump or
+/// Parquet file.
+///
+/// Usage: export_accounts <plugin-config.json> <output> [--owner <pubkey>]...
+///        [--format bson|parquet]
+///
+/// The export holds the newest version of every account, or of the accounts owned by
+/// the given programs. A BSON dump, the default, can be restored with `mongorestore`
+/// or read with `bsondump`. Parquet needs the `parquet` feature.
+use {
+    custom_geyser_plugin::{
+        export::{export_current_accounts, ExportSummary},
+        geyser_plugin_mongodb::GeyserPluginMongoDBConfig,
+        mongodb_client::{plugin_database, SimpleMongoDbClient},
+        schema::SchemaProfile,
+    },
+    mongodb::{error::Result, Client, Database},
+    std::{env, fs, io::BufWriter, process::exit},
+};
+
+#[cfg(feature = "parquet")]
+async fn export_parquet(
+    client: &Client,
+    database: &Database,
+    profile: SchemaProfile,
+    owners: &[String],
+    output: BufWriter<fs::File>,
+) -> Result<ExportSummary> {
+    custom_geyser_plugin::parquet_export::export_current_accounts_parquet(
+        client, database, profile, owners, output,
+    )
+    .await
+}
+
+#[cfg(not(feature = "parquet"))]
+async fn export_parquet(
+    _client: &Client,
+    _database: &Database,
+    _profile: SchemaProfile,
+    _owners: &[String],
+    _output: BufWriter<fs::File>,
+) -> Result<ExportSummary> {
+    eprintln!("Parquet output requires the binary to be built with the \"parquet\" feature");
+    exit(1);
+}
+
+#[tokio::main]
+async fn main() {
+    solana_logger::setup_with_default("info");
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!(
+            "Usage: {} <plugin-config.json> <output> [--owner <pubkey>]... \
+             [--format bson|parquet]",
+            args[0]
+        );
+        exit(1);
+    }
+    let mut owners = Vec::default();
+    let mut parquet = false;
+    let mut options = args[3..].iter();
+    while let Some(option) = options.next() {
+        match (option.as_str(), options.next()) {
+            ("--owner", Some(owner)) => owners.push(owner.clone()),
+            ("--format", Some(format)) if format == "bson" => parquet = false,
+            ("--format", Some(format)) if format == "parquet" => parquet = true,
+            _ => {
+                eprintln!("Unexpected argument {}", option);
+                exit(1);
+            }
+        }
+    }
+    let config = fs::read_to_string(&args[1])
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            serde_json::from_str::<GeyserPluginMongoDBConfig>(&contents)
+                .map_err(|err| err.to_string())
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to read the plugin config {}: {}", args[1], err);
+            exit(1);
+        });
+    let client = SimpleMongoDbClient::connect_to_db(&config)
+        .await
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to connect to MongoDB: {}", err);
+            exit(1);
+        });
+    let output = fs::File::create(&args[2]).unwrap_or_else(|err| {
+        eprintln!("Failed to create {}: {}", args[2], err);
+        exit(1);
+    });
+
+    let database = plugin_database(&client, &config);
+    let profile = config.schema_profile.unwrap_or_default();
+    let output = BufWriter::new(output);
+    let result = if parquet {
+        export_parquet(&client, &database, profile, &owners, output).await
+    } else {
+        export_current_accounts(&client, &database, profile, &owners, output).await
+    };
+    match result {
+        Ok(summary) => match summary.cluster_time {
+            Some(cluster_time) => log::info!(
+                "Exported {} accounts ({} bytes) at cluster time {:?} to {}",
+                summary.documents,
+                summary.bytes,
+                cluster_time,
+                args[2]
+            ),
+            None => log::warn!(
+                "Exported {} accounts ({} bytes) to {} without a snapshot, the server \
+                 does not support snapshot reads",
+                summary.documents,
+                summary.bytes,
+                args[2]
+            ),
+        },
+        Err(err) => {
+            eprintln!("Failed to export the accounts: {}", err);
+            exit(1);
+        }
+    }
+}
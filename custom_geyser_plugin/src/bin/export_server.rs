@@ -0,0 +1,180 @@
+/// An HTTP endpoint for bulk consumers reading the transactions or the account
+/// history in parallel chunks.
+///
+/// Usage: export_server <plugin-config.json> [bind-address]
+///
+/// `GET /chunks?collection=<name>&start_slot=<slot>&end_slot=<slot>&chunks=<count>`
+/// splits a slot range into chunks and returns a cursor per chunk.
+/// `GET /page?cursor=<cursor>&limit=<count>` returns the next documents of a chunk,
+/// as relaxed extended JSON, with the cursor to pass for the following page.
+use {
+    custom_geyser_plugin::{
+        export::{read_chunk_page, split_slot_range, ChunkCursor},
+        geyser_plugin_mongodb::GeyserPluginMongoDBConfig,
+        mongodb_client::{plugin_database, SimpleMongoDbClient},
+    },
+    mongodb::{bson::Bson, Database},
+    serde_json::{json, Value},
+    std::{collections::HashMap, env, fs, process::exit},
+    tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+    },
+};
+
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:8082";
+const DEFAULT_PAGE_LIMIT: i64 = 1000;
+const MAX_PAGE_LIMIT: i64 = 10_000;
+const MAX_CHUNKS: u32 = 1024;
+
+#[tokio::main]
+async fn main() {
+    solana_logger::setup_with_default("info");
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <plugin-config.json> [bind-address]", args[0]);
+        exit(1);
+    }
+    let bind_address = args
+        .get(2)
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_BIND_ADDRESS);
+
+    let config = fs::read_to_string(&args[1])
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            serde_json::from_str::<GeyserPluginMongoDBConfig>(&contents)
+                .map_err(|err| err.to_string())
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to read the plugin config {}: {}", args[1], err);
+            exit(1);
+        });
+    let client = SimpleMongoDbClient::connect_to_db(&config)
+        .await
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to connect to MongoDB: {}", err);
+            exit(1);
+        });
+    let database = plugin_database(&client, &config);
+
+    let listener = TcpListener::bind(bind_address).await.unwrap_or_else(|err| {
+        eprintln!("Failed to bind {}: {}", bind_address, err);
+        exit(1);
+    });
+    log::info!("Export server listening on http://{}", bind_address);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let database = database.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle_connection(stream, &database).await {
+                        log::warn!("Export request failed: {}", err);
+                    }
+                });
+            }
+            Err(err) => log::warn!("Failed to accept an export connection: {}", err),
+        }
+    }
+}
+
+/// The parameters of a query string. Cursors are URL safe and the other values are
+/// numbers or collection names, so no percent decoding is needed.
+fn query_parameters(query: &str) -> HashMap<&str, &str> {
+    query
+        .split('&')
+        .filter_map(|parameter| parameter.split_once('='))
+        .collect()
+}
+
+fn parse<T: std::str::FromStr>(
+    parameters: &HashMap<&str, &str>,
+    name: &str,
+) -> Result<Option<T>, String> {
+    parameters
+        .get(name)
+        .map(|value| value.parse().map_err(|_| format!("Invalid {}: {}", name, value)))
+        .transpose()
+}
+
+fn chunks(parameters: &HashMap<&str, &str>) -> Result<Value, String> {
+    let collection = parameters
+        .get("collection")
+        .ok_or_else(|| "Missing collection".to_string())?;
+    let (Some(start_slot), Some(end_slot)) = (
+        parse::<u64>(parameters, "start_slot")?,
+        parse::<u64>(parameters, "end_slot")?,
+    ) else {
+        return Err("Missing start_slot or end_slot".to_string());
+    };
+    let chunks = parse::<u32>(parameters, "chunks")?.unwrap_or(1).min(MAX_CHUNKS);
+    let chunks: Vec<Value> = split_slot_range(collection, start_slot, end_slot, chunks)
+        .into_iter()
+        .map(|cursor| {
+            json!({
+                "chunk": cursor.chunk,
+                "start_slot": cursor.start_slot,
+                "end_slot": cursor.end_slot,
+                "cursor": cursor.to_token(),
+            })
+        })
+        .collect();
+    Ok(json!({ "chunks": chunks }))
+}
+
+async fn page(database: &Database, parameters: &HashMap<&str, &str>) -> Result<Value, String> {
+    let mut cursor = ChunkCursor::from_token(
+        parameters
+            .get("cursor")
+            .ok_or_else(|| "Missing cursor".to_string())?,
+    )?;
+    let limit = parse::<i64>(parameters, "limit")?
+        .unwrap_or(DEFAULT_PAGE_LIMIT)
+        .clamp(1, MAX_PAGE_LIMIT);
+    let documents: Vec<Value> = read_chunk_page(database, &mut cursor, limit)
+        .await
+        .map_err(|err| format!("Failed to query MongoDB: {}", err))?
+        .into_iter()
+        .map(|document| Bson::Document(document).into_relaxed_extjson())
+        .collect();
+    Ok(json!({
+        "documents": documents,
+        "cursor": cursor.to_token(),
+        "done": cursor.done,
+    }))
+}
+
+async fn handle_connection(mut stream: TcpStream, database: &Database) -> std::io::Result<()> {
+    let mut buffer = [0u8; 4096];
+    let read = stream.read(&mut buffer).await?;
+    let request = String::from_utf8_lossy(&buffer[..read]);
+    let target = request
+        .lines()
+        .next()
+        .and_then(|request_line| request_line.split_whitespace().nth(1))
+        .unwrap_or("/");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let parameters = query_parameters(query);
+
+    let result = match path {
+        "/chunks" => Some(chunks(&parameters)),
+        "/page" => Some(page(database, &parameters).await),
+        _ => None,
+    };
+    let (status, body) = match result {
+        Some(Ok(body)) => ("200 OK", body.to_string()),
+        Some(Err(err)) => ("400 Bad Request", json!({ "error": err }).to_string()),
+        None => ("404 Not Found", json!({ "error": "Not found" }).to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,141 @@
+/// A GraphQL endpoint over the accounts and transactions stored by the plugin.
+///
+/// Usage: graphql_server <plugin-config.json> [bind-address]
+///
+/// Queries are POSTed as JSON to /graphql, GET / serves the GraphiQL explorer.
+use {
+    async_graphql::http::GraphiQLSource,
+    custom_geyser_plugin::{
+        geyser_plugin_mongodb::GeyserPluginMongoDBConfig,
+        graphql::{build_schema, ExplorerSchema},
+        mongodb_client::{plugin_database, SimpleMongoDbClient},
+    },
+    std::{env, fs, process::exit},
+    tokio::{
+        io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+        net::{TcpListener, TcpStream},
+    },
+};
+
+const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1:8081";
+const MAX_REQUEST_BODY_BYTES: usize = 1024 * 1024;
+
+#[tokio::main]
+async fn main() {
+    solana_logger::setup_with_default("info");
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <plugin-config.json> [bind-address]", args[0]);
+        exit(1);
+    }
+    let bind_address = args
+        .get(2)
+        .map(String::as_str)
+        .unwrap_or(DEFAULT_BIND_ADDRESS);
+
+    let config = fs::read_to_string(&args[1])
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            serde_json::from_str::<GeyserPluginMongoDBConfig>(&contents)
+                .map_err(|err| err.to_string())
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to read the plugin config {}: {}", args[1], err);
+            exit(1);
+        });
+    let client = SimpleMongoDbClient::connect_to_db(&config)
+        .await
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to connect to MongoDB: {}", err);
+            exit(1);
+        });
+    let schema = build_schema(
+        plugin_database(&client, &config),
+        config.schema_profile.unwrap_or_default(),
+    );
+
+    let listener = TcpListener::bind(bind_address).await.unwrap_or_else(|err| {
+        eprintln!("Failed to bind {}: {}", bind_address, err);
+        exit(1);
+    });
+    log::info!("GraphQL endpoint listening on http://{}/graphql", bind_address);
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let schema = schema.clone();
+                tokio::spawn(async move {
+                    if let Err(err) = handle_connection(stream, &schema).await {
+                        log::warn!("GraphQL request failed: {}", err);
+                    }
+                });
+            }
+            Err(err) => log::warn!("Failed to accept a GraphQL connection: {}", err),
+        }
+    }
+}
+
+async fn handle_connection(stream: TcpStream, schema: &ExplorerSchema) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or_default();
+    let path = parts.next().unwrap_or("/");
+    let (status, content_type, body) = match (method, path) {
+        ("GET", "/") => (
+            "200 OK",
+            "text/html; charset=utf-8",
+            GraphiQLSource::build().endpoint("/graphql").finish(),
+        ),
+        ("POST", "/graphql") if content_length > MAX_REQUEST_BODY_BYTES => (
+            "413 Payload Too Large",
+            "text/plain; charset=utf-8",
+            "Request body too large".to_string(),
+        ),
+        ("POST", "/graphql") => {
+            let mut body = vec![0u8; content_length];
+            reader.read_exact(&mut body).await?;
+            match serde_json::from_slice::<async_graphql::Request>(&body) {
+                Ok(request) => {
+                    let response = schema.execute(request).await;
+                    (
+                        "200 OK",
+                        "application/json",
+                        serde_json::to_string(&response).unwrap_or_default(),
+                    )
+                }
+                Err(err) => (
+                    "400 Bad Request",
+                    "text/plain; charset=utf-8",
+                    format!("Invalid GraphQL request: {}", err),
+                ),
+            }
+        }
+        _ => ("404 Not Found", "text/plain; charset=utf-8", "Not found".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    let mut stream = reader.into_inner();
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
@@ -0,0 +1,114 @@
+/// Writes stored accounts as `solana-test-validator` account files, cloning real
+/// program state into a local validator.
+///
+/// Usage: seed_test_validator <plugin-config.json> <output-dir>
+///            [--account <pubkey>]... [--owner <pubkey>]... [--limit <count>]
+///
+/// Every account is written to `<output-dir>/<pubkey>.json`. Load them with
+/// `solana-test-validator --account-dir <output-dir>` or one by one with
+/// `--account <pubkey> <file>`.
+use {
+    custom_geyser_plugin::{
+        export::{build_test_validator_account, find_current_accounts},
+        geyser_plugin_mongodb::GeyserPluginMongoDBConfig,
+        mongodb_client::{plugin_database, SimpleMongoDbClient},
+    },
+    std::{env, fs, path::Path, process::exit},
+};
+
+const DEFAULT_LIMIT: i64 = 1000;
+
+#[tokio::main]
+async fn main() {
+    solana_logger::setup_with_default("info");
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!(
+            "Usage: {} <plugin-config.json> <output-dir> [--account <pubkey>]... [--owner <pubkey>]... [--limit <count>]",
+            args[0]
+        );
+        exit(1);
+    }
+    let mut pubkeys = Vec::default();
+    let mut owners = Vec::default();
+    let mut limit = DEFAULT_LIMIT;
+    let mut options = args[3..].iter();
+    while let Some(option) = options.next() {
+        match (option.as_str(), options.next()) {
+            ("--account", Some(pubkey)) => pubkeys.push(pubkey.clone()),
+            ("--owner", Some(owner)) => owners.push(owner.clone()),
+            ("--limit", Some(count)) => {
+                limit = count.parse().unwrap_or_else(|err| {
+                    eprintln!("Invalid limit {}: {}", count, err);
+                    exit(1);
+                })
+            }
+            _ => {
+                eprintln!("Unexpected argument {}", option);
+                exit(1);
+            }
+        }
+    }
+    if pubkeys.is_empty() && owners.is_empty() {
+        eprintln!("At least one --account or --owner is required");
+        exit(1);
+    }
+
+    let config = fs::read_to_string(&args[1])
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            serde_json::from_str::<GeyserPluginMongoDBConfig>(&contents)
+                .map_err(|err| err.to_string())
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to read the plugin config {}: {}", args[1], err);
+            exit(1);
+        });
+    let client = SimpleMongoDbClient::connect_to_db(&config)
+        .await
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to connect to MongoDB: {}", err);
+            exit(1);
+        });
+    let accounts = find_current_accounts(
+        &plugin_database(&client, &config),
+        config.schema_profile.unwrap_or_default(),
+        &pubkeys,
+        &owners,
+        limit,
+    )
+    .await
+    .unwrap_or_else(|err| {
+        eprintln!("Failed to query the accounts: {}", err);
+        exit(1);
+    });
+
+    let output_dir = Path::new(&args[2]);
+    if let Err(err) = fs::create_dir_all(output_dir) {
+        eprintln!("Failed to create {}: {}", output_dir.display(), err);
+        exit(1);
+    }
+    let mut written = 0;
+    for account in &accounts {
+        let pubkey = account.get_str("pubkey").unwrap_or_default();
+        let fixture = match build_test_validator_account(account) {
+            Some(fixture) => fixture,
+            None => {
+                log::warn!("Skipping account {} stored without its data", pubkey);
+                continue;
+            }
+        };
+        let path = output_dir.join(format!("{}.json", pubkey));
+        if let Err(err) = fs::write(&path, fixture.to_string()) {
+            eprintln!("Failed to write {}: {}", path.display(), err);
+            exit(1);
+        }
+        written += 1;
+    }
+    log::info!(
+        "Wrote {} of {} accounts to {}",
+        written,
+        accounts.len(),
+        output_dir.display()
+    );
+}
@@ -0,0 +1,94 @@
+/// Reports the storage taken by every collection and by the largest account owners.
+///
+/// Usage: storage_usage <plugin-config.json> [--owners <count>]
+///
+/// The report is printed and submitted to solana_metrics, see `storage_usage`. Owner
+/// sizes are estimated from the whole accounts collection, so run it off-peak.
+use {
+    custom_geyser_plugin::{
+        geyser_plugin_mongodb::GeyserPluginMongoDBConfig,
+        mongodb_client::{plugin_database, SimpleMongoDbClient},
+        storage_usage::storage_usage,
+    },
+    std::{env, fs, process::exit},
+};
+
+const DEFAULT_OWNER_COUNT: i64 = 20;
+
+#[tokio::main]
+async fn main() {
+    solana_logger::setup_with_default("info");
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage: {} <plugin-config.json> [--owners <count>]", args[0]);
+        exit(1);
+    }
+    let mut owner_count = DEFAULT_OWNER_COUNT;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        match (option.as_str(), options.next()) {
+            ("--owners", Some(count)) => {
+                owner_count = count.parse().unwrap_or_else(|err| {
+                    eprintln!("Invalid owner count {}: {}", count, err);
+                    exit(1);
+                })
+            }
+            _ => {
+                eprintln!("Unexpected argument {}", option);
+                exit(1);
+            }
+        }
+    }
+
+    let config = fs::read_to_string(&args[1])
+        .map_err(|err| err.to_string())
+        .and_then(|contents| {
+            serde_json::from_str::<GeyserPluginMongoDBConfig>(&contents)
+                .map_err(|err| err.to_string())
+        })
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to read the plugin config {}: {}", args[1], err);
+            exit(1);
+        });
+    let client = SimpleMongoDbClient::connect_to_db(&config)
+        .await
+        .unwrap_or_else(|err| {
+            eprintln!("Failed to connect to MongoDB: {}", err);
+            exit(1);
+        });
+
+    let report = storage_usage(
+        &plugin_database(&client, &config),
+        config.schema_profile.unwrap_or_default(),
+        owner_count,
+    )
+    .await
+    .unwrap_or_else(|err| {
+        eprintln!("Failed to read the storage usage: {}", err);
+        exit(1);
+    });
+    println!(
+        "{:<32} {:>14} {:>16} {:>16} {:>16}",
+        "collection", "documents", "data bytes", "storage bytes", "index bytes"
+    );
+    for collection in &report.collections {
+        println!(
+            "{:<32} {:>14} {:>16} {:>16} {:>16}",
+            collection.collection,
+            collection.documents,
+            collection.data_bytes,
+            collection.storage_bytes,
+            collection.index_bytes
+        );
+    }
+    println!();
+    println!("{:<44} {:>14} {:>16}", "owner", "accounts", "estimated bytes");
+    for owner in &report.owners {
+        println!(
+            "{:<44} {:>14} {:>16}",
+            owner.owner, owner.accounts, owner.estimated_bytes
+        );
+    }
+    report.submit();
+    solana_metrics::flush();
+}
@@ -0,0 +1,277 @@
+/// Publishes cache invalidation messages when slots are rooted or abandoned.
+///
+/// Caches built from `processed` data may hold account states of slots that end up
+/// on a dead fork. The pubkeys updated in every slot are tracked until the slot is
+/// decided: rooting a slot publishes a `rooted` message for it, and every tracked
+/// older slot that was not rooted by then is on an abandoned fork and gets an
+/// `abandoned` message. Messages are POSTed as JSON to a webhook from a dedicated
+/// thread, a slow or failing endpoint never blocks the validator.
+use {
+    crate::{geyser_plugin_mongodb::GeyserPluginMongoDbError, instance, metrics},
+    crossbeam_channel::{bounded, Receiver, RecvTimeoutError, Sender},
+    log::*,
+    openssl::ssl::{SslConnector, SslMethod},
+    serde_derive::{Deserialize, Serialize},
+    serde_json::json,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::{
+        collections::{BTreeMap, HashSet},
+        io::{Read, Write},
+        net::TcpStream,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        thread::{Builder, JoinHandle},
+        time::Duration,
+    },
+};
+
+/// Messages waiting for the webhook, later ones are dropped and counted.
+const MAX_PENDING_MESSAGES: usize = 1024;
+/// Slots tracked without being decided, the oldest are dropped beyond that.
+const MAX_TRACKED_SLOTS: usize = 4096;
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct CacheInvalidationConfig {
+    /// The http:// or https:// URL the messages are POSTed to.
+    pub webhook_url: String,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum InvalidationKind {
+    Rooted,
+    Abandoned,
+}
+
+impl InvalidationKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            InvalidationKind::Rooted => "rooted",
+            InvalidationKind::Abandoned => "abandoned",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct InvalidationMessage {
+    pub slot: u64,
+    pub kind: InvalidationKind,
+    pub pubkeys: Vec<String>,
+}
+
+impl InvalidationMessage {
+    pub fn to_json(&self) -> String {
+        json!({
+            "slot": self.slot,
+            "kind": self.kind.as_str(),
+            "pubkeys": self.pubkeys,
+        })
+        .to_string()
+    }
+}
+
+/// The pubkeys updated per undecided slot.
+#[derive(Default)]
+pub struct SlotPubkeyTracker {
+    slots: Mutex<BTreeMap<u64, HashSet<Vec<u8>>>>,
+}
+
+impl SlotPubkeyTracker {
+    pub fn record(&self, slot: u64, pubkey: &[u8]) {
+        let mut slots = self.slots.lock().unwrap();
+        slots.entry(slot).or_default().insert(pubkey.to_vec());
+        while slots.len() > MAX_TRACKED_SLOTS {
+            slots.pop_first();
+            metrics::inc_counter("geyser-plugin-mongodb-invalidation-slots-dropped", 1);
+        }
+    }
+
+    /// Decides every tracked slot up to `root`: `root` is rooted, older slots are
+    /// abandoned.
+    pub fn root(&self, root: u64) -> Vec<InvalidationMessage> {
+        let mut slots = self.slots.lock().unwrap();
+        let newer = slots.split_off(&(root + 1));
+        let decided = std::mem::replace(&mut *slots, newer);
+        decided
+            .into_iter()
+            .map(|(slot, pubkeys)| InvalidationMessage {
+                slot,
+                kind: if slot == root {
+                    InvalidationKind::Rooted
+                } else {
+                    InvalidationKind::Abandoned
+                },
+                pubkeys: pubkeys
+                    .iter()
+                    .map(|pubkey| bs58::encode(pubkey).into_string())
+                    .collect(),
+            })
+            .collect()
+    }
+}
+
+struct WebhookUrl {
+    tls: bool,
+    host: String,
+    port: u16,
+    path: String,
+}
+
+impl WebhookUrl {
+    fn parse(url: &str) -> Result<Self, String> {
+        let (tls, rest) = if let Some(rest) = url.strip_prefix("https://") {
+            (true, rest)
+        } else if let Some(rest) = url.strip_prefix("http://") {
+            (false, rest)
+        } else {
+            return Err(format!("{} is not an http:// or https:// URL", url));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(index) => (&rest[..index], &rest[index..]),
+            None => (rest, "/"),
+        };
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) => (
+                host,
+                port.parse()
+                    .map_err(|err| format!("Invalid port in {}: {}", url, err))?,
+            ),
+            None => (authority, if tls { 443 } else { 80 }),
+        };
+        if host.is_empty() {
+            return Err(format!("{} has no host", url));
+        }
+        Ok(Self {
+            tls,
+            host: host.to_string(),
+            port,
+            path: path.to_string(),
+        })
+    }
+}
+
+fn post_json(url: &WebhookUrl, body: &str) -> Result<(), String> {
+    let stream = TcpStream::connect((url.host.as_str(), url.port)).map_err(|err| err.to_string())?;
+    stream
+        .set_read_timeout(Some(WEBHOOK_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(WEBHOOK_TIMEOUT)))
+        .map_err(|err| err.to_string())?;
+    let request = format!(
+        "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        url.path,
+        url.host,
+        body.len(),
+        body
+    );
+    let mut response = String::new();
+    if url.tls {
+        let connector = SslConnector::builder(SslMethod::tls())
+            .map_err(|err| err.to_string())?
+            .build();
+        let mut stream = connector
+            .connect(&url.host, stream)
+            .map_err(|err| err.to_string())?;
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|err| err.to_string())?;
+        stream
+            .read_to_string(&mut response)
+            .map_err(|err| err.to_string())?;
+    } else {
+        let mut stream = stream;
+        stream
+            .write_all(request.as_bytes())
+            .map_err(|err| err.to_string())?;
+        stream
+            .read_to_string(&mut response)
+            .map_err(|err| err.to_string())?;
+    }
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(format!("Unexpected response: {}", status_line)),
+    }
+}
+
+/// Tracks updated pubkeys and posts the invalidation messages to the webhook.
+pub struct CacheInvalidator {
+    tracker: SlotPubkeyTracker,
+    sender: Sender<InvalidationMessage>,
+    exit: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl CacheInvalidator {
+    pub fn new(config: &CacheInvalidationConfig) -> Result<Self, GeyserPluginError> {
+        let url = WebhookUrl::parse(&config.webhook_url).map_err(|msg| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::ConfigurationError {
+                msg: format!("Invalid \"cache_invalidation\" webhook_url: {}", msg),
+            }))
+        })?;
+        let (sender, receiver) = bounded(MAX_PENDING_MESSAGES);
+        let exit = Arc::new(AtomicBool::new(false));
+        let thread = Self::spawn_webhook_thread(url, receiver, exit.clone());
+        info!("Publishing cache invalidations to {}", config.webhook_url);
+        Ok(Self {
+            tracker: SlotPubkeyTracker::default(),
+            sender,
+            exit,
+            thread: Some(thread),
+        })
+    }
+
+    fn spawn_webhook_thread(
+        url: WebhookUrl,
+        receiver: Receiver<InvalidationMessage>,
+        exit: Arc<AtomicBool>,
+    ) -> JoinHandle<()> {
+        Builder::new()
+            .name("mongodb-cache-invalidation".to_string())
+            .spawn(instance::bind(move || {
+                while !exit.load(Ordering::Relaxed) {
+                    let message = match receiver.recv_timeout(Duration::from_millis(500)) {
+                        Ok(message) => message,
+                        Err(RecvTimeoutError::Timeout) => continue,
+                        Err(RecvTimeoutError::Disconnected) => break,
+                    };
+                    match post_json(&url, &message.to_json()) {
+                        Ok(()) => {
+                            metrics::inc_counter("geyser-plugin-mongodb-invalidations-sent", 1)
+                        }
+                        Err(err) => {
+                            warn!(
+                                "Failed to publish the {} invalidation of slot {}: {}",
+                                message.kind.as_str(),
+                                message.slot,
+                                err
+                            );
+                            metrics::inc_counter("geyser-plugin-mongodb-invalidations-failed", 1);
+                        }
+                    }
+                }
+            }))
+            .unwrap()
+    }
+
+    pub fn record(&self, slot: u64, pubkey: &[u8]) {
+        self.tracker.record(slot, pubkey);
+    }
+
+    /// Queues the messages of the slots decided by rooting `root`.
+    pub fn root(&self, root: u64) {
+        for message in self.tracker.root(root) {
+            if self.sender.try_send(message).is_err() {
+                metrics::inc_counter("geyser-plugin-mongodb-invalidations-dropped", 1);
+            }
+        }
+    }
+
+    pub fn join(&mut self) {
+        self.exit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,102 @@
+/// The clock behind the stored timestamps and the persist latency metrics.
+///
+/// Timestamps are UTC dates read from `chrono::Utc`. With the default `monotonic`
+/// source the wall clock is read once, when the plugin loads, and advanced by the
+/// monotonic clock after that: a step of the host clock, e.g. by NTP, then neither
+/// reorders `notified_at` and `ingested_at` nor turns a latency negative, at the cost
+/// of not following the correction until the plugin is reloaded. `system` reads the
+/// wall clock every time. `timestamp_precision` truncates the stored dates, so the
+/// timestamps of validators writing to one database compare at the same granularity.
+/// Every plugin instance has a clock of its own, the free functions read the clock of
+/// the instance the calling thread works for.
+use {
+    crate::instance,
+    chrono::Utc,
+    mongodb::bson,
+    serde_derive::{Deserialize, Serialize},
+    std::time::Instant,
+};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ClockSource {
+    /// The UTC time at load advanced by the monotonic clock.
+    #[default]
+    Monotonic,
+    /// The UTC time of the host, read every time.
+    System,
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TimestampPrecision {
+    Seconds,
+    /// The resolution of BSON dates.
+    #[default]
+    Millis,
+}
+
+/// The clock of one plugin instance.
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    source: ClockSource,
+    precision: TimestampPrecision,
+    /// The monotonic and the UTC time when the clock was created.
+    anchor: (Instant, i64),
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new(ClockSource::default(), TimestampPrecision::default())
+    }
+}
+
+impl Clock {
+    pub fn new(source: ClockSource, precision: TimestampPrecision) -> Self {
+        Self {
+            source,
+            precision,
+            anchor: (Instant::now(), Utc::now().timestamp_millis()),
+        }
+    }
+
+    pub fn now(&self) -> bson::DateTime {
+        let millis = match self.source {
+            ClockSource::Monotonic => {
+                let (instant, utc_millis) = self.anchor;
+                utc_millis + instant.elapsed().as_millis() as i64
+            }
+            ClockSource::System => Utc::now().timestamp_millis(),
+        };
+        bson::DateTime::from_millis(millis)
+    }
+
+    pub fn timestamp(&self, at: bson::DateTime) -> bson::DateTime {
+        match self.precision {
+            TimestampPrecision::Seconds => {
+                bson::DateTime::from_millis(at.timestamp_millis().div_euclid(1000) * 1000)
+            }
+            TimestampPrecision::Millis => at,
+        }
+    }
+}
+
+/// The current time at full precision, the time notifications are stamped with.
+pub fn now() -> bson::DateTime {
+    instance::with_current(|instance| instance.clock().now())
+}
+
+/// `at` truncated to the configured precision, as stored in documents.
+pub fn timestamp(at: bson::DateTime) -> bson::DateTime {
+    instance::with_current(|instance| instance.clock().timestamp(at))
+}
+
+/// The current time as stored in documents, e.g. `ingested_at`.
+pub fn timestamp_now() -> bson::DateTime {
+    timestamp(now())
+}
+
+/// Milliseconds elapsed since `since`, a time returned by `now`, zero if it is ahead.
+pub fn elapsed_ms(since: bson::DateTime) -> u64 {
+    (now().timestamp_millis() - since.timestamp_millis()).max(0) as u64
+}
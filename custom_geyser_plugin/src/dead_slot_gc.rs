@@ -0,0 +1,314 @@
+/// Removes the documents of slots that were never rooted.
+///
+/// Slots of forks that lost never get rooted, their accounts, transactions and blocks
+/// stay in the database next to the ones of the rooted fork. Every `interval_secs`
+/// the collector looks for slot documents more than `depth` slots below the newest
+/// rooted slot that the fork tracking marked dead or abandoned, and deletes the
+/// documents of those slots from the collections keyed by slot, the slot documents
+/// last so an interrupted run is resumed by the next one. Bucketed account history
+/// only loses the versions of those slots. With `dry_run`, the
+/// default, the documents are only counted, logged and reported in the alerts
+/// collection.
+use {
+    crate::{
+        geyser_plugin_mongodb::{GeyserPluginMongoDBConfig, GeyserPluginMongoDbError},
+        metrics,
+        mongodb_client::{
+            AccountStorageMode, ACCOUNTS_COLLECTION, ACCOUNT_HISTORY_COLLECTION,
+            ALERTS_COLLECTION, BLOCKS_COLLECTION, PROGRAMS_COLLECTION, SLOTS_COLLECTION,
+            TOKEN_BALANCE_CHANGES_COLLECTION, TOKEN_MINT_TRANSACTIONS_COLLECTION,
+            TRANSACTIONS_COLLECTION,
+        },
+        periodic_task::PeriodicTask,
+    },
+    futures::TryStreamExt,
+    log::*,
+    mongodb::{
+        bson::{self, doc, Document},
+        Collection, Database,
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::time::Duration,
+};
+
+const DEFAULT_DEAD_SLOT_GC_DEPTH: u64 = 1000;
+const DEFAULT_DEAD_SLOT_GC_INTERVAL_SECS: u64 = 600;
+const DEFAULT_DEAD_SLOT_GC_MAX_SLOTS_PER_RUN: i64 = 1000;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct DeadSlotGcConfig {
+    /// Slots at most this far below the newest root are left alone. The default is
+    /// 1000.
+    pub depth: Option<u64>,
+    /// Seconds between two runs. The default is 600.
+    pub interval_secs: Option<u64>,
+    /// Only counts and reports the documents that would be deleted. The default is
+    /// true.
+    pub dry_run: Option<bool>,
+    /// Slots collected per run at most. The default is 1000.
+    pub max_slots_per_run: Option<i64>,
+}
+
+fn gc_error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::DataSchemaError { msg }))
+}
+
+/// The newest rooted slot stored, None before the first root.
+async fn newest_root(database: &Database) -> Result<Option<u64>, GeyserPluginError> {
+    let root = database
+        .collection::<Document>(SLOTS_COLLECTION)
+        .find_one(doc! { "status": "rooted" })
+        .sort(doc! { "slot": -1 })
+        .projection(doc! { "_id": 0, "slot": 1 })
+        .await
+        .map_err(|err| gc_error(format!("Failed to read the newest root: {}", err)))?;
+    Ok(root.and_then(|root| root.get_i64("slot").ok()).map(|slot| slot as u64))
+}
+
+/// One run of the collector. `scanned_through` is the cutoff of the previous run that
+/// collected every slot below it, older slots are not looked at again.
+struct DeadSlotCollector {
+    depth: u64,
+    dry_run: bool,
+    max_slots_per_run: i64,
+    account_slot_field: String,
+    /// The accounts hold one document per pubkey, see `AccountStorageMode`.
+    current_state: bool,
+    /// The account history is stored in buckets of versions.
+    history_buckets: bool,
+    scanned_through: u64,
+}
+
+impl DeadSlotCollector {
+    /// The collections keyed by slot with the name of their slot field, the slots
+    /// collection last. In current state the document of an account is its only one,
+    /// deleting it would lose a live account, the accounts are left alone. Bucketed
+    /// history is collected by `collect_history_buckets` instead.
+    fn collections(&self) -> Vec<(&'static str, &str)> {
+        let mut collections = Vec::new();
+        if !self.current_state {
+            collections.push((ACCOUNTS_COLLECTION, self.account_slot_field.as_str()));
+        }
+        if !self.history_buckets {
+            collections.push((ACCOUNT_HISTORY_COLLECTION, "slot"));
+        }
+        collections.extend([
+            (PROGRAMS_COLLECTION, self.account_slot_field.as_str()),
+            (TRANSACTIONS_COLLECTION, "slot"),
+            (TOKEN_MINT_TRANSACTIONS_COLLECTION, "slot"),
+            (TOKEN_BALANCE_CHANGES_COLLECTION, "slot"),
+            (BLOCKS_COLLECTION, "slot"),
+            (SLOTS_COLLECTION, "slot"),
+        ]);
+        collections
+    }
+
+    /// Removes the versions of `slots` from the history buckets holding any, and the
+    /// buckets left empty, returning the number of buckets changed. `bytes` keeps
+    /// counting the removed versions, the bucket only fills up earlier.
+    async fn collect_history_buckets(
+        &self,
+        collection: &Collection<Document>,
+        slots: &[i64],
+    ) -> mongodb::error::Result<u64> {
+        let filter = doc! { "versions.slot": { "$in": slots } };
+        if self.dry_run {
+            return collection.count_documents(filter).await;
+        }
+        let update = vec![
+            doc! { "$set": { "versions": { "$filter": {
+                "input": "$versions",
+                "cond": { "$not": [{ "$in": ["$$this.slot", slots] }] },
+            } } } },
+            doc! { "$set": {
+                "count": { "$size": "$versions" },
+                "first_slot": { "$min": "$versions.slot" },
+                "last_slot": { "$max": "$versions.slot" },
+            } },
+        ];
+        let modified = collection.update_many(filter, update).await?.modified_count;
+        collection
+            .delete_many(doc! { "versions": { "$size": 0 } })
+            .await?;
+        Ok(modified)
+    }
+
+    async fn run(&mut self, database: &Database) -> Result<(), GeyserPluginError> {
+        let Some(root) = newest_root(database).await? else {
+            return Ok(());
+        };
+        let cutoff = root.saturating_sub(self.depth);
+        if cutoff <= self.scanned_through {
+            return Ok(());
+        }
+        let slots: Vec<Document> = database
+            .collection::<Document>(SLOTS_COLLECTION)
+            .find(doc! {
+                "slot": { "$gte": self.scanned_through as i64, "$lt": cutoff as i64 },
+                // A slot status never moves back, a dead or abandoned slot was never
+                // rooted. Processed or confirmed slots the tracking did not decide
+                // are left alone.
+                "status": { "$in": ["dead", "abandoned"] },
+            })
+            .sort(doc! { "slot": 1 })
+            .projection(doc! { "_id": 0, "slot": 1, "status": 1 })
+            .limit(self.max_slots_per_run)
+            .await
+            .map_err(|err| gc_error(format!("Failed to find the dead slots: {}", err)))?
+            .try_collect()
+            .await
+            .map_err(|err| gc_error(format!("Failed to find the dead slots: {}", err)))?;
+        let slots: Vec<i64> = slots
+            .iter()
+            .filter_map(|slot| slot.get_i64("slot").ok())
+            .collect();
+        // A full batch may have left slots below the cutoff, the next run resumes
+        // after the last one.
+        let scanned_through = match slots.last() {
+            Some(last) if slots.len() as i64 == self.max_slots_per_run => *last as u64 + 1,
+            _ => cutoff,
+        };
+        if slots.is_empty() {
+            self.scanned_through = scanned_through;
+            return Ok(());
+        }
+
+        let mut documents = Document::new();
+        if self.history_buckets {
+            let count = self
+                .collect_history_buckets(
+                    &database.collection::<Document>(ACCOUNT_HISTORY_COLLECTION),
+                    &slots,
+                )
+                .await
+                .map_err(|err| {
+                    gc_error(format!(
+                        "Failed to collect the dead slots of {}: {}",
+                        ACCOUNT_HISTORY_COLLECTION, err
+                    ))
+                })?;
+            documents.insert(ACCOUNT_HISTORY_COLLECTION, count as i64);
+        }
+        for (collection_name, slot_field) in self.collections() {
+            let collection = database.collection::<Document>(collection_name);
+            let filter = doc! { slot_field: { "$in": slots.clone() } };
+            let count = if self.dry_run {
+                collection.count_documents(filter).await
+            } else {
+                collection
+                    .delete_many(filter)
+                    .await
+                    .map(|result| result.deleted_count)
+            }
+            .map_err(|err| {
+                gc_error(format!(
+                    "Failed to collect the dead slots of {}: {}",
+                    collection_name, err
+                ))
+            })?;
+            documents.insert(collection_name, count as i64);
+        }
+        let total: i64 = documents.values().filter_map(|count| count.as_i64()).sum();
+        if self.dry_run {
+            info!(
+                "Dry run: {} documents of {} dead slots below {} would be deleted: {}",
+                total,
+                slots.len(),
+                cutoff,
+                documents
+            );
+            metrics::inc_counter(
+                "geyser-plugin-mongodb-dead-slot-gc-dry-run-documents",
+                total as usize,
+            );
+        } else {
+            info!(
+                "Deleted {} documents of {} dead slots below {}: {}",
+                total,
+                slots.len(),
+                cutoff,
+                documents
+            );
+            metrics::inc_counter(
+                "geyser-plugin-mongodb-dead-slot-gc-deleted-documents",
+                total as usize,
+            );
+        }
+        metrics::inc_counter("geyser-plugin-mongodb-dead-slot-gc-slots", slots.len());
+        let report = doc! {
+            "source": "dead_slot_gc",
+            "kind": if self.dry_run { "dry_run" } else { "deleted" },
+            "root": root as i64,
+            "cutoff": cutoff as i64,
+            "first_slot": slots.first().copied(),
+            "last_slot": slots.last().copied(),
+            "slots": slots.len() as i64,
+            "documents": documents,
+            "detected_at": bson::DateTime::now(),
+        };
+        if let Err(err) = database
+            .collection::<Document>(ALERTS_COLLECTION)
+            .insert_one(report)
+            .await
+        {
+            error!("Failed to insert the dead slot report: {}", err);
+        }
+        // A dry run reports the same slots again once the documents are deleted.
+        if !self.dry_run {
+            self.scanned_through = scanned_through;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct DeadSlotGc {
+    task: PeriodicTask,
+}
+
+impl DeadSlotGc {
+    /// Starts the collector configured by `dead_slot_gc`, None when unset.
+    pub fn spawn(config: &GeyserPluginMongoDBConfig) -> Option<Self> {
+        let gc_config = config.dead_slot_gc.clone()?;
+        if !config.track_forks.unwrap_or_default() {
+            warn!(
+                "\"dead_slot_gc\" only collects the slots \"track_forks\" marks dead or \
+                 abandoned, it is not set"
+            );
+        }
+        let interval = Duration::from_secs(
+            gc_config
+                .interval_secs
+                .unwrap_or(DEFAULT_DEAD_SLOT_GC_INTERVAL_SECS),
+        );
+        let mut collector = DeadSlotCollector {
+            depth: gc_config.depth.unwrap_or(DEFAULT_DEAD_SLOT_GC_DEPTH),
+            dry_run: gc_config.dry_run.unwrap_or(true),
+            max_slots_per_run: gc_config
+                .max_slots_per_run
+                .unwrap_or(DEFAULT_DEAD_SLOT_GC_MAX_SLOTS_PER_RUN)
+                .max(1),
+            account_slot_field: config
+                .schema_profile
+                .unwrap_or_default()
+                .account_field("slot")
+                .to_string(),
+            current_state: config.account_storage.unwrap_or_default()
+                == AccountStorageMode::CurrentState,
+            history_buckets: config.account_history_bucket_size.is_some(),
+            scanned_through: 0,
+        };
+        let task = PeriodicTask::spawn(
+            "mongodb-dead-slot-gc",
+            "collect the dead slots",
+            interval,
+            config,
+            move |runtime, database| runtime.block_on(collector.run(database)),
+        );
+        Some(Self { task })
+    }
+
+    pub fn join(&mut self) {
+        self.task.join();
+    }
+}
@@ -0,0 +1,171 @@
+/// Read-only queries over the collections written by the plugin. These back the
+/// companion binaries and never modify the data. The account queries take the
+/// `schema_profile` the accounts were stored with.
+use {
+    crate::{
+        mongodb_client::{ACCOUNTS_COLLECTION, SLOTS_COLLECTION, TRANSACTIONS_COLLECTION},
+        schema::{expand_account_document, SchemaProfile},
+    },
+    futures::TryStreamExt,
+    mongodb::{
+        bson::{doc, DateTime, Document},
+        error::Result,
+        Database,
+    },
+};
+
+/// Number of slots, counted back from the newest stored account, considered
+/// when ranking programs by account writes.
+pub const DEFAULT_TOP_PROGRAMS_SLOT_WINDOW: i64 = 1000;
+
+/// Everything the dashboard shows on one page.
+#[derive(Debug, Default)]
+pub struct ExplorerSummary {
+    pub recent_slots: Vec<Document>,
+    pub latest_account_slot: Option<i64>,
+    /// Seconds elapsed since the newest account document was written.
+    pub seconds_since_last_write: Option<i64>,
+    pub biggest_accounts: Vec<Document>,
+    pub top_programs: Vec<Document>,
+    pub recent_failed_transactions: Vec<Document>,
+}
+
+pub async fn recent_slots(database: &Database, limit: i64) -> Result<Vec<Document>> {
+    database
+        .collection::<Document>(SLOTS_COLLECTION)
+        .find(doc! {})
+        .sort(doc! { "slot": -1 })
+        .limit(limit)
+        .await?
+        .try_collect()
+        .await
+}
+
+/// Returns the slot of the newest account document and how long ago it was written.
+pub async fn ingestion_lag(
+    database: &Database,
+    profile: SchemaProfile,
+) -> Result<(Option<i64>, Option<i64>)> {
+    let slot = profile.account_field("slot");
+    let latest = database
+        .collection::<Document>(ACCOUNTS_COLLECTION)
+        .find_one(doc! {})
+        .sort(doc! { slot: -1 })
+        .projection(doc! { slot: 1, profile.account_field("updated_on"): 1 })
+        .await?
+        .map(expand_account_document);
+    Ok(match latest {
+        Some(latest) => {
+            let slot = latest.get_i64("slot").ok();
+            let lag = latest.get_datetime("updated_on").ok().map(|updated_on| {
+                (DateTime::now().timestamp_millis() - updated_on.timestamp_millis()) / 1000
+            });
+            (slot, lag)
+        }
+        None => (None, None),
+    })
+}
+
+/// The largest accounts by data length, one entry per pubkey.
+pub async fn biggest_accounts(
+    database: &Database,
+    profile: SchemaProfile,
+    limit: i64,
+) -> Result<Vec<Document>> {
+    let field = |name| format!("${}", profile.account_field(name));
+    let pipeline = vec![
+        doc! { "$project": {
+            "pubkey": field("pubkey"),
+            "owner": field("owner"),
+            "slot": field("slot"),
+            "data_len": { "$binarySize": field("data") },
+        }},
+        doc! { "$sort": { "data_len": -1 } },
+        // Several versions of one account may be stored, over-fetch before grouping.
+        doc! { "$limit": limit * 10 },
+        doc! { "$group": {
+            "_id": "$pubkey",
+            "owner": { "$first": "$owner" },
+            "data_len": { "$max": "$data_len" },
+            "slot": { "$max": "$slot" },
+        }},
+        doc! { "$sort": { "data_len": -1 } },
+        doc! { "$limit": limit },
+    ];
+    database
+        .collection::<Document>(ACCOUNTS_COLLECTION)
+        .aggregate(pipeline)
+        .await?
+        .try_collect()
+        .await
+}
+
+/// Owner programs ranked by the number of account writes within the last
+/// `slot_window` slots.
+pub async fn top_programs_by_writes(
+    database: &Database,
+    profile: SchemaProfile,
+    slot_window: i64,
+    limit: i64,
+) -> Result<Vec<Document>> {
+    let (latest_slot, _) = ingestion_lag(database, profile).await?;
+    let latest_slot = match latest_slot {
+        Some(latest_slot) => latest_slot,
+        None => return Ok(Vec::default()),
+    };
+    let pipeline = vec![
+        doc! { "$match": {
+            profile.account_field("slot"): { "$gte": latest_slot - slot_window },
+        }},
+        doc! { "$group": {
+            "_id": format!("${}", profile.account_field("owner")),
+            "writes": { "$sum": 1 },
+        }},
+        doc! { "$sort": { "writes": -1 } },
+        doc! { "$limit": limit },
+    ];
+    database
+        .collection::<Document>(ACCOUNTS_COLLECTION)
+        .aggregate(pipeline)
+        .await?
+        .try_collect()
+        .await
+}
+
+pub async fn recent_failed_transactions(
+    database: &Database,
+    limit: i64,
+) -> Result<Vec<Document>> {
+    database
+        .collection::<Document>(TRANSACTIONS_COLLECTION)
+        .find(doc! { "meta.error": { "$ne": null } })
+        .sort(doc! { "slot": -1 })
+        .limit(limit)
+        .projection(doc! { "signature": 1, "slot": 1, "meta.error": 1 })
+        .await?
+        .try_collect()
+        .await
+}
+
+pub async fn summary(
+    database: &Database,
+    profile: SchemaProfile,
+    limit: i64,
+) -> Result<ExplorerSummary> {
+    let (latest_account_slot, seconds_since_last_write) =
+        ingestion_lag(database, profile).await?;
+    Ok(ExplorerSummary {
+        recent_slots: recent_slots(database, limit).await?,
+        latest_account_slot,
+        seconds_since_last_write,
+        biggest_accounts: biggest_accounts(database, profile, limit).await?,
+        top_programs: top_programs_by_writes(
+            database,
+            profile,
+            DEFAULT_TOP_PROGRAMS_SLOT_WINDOW,
+            limit,
+        )
+        .await?,
+        recent_failed_transactions: recent_failed_transactions(database, limit).await?,
+    })
+}
@@ -0,0 +1,379 @@
+/// Point-in-time exports of the data stored by the plugin.
+///
+/// Exports read through a snapshot session, so every document reflects the same
+/// cluster time even while the plugin keeps writing. Snapshot reads need a replica
+/// set or sharded cluster of MongoDB 5.0 or later, older servers are read without a
+/// snapshot. They are bounded by the server's `minSnapshotHistoryWindowInSeconds`
+/// (5 minutes by default), which limits how long an export may run.
+///
+/// Full reads of the transactions or the account history are instead split into
+/// chunks of a slot range, read in pages with a cursor per chunk. The chunks of a
+/// range are always the same and each document falls in exactly one, ordered by
+/// slot and `_id`, so chunks can be read in parallel and a cursor saved after a page
+/// resumes its chunk without rereading or skipping a document.
+///
+/// The account readers take the `schema_profile` the accounts were stored with.
+use {
+    crate::{
+        mongodb_client::{ACCOUNTS_COLLECTION, ACCOUNT_HISTORY_COLLECTION, TRANSACTIONS_COLLECTION},
+        schema::{expand_account_document, SchemaProfile},
+        server_support::{ServerFeature, ServerSupport},
+    },
+    base64::{
+        engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD},
+        Engine,
+    },
+    futures::TryStreamExt,
+    mongodb::{
+        bson::{doc, oid::ObjectId, Bson, Document, Timestamp},
+        error::{Error, Result},
+        Client, Database,
+    },
+    serde_derive::{Deserialize, Serialize},
+    serde_json::json,
+    std::io::{self, Write},
+};
+
+/// What an export wrote.
+#[derive(Debug, Default)]
+pub struct ExportSummary {
+    pub documents: u64,
+    pub bytes: u64,
+    /// The cluster time the snapshot was read at, None when the server does not
+    /// support snapshot reads and the export may mix documents of several times.
+    pub cluster_time: Option<Timestamp>,
+}
+
+/// Matches the accounts given by pubkey or owned by one of `owners`, or all accounts
+/// when both are empty.
+pub fn accounts_filter(profile: SchemaProfile, pubkeys: &[String], owners: &[String]) -> Document {
+    let mut conditions = Vec::default();
+    if !pubkeys.is_empty() {
+        conditions.push(doc! { profile.account_field("pubkey"): { "$in": pubkeys } });
+    }
+    if !owners.is_empty() {
+        conditions.push(doc! { profile.account_field("owner"): { "$in": owners } });
+    }
+    match conditions.len() {
+        0 => doc! {},
+        1 => conditions.remove(0),
+        _ => doc! { "$or": conditions },
+    }
+}
+
+/// The aggregation reducing the accounts collection to the newest version of every
+/// account matching `filter`, the accounts stored with `profile`.
+pub fn current_accounts_pipeline(profile: SchemaProfile, filter: Document) -> Vec<Document> {
+    let [pubkey, slot, write_version] =
+        ["pubkey", "slot", "write_version"].map(|name| profile.account_field(name));
+    let mut pipeline = Vec::default();
+    if !filter.is_empty() {
+        pipeline.push(doc! { "$match": filter });
+    }
+    pipeline.extend([
+        doc! { "$sort": { pubkey: 1, slot: -1, write_version: -1 } },
+        doc! { "$group": { "_id": format!("${}", pubkey), "account": { "$first": "$$ROOT" } } },
+        doc! { "$replaceRoot": { "newRoot": "$account" } },
+        doc! { "$sort": { pubkey: 1 } },
+    ]);
+    pipeline
+}
+
+/// Reads the current state of every account, or of the accounts owned by `owners`,
+/// and passes each document as stored to `write`, which returns the bytes it wrote.
+/// The summary counts the documents and those bytes.
+pub async fn read_current_accounts<F>(
+    client: &Client,
+    database: &Database,
+    profile: SchemaProfile,
+    owners: &[String],
+    mut write: F,
+) -> Result<ExportSummary>
+where
+    F: FnMut(Document) -> Result<u64>,
+{
+    let snapshot = ServerSupport::read(client)
+        .await?
+        .supports(ServerFeature::SnapshotReads);
+    let mut session = client.start_session().snapshot(snapshot).await?;
+    let mut cursor = database
+        .collection::<Document>(ACCOUNTS_COLLECTION)
+        .aggregate(current_accounts_pipeline(
+            profile,
+            accounts_filter(profile, &[], owners),
+        ))
+        .allow_disk_use(true)
+        .session(&mut session)
+        .await?;
+
+    let mut summary = ExportSummary::default();
+    while let Some(account) = cursor.next(&mut session).await.transpose()? {
+        summary.bytes += write(account)?;
+        summary.documents += 1;
+    }
+    summary.cluster_time = session.operation_time().filter(|_| snapshot);
+    Ok(summary)
+}
+
+/// Writes the current state of every account, or of the accounts owned by `owners`,
+/// to `writer` as concatenated BSON documents, the format of `mongodump` and
+/// `bsondump`. The documents are written as stored, with the field names of `profile`.
+pub async fn export_current_accounts<W: Write>(
+    client: &Client,
+    database: &Database,
+    profile: SchemaProfile,
+    owners: &[String],
+    mut writer: W,
+) -> Result<ExportSummary> {
+    let summary = read_current_accounts(client, database, profile, owners, |account| {
+        let bytes = mongodb::bson::to_vec(&account)?;
+        writer.write_all(&bytes)?;
+        Ok(bytes.len() as u64)
+    })
+    .await?;
+    writer.flush()?;
+    Ok(summary)
+}
+
+/// The newest version of the accounts given by pubkey or owned by one of `owners`,
+/// at most `limit` of them, with the standard field names.
+pub async fn find_current_accounts(
+    database: &Database,
+    profile: SchemaProfile,
+    pubkeys: &[String],
+    owners: &[String],
+    limit: i64,
+) -> Result<Vec<Document>> {
+    let mut pipeline =
+        current_accounts_pipeline(profile, accounts_filter(profile, pubkeys, owners));
+    pipeline.push(doc! { "$limit": limit });
+    let accounts: Vec<Document> = database
+        .collection::<Document>(ACCOUNTS_COLLECTION)
+        .aggregate(pipeline)
+        .allow_disk_use(true)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(accounts.into_iter().map(expand_account_document).collect())
+}
+
+fn get_u64(document: &Document, key: &str) -> u64 {
+    match document.get(key) {
+        // Unsigned values are stored as i64, rent epochs of u64::MAX wrap to -1.
+        Some(Bson::Int64(value)) => *value as u64,
+        Some(Bson::Int32(value)) => *value as u64,
+        _ => 0,
+    }
+}
+
+/// Converts a stored account into the JSON account file `solana-test-validator
+/// --account <pubkey> <file>` loads, the format written by `solana account --output json`.
+/// Returns None for documents stored without their data.
+pub fn build_test_validator_account(account: &Document) -> Option<serde_json::Value> {
+    let data = account.get_binary_generic("data").ok()?;
+    Some(json!({
+        "pubkey": account.get_str("pubkey").ok()?,
+        "account": {
+            "lamports": get_u64(account, "lamports"),
+            "data": [STANDARD.encode(data), "base64"],
+            "owner": account.get_str("owner").ok()?,
+            "executable": account.get_bool("executable").unwrap_or_default(),
+            "rentEpoch": get_u64(account, "rent_epoch"),
+            "space": data.len(),
+        },
+    }))
+}
+
+/// The collections `read_chunk_page` reads. History buckets, see
+/// `account_history_bucket_size`, have no `slot` and are not exported.
+pub const CHUNKED_EXPORT_COLLECTIONS: [&str; 2] =
+    [TRANSACTIONS_COLLECTION, ACCOUNT_HISTORY_COLLECTION];
+
+/// Where the export of one chunk stands: the chunk, a slot range of a collection,
+/// and the last document read. Saved between pages to resume the chunk.
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ChunkCursor {
+    pub collection: String,
+    pub chunk: u32,
+    pub start_slot: u64,
+    /// Inclusive.
+    pub end_slot: u64,
+    /// The slot and `_id` of the last document read, None before the first page.
+    pub after_slot: Option<u64>,
+    pub after_id: Option<String>,
+    /// Set once the chunk has been read to its end.
+    pub done: bool,
+}
+
+impl ChunkCursor {
+    /// The cursor as an opaque URL safe token.
+    pub fn to_token(&self) -> String {
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(self).unwrap_or_default())
+    }
+
+    pub fn from_token(token: &str) -> std::result::Result<Self, String> {
+        let bytes = URL_SAFE_NO_PAD
+            .decode(token)
+            .map_err(|err| format!("Invalid cursor: {}", err))?;
+        serde_json::from_slice(&bytes).map_err(|err| format!("Invalid cursor: {}", err))
+    }
+}
+
+/// Splits `start_slot..=end_slot` of `collection` into `chunks` contiguous ranges of
+/// nearly equal length, fewer when the range has fewer slots, each with a fresh
+/// cursor. The same arguments always give the same chunks.
+pub fn split_slot_range(
+    collection: &str,
+    start_slot: u64,
+    end_slot: u64,
+    chunks: u32,
+) -> Vec<ChunkCursor> {
+    if end_slot < start_slot || chunks == 0 {
+        return Vec::default();
+    }
+    let slots = end_slot - start_slot + 1;
+    let chunks = (chunks as u64).min(slots);
+    let (length, remainder) = (slots / chunks, slots % chunks);
+    let mut next_slot = start_slot;
+    (0..chunks)
+        .map(|chunk| {
+            // The first `remainder` chunks take one slot more.
+            let chunk_length = length + u64::from(chunk < remainder);
+            let cursor = ChunkCursor {
+                collection: collection.to_string(),
+                chunk: chunk as u32,
+                start_slot: next_slot,
+                end_slot: next_slot + chunk_length - 1,
+                ..ChunkCursor::default()
+            };
+            next_slot += chunk_length;
+            cursor
+        })
+        .collect()
+}
+
+fn invalid_input(message: String) -> Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message).into()
+}
+
+/// Reads the next page of at most `limit` documents of the chunk of `cursor`, ordered
+/// by slot and `_id`, and moves `cursor` past them. An empty page, or `cursor.done`,
+/// means the chunk was read to its end.
+pub async fn read_chunk_page(
+    database: &Database,
+    cursor: &mut ChunkCursor,
+    limit: i64,
+) -> Result<Vec<Document>> {
+    if !CHUNKED_EXPORT_COLLECTIONS.contains(&cursor.collection.as_str()) {
+        return Err(invalid_input(format!(
+            "{} cannot be exported in chunks",
+            cursor.collection
+        )));
+    }
+    if cursor.done {
+        return Ok(Vec::default());
+    }
+    let mut filter = doc! {
+        "slot": { "$gte": cursor.start_slot as i64, "$lte": cursor.end_slot as i64 },
+    };
+    if let (Some(after_slot), Some(after_id)) = (cursor.after_slot, &cursor.after_id) {
+        let after_id = ObjectId::parse_str(after_id)
+            .map_err(|err| invalid_input(format!("Invalid cursor: {}", err)))?;
+        filter.insert(
+            "$or",
+            vec![
+                doc! { "slot": { "$gt": after_slot as i64 } },
+                doc! { "slot": after_slot as i64, "_id": { "$gt": after_id } },
+            ],
+        );
+    }
+    let documents: Vec<Document> = database
+        .collection::<Document>(&cursor.collection)
+        .find(filter)
+        .sort(doc! { "slot": 1, "_id": 1 })
+        .limit(limit)
+        .await?
+        .try_collect()
+        .await?;
+    match documents.last() {
+        Some(last) => {
+            cursor.after_slot = last.get_i64("slot").ok().map(|slot| slot as u64);
+            cursor.after_id = last.get_object_id("_id").ok().map(|id| id.to_hex());
+        }
+        None => cursor.done = true,
+    }
+    if (documents.len() as i64) < limit {
+        cursor.done = true;
+    }
+    Ok(documents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ranges(cursors: &[ChunkCursor]) -> Vec<(u64, u64)> {
+        cursors
+            .iter()
+            .map(|cursor| (cursor.start_slot, cursor.end_slot))
+            .collect()
+    }
+
+    #[test]
+    fn test_split_slot_range_covers_the_range() {
+        let cursors = split_slot_range(TRANSACTIONS_COLLECTION, 100, 110, 3);
+        // 11 slots: the first 2 chunks take the remainder.
+        assert_eq!(ranges(&cursors), vec![(100, 103), (104, 107), (108, 110)]);
+        for (index, cursor) in cursors.iter().enumerate() {
+            assert_eq!(cursor.collection, TRANSACTIONS_COLLECTION);
+            assert_eq!(cursor.chunk, index as u32);
+            assert_eq!((cursor.after_slot, &cursor.after_id, cursor.done), (None, &None, false));
+        }
+
+        for (start_slot, end_slot, chunks) in [(0, 0, 1), (5, 104, 7), (1, 1000, 16), (3, 9, 7)] {
+            let cursors = split_slot_range(TRANSACTIONS_COLLECTION, start_slot, end_slot, chunks);
+            assert_eq!(cursors.len(), chunks as usize);
+            assert_eq!(cursors.first().unwrap().start_slot, start_slot);
+            assert_eq!(cursors.last().unwrap().end_slot, end_slot);
+            for pair in cursors.windows(2) {
+                assert_eq!(pair[1].start_slot, pair[0].end_slot + 1);
+            }
+            let lengths: Vec<u64> = cursors
+                .iter()
+                .map(|cursor| cursor.end_slot - cursor.start_slot + 1)
+                .collect();
+            assert!(lengths.windows(2).all(|pair| pair[0] == pair[1] || pair[0] == pair[1] + 1));
+        }
+    }
+
+    #[test]
+    fn test_split_slot_range_with_more_chunks_than_slots() {
+        let cursors = split_slot_range(ACCOUNT_HISTORY_COLLECTION, 7, 9, 10);
+        assert_eq!(ranges(&cursors), vec![(7, 7), (8, 8), (9, 9)]);
+    }
+
+    #[test]
+    fn test_split_slot_range_without_chunks_or_slots() {
+        assert!(split_slot_range(TRANSACTIONS_COLLECTION, 1, 10, 0).is_empty());
+        assert!(split_slot_range(TRANSACTIONS_COLLECTION, 10, 9, 4).is_empty());
+    }
+
+    #[test]
+    fn test_chunk_cursor_token_round_trip() {
+        let mut cursor = split_slot_range(ACCOUNT_HISTORY_COLLECTION, 0, 99, 4).remove(2);
+        assert_eq!(ChunkCursor::from_token(&cursor.to_token()), Ok(cursor.clone()));
+
+        cursor.after_slot = Some(60);
+        cursor.after_id = Some(ObjectId::new().to_hex());
+        let token = cursor.to_token();
+        assert!(token
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || byte == b'-' || byte == b'_'));
+        assert_eq!(ChunkCursor::from_token(&token), Ok(cursor.clone()));
+
+        cursor.done = true;
+        assert_eq!(ChunkCursor::from_token(&cursor.to_token()), Ok(cursor));
+        assert!(ChunkCursor::from_token("not a cursor").is_err());
+        assert!(ChunkCursor::from_token(&URL_SAFE_NO_PAD.encode(b"{}")).is_err());
+    }
+}
@@ -1,9 +1,46 @@
 /// Main entry for the  plugin
 use {
     crate::{
-        accounts_selector::AccountsSelector,
-        mongodb_client::{ParallelMongodbClient, MongoClientBuilder},
-        transaction_selector::TransactionSelector,
+        account_decoder::AccountDecoderConfig,
+        accounts_selector::{AccountSelection, AccountSelectionCounters, SelectorSet},
+        anomaly_detector::OwnerRateConfig,
+        backpressure::BackpressureConfig,
+        cache_invalidation::CacheInvalidationConfig,
+        clock::{Clock, ClockSource, TimestampPrecision},
+        dead_slot_gc::DeadSlotGcConfig,
+        history_compaction::HistoryCompactionConfig,
+        history_retention::HistoryRetentionConfig,
+        index_builds::StartupIndexBuild,
+        instance::{self, Instance, InstanceGuard},
+        instruction_decoder::ProgramInstructionLayouts,
+        logging::{setup_logging, LogFormat},
+        metrics,
+        metrics_exporters::MetricsExportersConfig,
+        metrics_journal::MetricsJournalConfig,
+        middleware::MiddlewareConfig,
+        oversized_transactions::OversizedTransactionConfig,
+        profiles::{apply_profile, IngestionProfile},
+        mongodb_client::{
+            validate_instance_name, AccountDataOverflowConfig, AccountStorageMode,
+            AuthMechanismConfig, ClosedAccountMode, MongoClientBuilder, ParallelMongodbClient,
+            ProgramAccountMode, TokenIndexBatchConfig, WriteConcernConfig, WriteRetryConfig,
+        },
+        redaction::RedactionConfig,
+        sampling::SamplingRuleConfig,
+        schema::SchemaProfile,
+        schema_validation::SchemaValidationConfig,
+        schema_verification::SchemaVerificationConfig,
+        sharding::ShardingConfig,
+        selector_reload::{SelectorReloader, SharedAccountsSelector, SharedTransactionSelector},
+        selector_rules::{SelectorRulesConfig, SelectorRulesPoller},
+        slot_batching::SlotBatchingConfig,
+        time_series::TimeSeriesConfig,
+        transaction_cap::TransactionCapConfig,
+        transaction_enrichment::TransactionEnrichmentConfig,
+        transaction_selector::{
+            invoked_programs, TransactionSelection, TransactionSelectionCounters,
+            TransactionSelector,
+        },
     },
     bs58,
     log::*,
@@ -14,16 +51,34 @@ use {
         ReplicaTransactionInfoVersions, Result, SlotStatus,
     },
     solana_measure::measure::Measure,
-    solana_metrics::*,
-    std::{fs::File, io::Read},
+    std::{
+        collections::HashMap,
+        fs::File,
+        io::Read,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, RwLock,
+        },
+        time::Duration,
+    },
     thiserror::Error,
 };
 #[derive(Default)]
 pub struct GeyserPluginMongodb {
+    /// The name, metrics and clock of this instance, current while it is called.
+    instance: Option<Arc<Instance>>,
     client: Option<ParallelMongodbClient>,
-    accounts_selector: Option<AccountsSelector>,
-    transaction_selector: Option<TransactionSelector>,
+    accounts_selector: Option<SharedAccountsSelector>,
+    transaction_selector: Option<SharedTransactionSelector>,
+    /// Resolved with the selectors, in the registry of the instance.
+    account_selection_counters: Option<AccountSelectionCounters>,
+    transaction_selection_counters: Option<TransactionSelectionCounters>,
     batch_starting_slot: Option<u64>,
+    slot_window: SlotWindow,
+    selector_reloader: Option<SelectorReloader>,
+    selector_rules_poller: Option<SelectorRulesPoller>,
+    /// Set from `skip_startup_accounts`, reloaded with the selectors.
+    skip_startup_accounts: Arc<AtomicBool>,
 }
 
 impl std::fmt::Debug for GeyserPluginMongodb {
@@ -35,17 +90,21 @@ impl std::fmt::Debug for GeyserPluginMongodb {
 /// The Configuration for the MongoDb plugin
 #[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub struct GeyserPluginMongoDBConfig {
-    /// The host name or IP of the MongoDb server
+    /// The host name or IP of the MongoDb server. Several comma separated
+    /// hosts can be given to seed a replica set.
     pub host: Option<String>,
 
-    /// The user name of the MongoDb server.
+    /// The user name of the MongoDb server, applied as the credential of the client
+    /// together with `password` or `password_file` and `auth_db`.
     pub user: Option<String>,
 
     /// The port number of the MongoDb database, the default is 27017
     pub port: Option<u16>,
 
     /// The connection string of MongoDb database, if this is set
-    /// `host`, `user` and `port` will be ignored.
+    /// `host`, `user` and `port` will be ignored, but `password`, `password_file` and
+    /// `auth_db` complete the credential of the connection string. Both `mongodb://` seedlists
+    /// and DNS based `mongodb+srv://` URIs are accepted.
     pub connection_str: Option<String>,
 
     /// Controls the number of threads establishing connections to
@@ -56,6 +115,10 @@ pub struct GeyserPluginMongoDBConfig {
     /// The default is 10.
     pub batch_size: Option<usize>,
 
+    /// Accounts buffered at startup are inserted at least this often, even when the
+    /// batch is not full. The default is 1000.
+    pub batch_flush_interval_ms: Option<u64>,
+
     /// Controls whether to panic the validator in case of errors
     /// writing to MongoDb server. The default is false
     pub panic_on_db_errors: Option<bool>,
@@ -63,6 +126,11 @@ pub struct GeyserPluginMongoDBConfig {
     /// Indicates whether to store historical data for accounts
     pub store_account_historical_data: Option<bool>,
 
+    /// When set, historical versions of an account are grouped into bucket
+    /// documents holding up to this many versions each instead of one
+    /// document per version. Unset by default.
+    pub account_history_bucket_size: Option<usize>,
+
     /// Controls whether to use SSL based connection to the database server.
     /// The default is false
     pub use_ssl: Option<bool>,
@@ -70,10 +138,12 @@ pub struct GeyserPluginMongoDBConfig {
     /// Specify the path to MongoDB server's certificate file
     pub server_ca: Option<String>,
 
-    /// Specify the path to the local client's certificate file
+    /// Specify the path to the local client's certificate file. This may be a
+    /// combined PEM file holding both the certificate and its private key.
     pub client_cert: Option<String>,
 
-    /// Specify the path to the local client's private PEM key file.
+    /// Specify the path to the local client's private PEM key file. Not needed
+    /// when `client_cert` is a combined PEM file.
     pub client_key: Option<String>,
 
     /// Controls whether to index the token owners. The default is false
@@ -86,6 +156,340 @@ pub struct GeyserPluginMongoDBConfig {
     /// and ignore upsetr accounts (at_startup) that should already exist in DB
     #[serde(default)]
     pub skip_upsert_existing_accounts_at_startup: bool,
+
+    /// When set, a WebSocket server is started on this address that pushes
+    /// persisted account updates to subscribers.
+    pub websocket_bind_address: Option<String>,
+
+    /// Controls whether account updates are pushed to WebSocket subscribers
+    /// straight from the geyser callback instead of after they are persisted.
+    /// Such notifications carry `persisted: false`. The default is false.
+    pub websocket_fast_lane: Option<bool>,
+
+    /// The number of account updates the WebSocket gateway buffers for its
+    /// subscribers. A subscriber lagging further behind misses the overflow.
+    /// The default is 65536.
+    pub websocket_channel_capacity: Option<usize>,
+
+    /// Controls whether the `block_time` of a block is also written onto the
+    /// account and transaction documents of its slot once the block metadata
+    /// arrives. The default is false.
+    pub backfill_block_time: Option<bool>,
+
+    /// Controls whether the data of accounts owned by programs with a built-in
+    /// decoder (SPL Token and Token-2022) is decoded. The default is false.
+    pub decode_accounts: Option<bool>,
+
+    /// Per-owner decoder settings, adding owners or overriding the decoder and
+    /// the handling of decode failures of built-in owners.
+    pub account_decoders: Option<Vec<AccountDecoderConfig>>,
+
+    /// Enables the replication friendly write profile, which caps the size of
+    /// bulk inserts and of stored account data and paces bulk inserts so
+    /// secondaries can keep up. Limits set explicitly take precedence over the
+    /// profile defaults. The default is false.
+    pub replication_friendly: Option<bool>,
+
+    /// Upper bound in bytes of one bulk insert of startup accounts, on top of
+    /// `batch_size`. The profile default is 1MB.
+    pub max_batch_bytes: Option<usize>,
+
+    /// Upper bound of the write rate of bulk inserts in bytes per second.
+    /// The profile default is 8MB/s.
+    pub max_bulk_write_bytes_per_second: Option<usize>,
+
+    /// Account data larger than this many bytes is not stored, the document keeps
+    /// the data length and a `data_omitted` flag. The profile default is 1MB.
+    pub max_account_data_bytes: Option<usize>,
+
+    /// The write concern of all collections without an override, e.g.
+    /// `{"w": "majority", "journal": true, "w_timeout_ms": 5000}`. Unset uses the
+    /// connection string or server default.
+    pub write_concern: Option<WriteConcernConfig>,
+
+    /// Write concern overrides by collection name, so durability is paid for only
+    /// where consumers need it.
+    pub collection_write_concerns: Option<HashMap<String, WriteConcernConfig>>,
+
+    /// Owners whose account update rate is watched for sudden spikes or
+    /// silences, reported as metrics and stored in the alerts collection.
+    pub rate_anomaly_detection: Option<Vec<OwnerRateConfig>>,
+
+    /// Controls whether documents that could not be written are kept in the
+    /// `dead_letters` collection together with the error. The default is false.
+    pub store_dead_letters: Option<bool>,
+
+    /// The log format, `text` or `json`. JSON lines carry the worker, slot,
+    /// collection and batch being worked on. The default is text.
+    pub log_format: Option<LogFormat>,
+
+    /// Accounts, by pubkey or owner, whose data is replaced with its SHA-256 hash
+    /// before it is stored or published, e.g.
+    /// `{"accounts": ["<pubkey>"], "owners": ["<program-id>"]}`.
+    pub redaction: Option<RedactionConfig>,
+
+    /// Names this plugin instance when several run on one validator, e.g. with
+    /// disjoint selectors. The instance writes to its own database
+    /// `<database>_<instance_name>`, tags its metrics with `instance` and names
+    /// itself in JSON logs.
+    pub instance_name: Option<String>,
+
+    /// When set, a message listing the pubkeys updated in a slot is POSTed to a
+    /// webhook once the slot is rooted or abandoned, e.g.
+    /// `{"webhook_url": "https://cache.internal/invalidate"}`.
+    pub cache_invalidation: Option<CacheInvalidationConfig>,
+
+    /// Controls whether every logged transaction is indexed by the token mints it
+    /// touched in the `token_mint_transactions` collection. The default is false.
+    pub index_token_mint_transactions: Option<bool>,
+
+    /// Controls whether observed associated token accounts are mapped to their
+    /// wallet owner and mint in the `associated_token_accounts` collection. The
+    /// default is false.
+    pub index_associated_token_accounts: Option<bool>,
+
+    /// Controls whether the number and data bytes of the account updates of every
+    /// slot are stored on its slot document when the slot is rooted. The default
+    /// is false.
+    pub track_slot_account_writes: Option<bool>,
+
+    /// Built-in transforms applied in order to every document before it is
+    /// written, e.g. `[{"drop_fields": {"collections": ["accounts"], "fields":
+    /// ["data"]}}, {"sample": {"collections": ["transaction"], "percent": 10}}]`.
+    pub middleware: Option<Vec<MiddlewareConfig>>,
+
+    /// When the query indexes are built: `immediate` at load, `deferred` after the
+    /// startup snapshot is loaded, or `rebuild` to also drop existing ones at load.
+    /// The indexes are not managed by the plugin when unset.
+    pub startup_index_build: Option<StartupIndexBuild>,
+
+    /// The field names of the account documents, `standard` or `compact`. The
+    /// compact profile uses the short names documented in the `schema` module. The
+    /// default is standard.
+    pub schema_profile: Option<SchemaProfile>,
+
+    /// Controls whether every persisted document carries a `_meta` envelope with
+    /// the schema version, source validator, plugin version and ingest time. The
+    /// default is false.
+    pub document_envelope: Option<bool>,
+
+    /// The source validator named in the envelope, the host name by default.
+    pub source_validator: Option<String>,
+
+    /// How program binaries, i.e. executable accounts and upgradeable loader
+    /// ProgramData and Buffer accounts, are stored: `store`, `skip_data`,
+    /// `hash_only` or `programs_collection`. The default is store.
+    pub program_accounts: Option<ProgramAccountMode>,
+
+    /// When set, the config file is checked for changes every this many seconds and
+    /// the `accounts_selector` and `transaction_selector` sections are reloaded
+    /// without restarting the validator.
+    pub selector_reload_interval_secs: Option<u64>,
+
+    /// Accounts whose updates are never batched, are written one by one with a
+    /// majority, journaled write concern and are published as soon as they are
+    /// written, also during startup.
+    pub watchlist: Option<Vec<String>>,
+
+    /// Caps the transactions stored per slot, e.g.
+    /// `{"max_transactions_per_slot": 2000, "max_bytes_per_slot": 16777216}`.
+    /// Non-vote and succeeded transactions are kept first.
+    pub transaction_slot_cap: Option<TransactionCapConfig>,
+
+    /// Controls whether the slots that lose their fork are stored as `dead` or
+    /// `abandoned`, with the `competing_root` that won over them. The default is
+    /// false.
+    pub track_forks: Option<bool>,
+
+    /// How updates of accounts drained to zero lamports are handled: `store`,
+    /// `skip`, `tombstone` or `delete`. The default is store.
+    pub closed_accounts: Option<ClosedAccountMode>,
+
+    /// Checks periodically that the indexes the plugin creates still exist, e.g.
+    /// `{"action": "recreate", "interval_secs": 600}`. Unset, no check is made.
+    pub schema_verification: Option<SchemaVerificationConfig>,
+
+    /// Batching of the token owner index writes, e.g.
+    /// `{"batch_size": 5000, "flush_interval_ms": 1000}`.
+    pub token_owner_index_batch: Option<TokenIndexBatchConfig>,
+
+    /// Batching of the token mint index writes, like `token_owner_index_batch`.
+    pub token_mint_index_batch: Option<TokenIndexBatchConfig>,
+
+    /// Rules thinning out the updates of high-churn accounts, e.g.
+    /// `[{"owners": ["<program>"], "every_n_slots": 10}]`.
+    pub sampling: Option<Vec<SamplingRuleConfig>>,
+
+    /// Builds the account selector from the rules of a MongoDB collection instead of
+    /// `accounts_selector`, e.g. `{"collection": "selector_rules", "poll_interval_secs": 30}`.
+    pub selector_rules: Option<SelectorRulesConfig>,
+
+    /// Stores the body of a transaction message once in the messages collection, keyed
+    /// by its message hash, instead of in every transaction carrying it.
+    pub deduplicate_transaction_messages: Option<bool>,
+
+    /// Drops vote transactions before they are serialized, whatever the transaction
+    /// selector says.
+    pub skip_vote_transactions: Option<bool>,
+
+    /// Buffers the steady-state account updates of a slot and writes them in batches
+    /// at a slot status, e.g. `{"flush_on": "confirmed", "max_batch_size": 1000}`.
+    pub slot_batching: Option<SlotBatchingConfig>,
+
+    /// Stores `data_hash`, the blake3 hash of the data, on every account document so
+    /// pollers can tell whether an account changed without reading its data.
+    pub hash_account_data: Option<bool>,
+
+    /// Stores transactions that failed. When false they are dropped before they are
+    /// serialized, whatever the transaction selector says. The default is true.
+    pub store_failed_transactions: Option<bool>,
+
+    /// Notifications of slots before this one are ignored: accounts, transactions,
+    /// slot statuses and block metadata alike.
+    pub start_slot: Option<u64>,
+
+    /// Notifications of slots after this one are ignored, like before `start_slot`.
+    pub end_slot: Option<u64>,
+
+    /// Instruction layouts per program, decoding the top level instructions of
+    /// transactions into `decoded_instructions`.
+    pub instruction_layouts: Option<Vec<ProgramInstructionLayouts>>,
+
+    /// Periodically removes the documents of slots that were never rooted, e.g.
+    /// `{"depth": 1000, "interval_secs": 600, "dry_run": false}`.
+    pub dead_slot_gc: Option<DeadSlotGcConfig>,
+
+    /// When account and transaction notifications may block the validator on a full
+    /// queue and for how long, e.g. `{"accounts": {"max_block_ms": 50}}`. Unset, they
+    /// wait as long as it takes.
+    pub backpressure: Option<BackpressureConfig>,
+
+    /// How the accounts collection keeps the versions of an account: `append`
+    /// inserts every update, `current_state` keeps one document per pubkey, replaced
+    /// by newer updates only. The default is append.
+    pub account_storage: Option<AccountStorageMode>,
+
+    /// Journals the pipeline metrics to a local file every second, e.g.
+    /// `{"path": "/var/log/geyser-mongodb-metrics.jsonl"}`.
+    pub metrics_journal: Option<MetricsJournalConfig>,
+
+    /// Exports the metrics to Prometheus and StatsD as well, e.g.
+    /// `{"prometheus_bind_address": "0.0.0.0:9464", "statsd_address": "127.0.0.1:8125"}`.
+    pub metrics_exporters: Option<MetricsExportersConfig>,
+
+    /// With `store_account_historical_data`, the versions of an account kept in the
+    /// history at most, older ones are deleted in the background. Unbounded by default,
+    /// not supported with `account_history_bucket_size`.
+    pub account_history_max_versions: Option<usize>,
+
+    /// Seconds between two runs of the `account_history_max_versions` pruner. The
+    /// default is 60.
+    pub account_history_prune_interval_secs: Option<u64>,
+
+    /// The clock of `notified_at`, `ingested_at` and the persist latencies:
+    /// "monotonic" (the default) or "system".
+    pub clock_source: Option<ClockSource>,
+
+    /// The precision of the stored timestamps: "millis" (the default) or "seconds".
+    pub timestamp_precision: Option<TimestampPrecision>,
+
+    /// How writes failing with a transient error, e.g. during a primary election or
+    /// a network failure, are retried before they are reported.
+    pub write_retry: Option<WriteRetryConfig>,
+
+    /// The passphrase of an encrypted private key in `client_key`, or in
+    /// `client_cert` when it is a combined PEM file. The key must be in the PKCS#8
+    /// encrypted format, it is decrypted by the driver.
+    pub client_key_passphrase: Option<String>,
+
+    /// Skips the verification of the server certificate, `server_ca` is then
+    /// optional. For test clusters only, the default is false.
+    pub allow_invalid_certificates: Option<bool>,
+
+    /// Stamps transactions with the `epoch` of their slot and, once the block
+    /// metadata of the slot arrives, its `leader`. Disabled by default.
+    pub transaction_enrichment: Option<TransactionEnrichmentConfig>,
+
+    /// The password of `user`. Prefer `password_file` to keep it out of the config.
+    pub password: Option<String>,
+
+    /// A file holding the password of `user`, a trailing line break is ignored.
+    pub password_file: Option<String>,
+
+    /// The database `user` is defined in, the default is "admin".
+    pub auth_db: Option<String>,
+
+    /// "SCRAM-SHA-256", "SCRAM-SHA-1", "MONGODB-AWS" or "MONGODB-X509". Negotiated
+    /// with the server by default; "MONGODB-AWS" requires the `aws-auth` feature and
+    /// "MONGODB-X509" `use_ssl` with a `client_cert`.
+    pub auth_mechanism: Option<AuthMechanismConfig>,
+
+    /// Controls whether program derived addresses are recorded in the `pda_registry`
+    /// collection by owning program. The default is false.
+    pub index_program_derived_addresses: Option<bool>,
+
+    /// Deletes account history past a retention window, set per account and owner
+    /// program. Requires `store_account_historical_data`. Unset by default.
+    pub history_retention: Option<HistoryRetentionConfig>,
+
+    /// Stores on every rooted slot document the `ancestors` array of the rooted slots
+    /// before it, at most this many. Unset by default.
+    pub slot_ancestors_depth: Option<usize>,
+
+    /// Appends the slot status notifications and snapshots of the metrics to
+    /// time-series collections, on MongoDB 5.0 or later. Unset by default.
+    pub time_series: Option<TimeSeriesConfig>,
+
+    /// Creates the transactions collection, when missing, as a capped collection of
+    /// this many bytes, keeping only the most recent transactions. Unset by default.
+    pub transactions_capped_size_bytes: Option<u64>,
+
+    /// Stores the derived `rent_exempt`, `on_curve` and `pda_likely` flags on every
+    /// account document. The default is false.
+    pub store_account_flags: Option<bool>,
+
+    /// Stores the data of accounts whose documents would exceed a size threshold in
+    /// GridFS, keeping a reference in the account document. Unset by default.
+    pub account_data_overflow: Option<AccountDataOverflowConfig>,
+
+    /// Compacts the old history of frequently updated accounts by sampling or delta
+    /// compression. Unset by default.
+    pub history_compaction: Option<HistoryCompactionConfig>,
+
+    /// Shrinks transaction documents above a size threshold with a chain of
+    /// downgrades, or skips them. Unset by default.
+    pub oversized_transactions: Option<OversizedTransactionConfig>,
+
+    /// The ingestion profile presetting the defaults of the other keys: "explorer",
+    /// "token-indexer", "program-debugger" or "minimal". Unset by default.
+    pub profile: Option<IngestionProfile>,
+
+    /// Whether the indexes the writes of the plugin need are created when it loads.
+    /// Operators managing the indexes themselves set it to false, missing ones are
+    /// then only logged. The default is true.
+    pub create_indexes: Option<bool>,
+
+    /// Installs `$jsonSchema` validators on the accounts, transactions and slots
+    /// collections when the plugin loads. Unset by default.
+    pub schema_validation: Option<SchemaValidationConfig>,
+
+    /// Shards the accounts, account history and transactions collections on the
+    /// configured keys when connected to a mongos. Unset by default.
+    pub sharding: Option<ShardingConfig>,
+
+    /// Stores a document per token account whose balance a transaction changed, from
+    /// its pre and post token balances, in `token_balance_changes`. False by default.
+    pub index_token_balance_changes: Option<bool>,
+
+    /// Skips the accounts of the startup snapshot, only the updates after startup are
+    /// stored. Reloaded at run time with the selectors when
+    /// `selector_reload_interval_secs` is set. False by default.
+    pub skip_startup_accounts: Option<bool>,
+
+    /// Days the documents of a collection are kept, by collection name, e.g.
+    /// `{"transactions": 30, "account_audit": 7}`. Expired by TTL indexes on
+    /// `ingested_at`. Unset by default, nothing expires.
+    pub retention_days: Option<HashMap<String, u64>>,
 }
 
 #[derive(Error, Debug)]
@@ -96,13 +500,715 @@ pub enum GeyserPluginMongoDbError {
     #[error("Error preparing data store schema. Error message: ({msg})")]
     DataSchemaError { msg: String },
 
-    #[error("Error preparing data store schema. Error message: ({msg})")]
+    #[error("Invalid plugin configuration. Error message: ({msg})")]
     ConfigurationError { msg: String },
 
     #[error("Replica account V0.0.1 not supported anymore")]
     ReplicaAccountV001NotSupported,
+
+    #[error("Replica account V0.0.2 not supported anymore")]
+    ReplicaAccountV002NotSupported,
+
+    #[error("Failed to serialize {what} ({context}). Error message: ({msg})")]
+    SerializationError {
+        what: String,
+        context: WriteContext,
+        msg: String,
+    },
+
+    #[error("Document for {collection} exceeds the maximum BSON size ({context}). Error message: ({msg})")]
+    DocumentTooLarge {
+        collection: String,
+        context: WriteContext,
+        msg: String,
+    },
+
+    #[error("Bulk write to {collection} wrote {written} of {total} documents ({context}). Error message: ({msg})")]
+    BulkWritePartialFailure {
+        collection: String,
+        written: usize,
+        total: usize,
+        /// The positions in the batch of the documents that were not written.
+        failed_indexes: Vec<usize>,
+        context: WriteContext,
+        msg: String,
+    },
+
+    #[error("Write to {collection} timed out ({context}). Error message: ({msg})")]
+    Timeout {
+        collection: String,
+        context: WriteContext,
+        msg: String,
+    },
+
+    #[error("Write to {collection} failed ({context}). Error message: ({msg})")]
+    WriteError {
+        collection: String,
+        context: WriteContext,
+        msg: String,
+    },
+
+    #[error("Error spilling pending writes to disk. Error message: ({msg})")]
+    SpillError { msg: String },
+
+    #[error("Failed to decode the data of account {pubkey} owned by {owner}. Error message: ({msg})")]
+    DecodeError {
+        pubkey: String,
+        owner: String,
+        msg: String,
+    },
+}
+
+/// The `ConfigurationError` of `msg`, as returned by the plugin callbacks.
+pub(crate) fn to_config_error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::ConfigurationError { msg }))
+}
+
+impl GeyserPluginMongoDbError {
+    /// A short stable name of the variant, used as a metric tag and in dead-letter documents.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::DataStoreConnectionError { .. } => "connect",
+            Self::DataSchemaError { .. } => "schema",
+            Self::ConfigurationError { .. } => "config",
+            Self::ReplicaAccountV001NotSupported | Self::ReplicaAccountV002NotSupported => {
+                "unsupported"
+            }
+            Self::SerializationError { .. } => "serialize",
+            Self::DocumentTooLarge { .. } => "too_large",
+            Self::BulkWritePartialFailure { .. } => "bulk_partial",
+            Self::Timeout { .. } => "timeout",
+            Self::WriteError { .. } => "write",
+            Self::SpillError { .. } => "spill",
+            Self::DecodeError { .. } => "decode",
+        }
+    }
+
+    /// The slot, pubkey and signature the failed operation was about, if any.
+    pub fn context(&self) -> Option<&WriteContext> {
+        match self {
+            Self::SerializationError { context, .. }
+            | Self::DocumentTooLarge { context, .. }
+            | Self::BulkWritePartialFailure { context, .. }
+            | Self::Timeout { context, .. }
+            | Self::WriteError { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+}
+
+impl From<GeyserPluginMongoDbError> for GeyserPluginError {
+    fn from(err: GeyserPluginMongoDbError) -> Self {
+        GeyserPluginError::Custom(Box::new(err))
+    }
+}
+
+/// The slots notifications are ingested for, from `start_slot` through `end_slot`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SlotWindow {
+    pub start_slot: Option<u64>,
+    pub end_slot: Option<u64>,
+}
+
+impl SlotWindow {
+    pub fn from_config(config: &GeyserPluginMongoDBConfig) -> Result<Self, GeyserPluginError> {
+        if let (Some(start_slot), Some(end_slot)) = (config.start_slot, config.end_slot) {
+            if start_slot > end_slot {
+                return Err(GeyserPluginError::Custom(Box::new(
+                    GeyserPluginMongoDbError::ConfigurationError {
+                        msg: format!(
+                            "\"start_slot\" {} is after \"end_slot\" {}",
+                            start_slot, end_slot
+                        ),
+                    },
+                )));
+            }
+        }
+        Ok(Self {
+            start_slot: config.start_slot,
+            end_slot: config.end_slot,
+        })
+    }
+
+    /// Whether notifications of `slot` are ingested, counting those that are not.
+    pub fn contains(&self, slot: u64) -> bool {
+        let contains = self.start_slot.map_or(true, |start_slot| slot >= start_slot)
+            && self.end_slot.map_or(true, |end_slot| slot <= end_slot);
+        if !contains {
+            metrics::inc_counter("geyser-plugin-mongodb-outside-slot-window", 1);
+        }
+        contains
+    }
+}
+
+/// What a write was about, carried in errors so logs and dead-letter documents
+/// name the slot, account or transaction that failed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct WriteContext {
+    pub slot: Option<u64>,
+    pub pubkey: Option<String>,
+    pub signature: Option<String>,
+}
+
+impl WriteContext {
+    pub fn slot(slot: u64) -> Self {
+        Self {
+            slot: Some(slot),
+            ..Self::default()
+        }
+    }
+
+    pub fn account(slot: u64, pubkey: String) -> Self {
+        Self {
+            slot: Some(slot),
+            pubkey: Some(pubkey),
+            ..Self::default()
+        }
+    }
+
+    pub fn transaction(slot: u64, signature: String) -> Self {
+        Self {
+            slot: Some(slot),
+            signature: Some(signature),
+            ..Self::default()
+        }
+    }
+}
+
+impl std::fmt::Display for WriteContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut fields = Vec::with_capacity(3);
+        if let Some(slot) = self.slot {
+            fields.push(format!("slot: {}", slot));
+        }
+        if let Some(pubkey) = &self.pubkey {
+            fields.push(format!("pubkey: {}", pubkey));
+        }
+        if let Some(signature) = &self.signature {
+            fields.push(format!("signature: {}", signature));
+        }
+        if fields.is_empty() {
+            write!(f, "no slot")
+        } else {
+            write!(f, "{}", fields.join(", "))
+        }
+    }
 }
 
 impl GeyserPlugin for GeyserPluginMongodb{
-    
+    fn name(&self) -> &'static str {
+        "GeyserPluginMongodb"
+    }
+
+    /// Do initialization for the MongoDb plugin.
+    ///
+    /// # Format of the config file:
+    /// * The `accounts_selector` section allows the user to controls accounts selections.
+    /// "accounts_selector" : {
+    ///     "accounts" : \["pubkey-1", "pubkey-2", ..., "pubkey-n"\],
+    /// }
+    /// or:
+    /// "accounts_selector" = {
+    ///     "owners" : \["pubkey-1", "pubkey-2", ..., "pubkey-m"\]
+    /// }
+    /// Accounts either satisyfing the accounts condition or owners condition will be selected.
+    /// When only owners is specified,
+    /// all accounts belonging to the owners will be streamed.
+    /// The accounts field supports wildcard to select all accounts:
+    /// "accounts_selector" : {
+    ///     "accounts" : \["*"\],
+    /// }
+    /// "exclude_accounts" and "exclude_owners" remove accounts from a selector,
+    /// whatever its other rules select.
+    /// Sysvars and builtin programs are only selected when named in "accounts" or
+    /// "owners", unless "exclude_sysvars" is false.
+    /// A selector can be narrowed by data filters, like the RPC memcmp filters:
+    /// "accounts_selector" : {
+    ///     "owners" : \["pubkey-1"\],
+    ///     "filters" : \[{ "memcmp" : { "offset" : 32, "bytes" : "pubkey-2" } }\]
+    /// }
+    /// Selectors can be combined with "union", "intersection" and "difference":
+    /// "accounts_selector" : {
+    ///     "difference" : \[{ "owners" : \["pubkey-1"\] }, { "accounts" : \["pubkey-2"\] }\]
+    /// }
+    /// * The `transaction_selector` section allows the user to control transaction selections.
+    /// "transaction_selector" : {
+    ///     "mentions" : \["pubkey-1", "pubkey-2", ..., "pubkey-n"\],
+    /// }
+    /// The mentions field supports "*" or "all" to select all transactions and
+    /// "all_votes" to select all vote transactions. "programs" selects the transactions
+    /// invoking the listed programs, and "votes" set to true or false always or never
+    /// selects vote transactions.
+    /// * "host", optional, specifies the MongoDb server.
+    /// * "user", optional, specifies the MongoDb user.
+    /// * "password" or "password_file", optional, specifies the password of the user.
+    /// * "auth_db", optional, specifies the database the user is defined in.
+    /// * "port", optional, specifies the MongoDb server's port.
+    /// * "connection_str", optional, the custom MongoDb connection string.
+    /// Please refer to https://www.mongodb.com/docs/manual/reference/connection-string/ for the connection configuration.
+    /// When `connection_str` is set, the values in "host", "user" and "port" are ignored. If `connection_str` is not given,
+    /// `host` must be given.
+    /// * "threads" optional, specifies the number of worker threads for the plugin. A thread
+    /// maintains a connection to the MongoDb database. The default is '10'.
+    /// * "batch_size" optional, specifies the batch size of bulk insert when the AccountsDb is created
+    /// from restoring a snapshot. The default is '10'.
+    /// * "panic_on_db_errors", optional, contols if to panic when there are errors replicating data to the
+    /// MongoDb database. The default is 'false'.
+    /// # Examples
+    ///
+    /// {
+    ///    "libpath": "/home/solana/target/release/libcustom_geyser_plugin.so",
+    ///    "host": "host_foo",
+    ///    "user": "solana",
+    ///    "threads": 10,
+    ///    "accounts_selector" : {
+    ///       "owners" : ["9oT9R5ZyRovSVnt37QvVoBttGpNqR3J7unkb567NP8k3"]
+    ///    }
+    /// }
+
+    fn on_load(&mut self, config_file: &str) -> Result<()> {
+        let mut file = File::open(config_file)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        let result: serde_json::Value = serde_json::from_str(&contents)
+            .map_err(|err| GeyserPluginError::ConfigFileReadError {
+                msg: format!("The config file is not in the JSON format: {:?}", err),
+            })
+            .and_then(|result| {
+                apply_profile(result).map_err(|msg| GeyserPluginError::ConfigFileReadError { msg })
+            })?;
+        let config: GeyserPluginMongoDBConfig = serde_json::from_value(result.clone())
+            .map_err(|err| GeyserPluginError::ConfigFileReadError {
+                msg: format!(
+                    "The config file is not in the JSON format expected: {:?}",
+                    err
+                ),
+            })?;
+        // The logger depends on the config, so it is set up once the config is read.
+        setup_logging(config.log_format.unwrap_or_default());
+        validate_instance_name(&config)?;
+        let instance = Instance::new(
+            config.instance_name.clone(),
+            Clock::new(
+                config.clock_source.unwrap_or_default(),
+                config.timestamp_precision.unwrap_or_default(),
+            ),
+        );
+        // The threads spawned while loading work for the instance current here.
+        let _instance = instance::enter(&instance);
+        self.instance = Some(instance);
+        let slot_window = SlotWindow::from_config(&config)?;
+        info!(
+            "Loading plugin {:?} from config_file {:?}",
+            self.name(),
+            config_file
+        );
+
+        if let Some(profile) = config.profile {
+            info!("Using the ingestion profile {:?}", profile);
+        }
+        let accounts_selector = Arc::new(RwLock::new(Self::create_accounts_selector_from_config(
+            &result,
+        )?));
+        let transaction_selector = Arc::new(RwLock::new(
+            Self::create_transaction_selector_from_config(&result)?,
+        ));
+        if let Some(rules_config) = &config.selector_rules {
+            self.selector_rules_poller = Some(SelectorRulesPoller::spawn(
+                &config,
+                rules_config,
+                accounts_selector.clone(),
+            )?);
+        }
+        let skip_startup_accounts = config.skip_startup_accounts.unwrap_or_default();
+        if skip_startup_accounts {
+            info!("Skipping the accounts of the startup snapshot");
+        }
+        self.skip_startup_accounts
+            .store(skip_startup_accounts, Ordering::Relaxed);
+        if let Some(interval) = config.selector_reload_interval_secs {
+            self.selector_reloader = Some(SelectorReloader::spawn(
+                config_file.to_string(),
+                self.selector_rules_poller
+                    .is_none()
+                    .then(|| accounts_selector.clone()),
+                transaction_selector.clone(),
+                self.skip_startup_accounts.clone(),
+                Duration::from_secs(interval.max(1)),
+            ));
+        }
+        self.accounts_selector = Some(accounts_selector);
+        self.transaction_selector = Some(transaction_selector);
+        self.account_selection_counters = Some(AccountSelection::counters());
+        self.transaction_selection_counters = Some(TransactionSelection::counters());
+
+        let (client, batch_optimize_by_skiping_older_slots) =
+            MongoClientBuilder::build_parallel_mongodb_client(&config)?;
+        self.client = Some(client);
+        self.batch_starting_slot = batch_optimize_by_skiping_older_slots;
+        self.slot_window = slot_window;
+
+        Ok(())
+    }
+
+    fn on_unload(&mut self) {
+        let _instance = self.enter_instance();
+        info!("Unloading plugin: {:?}", self.name());
+        if let Some(selector_reloader) = &mut self.selector_reloader {
+            selector_reloader.join();
+        }
+        if let Some(selector_rules_poller) = &mut self.selector_rules_poller {
+            selector_rules_poller.join();
+        }
+
+        match &mut self.client {
+            None => {}
+            Some(client) => {
+                client.join().unwrap();
+            }
+        }
+    }
+
+    fn update_account(
+        &self,
+        account: ReplicaAccountInfoVersions,
+        slot: u64,
+        is_startup: bool,
+    ) -> Result<()> {
+        let _instance = self.enter_instance();
+        if is_startup && self.skip_startup_accounts.load(Ordering::Relaxed) {
+            metrics::inc_counter("geyser-plugin-mongodb-startup-accounts-skipped", 1);
+            if let Some(client) = &self.client {
+                client.skip_startup_account();
+            }
+            return Ok(());
+        }
+        // skip updating account on startup of batch_optimize_by_skiping_older_slots
+        // is configured
+        if is_startup
+            && self
+                .batch_starting_slot
+                .map(|slot_limit| slot < slot_limit)
+                .unwrap_or(false)
+        {
+            return Ok(());
+        }
+        if !self.slot_window.contains(slot) {
+            return Ok(());
+        }
+
+        let mut measure_all = Measure::start("geyser-plugin-mongodb-update-account-main");
+        match account {
+            ReplicaAccountInfoVersions::V0_0_1(_) => {
+                return Err(GeyserPluginError::Custom(Box::new(
+                    GeyserPluginMongoDbError::ReplicaAccountV001NotSupported,
+                )));
+            }
+            ReplicaAccountInfoVersions::V0_0_2(_) => {
+                return Err(GeyserPluginError::Custom(Box::new(
+                    GeyserPluginMongoDbError::ReplicaAccountV002NotSupported,
+                )));
+            }
+            ReplicaAccountInfoVersions::V0_0_3(account) => {
+                let mut measure_select =
+                    Measure::start("geyser-plugin-mongodb-update-account-select");
+                if let (Some(accounts_selector), Some(counters)) =
+                    (&self.accounts_selector, &self.account_selection_counters)
+                {
+                    if !accounts_selector.read().unwrap().is_account_selected(
+                        counters,
+                        account.pubkey,
+                        account.owner,
+                        account.lamports,
+                        account.executable,
+                        account.data,
+                    ) {
+                        return Ok(());
+                    }
+                } else {
+                    return Ok(());
+                }
+                measure_select.stop();
+                metrics::record(
+                    "geyser-plugin-mongodb-update-account-select-us",
+                    measure_select.as_us(),
+                );
+
+                debug!(
+                    "Updating account {:?} with owner {:?} at slot {:?} using account selector {:?}",
+                    bs58::encode(account.pubkey).into_string(),
+                    bs58::encode(account.owner).into_string(),
+                    slot,
+                    self.accounts_selector.as_ref().unwrap().read().unwrap()
+                );
+
+                match &self.client {
+                    None => {
+                        return Err(GeyserPluginError::Custom(Box::new(
+                            GeyserPluginMongoDbError::DataStoreConnectionError {
+                                msg: "There is no connection to the MongoDb database."
+                                    .to_string(),
+                            },
+                        )));
+                    }
+                    Some(client) => {
+                        let mut measure_update =
+                            Measure::start("geyser-plugin-mongodb-update-account-client");
+                        let result = { client.update_account(account, slot, is_startup) };
+                        measure_update.stop();
+
+                        metrics::record(
+                            "geyser-plugin-mongodb-update-account-client-us",
+                            measure_update.as_us(),
+                        );
+
+                        if let Err(err) = result {
+                            return Err(GeyserPluginError::AccountsUpdateError {
+                                msg: format!("Failed to persist the update of account to the MongoDb database. Error: {:?}", err)
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        measure_all.stop();
+
+        metrics::record("geyser-plugin-mongodb-update-account-main-us", measure_all.as_us());
+
+        Ok(())
+    }
+
+    fn update_slot_status(&self, slot: u64, parent: Option<u64>, status: SlotStatus) -> Result<()> {
+        let _instance = self.enter_instance();
+        info!("Updating slot {:?} at with status {:?}", slot, status);
+        if !self.slot_window.contains(slot) {
+            return Ok(());
+        }
+
+        match &self.client {
+            None => {
+                return Err(GeyserPluginError::Custom(Box::new(
+                    GeyserPluginMongoDbError::DataStoreConnectionError {
+                        msg: "There is no connection to the MongoDb database.".to_string(),
+                    },
+                )));
+            }
+            Some(client) => {
+                let result = client.update_slot_status(slot, parent, status);
+
+                if let Err(err) = result {
+                    return Err(GeyserPluginError::SlotStatusUpdateError{
+                        msg: format!("Failed to persist the update of slot to the MongoDb database. Error: {:?}", err)
+                    });
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn notify_end_of_startup(&self) -> Result<()> {
+        let _instance = self.enter_instance();
+        info!("Notifying the end of startup for accounts notifications");
+        match &self.client {
+            None => {
+                return Err(GeyserPluginError::Custom(Box::new(
+                    GeyserPluginMongoDbError::DataStoreConnectionError {
+                        msg: "There is no connection to the MongoDb database.".to_string(),
+                    },
+                )));
+            }
+            Some(client) => {
+                let result = client.notify_end_of_startup();
+
+                if let Err(err) = result {
+                    return Err(GeyserPluginError::SlotStatusUpdateError{
+                        msg: format!("Failed to notify the end of startup for accounts notifications. Error: {:?}", err)
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if the plugin is interested in account data
+    /// Default is true -- if the plugin is not interested in
+    /// account data, please return false.
+    fn account_data_notifications_enabled(&self) -> bool {
+        // Asked once at load, selectors enabled later by a reload receive nothing.
+        // Rules may be added to the collection at any time.
+        self.selector_rules_poller.is_some()
+            || self
+                .accounts_selector
+                .as_ref()
+                .map_or_else(|| false, |selector| selector.read().unwrap().is_enabled())
+    }
+
+    /// Check if the plugin is interested in transaction data
+    fn transaction_notifications_enabled(&self) -> bool {
+        self.transaction_selector
+            .as_ref()
+            .map_or_else(|| false, |selector| selector.read().unwrap().is_enabled())
+    }
+
+    fn notify_transaction(
+        &self,
+        transaction_info: ReplicaTransactionInfoVersions,
+        slot: u64,
+    ) -> Result<()> {
+        let _instance = self.enter_instance();
+        if !self.slot_window.contains(slot) {
+            return Ok(());
+        }
+        match &self.client {
+            None => {
+                return Err(GeyserPluginError::Custom(Box::new(
+                    GeyserPluginMongoDbError::DataStoreConnectionError {
+                        msg: "There is no connection to the MongoDb database.".to_string(),
+                    },
+                )));
+            }
+            Some(client) => match transaction_info {
+                ReplicaTransactionInfoVersions::V0_0_2(transaction_info) => {
+                    if let (Some(transaction_selector), Some(counters)) =
+                        (&self.transaction_selector, &self.transaction_selection_counters)
+                    {
+                        if !transaction_selector.read().unwrap().is_transaction_selected(
+                            counters,
+                            transaction_info.is_vote,
+                            Box::new(transaction_info.transaction.message().account_keys().iter()),
+                            Box::new(invoked_programs(
+                                transaction_info.transaction,
+                                transaction_info.transaction_status_meta,
+                            )),
+                        ) {
+                            return Ok(());
+                        }
+                    } else {
+                        return Ok(());
+                    }
+
+                    let result = client.log_transaction_info(transaction_info, slot);
+
+                    if let Err(err) = result {
+                        return Err(GeyserPluginError::SlotStatusUpdateError{
+                            msg: format!("Failed to persist the transaction info to the MongoDb database. Error: {:?}", err)
+                        });
+                    }
+                }
+                _ => {
+                    return Err(GeyserPluginError::SlotStatusUpdateError{
+                        msg: "Failed to persist the transaction info to the MongoDb database. Unsupported format.".to_string()
+                    });
+                }
+            },
+        }
+
+        Ok(())
+    }
+
+    fn notify_block_metadata(&self, block_info: ReplicaBlockInfoVersions) -> Result<()> {
+        let _instance = self.enter_instance();
+        match &self.client {
+            None => {
+                return Err(GeyserPluginError::Custom(Box::new(
+                    GeyserPluginMongoDbError::DataStoreConnectionError {
+                        msg: "There is no connection to the MongoDb database.".to_string(),
+                    },
+                )));
+            }
+            Some(client) => match block_info {
+                ReplicaBlockInfoVersions::V0_0_3(block_info) => {
+                    if !self.slot_window.contains(block_info.slot) {
+                        return Ok(());
+                    }
+                    let result = client.update_block_metadata(block_info);
+
+                    if let Err(err) = result {
+                        return Err(GeyserPluginError::SlotStatusUpdateError{
+                            msg: format!("Failed to persist the update of block metadata to the MongoDb database. Error: {:?}", err)
+                        });
+                    }
+                }
+                _ => {
+                    return Err(GeyserPluginError::SlotStatusUpdateError{
+                        msg: "Failed to persist the block metadata to the MongoDb database. Unsupported format.".to_string()
+                    });
+                }
+            },
+        }
+
+        Ok(())
+    }
+}
+
+/// The host name of the validator, the default `source_validator`.
+pub(crate) fn metrics_hostname() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .map(|hostname| hostname.trim().to_string())
+        .unwrap_or_else(|_| "localhost".to_string())
+}
+
+impl GeyserPluginMongodb {
+    /// Makes the instance of this plugin current for a call of the validator.
+    fn enter_instance(&self) -> Option<InstanceGuard> {
+        self.instance.as_ref().map(instance::enter)
+    }
+
+    pub(crate) fn create_accounts_selector_from_config(
+        config: &serde_json::Value,
+    ) -> Result<SelectorSet> {
+        SelectorSet::from_config(&config["accounts_selector"])
+    }
+
+    pub(crate) fn create_transaction_selector_from_config(
+        config: &serde_json::Value,
+    ) -> Result<TransactionSelector> {
+        let transaction_selector = &config["transaction_selector"];
+
+        if transaction_selector.is_null() {
+            Ok(TransactionSelector::default())
+        } else {
+            let keys = |field: &str| -> Result<Vec<String>> {
+                let Some(keys) = transaction_selector[field].as_array() else {
+                    return Ok(Vec::default());
+                };
+                keys.iter()
+                    .map(|key| {
+                        key.as_str().map(str::to_string).ok_or_else(|| {
+                            GeyserPluginMongoDbError::ConfigurationError {
+                                msg: format!(
+                                    "\"{}\" of \"transaction_selector\" must hold strings",
+                                    field
+                                ),
+                            }
+                            .into()
+                        })
+                    })
+                    .collect()
+            };
+            TransactionSelector::new(
+                &keys("mentions")?,
+                &keys("programs")?,
+                transaction_selector["votes"].as_bool(),
+            )
+        }
+    }
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[no_mangle]
+#[allow(improper_ctypes_definitions)]
+/// # Safety
+///
+/// This function returns the GeyserPluginMongodb pointer as trait GeyserPlugin.
+pub unsafe extern "C" fn _create_plugin() -> *mut dyn GeyserPlugin {
+    let plugin = GeyserPluginMongodb::new();
+    let plugin: Box<dyn GeyserPlugin> = Box::new(plugin);
+    Box::into_raw(plugin)
 }
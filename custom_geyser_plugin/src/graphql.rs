@@ -0,0 +1,292 @@
+/// GraphQL schema over the accounts and transactions stored by the plugin.
+///
+/// The object types mirror the stored documents (see `build_account_document` and the
+/// transaction documents in mongodb_client), resolving nested fields lazily so a query
+/// like account -> transactions -> instructions only reads what it selects. Accounts
+/// are read with the `schema_profile` they were stored with.
+use {
+    crate::{
+        mongodb_client::{ACCOUNTS_COLLECTION, MESSAGES_COLLECTION, TRANSACTIONS_COLLECTION},
+        schema::{expand_account_document, SchemaProfile},
+    },
+    async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Result, Schema},
+    base64::{engine::general_purpose::STANDARD, Engine},
+    futures::TryStreamExt,
+    mongodb::{
+        bson::{doc, Bson, Document},
+        Database,
+    },
+};
+
+const MAX_TRANSACTIONS_LIMIT: i64 = 1000;
+
+pub type ExplorerSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+pub fn build_schema(database: Database, profile: SchemaProfile) -> ExplorerSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(database)
+        .data(profile)
+        .finish()
+}
+
+fn get_i64(document: &Document, key: &str) -> i64 {
+    match document.get(key) {
+        Some(Bson::Int64(value)) => *value,
+        Some(Bson::Int32(value)) => *value as i64,
+        _ => 0,
+    }
+}
+
+fn get_string(document: &Document, key: &str) -> Option<String> {
+    document.get_str(key).ok().map(str::to_string)
+}
+
+async fn find_transactions(
+    database: &Database,
+    filter: Document,
+    limit: i64,
+) -> Result<Vec<Transaction>> {
+    let transactions: Vec<Document> = database
+        .collection::<Document>(TRANSACTIONS_COLLECTION)
+        .find(filter)
+        .sort(doc! { "slot": -1, "index": -1 })
+        .limit(limit.clamp(1, MAX_TRANSACTIONS_LIMIT))
+        .await?
+        .try_collect()
+        .await?;
+    Ok(transactions.into_iter().map(Transaction).collect())
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// The latest stored version of an account.
+    async fn account(&self, ctx: &Context<'_>, pubkey: String) -> Result<Option<Account>> {
+        let database = ctx.data::<Database>()?;
+        let profile = *ctx.data::<SchemaProfile>()?;
+        let account = database
+            .collection::<Document>(ACCOUNTS_COLLECTION)
+            .find_one(doc! { profile.account_field("pubkey"): &pubkey })
+            .sort(doc! {
+                profile.account_field("slot"): -1,
+                profile.account_field("write_version"): -1,
+            })
+            .await?;
+        Ok(account.map(|account| Account(expand_account_document(account))))
+    }
+
+    async fn transaction(
+        &self,
+        ctx: &Context<'_>,
+        signature: String,
+    ) -> Result<Option<Transaction>> {
+        let database = ctx.data::<Database>()?;
+        let transaction = database
+            .collection::<Document>(TRANSACTIONS_COLLECTION)
+            .find_one(doc! { "signature": &signature })
+            .await?;
+        Ok(transaction.map(Transaction))
+    }
+
+    /// The most recent transactions, optionally only those that failed.
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = false)] failed_only: bool,
+        #[graphql(default = 10)] limit: i64,
+    ) -> Result<Vec<Transaction>> {
+        let database = ctx.data::<Database>()?;
+        let filter = if failed_only {
+            doc! { "meta.error": { "$ne": null } }
+        } else {
+            doc! {}
+        };
+        find_transactions(database, filter, limit).await
+    }
+}
+
+pub struct Account(Document);
+
+#[Object]
+impl Account {
+    async fn pubkey(&self) -> Option<String> {
+        get_string(&self.0, "pubkey")
+    }
+
+    async fn owner(&self) -> Option<String> {
+        get_string(&self.0, "owner")
+    }
+
+    async fn lamports(&self) -> i64 {
+        get_i64(&self.0, "lamports")
+    }
+
+    async fn executable(&self) -> bool {
+        self.0.get_bool("executable").unwrap_or_default()
+    }
+
+    async fn rent_epoch(&self) -> i64 {
+        get_i64(&self.0, "rent_epoch")
+    }
+
+    async fn slot(&self) -> i64 {
+        get_i64(&self.0, "slot")
+    }
+
+    async fn write_version(&self) -> i64 {
+        get_i64(&self.0, "write_version")
+    }
+
+    async fn txn_signature(&self) -> Option<String> {
+        get_string(&self.0, "txn_signature")
+    }
+
+    async fn data_len(&self) -> usize {
+        self.0
+            .get_binary_generic("data")
+            .map(|data| data.len())
+            .unwrap_or_default()
+    }
+
+    /// The account data, base64 encoded.
+    async fn data(&self) -> Option<String> {
+        self.0
+            .get_binary_generic("data")
+            .ok()
+            .map(|data| STANDARD.encode(data))
+    }
+
+    /// The most recent transactions referencing this account.
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 10)] limit: i64,
+    ) -> Result<Vec<Transaction>> {
+        let database = ctx.data::<Database>()?;
+        match self.0.get_str("pubkey") {
+            Ok(pubkey) => {
+                find_transactions(database, doc! { "account_keys": pubkey }, limit).await
+            }
+            Err(_) => Ok(Vec::default()),
+        }
+    }
+}
+
+pub struct Transaction(Document);
+
+impl Transaction {
+    fn stored_account_keys(&self) -> Vec<String> {
+        self.0
+            .get_array("account_keys")
+            .map(|keys| {
+                keys.iter()
+                    .filter_map(|key| key.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    fn meta(&self) -> Option<&Document> {
+        self.0.get_document("meta").ok()
+    }
+}
+
+#[Object]
+impl Transaction {
+    async fn signature(&self) -> Option<String> {
+        get_string(&self.0, "signature")
+    }
+
+    async fn slot(&self) -> i64 {
+        get_i64(&self.0, "slot")
+    }
+
+    async fn index(&self) -> i64 {
+        get_i64(&self.0, "index")
+    }
+
+    async fn is_vote(&self) -> bool {
+        self.0.get_bool("is_vote").unwrap_or_default()
+    }
+
+    async fn fee(&self) -> i64 {
+        self.meta().map(|meta| get_i64(meta, "fee")).unwrap_or_default()
+    }
+
+    /// The error code when the transaction failed.
+    async fn error(&self) -> Option<String> {
+        self.meta()
+            .and_then(|meta| meta.get_document("error").ok())
+            .and_then(|error| get_string(error, "error_code"))
+    }
+
+    async fn log_messages(&self) -> Vec<String> {
+        self.meta()
+            .and_then(|meta| meta.get_array("log_messages").ok())
+            .map(|logs| {
+                logs.iter()
+                    .filter_map(|log| log.as_str().map(str::to_string))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    async fn account_keys(&self) -> Vec<String> {
+        self.stored_account_keys()
+    }
+
+    /// The top level instructions with program and account indexes resolved to pubkeys.
+    async fn instructions(&self, ctx: &Context<'_>) -> Result<Vec<Instruction>> {
+        let account_keys = self.stored_account_keys();
+        let resolve = |index: i64| account_keys.get(index as usize).cloned().unwrap_or_default();
+        // A deduplicated message is stored apart from the transaction.
+        let message = match (self.0.contains_key("instructions"), self.0.get_str("message_hash")) {
+            (false, Ok(message_hash)) => ctx
+                .data::<Database>()?
+                .collection::<Document>(MESSAGES_COLLECTION)
+                .find_one(doc! { "_id": message_hash })
+                .await?,
+            _ => None,
+        };
+        Ok(message
+            .as_ref()
+            .unwrap_or(&self.0)
+            .get_array("instructions")
+            .map(|instructions| {
+                instructions
+                    .iter()
+                    .filter_map(Bson::as_document)
+                    .map(|instruction| Instruction {
+                        program_id: resolve(get_i64(instruction, "program_id_index")),
+                        accounts: instruction
+                            .get_array("accounts")
+                            .map(|accounts| {
+                                accounts
+                                    .iter()
+                                    .filter_map(|index| match index {
+                                        Bson::Int32(index) => Some(resolve(*index as i64)),
+                                        Bson::Int64(index) => Some(resolve(*index)),
+                                        _ => None,
+                                    })
+                                    .collect()
+                            })
+                            .unwrap_or_default(),
+                        data: instruction
+                            .get_binary_generic("data")
+                            .map(|data| bs58::encode(data).into_string())
+                            .unwrap_or_default(),
+                    })
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+}
+
+#[derive(async_graphql::SimpleObject)]
+pub struct Instruction {
+    pub program_id: String,
+    pub accounts: Vec<String>,
+    /// The instruction data, base58 encoded.
+    pub data: String,
+}
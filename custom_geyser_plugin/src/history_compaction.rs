@@ -0,0 +1,381 @@
+/// Compacts the old account history of accounts updated very frequently with data of
+/// an unchanging size, e.g. oracles and AMM pools, keeping every version only for the
+/// recent window.
+///
+/// Every `interval_secs` the compactor looks at the versions older than
+/// `compact_after_days`, mapped to a slot through the block times of the slots
+/// collection as for `history_retention`. The accounts with at least `min_versions`
+/// such versions, all of the same data size, are compacted, at most
+/// `max_accounts_per_run` per run:
+///
+/// - `sample` keeps the first version of every `sample_slots` slots and deletes the
+///   others.
+/// - `delta` keeps every `keyframe_interval`th version whole, as a keyframe, and
+///   replaces the data of the versions in between by `data_delta`, the byte runs
+///   differing from their keyframe, named by `keyframe_slot` and
+///   `keyframe_write_version`. `queries::account_history` rebuilds their data.
+///
+/// Compacted versions carry `compacted`, "sample", "keyframe" or "delta", and are not
+/// compacted again.
+/// Only history stored one document per version is compacted.
+use {
+    crate::{
+        geyser_plugin_mongodb::{
+            to_config_error, GeyserPluginMongoDBConfig, GeyserPluginMongoDbError,
+        },
+        history_retention::cutoff_slot,
+        metrics,
+        mongodb_client::ACCOUNT_HISTORY_COLLECTION,
+        periodic_task::PeriodicTask,
+    },
+    futures::TryStreamExt,
+    log::*,
+    mongodb::{
+        bson::{doc, spec::BinarySubtype, Binary, Bson, Document},
+        Collection, Database,
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::time::Duration,
+};
+
+const DEFAULT_COMPACT_AFTER_DAYS: u64 = 7;
+const DEFAULT_MIN_VERSIONS: u64 = 1000;
+/// About a minute of slots.
+const DEFAULT_SAMPLE_SLOTS: u64 = 150;
+const DEFAULT_KEYFRAME_INTERVAL: u64 = 100;
+const DEFAULT_MAX_ACCOUNTS_PER_RUN: i64 = 100;
+const DEFAULT_COMPACTION_INTERVAL_SECS: u64 = 3600;
+/// The versions deleted or marked by one command.
+const COMPACTION_CHUNK: usize = 1000;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HistoryCompactionMode {
+    /// Keeps one version per `sample_slots` slots.
+    #[default]
+    Sample,
+    /// Stores the versions between keyframes as their differences to the keyframe.
+    Delta,
+}
+
+impl HistoryCompactionMode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            HistoryCompactionMode::Sample => "sample",
+            HistoryCompactionMode::Delta => "delta",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HistoryCompactionConfig {
+    /// "sample" or "delta". The default is "sample".
+    pub mode: Option<HistoryCompactionMode>,
+    /// The age in days of the versions compacted. The default is 7.
+    pub compact_after_days: Option<u64>,
+    /// The old versions an account needs to be compacted. The default is 1000.
+    pub min_versions: Option<u64>,
+    /// The slots per version kept by "sample". The default is 150.
+    pub sample_slots: Option<u64>,
+    /// The versions per keyframe of "delta". The default is 100.
+    pub keyframe_interval: Option<u64>,
+    /// The accounts compacted per run at most. The default is 100.
+    pub max_accounts_per_run: Option<i64>,
+    /// Seconds between two runs. The default is 3600.
+    pub interval_secs: Option<u64>,
+}
+
+fn compaction_error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::DataSchemaError { msg }))
+}
+
+/// The byte runs of `data` differing from `keyframe`, of the same length.
+fn data_delta<'a>(keyframe: &[u8], data: &'a [u8]) -> Vec<(usize, &'a [u8])> {
+    let mut runs = Vec::default();
+    let mut offset = 0;
+    while offset < data.len() {
+        if data[offset] == keyframe[offset] {
+            offset += 1;
+            continue;
+        }
+        let start = offset;
+        while offset < data.len() && data[offset] != keyframe[offset] {
+            offset += 1;
+        }
+        runs.push((start, &data[start..offset]));
+    }
+    runs
+}
+
+struct HistoryCompactor {
+    mode: HistoryCompactionMode,
+    compact_after_days: u64,
+    min_versions: i64,
+    sample_slots: u64,
+    keyframe_interval: u64,
+    max_accounts_per_run: i64,
+}
+
+impl HistoryCompactor {
+    fn new(config: &HistoryCompactionConfig) -> Result<Self, GeyserPluginError> {
+        let sample_slots = config.sample_slots.unwrap_or(DEFAULT_SAMPLE_SLOTS);
+        let keyframe_interval = config.keyframe_interval.unwrap_or(DEFAULT_KEYFRAME_INTERVAL);
+        if sample_slots == 0 || keyframe_interval == 0 {
+            return Err(to_config_error(
+                "\"history_compaction\": sample_slots and keyframe_interval must be positive"
+                    .to_string(),
+            ));
+        }
+        Ok(Self {
+            mode: config.mode.unwrap_or_default(),
+            compact_after_days: config
+                .compact_after_days
+                .unwrap_or(DEFAULT_COMPACT_AFTER_DAYS),
+            min_versions: config.min_versions.unwrap_or(DEFAULT_MIN_VERSIONS) as i64,
+            sample_slots,
+            keyframe_interval,
+            max_accounts_per_run: config
+                .max_accounts_per_run
+                .unwrap_or(DEFAULT_MAX_ACCOUNTS_PER_RUN),
+        })
+    }
+
+    /// The uncompacted versions of `pubkey` below `cutoff`, or of every account.
+    fn old_versions(pubkey: Option<&str>, cutoff: i64) -> Document {
+        let mut filter = doc! {
+            "slot": { "$lt": cutoff },
+            "compacted": { "$exists": false },
+            "data": { "$type": "binData" },
+        };
+        if let Some(pubkey) = pubkey {
+            filter.insert("pubkey", pubkey);
+        }
+        filter
+    }
+
+    /// The accounts with at least `min_versions` old versions, all of one data size.
+    async fn candidates(
+        &self,
+        collection: &Collection<Document>,
+        cutoff: i64,
+    ) -> Result<Vec<String>, GeyserPluginError> {
+        let pipeline = vec![
+            doc! { "$match": Self::old_versions(None, cutoff) },
+            doc! { "$group": {
+                "_id": "$pubkey",
+                "versions": { "$sum": 1 },
+                "min_size": { "$min": { "$binarySize": "$data" } },
+                "max_size": { "$max": { "$binarySize": "$data" } },
+            }},
+            doc! { "$match": {
+                "versions": { "$gte": self.min_versions },
+                "$expr": { "$eq": ["$min_size", "$max_size"] },
+            }},
+            doc! { "$limit": self.max_accounts_per_run },
+        ];
+        let to_error = |err: mongodb::error::Error| {
+            compaction_error(format!("Failed to find the accounts to compact: {}", err))
+        };
+        let candidates: Vec<Document> = collection
+            .aggregate(pipeline)
+            .allow_disk_use(true)
+            .await
+            .map_err(to_error)?
+            .try_collect()
+            .await
+            .map_err(to_error)?;
+        Ok(candidates
+            .into_iter()
+            .filter_map(|candidate| candidate.get_str("_id").ok().map(str::to_string))
+            .collect())
+    }
+
+    async fn run(&self, database: &Database) -> Result<(), GeyserPluginError> {
+        let Some(cutoff) = cutoff_slot(database, self.compact_after_days).await? else {
+            return Ok(());
+        };
+        let collection = database.collection::<Document>(ACCOUNT_HISTORY_COLLECTION);
+        for pubkey in self.candidates(&collection, cutoff).await? {
+            let result = match self.mode {
+                HistoryCompactionMode::Sample => self.sample(&collection, &pubkey, cutoff).await,
+                HistoryCompactionMode::Delta => self.delta(&collection, &pubkey, cutoff).await,
+            };
+            result.map_err(|err| {
+                compaction_error(format!("Failed to compact the history of {}: {}", pubkey, err))
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Keeps the first version of every `sample_slots` slots, deleting the others.
+    async fn sample(
+        &self,
+        collection: &Collection<Document>,
+        pubkey: &str,
+        cutoff: i64,
+    ) -> mongodb::error::Result<()> {
+        let versions: Vec<Document> = collection
+            .find(Self::old_versions(Some(pubkey), cutoff))
+            .sort(doc! { "slot": 1, "write_version": 1 })
+            .projection(doc! { "_id": 1, "slot": 1 })
+            .await?
+            .try_collect()
+            .await?;
+        let mut kept = Vec::default();
+        let mut dropped = Vec::default();
+        let mut window = None;
+        for version in versions {
+            let Some(id) = version.get("_id").cloned() else {
+                continue;
+            };
+            let slot = version.get_i64("slot").unwrap_or_default() as u64;
+            let slot_window = slot / self.sample_slots;
+            if window == Some(slot_window) {
+                dropped.push(id);
+            } else {
+                window = Some(slot_window);
+                kept.push(id);
+            }
+        }
+        for ids in kept.chunks(COMPACTION_CHUNK) {
+            collection
+                .update_many(
+                    doc! { "_id": { "$in": ids.to_vec() } },
+                    doc! { "$set": { "compacted": self.mode.as_str() } },
+                )
+                .await?;
+        }
+        for ids in dropped.chunks(COMPACTION_CHUNK) {
+            collection
+                .delete_many(doc! { "_id": { "$in": ids.to_vec() } })
+                .await?;
+        }
+        debug!("Sampled the history of {}, deleted {} versions", pubkey, dropped.len());
+        metrics::inc_counter("geyser-plugin-mongodb-history-compaction-deleted", dropped.len());
+        Ok(())
+    }
+
+    /// Replaces the data of the versions between keyframes by their differences to
+    /// the keyframe. A version of another size, or differing from its keyframe in
+    /// half of its bytes or more, becomes a keyframe itself.
+    async fn delta(
+        &self,
+        collection: &Collection<Document>,
+        pubkey: &str,
+        cutoff: i64,
+    ) -> mongodb::error::Result<()> {
+        let mut cursor = collection
+            .find(Self::old_versions(Some(pubkey), cutoff))
+            .sort(doc! { "slot": 1, "write_version": 1 })
+            .await?;
+        let mut keyframe: Option<(Bson, Bson, Vec<u8>)> = None;
+        let mut since_keyframe = 0;
+        let mut deltas = 0;
+        while let Some(version) = cursor.try_next().await? {
+            let Some(id) = version.get("_id").cloned() else {
+                continue;
+            };
+            let Ok(data) = version.get_binary_generic("data") else {
+                continue;
+            };
+            let slot = version.get("slot").cloned().unwrap_or(Bson::Null);
+            let write_version = version.get("write_version").cloned().unwrap_or(Bson::Null);
+            let delta = match &keyframe {
+                Some((_, _, keyframe_data))
+                    if since_keyframe < self.keyframe_interval
+                        && keyframe_data.len() == data.len() =>
+                {
+                    let runs = data_delta(keyframe_data, data);
+                    let delta_bytes: usize = runs.iter().map(|(_, bytes)| bytes.len()).sum();
+                    (delta_bytes < data.len() / 2).then_some(runs)
+                }
+                _ => None,
+            };
+            let keyframe_key = keyframe
+                .as_ref()
+                .map(|(keyframe_slot, keyframe_write_version, _)| {
+                    (keyframe_slot.clone(), keyframe_write_version.clone())
+                });
+            let update = match (delta, keyframe_key) {
+                (Some(runs), Some((keyframe_slot, keyframe_write_version))) => {
+                    since_keyframe += 1;
+                    deltas += 1;
+                    let runs: Vec<Document> = runs
+                        .into_iter()
+                        .map(|(offset, bytes)| {
+                            doc! {
+                                "offset": offset as i64,
+                                "bytes": Binary {
+                                    subtype: BinarySubtype::Generic,
+                                    bytes: bytes.to_vec(),
+                                },
+                            }
+                        })
+                        .collect();
+                    doc! {
+                        "$set": {
+                            "data_delta": runs,
+                            "keyframe_slot": keyframe_slot,
+                            "keyframe_write_version": keyframe_write_version,
+                            "compacted": self.mode.as_str(),
+                        },
+                        "$unset": { "data": "" },
+                    }
+                }
+                _ => {
+                    since_keyframe = 1;
+                    keyframe = Some((slot, write_version, data.to_vec()));
+                    doc! { "$set": { "compacted": "keyframe" } }
+                }
+            };
+            collection.update_one(doc! { "_id": id }, update).await?;
+        }
+        debug!("Delta compressed {} versions of {}", deltas, pubkey);
+        metrics::inc_counter("geyser-plugin-mongodb-history-compaction-deltas", deltas);
+        Ok(())
+    }
+}
+
+pub(crate) struct HistoryCompactionWorker {
+    task: PeriodicTask,
+}
+
+impl HistoryCompactionWorker {
+    /// Starts the compactor configured by `history_compaction`, None when unset.
+    pub fn spawn(config: &GeyserPluginMongoDBConfig) -> Result<Option<Self>, GeyserPluginError> {
+        let Some(compaction_config) = &config.history_compaction else {
+            return Ok(None);
+        };
+        if !config.store_account_historical_data.unwrap_or_default() {
+            return Err(to_config_error(
+                "\"history_compaction\" requires \"store_account_historical_data\"".to_string(),
+            ));
+        }
+        if config.account_history_bucket_size.is_some() {
+            return Err(to_config_error(
+                "\"history_compaction\" does not apply to bucketed history, unset \
+                 \"account_history_bucket_size\""
+                    .to_string(),
+            ));
+        }
+        let compactor = HistoryCompactor::new(compaction_config)?;
+        let interval = Duration::from_secs(
+            compaction_config
+                .interval_secs
+                .unwrap_or(DEFAULT_COMPACTION_INTERVAL_SECS),
+        );
+        let task = PeriodicTask::spawn(
+            "mongodb-history-compaction",
+            "compact the account history",
+            interval,
+            config,
+            move |runtime, database| runtime.block_on(compactor.run(database)),
+        );
+        Ok(Some(Self { task }))
+    }
+
+    pub fn join(&mut self) {
+        self.task.join();
+    }
+}
@@ -0,0 +1,164 @@
+/// Caps the versions kept in the account history of each account, with
+/// `account_history_max_versions`.
+///
+/// The workers only record the accounts whose history they appended to, so the cap
+/// costs no round trip on the write path. Every `account_history_prune_interval_secs`
+/// the pruner finds, in one aggregation per chunk of the recorded accounts, the
+/// oldest version each of them keeps, and deletes the older versions of all of them
+/// with one deletion per chunk. Between two runs an account may exceed the cap.
+/// Accounts recorded when the plugin unloads are pruned after their next update.
+use {
+    crate::{
+        geyser_plugin_mongodb::{GeyserPluginMongoDBConfig, GeyserPluginMongoDbError},
+        history_retention::delete_history_versions,
+        metrics,
+        mongodb_client::{DbAccountInfo, ACCOUNT_HISTORY_COLLECTION},
+        periodic_task::PeriodicTask,
+    },
+    futures::TryStreamExt,
+    mongodb::{
+        bson::{doc, Document},
+        Database,
+    },
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::{
+        collections::HashSet,
+        mem,
+        sync::{Arc, Mutex},
+        time::Duration,
+    },
+};
+
+const DEFAULT_ACCOUNT_HISTORY_PRUNE_INTERVAL_SECS: u64 = 60;
+/// The accounts pruned by one aggregation and one deletion.
+const PRUNE_CHUNK: usize = 1000;
+
+/// The accounts whose history grew since the last run of the pruner.
+#[derive(Clone, Default)]
+pub(crate) struct HistoryDepthRecorder {
+    pubkeys: Arc<Mutex<HashSet<String>>>,
+}
+
+impl HistoryDepthRecorder {
+    pub fn record(&self, accounts: &[DbAccountInfo]) {
+        let mut pubkeys = self.pubkeys.lock().unwrap();
+        for account in accounts {
+            pubkeys.insert(bs58::encode(&account.pubkey).into_string());
+        }
+    }
+
+    fn take(&self) -> HashSet<String> {
+        mem::take(&mut *self.pubkeys.lock().unwrap())
+    }
+}
+
+fn pruner_error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::DataSchemaError { msg }))
+}
+
+/// Deletes the versions of `pubkeys` older than their `max_versions` newest ones.
+async fn prune(
+    database: &Database,
+    pubkeys: &[String],
+    max_versions: usize,
+) -> mongodb::error::Result<u64> {
+    let collection = database.collection::<Document>(ACCOUNT_HISTORY_COLLECTION);
+    // The oldest version kept of every account holding more than `max_versions`.
+    let oldest_kept: Vec<Document> = collection
+        .aggregate(vec![
+            doc! { "$match": {
+                "pubkey": { "$in": pubkeys.to_vec() },
+                "slot": { "$exists": true },
+            }},
+            doc! { "$sort": { "pubkey": 1, "slot": -1, "write_version": -1 } },
+            doc! { "$group": {
+                "_id": "$pubkey",
+                "versions": { "$push": { "slot": "$slot", "write_version": "$write_version" } },
+            }},
+            doc! { "$project": {
+                "oldest_kept": { "$arrayElemAt": ["$versions", max_versions as i64 - 1] },
+            }},
+            doc! { "$match": { "oldest_kept": { "$exists": true } } },
+        ])
+        .await?
+        .try_collect()
+        .await?;
+    let older: Vec<Document> = oldest_kept
+        .iter()
+        .filter_map(|account| {
+            let pubkey = account.get_str("_id").ok()?;
+            let version = account.get_document("oldest_kept").ok()?;
+            let slot = version.get_i64("slot").ok()?;
+            let write_version = version.get_i64("write_version").ok()?;
+            Some(doc! {
+                "pubkey": pubkey,
+                "$or": [
+                    { "slot": { "$lt": slot } },
+                    { "slot": slot, "write_version": { "$lt": write_version } },
+                ],
+            })
+        })
+        .collect();
+    if older.is_empty() {
+        return Ok(0);
+    }
+    delete_history_versions(&collection, doc! { "$or": older }).await
+}
+
+pub(crate) struct HistoryDepthPruner {
+    recorder: HistoryDepthRecorder,
+    task: PeriodicTask,
+}
+
+impl HistoryDepthPruner {
+    /// Starts the pruner configured by `account_history_max_versions`, None when
+    /// unset.
+    pub fn spawn(config: &GeyserPluginMongoDBConfig) -> Option<Self> {
+        let max_versions = config.account_history_max_versions?.max(1);
+        let interval = Duration::from_secs(
+            config
+                .account_history_prune_interval_secs
+                .unwrap_or(DEFAULT_ACCOUNT_HISTORY_PRUNE_INTERVAL_SECS),
+        );
+        let recorder = HistoryDepthRecorder::default();
+        let pending = recorder.clone();
+        let task = PeriodicTask::spawn(
+            "mongodb-history-depth",
+            "cap the account history",
+            interval,
+            config,
+            move |runtime, database| {
+                let pubkeys: Vec<String> = pending.take().into_iter().collect();
+                for (index, chunk) in pubkeys.chunks(PRUNE_CHUNK).enumerate() {
+                    match runtime.block_on(prune(database, chunk, max_versions)) {
+                        Ok(pruned) => metrics::inc_counter(
+                            "geyser-plugin-mongodb-account-history-pruned",
+                            pruned as usize,
+                        ),
+                        Err(err) => {
+                            // The accounts not pruned are retried by the next run.
+                            let remaining = &pubkeys[index * PRUNE_CHUNK..];
+                            pending.pubkeys.lock().unwrap().extend(remaining.iter().cloned());
+                            return Err(pruner_error(format!(
+                                "Failed to cap the history of {} accounts: {}",
+                                remaining.len(),
+                                err
+                            )));
+                        }
+                    }
+                }
+                Ok(())
+            },
+        );
+        Some(Self { recorder, task })
+    }
+
+    /// The handle the workers record the accounts whose history grew through.
+    pub fn recorder(&self) -> HistoryDepthRecorder {
+        self.recorder.clone()
+    }
+
+    pub fn join(&mut self) {
+        self.task.join();
+    }
+}
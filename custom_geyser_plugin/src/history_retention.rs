@@ -0,0 +1,311 @@
+/// Deletes account history older than a retention window, chosen per account or
+/// per owner program.
+///
+/// A rule lists accounts or owners with the days their history is kept, forever when
+/// `retain_days` is left out; the history of everything else is kept for
+/// `default_retain_days`, forever when unset. The most specific rule applies: a rule
+/// naming an account takes precedence over a rule naming its owner, which takes
+/// precedence over the default. Every `interval_secs` the pruner maps each window to
+/// the newest slot whose block time is older than it, from the slots collection, and
+/// deletes the versions of the matching accounts below that slot. Bucketed history is
+/// deleted by whole buckets, once the newest version of a bucket is out of the window.
+/// A keyframe of delta compacted history is kept while versions in the window refer
+/// to it.
+use {
+    crate::{
+        accounts_selector::resolve_owner_alias,
+        geyser_plugin_mongodb::{
+            to_config_error, GeyserPluginMongoDBConfig, GeyserPluginMongoDbError,
+        },
+        metrics,
+        mongodb_client::{ACCOUNT_HISTORY_COLLECTION, SLOTS_COLLECTION},
+        periodic_task::PeriodicTask,
+    },
+    futures::TryStreamExt,
+    log::*,
+    mongodb::{
+        bson::{self, doc, Bson, Document},
+        Collection, Database,
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_sdk::pubkey::Pubkey,
+    std::{collections::HashSet, str::FromStr, time::Duration},
+};
+
+const DEFAULT_HISTORY_RETENTION_INTERVAL_SECS: u64 = 3600;
+const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HistoryRetentionRuleConfig {
+    /// The accounts the rule applies to.
+    pub accounts: Option<Vec<String>>,
+    /// The owner programs, or their aliases, whose accounts the rule applies to.
+    pub owners: Option<Vec<String>>,
+    /// The days the history is kept, forever when unset.
+    pub retain_days: Option<u64>,
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct HistoryRetentionConfig {
+    /// The days the history of accounts matching no rule is kept, forever when unset.
+    pub default_retain_days: Option<u64>,
+    /// The overrides by account and owner.
+    pub rules: Option<Vec<HistoryRetentionRuleConfig>>,
+    /// Seconds between two runs. The default is 3600.
+    pub interval_secs: Option<u64>,
+}
+
+/// What one deletion of the pruner removes: the history matching `filter` older than
+/// `retain_days`.
+#[derive(Debug)]
+struct RetentionScope {
+    description: String,
+    filter: Document,
+    retain_days: u64,
+}
+
+/// The deletions implementing `config`, one per rule with a window and one for the
+/// default. The filters exclude the accounts and owners of more specific rules.
+fn retention_scopes(
+    config: &HistoryRetentionConfig,
+) -> Result<Vec<RetentionScope>, GeyserPluginError> {
+    let rules = config.rules.clone().unwrap_or_default();
+    let mut all_accounts = HashSet::new();
+    let mut all_owners = HashSet::new();
+    for rule in &rules {
+        for account in rule.accounts.iter().flatten() {
+            Pubkey::from_str(account).map_err(|err| {
+                to_config_error(format!(
+                    "Invalid account {} in \"history_retention\": {}",
+                    account, err
+                ))
+            })?;
+            if !all_accounts.insert(account.clone()) {
+                return Err(to_config_error(format!(
+                    "Account {} is in several \"history_retention\" rules",
+                    account
+                )));
+            }
+        }
+        for owner in rule.owners.iter().flatten() {
+            let program = resolve_owner_alias(owner).to_string();
+            Pubkey::from_str(&program).map_err(|err| {
+                to_config_error(format!(
+                    "Invalid owner {} in \"history_retention\": {}",
+                    owner, err
+                ))
+            })?;
+            if !all_owners.insert(program) {
+                return Err(to_config_error(format!(
+                    "Owner {} is in several \"history_retention\" rules",
+                    owner
+                )));
+            }
+        }
+        if rule.accounts.iter().flatten().count() + rule.owners.iter().flatten().count() == 0 {
+            return Err(to_config_error(
+                "A \"history_retention\" rule needs \"accounts\" or \"owners\"".to_string(),
+            ));
+        }
+    }
+    let all_accounts: Vec<String> = all_accounts.into_iter().collect();
+    let all_owners: Vec<String> = all_owners.into_iter().collect();
+
+    let mut scopes = Vec::default();
+    for rule in &rules {
+        let Some(retain_days) = rule.retain_days else {
+            continue;
+        };
+        let accounts: Vec<String> = rule.accounts.iter().flatten().cloned().collect();
+        let owners: Vec<String> = rule
+            .owners
+            .iter()
+            .flatten()
+            .map(|owner| resolve_owner_alias(owner).to_string())
+            .collect();
+        // Versions stored one per document carry the owner, buckets per version.
+        let owned = doc! {
+            "pubkey": { "$nin": all_accounts.clone() },
+            "$or": [
+                { "owner": { "$in": owners.clone() } },
+                { "versions.owner": { "$in": owners.clone() } },
+            ],
+        };
+        let filter = match (accounts.is_empty(), owners.is_empty()) {
+            (false, true) => doc! { "pubkey": { "$in": accounts.clone() } },
+            (true, false) => owned,
+            _ => doc! { "$or": [{ "pubkey": { "$in": accounts.clone() } }, owned] },
+        };
+        scopes.push(RetentionScope {
+            description: format!("accounts {:?} and owners {:?}", accounts, owners),
+            filter,
+            retain_days,
+        });
+    }
+    if let Some(retain_days) = config.default_retain_days {
+        scopes.push(RetentionScope {
+            description: "accounts matching no rule".to_string(),
+            filter: doc! {
+                "pubkey": { "$nin": all_accounts.clone() },
+                "owner": { "$nin": all_owners.clone() },
+                "versions.owner": { "$nin": all_owners.clone() },
+            },
+            retain_days,
+        });
+    }
+    Ok(scopes)
+}
+
+fn pruner_error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::DataSchemaError { msg }))
+}
+
+/// The newest slot whose block time is at least `retain_days` old, None when no such
+/// slot is stored.
+pub(crate) async fn cutoff_slot(
+    database: &Database,
+    retain_days: u64,
+) -> Result<Option<i64>, GeyserPluginError> {
+    let cutoff_time = bson::DateTime::from_millis(
+        bson::DateTime::now().timestamp_millis()
+            - (retain_days as i64).saturating_mul(SECONDS_PER_DAY * 1000),
+    );
+    let slot = database
+        .collection::<Document>(SLOTS_COLLECTION)
+        .find_one(doc! { "block_time": { "$lte": cutoff_time } })
+        .sort(doc! { "slot": -1 })
+        .projection(doc! { "_id": 0, "slot": 1 })
+        .await
+        .map_err(|err| pruner_error(format!("Failed to find the retention cutoff: {}", err)))?;
+    Ok(slot.and_then(|slot| slot.get_i64("slot").ok()))
+}
+
+/// The keyframes deleted by one command.
+const KEYFRAME_DELETE_CHUNK: usize = 1000;
+
+/// Deletes the history versions matching `filter`. A version delta compacted by
+/// `history_compaction` is rebuilt from its keyframe, so a keyframe is only deleted
+/// once no delta referring to it is left: a chain is deleted whole, or its keyframe
+/// is kept with the deltas that remain.
+pub(crate) async fn delete_history_versions(
+    collection: &Collection<Document>,
+    filter: Document,
+) -> mongodb::error::Result<u64> {
+    let mut deleted = collection
+        .delete_many(doc! { "$and": [filter.clone(), { "compacted": { "$ne": "keyframe" } }] })
+        .await?
+        .deleted_count;
+    let orphaned_keyframes: Vec<Document> = collection
+        .aggregate(vec![
+            doc! { "$match": { "$and": [filter, { "compacted": "keyframe" }] } },
+            doc! { "$lookup": {
+                "from": collection.name(),
+                "let": {
+                    "pubkey": "$pubkey",
+                    "slot": "$slot",
+                    "write_version": "$write_version",
+                },
+                "pipeline": [
+                    { "$match": { "$expr": { "$and": [
+                        { "$eq": ["$pubkey", "$$pubkey"] },
+                        { "$eq": ["$keyframe_slot", "$$slot"] },
+                        { "$eq": ["$keyframe_write_version", "$$write_version"] },
+                    ] } } },
+                    { "$limit": 1 },
+                    { "$project": { "_id": 1 } },
+                ],
+                "as": "deltas",
+            }},
+            doc! { "$match": { "deltas": { "$size": 0 } } },
+            doc! { "$project": { "_id": 1 } },
+        ])
+        .await?
+        .try_collect()
+        .await?;
+    let ids: Vec<Bson> = orphaned_keyframes
+        .into_iter()
+        .filter_map(|keyframe| keyframe.get("_id").cloned())
+        .collect();
+    for ids in ids.chunks(KEYFRAME_DELETE_CHUNK) {
+        deleted += collection
+            .delete_many(doc! { "_id": { "$in": ids.to_vec() } })
+            .await?
+            .deleted_count;
+    }
+    Ok(deleted)
+}
+
+async fn prune(database: &Database, scopes: &[RetentionScope]) -> Result<(), GeyserPluginError> {
+    let collection = database.collection::<Document>(ACCOUNT_HISTORY_COLLECTION);
+    for scope in scopes {
+        let Some(cutoff) = cutoff_slot(database, scope.retain_days).await? else {
+            continue;
+        };
+        let filter = doc! {
+            "$and": [
+                scope.filter.clone(),
+                { "$or": [
+                    { "slot": { "$lt": cutoff } },
+                    { "last_slot": { "$lt": cutoff } },
+                ] },
+            ],
+        };
+        let deleted = delete_history_versions(&collection, filter)
+            .await
+            .map_err(|err| {
+                pruner_error(format!(
+                    "Failed to prune the history of {}: {}",
+                    scope.description, err
+                ))
+            })?;
+        if deleted > 0 {
+            info!(
+                "Pruned {} history documents of {} below slot {}",
+                deleted, scope.description, cutoff
+            );
+        }
+        metrics::inc_counter("geyser-plugin-mongodb-history-retention-deleted", deleted as usize);
+    }
+    Ok(())
+}
+
+pub(crate) struct HistoryRetentionPruner {
+    task: PeriodicTask,
+}
+
+impl HistoryRetentionPruner {
+    /// Starts the pruner configured by `history_retention`, None when unset or when
+    /// no rule has a window.
+    pub fn spawn(config: &GeyserPluginMongoDBConfig) -> Result<Option<Self>, GeyserPluginError> {
+        let Some(retention_config) = &config.history_retention else {
+            return Ok(None);
+        };
+        if !config.store_account_historical_data.unwrap_or_default() {
+            return Err(to_config_error(
+                "\"history_retention\" requires \"store_account_historical_data\"".to_string(),
+            ));
+        }
+        let scopes = retention_scopes(retention_config)?;
+        if scopes.is_empty() {
+            return Ok(None);
+        }
+        let interval = Duration::from_secs(
+            retention_config
+                .interval_secs
+                .unwrap_or(DEFAULT_HISTORY_RETENTION_INTERVAL_SECS),
+        );
+        let task = PeriodicTask::spawn(
+            "mongodb-history-retention",
+            "prune the account history",
+            interval,
+            config,
+            move |runtime, database| runtime.block_on(prune(database, &scopes)),
+        );
+        Ok(Some(Self { task }))
+    }
+
+    pub fn join(&mut self) {
+        self.task.join();
+    }
+}
@@ -0,0 +1,308 @@
+/// Builds the query indexes around the startup snapshot load.
+///
+/// Maintaining secondary indexes during the snapshot load slows the bulk insert of
+/// millions of accounts down considerably. With `startup_index_build` set to
+/// `deferred` the indexes are only built once the end of startup is notified, with
+/// `rebuild` existing ones are also dropped when the plugin loads. The post-startup
+/// build runs on its own thread and logs the progress MongoDB reports until every
+/// index is built.
+///
+/// The core indexes the writes of the plugin need, by pubkey and slot, signature and
+/// token index key, are created when the plugin loads unless `create_indexes` is
+/// false, when they are only verified and the missing ones logged.
+use {
+    crate::{
+        geyser_plugin_mongodb::{GeyserPluginMongoDBConfig, GeyserPluginMongoDbError},
+        instance,
+        metrics,
+        mongodb_client::{
+            is_namespace_not_found, plugin_database, SimpleMongoDbClient, ACCOUNTS_COLLECTION,
+            SLOTS_COLLECTION, TOKEN_MINT_INDEX_COLLECTION, TOKEN_OWNER_INDEX_COLLECTION,
+            TRANSACTIONS_COLLECTION,
+        },
+        queries::query_indexes,
+        schema::SchemaProfile,
+    },
+    futures::TryStreamExt,
+    log::*,
+    mongodb::{
+        bson::{doc, Document},
+        Client, Database, IndexModel,
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_measure::measure::Measure,
+    std::{
+        thread::{Builder, JoinHandle},
+        time::Duration,
+    },
+};
+
+const INDEX_BUILD_PROGRESS_INTERVAL: Duration = Duration::from_secs(30);
+pub(crate) const DEFAULT_CREATE_INDEXES: bool = true;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StartupIndexBuild {
+    /// The indexes are built when the plugin loads, before the snapshot.
+    Immediate,
+    /// Building is skipped at load and done after the end of startup.
+    Deferred,
+    /// Existing indexes are dropped at load and rebuilt after the end of startup.
+    Rebuild,
+}
+
+fn index_build_error(action: &str, err: mongodb::error::Error) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::DataSchemaError {
+        msg: format!("Failed to {}: {}", action, err),
+    }))
+}
+
+fn runtime() -> Result<tokio::runtime::Runtime, GeyserPluginError> {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::ConfigurationError {
+                msg: format!("Failed to create the index build runtime: {}", err),
+            }))
+        })
+}
+
+async fn drop_query_indexes(
+    database: &Database,
+    profile: SchemaProfile,
+) -> mongodb::error::Result<()> {
+    for (collection, keys) in query_indexes(profile) {
+        let collection = database.collection::<Document>(collection);
+        let existing: Vec<IndexModel> = match collection.list_indexes().await {
+            Ok(indexes) => indexes.try_collect().await?,
+            // The collection does not exist yet, nothing to drop.
+            Err(_) => continue,
+        };
+        let name = existing
+            .into_iter()
+            .find(|index| index.keys == keys)
+            .and_then(|index| index.options.and_then(|options| options.name));
+        if let Some(name) = name {
+            info!("Dropping index {} of {}", name, collection.name());
+            collection.drop_index(name).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Logs the progress of the index builds running on the server.
+async fn log_index_build_progress(client: &Client) {
+    let current_op = doc! {
+        "currentOp": 1,
+        "command.createIndexes": { "$exists": true },
+    };
+    match client.database("admin").run_command(current_op).await {
+        Ok(reply) => {
+            for operation in reply.get_array("inprog").into_iter().flatten() {
+                if let Some(operation) = operation.as_document() {
+                    info!(
+                        "Index build on {}: {}",
+                        operation.get_str("ns").unwrap_or_default(),
+                        operation.get_str("msg").unwrap_or("in progress")
+                    );
+                }
+            }
+        }
+        Err(err) => debug!("Failed to read the index build progress: {}", err),
+    }
+}
+
+async fn build_query_indexes(
+    client: &Client,
+    database: &Database,
+    profile: SchemaProfile,
+) -> mongodb::error::Result<()> {
+    let indexes = query_indexes(profile);
+    let total = indexes.len();
+    for (built, (collection, keys)) in indexes.into_iter().enumerate() {
+        info!(
+            "Building index {} of {} on {}: {}",
+            built + 1,
+            total,
+            collection,
+            keys
+        );
+        let mut measure = Measure::start("geyser-plugin-mongodb-build-index");
+        let build = database
+            .collection::<Document>(collection)
+            .create_index(IndexModel::builder().keys(keys).build());
+        tokio::pin!(build);
+        loop {
+            tokio::select! {
+                result = &mut build => {
+                    result?;
+                    break;
+                }
+                _ = tokio::time::sleep(INDEX_BUILD_PROGRESS_INTERVAL) => {
+                    log_index_build_progress(client).await;
+                }
+            }
+        }
+        measure.stop();
+        info!("Built index {} of {} in {}ms", built + 1, total, measure.as_ms());
+    }
+    Ok(())
+}
+
+/// Prepares the query indexes when the plugin loads, according to `startup_index_build`.
+pub fn prepare_startup_indexes(config: &GeyserPluginMongoDBConfig) -> Result<(), GeyserPluginError> {
+    let mode = match config.startup_index_build {
+        Some(mode) => mode,
+        None => return Ok(()),
+    };
+    let profile = config.schema_profile.unwrap_or_default();
+    let runtime = runtime()?;
+    runtime.block_on(async {
+        let client = SimpleMongoDbClient::connect_to_db(config).await?;
+        let database = plugin_database(&client, config);
+        match mode {
+            StartupIndexBuild::Immediate => build_query_indexes(&client, &database, profile)
+                .await
+                .map_err(|err| index_build_error("build the query indexes", err)),
+            StartupIndexBuild::Deferred => {
+                info!("Deferring the query index builds until the end of startup");
+                Ok(())
+            }
+            StartupIndexBuild::Rebuild => {
+                info!("Dropping the query indexes until the end of startup");
+                drop_query_indexes(&database, profile)
+                    .await
+                    .map_err(|err| index_build_error("drop the query indexes", err))
+            }
+        }
+    })
+}
+
+/// Starts building the deferred query indexes once the startup snapshot is loaded.
+/// Returns None when nothing was deferred.
+pub fn spawn_deferred_index_build(config: &GeyserPluginMongoDBConfig) -> Option<JoinHandle<()>> {
+    match config.startup_index_build {
+        Some(StartupIndexBuild::Deferred) | Some(StartupIndexBuild::Rebuild) => {}
+        _ => return None,
+    }
+    let config = config.clone();
+    let thread = Builder::new()
+        .name("mongodb-index-build".to_string())
+        .spawn(instance::bind(move || {
+            let result = runtime().and_then(|runtime| {
+                runtime.block_on(async {
+                    let client = SimpleMongoDbClient::connect_to_db(&config).await?;
+                    let database = plugin_database(&client, &config);
+                    let profile = config.schema_profile.unwrap_or_default();
+                    build_query_indexes(&client, &database, profile)
+                        .await
+                        .map_err(|err| index_build_error("build the query indexes", err))
+                })
+            });
+            match result {
+                Ok(()) => info!("Done with building the deferred query indexes"),
+                Err(err) => error!("{}", err),
+            }
+        }))
+        .unwrap();
+    Some(thread)
+}
+
+/// The collections and keys of the indexes the writes of the plugin need.
+pub fn core_indexes(config: &GeyserPluginMongoDBConfig) -> Vec<(&'static str, Document)> {
+    let profile = config.schema_profile.unwrap_or_default();
+    let mut indexes = vec![
+        (
+            ACCOUNTS_COLLECTION,
+            doc! {
+                profile.account_field("pubkey"): 1,
+                profile.account_field("slot"): -1,
+                profile.account_field("write_version"): -1,
+            },
+        ),
+        (TRANSACTIONS_COLLECTION, doc! { "signature": 1 }),
+        (TRANSACTIONS_COLLECTION, doc! { "slot": 1, "index": 1 }),
+        (SLOTS_COLLECTION, doc! { "slot": 1 }),
+    ];
+    if config.backfill_block_time.unwrap_or_default() {
+        // The block_time backfill updates the accounts of each block by slot.
+        indexes.push((ACCOUNTS_COLLECTION, doc! { profile.account_field("slot"): 1 }));
+    }
+    for (enabled, collection) in [
+        (config.index_token_owner, TOKEN_OWNER_INDEX_COLLECTION),
+        (config.index_token_mint, TOKEN_MINT_INDEX_COLLECTION),
+    ] {
+        if enabled.unwrap_or_default() {
+            indexes.push((collection, doc! { "secondary_key": 1, "account_key": 1 }));
+        }
+    }
+    indexes
+}
+
+/// The core indexes missing from `database`, logged and counted.
+async fn missing_core_indexes(
+    database: &Database,
+    indexes: Vec<(&'static str, Document)>,
+) -> mongodb::error::Result<usize> {
+    let mut missing = 0;
+    for (collection, keys) in indexes {
+        let existing: Vec<IndexModel> =
+            match database.collection::<Document>(collection).list_indexes().await {
+                Ok(indexes) => indexes.try_collect().await?,
+                // The collection does not exist yet, neither do its indexes.
+                Err(err) if is_namespace_not_found(&err) => Vec::default(),
+                Err(err) => return Err(err),
+            };
+        if !existing.iter().any(|index| index.keys == keys) {
+            warn!("Index {} of {} is missing", keys, collection);
+            missing += 1;
+        }
+    }
+    metrics::inc_counter("geyser-plugin-mongodb-missing-core-indexes", missing);
+    Ok(missing)
+}
+
+/// Creates the core indexes when the plugin loads, or only verifies them when
+/// `create_indexes` is false. Those among the query indexes are left to the
+/// deferred build when `startup_index_build` defers it.
+pub fn prepare_core_indexes(config: &GeyserPluginMongoDBConfig) -> Result<(), GeyserPluginError> {
+    let create = config.create_indexes.unwrap_or(DEFAULT_CREATE_INDEXES);
+    let deferred = matches!(
+        config.startup_index_build,
+        Some(StartupIndexBuild::Deferred) | Some(StartupIndexBuild::Rebuild)
+    );
+    let query_indexes = query_indexes(config.schema_profile.unwrap_or_default());
+    let indexes: Vec<_> = core_indexes(config)
+        .into_iter()
+        .filter(|index| !(deferred && query_indexes.contains(index)))
+        .collect();
+    let runtime = runtime()?;
+    runtime.block_on(async {
+        let client = SimpleMongoDbClient::connect_to_db(config).await?;
+        let database = plugin_database(&client, config);
+        if !create {
+            let missing = missing_core_indexes(&database, indexes)
+                .await
+                .map_err(|err| index_build_error("verify the core indexes", err))?;
+            if missing > 0 {
+                warn!(
+                    "{} core indexes are missing and \"create_indexes\" is false, writes \
+                     and reads of the affected collections scan them",
+                    missing
+                );
+            }
+            return Ok(());
+        }
+        for (collection, keys) in indexes {
+            database
+                .collection::<Document>(collection)
+                .create_index(IndexModel::builder().keys(keys).build())
+                .await
+                .map_err(|err| index_build_error("create the core indexes", err))?;
+        }
+        info!("Created the core indexes");
+        Ok(())
+    })
+}
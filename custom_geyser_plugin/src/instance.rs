@@ -0,0 +1,107 @@
+/// The state kept per plugin instance.
+///
+/// A validator running several instances of the plugin loads the library once, so
+/// its statics are shared by every instance. What must differ between instances,
+/// the name, the metrics, the pending work and the clock, is kept in an `Instance`
+/// instead. An instance is current on a thread while the thread works for it: a
+/// validator thread for the duration of a plugin callback, a thread spawned by the
+/// plugin for its whole life. Threads outside any instance, e.g. those of the
+/// command line tools, share a default unnamed instance.
+use {
+    crate::{clock::Clock, metrics::MetricsRegistry, pending_work::PendingWork},
+    std::{
+        cell::RefCell,
+        sync::{Arc, OnceLock},
+    },
+};
+
+static DEFAULT_INSTANCE: OnceLock<Arc<Instance>> = OnceLock::new();
+
+thread_local! {
+    static CURRENT_INSTANCE: RefCell<Option<Arc<Instance>>> = RefCell::default();
+}
+
+#[derive(Default)]
+pub struct Instance {
+    name: Option<String>,
+    metrics: Arc<MetricsRegistry>,
+    pending_work: Arc<PendingWork>,
+    clock: Clock,
+}
+
+impl Instance {
+    pub fn new(name: Option<String>, clock: Clock) -> Arc<Self> {
+        Arc::new(Self {
+            name,
+            clock,
+            ..Self::default()
+        })
+    }
+
+    /// The `instance_name` of the config, None for an unnamed instance.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    pub fn metrics(&self) -> &Arc<MetricsRegistry> {
+        &self.metrics
+    }
+
+    pub fn pending_work(&self) -> &Arc<PendingWork> {
+        &self.pending_work
+    }
+
+    pub fn clock(&self) -> &Clock {
+        &self.clock
+    }
+}
+
+/// Makes `instance` current until the guard is dropped, then restores the previous one.
+pub struct InstanceGuard {
+    previous: Option<Arc<Instance>>,
+}
+
+impl Drop for InstanceGuard {
+    fn drop(&mut self) {
+        let previous = self.previous.take();
+        CURRENT_INSTANCE.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+pub fn enter(instance: &Arc<Instance>) -> InstanceGuard {
+    let previous = CURRENT_INSTANCE.with(|current| current.borrow_mut().replace(instance.clone()));
+    InstanceGuard { previous }
+}
+
+/// Makes `instance` current for the rest of the life of the current thread, for
+/// threads the plugin does not spawn itself, e.g. those of a tokio runtime.
+pub fn set_current(instance: Arc<Instance>) {
+    CURRENT_INSTANCE.with(|current| *current.borrow_mut() = Some(instance));
+}
+
+/// The instance the current thread works for.
+pub fn current() -> Arc<Instance> {
+    with_current(Arc::clone)
+}
+
+pub fn with_current<R>(f: impl FnOnce(&Arc<Instance>) -> R) -> R {
+    CURRENT_INSTANCE.with(|current| match &*current.borrow() {
+        Some(instance) => f(instance),
+        None => f(DEFAULT_INSTANCE.get_or_init(Arc::default)),
+    })
+}
+
+/// Wraps the body of a thread spawned by the plugin so it works for the instance
+/// current on the spawning thread.
+pub fn bind<F, R>(f: F) -> impl FnOnce() -> R + Send + 'static
+where
+    F: FnOnce() -> R + Send + 'static,
+    R: 'static,
+{
+    let instance = current();
+    move || {
+        let _instance = enter(&instance);
+        f()
+    }
+}
+
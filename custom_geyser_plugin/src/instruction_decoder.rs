@@ -0,0 +1,336 @@
+/// Decodes the data of top level instructions into named fields, using per-program
+/// layout descriptors from the config.
+///
+/// A layout names an instruction, the discriminator its data starts with and the
+/// types of the arguments following it, encoded with borsh or bincode. Decoded
+/// instructions are stored under `decoded_instructions` on the transaction document,
+/// next to the raw `instructions`, so instruction history can be queried by name and
+/// argument without full IDL support. Instructions of programs without layouts are
+/// left out; those that do not match any layout get a `decode_error`.
+use {
+    crate::{
+        geyser_plugin_mongodb::{GeyserPluginMongoDBConfig, GeyserPluginMongoDbError},
+        metrics,
+    },
+    log::*,
+    mongodb::bson::{doc, spec::BinarySubtype, Binary, Bson, Document},
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_sdk::pubkey::Pubkey,
+    std::{collections::HashMap, str::FromStr},
+};
+
+/// How the arguments of a program's instructions are serialized.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LayoutEncoding {
+    /// Lengths are u32, as written by Anchor and most native programs.
+    #[default]
+    Borsh,
+    /// Lengths are u64.
+    Bincode,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct InstructionArgLayout {
+    pub name: String,
+    /// One of u8 through u128, i8 through i128, bool, pubkey, string, bytes,
+    /// option<T> and vec<T>.
+    #[serde(rename = "type")]
+    pub arg_type: String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct InstructionLayout {
+    pub name: String,
+    /// The bytes the instruction data starts with, e.g. the 8 byte Anchor
+    /// discriminator or the one byte tag of a native program.
+    pub discriminator: Vec<u8>,
+    #[serde(default)]
+    pub args: Vec<InstructionArgLayout>,
+}
+
+/// The instruction layouts of one program.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ProgramInstructionLayouts {
+    pub program_id: String,
+    #[serde(default)]
+    pub encoding: LayoutEncoding,
+    pub instructions: Vec<InstructionLayout>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum ArgType {
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    Bool,
+    Pubkey,
+    String,
+    Bytes,
+    Option(Box<ArgType>),
+    Vec(Box<ArgType>),
+}
+
+impl ArgType {
+    fn parse(arg_type: &str) -> Option<Self> {
+        let arg_type = arg_type.trim();
+        let inner = |prefix: &str| {
+            arg_type
+                .strip_prefix(prefix)
+                .and_then(|rest| rest.strip_suffix('>'))
+                .and_then(|inner| Self::parse(inner).map(Box::new))
+        };
+        Some(match arg_type {
+            "u8" => ArgType::U8,
+            "u16" => ArgType::U16,
+            "u32" => ArgType::U32,
+            "u64" => ArgType::U64,
+            "u128" => ArgType::U128,
+            "i8" => ArgType::I8,
+            "i16" => ArgType::I16,
+            "i32" => ArgType::I32,
+            "i64" => ArgType::I64,
+            "i128" => ArgType::I128,
+            "bool" => ArgType::Bool,
+            "pubkey" => ArgType::Pubkey,
+            "string" => ArgType::String,
+            "bytes" => ArgType::Bytes,
+            _ if arg_type.starts_with("option<") => ArgType::Option(inner("option<")?),
+            _ if arg_type.starts_with("vec<") => ArgType::Vec(inner("vec<")?),
+            _ => return None,
+        })
+    }
+}
+
+/// Reads the arguments of one instruction.
+struct ArgReader<'a> {
+    data: &'a [u8],
+    offset: usize,
+    encoding: LayoutEncoding,
+}
+
+impl<'a> ArgReader<'a> {
+    fn take<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let bytes = self.take_slice(N)?;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    fn take_slice(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| format!("Instruction data ends at byte {}", self.data.len()))?;
+        let bytes = &self.data[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn read_len(&mut self) -> Result<usize, String> {
+        match self.encoding {
+            LayoutEncoding::Borsh => Ok(u32::from_le_bytes(self.take()?) as usize),
+            LayoutEncoding::Bincode => usize::try_from(u64::from_le_bytes(self.take()?))
+                .map_err(|err| format!("Invalid length: {}", err)),
+        }
+    }
+
+    // Integers that may exceed i64 are stored as strings, like the token amounts.
+    fn read(&mut self, arg_type: &ArgType) -> Result<Bson, String> {
+        Ok(match arg_type {
+            ArgType::U8 => Bson::Int32(u8::from_le_bytes(self.take()?) as i32),
+            ArgType::U16 => Bson::Int32(u16::from_le_bytes(self.take()?) as i32),
+            ArgType::U32 => Bson::Int64(u32::from_le_bytes(self.take()?) as i64),
+            ArgType::U64 => Bson::String(u64::from_le_bytes(self.take()?).to_string()),
+            ArgType::U128 => Bson::String(u128::from_le_bytes(self.take()?).to_string()),
+            ArgType::I8 => Bson::Int32(i8::from_le_bytes(self.take()?) as i32),
+            ArgType::I16 => Bson::Int32(i16::from_le_bytes(self.take()?) as i32),
+            ArgType::I32 => Bson::Int32(i32::from_le_bytes(self.take()?)),
+            ArgType::I64 => Bson::Int64(i64::from_le_bytes(self.take()?)),
+            ArgType::I128 => Bson::String(i128::from_le_bytes(self.take()?).to_string()),
+            ArgType::Bool => match self.take::<1>()? {
+                [0] => Bson::Boolean(false),
+                [1] => Bson::Boolean(true),
+                [value] => return Err(format!("Invalid bool {}", value)),
+            },
+            ArgType::Pubkey => Bson::String(bs58::encode(self.take::<32>()?).into_string()),
+            ArgType::String => {
+                let len = self.read_len()?;
+                let bytes = self.take_slice(len)?;
+                Bson::String(
+                    String::from_utf8(bytes.to_vec())
+                        .map_err(|err| format!("Invalid string: {}", err))?,
+                )
+            }
+            ArgType::Bytes => {
+                let len = self.read_len()?;
+                Bson::Binary(Binary {
+                    subtype: BinarySubtype::Generic,
+                    bytes: self.take_slice(len)?.to_vec(),
+                })
+            }
+            ArgType::Option(inner) => match self.take::<1>()? {
+                [0] => Bson::Null,
+                [1] => self.read(inner)?,
+                [tag] => return Err(format!("Invalid option tag {}", tag)),
+            },
+            // No capacity is reserved, the length is not trusted.
+            ArgType::Vec(inner) => {
+                let len = self.read_len()?;
+                let mut values = Vec::default();
+                for _ in 0..len {
+                    values.push(self.read(inner)?);
+                }
+                Bson::Array(values)
+            }
+        })
+    }
+}
+
+struct DecodableInstruction {
+    name: String,
+    discriminator: Vec<u8>,
+    args: Vec<(String, ArgType)>,
+}
+
+struct ProgramLayouts {
+    encoding: LayoutEncoding,
+    instructions: Vec<DecodableInstruction>,
+}
+
+impl ProgramLayouts {
+    fn decode(&self, data: &[u8]) -> Result<Document, String> {
+        let instruction = self
+            .instructions
+            .iter()
+            .find(|instruction| data.starts_with(&instruction.discriminator))
+            .ok_or_else(|| "No layout matches the instruction discriminator".to_string())?;
+        let mut reader = ArgReader {
+            data,
+            offset: instruction.discriminator.len(),
+            encoding: self.encoding,
+        };
+        let mut args = Document::new();
+        for (name, arg_type) in &instruction.args {
+            let value = reader
+                .read(arg_type)
+                .map_err(|err| format!("{}.{}: {}", instruction.name, name, err))?;
+            args.insert(name.clone(), value);
+        }
+        Ok(doc! { "name": &instruction.name, "args": args })
+    }
+}
+
+/// The instruction layouts by program id.
+#[derive(Default)]
+pub struct InstructionDecoder {
+    programs: HashMap<String, ProgramLayouts>,
+}
+
+impl InstructionDecoder {
+    pub fn from_config(config: &GeyserPluginMongoDBConfig) -> Result<Self, GeyserPluginError> {
+        let to_error = |msg: String| {
+            GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::ConfigurationError {
+                msg,
+            }))
+        };
+        let mut decoder = Self::default();
+        for program in config.instruction_layouts.iter().flatten() {
+            Pubkey::from_str(&program.program_id).map_err(|err| {
+                to_error(format!(
+                    "Invalid instruction layout program {}: {}",
+                    program.program_id, err
+                ))
+            })?;
+            let mut instructions = Vec::with_capacity(program.instructions.len());
+            for layout in &program.instructions {
+                let mut args = Vec::with_capacity(layout.args.len());
+                for arg in &layout.args {
+                    let arg_type = ArgType::parse(&arg.arg_type).ok_or_else(|| {
+                        to_error(format!(
+                            "Unknown type {} of {}.{} in the layouts of {}",
+                            arg.arg_type, layout.name, arg.name, program.program_id
+                        ))
+                    })?;
+                    args.push((arg.name.clone(), arg_type));
+                }
+                instructions.push(DecodableInstruction {
+                    name: layout.name.clone(),
+                    discriminator: layout.discriminator.clone(),
+                    args,
+                });
+            }
+            // The longest discriminators are tried first, so a layout is not shadowed
+            // by one whose discriminator is a prefix of its own.
+            instructions.sort_by(|a, b| b.discriminator.len().cmp(&a.discriminator.len()));
+            decoder.programs.insert(
+                program.program_id.clone(),
+                ProgramLayouts {
+                    encoding: program.encoding,
+                    instructions,
+                },
+            );
+        }
+        if !decoder.programs.is_empty() {
+            info!(
+                "Decoding the instructions of {} programs",
+                decoder.programs.len()
+            );
+        }
+        Ok(decoder)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.programs.is_empty()
+    }
+
+    /// Adds `decoded_instructions` to a transaction `document` for its top level
+    /// instructions invoking a program with layouts.
+    pub fn decode_into(&self, document: &mut Document) {
+        if self.is_empty() {
+            return;
+        }
+        let (Ok(account_keys), Ok(instructions)) =
+            (document.get_array("account_keys"), document.get_array("instructions"))
+        else {
+            return;
+        };
+        let mut decoded_instructions = Vec::default();
+        for (index, instruction) in instructions.iter().enumerate() {
+            let Some(instruction) = instruction.as_document() else {
+                continue;
+            };
+            let program_id = instruction
+                .get_i32("program_id_index")
+                .ok()
+                .and_then(|program_id_index| account_keys.get(program_id_index as usize))
+                .and_then(Bson::as_str);
+            let (Some(program_id), Ok(data)) = (program_id, instruction.get_binary_generic("data"))
+            else {
+                continue;
+            };
+            let Some(layouts) = self.programs.get(program_id) else {
+                continue;
+            };
+            let mut decoded = doc! { "index": index as i32, "program_id": program_id };
+            match layouts.decode(data) {
+                Ok(fields) => decoded.extend(fields),
+                Err(err) => {
+                    metrics::inc_counter("geyser-plugin-mongodb-instruction-decode-failures", 1);
+                    decoded.insert("decode_error", err);
+                }
+            }
+            decoded_instructions.push(decoded);
+        }
+        if !decoded_instructions.is_empty() {
+            document.insert("decoded_instructions", decoded_instructions);
+        }
+    }
+}
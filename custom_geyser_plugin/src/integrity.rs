@@ -0,0 +1,300 @@
+/// Verifies the invariants between the collections of the plugin, e.g. after an
+/// incident, a restore or a manual cleanup:
+///
+/// - `transaction_slots`: the slot of every transaction has a document in `slots`.
+/// - `token_index_accounts`: the account of every token index entry is stored.
+/// - `history_versions`: no version of the account history is newer, by slot and
+///   write version, than the current version of its account.
+///
+/// Each check reads the documents of a slot range, all of them or a random sample,
+/// and joins them to the collection they refer to on the server. The report counts
+/// the documents checked and the violations, with the first ones as examples.
+/// Accounts without a current version, e.g. deleted closed accounts, are not
+/// history violations.
+use {
+    crate::{
+        metrics,
+        mongodb_client::{
+            ACCOUNTS_COLLECTION, ACCOUNT_HISTORY_COLLECTION, SLOTS_COLLECTION,
+            TOKEN_MINT_INDEX_COLLECTION, TOKEN_OWNER_INDEX_COLLECTION, TRANSACTIONS_COLLECTION,
+        },
+        schema::SchemaProfile,
+    },
+    futures::TryStreamExt,
+    log::Level,
+    mongodb::{
+        bson::{doc, Bson, Document},
+        error::Result,
+        Database,
+    },
+    solana_metrics::datapoint::DataPoint,
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum IntegrityCheck {
+    TransactionSlots,
+    TokenIndexAccounts,
+    HistoryVersions,
+}
+
+impl IntegrityCheck {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IntegrityCheck::TransactionSlots => "transaction_slots",
+            IntegrityCheck::TokenIndexAccounts => "token_index_accounts",
+            IntegrityCheck::HistoryVersions => "history_versions",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct IntegrityOptions {
+    pub start_slot: u64,
+    pub end_slot: u64,
+    /// Documents checked per collection, drawn at random from the slot range. All of
+    /// them are checked when None.
+    pub sample_size: Option<u64>,
+    /// Violations kept as examples per check.
+    pub max_examples: usize,
+    pub schema_profile: SchemaProfile,
+    /// Whether the account history is stored in buckets, see
+    /// `account_history_bucket_size`.
+    pub bucketed_history: bool,
+}
+
+/// The outcome of one check over one collection.
+#[derive(Clone, Debug)]
+pub struct CheckReport {
+    pub check: IntegrityCheck,
+    pub collection: &'static str,
+    pub checked: i64,
+    pub violations: i64,
+    pub examples: Vec<Document>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct IntegrityReport {
+    pub checks: Vec<CheckReport>,
+}
+
+fn number(document: &Document, field: &str) -> i64 {
+    match document.get(field) {
+        Some(Bson::Int32(value)) => *value as i64,
+        Some(Bson::Int64(value)) => *value,
+        _ => 0,
+    }
+}
+
+/// Runs `check` over the documents `selection` picks from `collection`, reporting
+/// those `violations` leaves.
+async fn run_check(
+    database: &Database,
+    check: IntegrityCheck,
+    collection: &'static str,
+    mut selection: Vec<Document>,
+    violations: Vec<Document>,
+    options: &IntegrityOptions,
+) -> Result<CheckReport> {
+    if let Some(sample_size) = options.sample_size {
+        selection.push(doc! { "$sample": { "size": sample_size as i64 } });
+    }
+    let collection_handle = database.collection::<Document>(collection);
+    let mut counting = selection.clone();
+    counting.push(doc! { "$count": "checked" });
+    let checked = collection_handle
+        .aggregate(counting)
+        .allow_disk_use(true)
+        .await?
+        .try_next()
+        .await?
+        .map_or(0, |counted| number(&counted, "checked"));
+    let mut report = CheckReport {
+        check,
+        collection,
+        checked,
+        violations: 0,
+        examples: Vec::default(),
+    };
+    selection.extend(violations);
+    let mut cursor = collection_handle
+        .aggregate(selection)
+        .allow_disk_use(true)
+        .await?;
+    while let Some(violation) = cursor.try_next().await? {
+        report.violations += 1;
+        if report.examples.len() < options.max_examples {
+            report.examples.push(violation);
+        }
+    }
+    Ok(report)
+}
+
+fn slot_range(options: &IntegrityOptions) -> Document {
+    doc! { "$gte": options.start_slot as i64, "$lte": options.end_slot as i64 }
+}
+
+async fn check_transaction_slots(
+    database: &Database,
+    options: &IntegrityOptions,
+) -> Result<CheckReport> {
+    let selection = vec![doc! { "$match": { "slot": slot_range(options) } }];
+    let violations = vec![
+        doc! { "$lookup": {
+            "from": SLOTS_COLLECTION,
+            "localField": "slot",
+            "foreignField": "slot",
+            "as": "slot_documents",
+        }},
+        doc! { "$match": { "slot_documents": { "$size": 0 } } },
+        doc! { "$project": { "_id": 0, "signature": 1, "slot": 1 } },
+    ];
+    run_check(
+        database,
+        IntegrityCheck::TransactionSlots,
+        TRANSACTIONS_COLLECTION,
+        selection,
+        violations,
+        options,
+    )
+    .await
+}
+
+async fn check_token_index_accounts(
+    database: &Database,
+    collection: &'static str,
+    options: &IntegrityOptions,
+) -> Result<CheckReport> {
+    let pubkey_field = options.schema_profile.account_field("pubkey");
+    let selection = vec![doc! { "$match": { "slot": slot_range(options) } }];
+    let violations = vec![
+        doc! { "$lookup": {
+            "from": ACCOUNTS_COLLECTION,
+            "let": { "account": "$account_key" },
+            "pipeline": [
+                { "$match": { "$expr": { "$eq": [format!("${}", pubkey_field), "$$account"] } } },
+                { "$limit": 1 },
+                { "$project": { "_id": 1 } },
+            ],
+            "as": "account",
+        }},
+        doc! { "$match": { "account": { "$size": 0 } } },
+        doc! { "$project": { "_id": 0, "account_key": 1, "secondary_key": 1, "slot": 1 } },
+    ];
+    run_check(
+        database,
+        IntegrityCheck::TokenIndexAccounts,
+        collection,
+        selection,
+        violations,
+        options,
+    )
+    .await
+}
+
+async fn check_history_versions(
+    database: &Database,
+    options: &IntegrityOptions,
+) -> Result<CheckReport> {
+    let profile = options.schema_profile;
+    let (pubkey_field, slot_field, write_version_field) = (
+        format!("${}", profile.account_field("pubkey")),
+        format!("${}", profile.account_field("slot")),
+        format!("${}", profile.account_field("write_version")),
+    );
+    let selection = if options.bucketed_history {
+        vec![
+            doc! { "$match": {
+                "first_slot": { "$lte": options.end_slot as i64 },
+                "last_slot": { "$gte": options.start_slot as i64 },
+            }},
+            doc! { "$unwind": "$versions" },
+            doc! { "$project": {
+                "pubkey": 1,
+                "slot": "$versions.slot",
+                "write_version": "$versions.write_version",
+            }},
+            doc! { "$match": { "slot": slot_range(options) } },
+        ]
+    } else {
+        vec![doc! { "$match": { "slot": slot_range(options) } }]
+    };
+    let violations = vec![
+        doc! { "$lookup": {
+            "from": ACCOUNTS_COLLECTION,
+            "let": { "pubkey": "$pubkey" },
+            "pipeline": [
+                { "$match": { "$expr": { "$eq": [pubkey_field, "$$pubkey"] } } },
+                { "$sort": {
+                    profile.account_field("slot"): -1,
+                    profile.account_field("write_version"): -1,
+                }},
+                { "$limit": 1 },
+                { "$project": {
+                    "_id": 0,
+                    "slot": slot_field,
+                    "write_version": write_version_field,
+                }},
+            ],
+            "as": "current",
+        }},
+        doc! { "$unwind": "$current" },
+        doc! { "$match": { "$expr": { "$or": [
+            { "$gt": ["$slot", "$current.slot"] },
+            { "$and": [
+                { "$eq": ["$slot", "$current.slot"] },
+                { "$gt": ["$write_version", "$current.write_version"] },
+            ]},
+        ]}}},
+        doc! { "$project": {
+            "_id": 0,
+            "pubkey": 1,
+            "slot": 1,
+            "write_version": 1,
+            "current_slot": "$current.slot",
+            "current_write_version": "$current.write_version",
+        }},
+    ];
+    run_check(
+        database,
+        IntegrityCheck::HistoryVersions,
+        ACCOUNT_HISTORY_COLLECTION,
+        selection,
+        violations,
+        options,
+    )
+    .await
+}
+
+/// Runs every check over the slot range of `options`. Collections that do not exist
+/// check no documents.
+pub async fn check_integrity(
+    database: &Database,
+    options: &IntegrityOptions,
+) -> Result<IntegrityReport> {
+    let mut checks = vec![check_transaction_slots(database, options).await?];
+    for collection in [TOKEN_OWNER_INDEX_COLLECTION, TOKEN_MINT_INDEX_COLLECTION] {
+        checks.push(check_token_index_accounts(database, collection, options).await?);
+    }
+    checks.push(check_history_versions(database, options).await?);
+    Ok(IntegrityReport { checks })
+}
+
+impl IntegrityReport {
+    pub fn violations(&self) -> i64 {
+        self.checks.iter().map(|check| check.violations).sum()
+    }
+
+    /// Reports one data point per check to solana_metrics.
+    pub fn submit(&self) {
+        for check in &self.checks {
+            metrics::submit(
+                DataPoint::new("geyser-plugin-mongodb-integrity-check")
+                    .add_field_str("check", check.check.as_str())
+                    .add_field_str("collection", check.collection)
+                    .add_field_i64("checked", check.checked)
+                    .add_field_i64("violations", check.violations),
+                Level::Info,
+            );
+        }
+    }
+}
@@ -1,7 +1,55 @@
+pub mod account_decoder;
+pub mod account_flags;
+pub mod address_labels;
+pub mod account_diff;
+pub mod anomaly_detector;
+pub mod backpressure;
+pub mod cache_invalidation;
+pub mod clock;
+pub mod dead_slot_gc;
 pub mod accounts_selector;
+pub mod transaction_cap;
+pub mod transaction_enrichment;
 pub mod transaction_selector;
 pub mod geyser_plugin_mongodb;
+pub mod history_compaction;
+pub mod history_depth;
+pub mod history_retention;
+pub mod index_builds;
+pub mod instance;
+pub mod instruction_decoder;
+pub mod integrity;
+pub mod logging;
+pub mod metrics;
+pub mod metrics_exporters;
+pub mod metrics_journal;
+pub mod middleware;
 pub mod mongodb_client;
+pub mod oversized_transactions;
+#[cfg(feature = "parquet")]
+pub mod parquet_export;
+pub mod pending_work;
+pub mod periodic_task;
+pub mod profiles;
+pub mod queries;
+pub mod redaction;
+pub mod sampling;
+pub mod schema;
+pub mod schema_validation;
+pub mod schema_verification;
+pub mod selector_reload;
+pub mod selector_rules;
+pub mod server_support;
+pub mod sharding;
+pub mod slot_batching;
+pub mod storage_usage;
+pub mod time_series;
+pub mod ttl_retention;
+pub mod explorer;
+pub mod export;
+pub mod websocket_gateway;
+#[cfg(feature = "graphql")]
+pub mod graphql;
 
 
 
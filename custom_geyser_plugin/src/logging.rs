@@ -0,0 +1,148 @@
+/// Optional JSON log format with correlation fields.
+///
+/// Every line is a JSON object carrying the worker, slot, collection and batch the
+/// logging thread is working on, so log aggregation systems can line plugin logs
+/// up with validator and MongoDB logs. The fields are kept per thread and set by
+/// the workers as they pick up work; fields that are unset are left out. Lines
+/// logged for a named plugin instance carry its name.
+use {
+    crate::instance,
+    chrono::{SecondsFormat, Utc},
+    log::{LevelFilter, Log, Metadata, Record},
+    serde_derive::{Deserialize, Serialize},
+    serde_json::{json, Map, Value},
+    std::{
+        cell::RefCell,
+        env,
+        io::Write,
+        str::FromStr,
+        sync::atomic::{AtomicU64, Ordering},
+    },
+};
+
+const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+
+static NEXT_BATCH_ID: AtomicU64 = AtomicU64::new(1);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// The validator's plain text format.
+    #[default]
+    Text,
+    /// One JSON object per line with correlation fields.
+    Json,
+}
+
+/// What the current thread is working on.
+#[derive(Clone, Debug, Default)]
+pub struct LogContext {
+    pub worker_id: Option<usize>,
+    pub slot: Option<u64>,
+    pub collection: Option<String>,
+    pub batch_id: Option<u64>,
+}
+
+thread_local! {
+    static LOG_CONTEXT: RefCell<LogContext> = RefCell::default();
+}
+
+/// Marks the current thread as the worker `worker_id` for the rest of its life.
+pub fn set_worker_id(worker_id: usize) {
+    LOG_CONTEXT.with(|context| context.borrow_mut().worker_id = Some(worker_id));
+}
+
+/// Sets the slot and collection of the work item the current thread starts on,
+/// clearing the batch of the previous item.
+pub fn set_work_item(slot: Option<u64>, collection: &str) {
+    LOG_CONTEXT.with(|context| {
+        let mut context = context.borrow_mut();
+        context.slot = slot;
+        context.collection = Some(collection.to_string());
+        context.batch_id = None;
+    });
+}
+
+/// Starts a new batch on the current thread. A batch spans many slots, so the
+/// slot is cleared. Returns the batch id, unique within the process.
+pub fn start_batch() -> u64 {
+    let batch_id = NEXT_BATCH_ID.fetch_add(1, Ordering::Relaxed);
+    LOG_CONTEXT.with(|context| {
+        let mut context = context.borrow_mut();
+        context.slot = None;
+        context.batch_id = Some(batch_id);
+    });
+    batch_id
+}
+
+pub fn current_context() -> LogContext {
+    LOG_CONTEXT.with(|context| context.borrow().clone())
+}
+
+struct JsonLogger {
+    level: LevelFilter,
+}
+
+impl Log for JsonLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let context = current_context();
+        let mut line = Map::new();
+        line.insert(
+            "timestamp".to_string(),
+            json!(Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true)),
+        );
+        line.insert("level".to_string(), json!(record.level().as_str()));
+        line.insert("target".to_string(), json!(record.target()));
+        line.insert("message".to_string(), json!(record.args().to_string()));
+        instance::with_current(|instance| {
+            if let Some(instance_name) = instance.name() {
+                line.insert("instance".to_string(), json!(instance_name));
+            }
+        });
+        if let Some(thread) = std::thread::current().name() {
+            line.insert("thread".to_string(), json!(thread));
+        }
+        let fields = [
+            ("worker_id", context.worker_id.map(|id| json!(id))),
+            ("slot", context.slot.map(|slot| json!(slot))),
+            ("collection", context.collection.map(Value::String)),
+            ("batch_id", context.batch_id.map(|id| json!(id))),
+        ];
+        for (name, value) in fields {
+            if let Some(value) = value {
+                line.insert(name.to_string(), value);
+            }
+        }
+        let mut stderr = std::io::stderr().lock();
+        let _ = writeln!(stderr, "{}", Value::Object(line));
+    }
+
+    fn flush(&self) {
+        let _ = std::io::stderr().flush();
+    }
+}
+
+/// Installs the plugin's logger. The JSON logger honours a plain level in
+/// `RUST_LOG`, e.g. `debug`, and logs at info otherwise.
+pub fn setup_logging(format: LogFormat) {
+    match format {
+        LogFormat::Text => solana_logger::setup_with_default("info"),
+        LogFormat::Json => {
+            let level = env::var("RUST_LOG")
+                .ok()
+                .and_then(|level| LevelFilter::from_str(&level).ok())
+                .unwrap_or(DEFAULT_LOG_LEVEL);
+            // Fails when the plugin is reloaded, the first logger stays in place.
+            if log::set_boxed_logger(Box::new(JsonLogger { level })).is_ok() {
+                log::set_max_level(level);
+            }
+        }
+    }
+}
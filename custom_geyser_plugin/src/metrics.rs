@@ -0,0 +1,339 @@
+/// The metrics of the plugin, shared by the workers and the plugin threads.
+///
+/// Counters and histograms are registered by name in the registry of the plugin
+/// instance and updated with atomics, so every worker records into the same metric
+/// without a lock on the hot path; callers recording often keep the handle returned by
+/// `counter` or `histogram`. Exporters only read the registry: the solana_metrics
+/// exporter reports every counter as the increase since its last report and every
+/// histogram as the percentiles of the values recorded since then, tagged with the
+/// name of a named instance.
+use {
+    crate::{
+        instance,
+        pending_work::pending_work,
+        periodic_task::{FirstRun, PeriodicTask},
+    },
+    log::*,
+    solana_metrics::datapoint::DataPoint,
+    std::{
+        collections::HashMap,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex, RwLock,
+        },
+        time::Duration,
+    },
+};
+
+/// Values are bucketed by their bit length, bucket `i` holds values below 2^i.
+const HISTOGRAM_BUCKETS: usize = 65;
+const DEFAULT_REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Default)]
+pub struct Counter {
+    value: AtomicU64,
+    reported: AtomicU64,
+}
+
+impl Counter {
+    pub fn add(&self, value: u64) {
+        self.value.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// The total since the plugin loaded.
+    pub fn get(&self) -> u64 {
+        self.value.load(Ordering::Relaxed)
+    }
+
+    /// The increase since the previous call.
+    fn take_delta(&self) -> u64 {
+        let value = self.get();
+        value.saturating_sub(self.reported.swap(value, Ordering::Relaxed))
+    }
+}
+
+/// Values are counted since the plugin loaded, the maximum since the previous report.
+#[derive(Debug)]
+pub struct Histogram {
+    buckets: [AtomicU64; HISTOGRAM_BUCKETS],
+    sum: AtomicU64,
+    max: AtomicU64,
+    /// The totals at the previous report.
+    reported: Mutex<HistogramSnapshot>,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self {
+            buckets: std::array::from_fn(|_| AtomicU64::default()),
+            sum: AtomicU64::default(),
+            max: AtomicU64::default(),
+            reported: Mutex::default(),
+        }
+    }
+}
+
+/// The largest value of bucket `bucket`.
+fn bucket_upper_bound(bucket: usize) -> u64 {
+    1u64.checked_shl(bucket as u32).unwrap_or(u64::MAX) - 1
+}
+
+/// The values a histogram recorded over one period, or since the plugin loaded.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct HistogramSnapshot {
+    pub count: u64,
+    pub sum: u64,
+    pub max: u64,
+    buckets: Vec<u64>,
+}
+
+impl HistogramSnapshot {
+    /// The upper bound of the bucket holding the `p`th percentile, at most the maximum.
+    pub fn percentile(&self, p: u64) -> u64 {
+        let rank = (self.count.saturating_sub(1) * p / 100) + 1;
+        let mut seen = 0;
+        for (bucket, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                return bucket_upper_bound(bucket).min(self.max);
+            }
+        }
+        self.max
+    }
+
+    pub fn mean(&self) -> u64 {
+        self.sum.checked_div(self.count).unwrap_or_default()
+    }
+
+    /// The largest value of every bucket up to the highest one holding a value, with
+    /// the values recorded up to that bound, as in a Prometheus histogram.
+    pub fn cumulative_buckets(&self) -> Vec<(u64, u64)> {
+        let highest = self.buckets.iter().rposition(|count| *count > 0);
+        let mut seen = 0;
+        self.buckets[..highest.map_or(0, |bucket| bucket + 1)]
+            .iter()
+            .enumerate()
+            .map(|(bucket, count)| {
+                seen += count;
+                (bucket_upper_bound(bucket), seen)
+            })
+            .collect()
+    }
+
+    /// The values recorded between the totals `previous` and these totals. The maximum
+    /// is not known, the largest value of the highest bucket holding a value stands in.
+    pub fn since(&self, previous: &HistogramSnapshot) -> HistogramSnapshot {
+        let buckets: Vec<u64> = self
+            .buckets
+            .iter()
+            .enumerate()
+            .map(|(bucket, count)| {
+                count.saturating_sub(previous.buckets.get(bucket).copied().unwrap_or_default())
+            })
+            .collect();
+        HistogramSnapshot {
+            count: buckets.iter().sum(),
+            sum: self.sum.saturating_sub(previous.sum),
+            max: buckets
+                .iter()
+                .rposition(|count| *count > 0)
+                .map_or(0, bucket_upper_bound),
+            buckets,
+        }
+    }
+}
+
+impl Histogram {
+    pub fn record(&self, value: u64) {
+        let bucket = (u64::BITS - value.leading_zeros()) as usize;
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+        self.max.fetch_max(value, Ordering::Relaxed);
+    }
+
+    /// The values recorded since the plugin loaded, with the maximum since the
+    /// previous report.
+    pub fn totals(&self) -> HistogramSnapshot {
+        let buckets: Vec<u64> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+        HistogramSnapshot {
+            count: buckets.iter().sum(),
+            sum: self.sum.load(Ordering::Relaxed),
+            max: self.max.load(Ordering::Relaxed),
+            buckets,
+        }
+    }
+
+    /// The values recorded since the previous call. Values recorded while the
+    /// snapshot is taken may be counted in the next period.
+    fn take_snapshot(&self) -> HistogramSnapshot {
+        let totals = self.totals();
+        let mut reported = self.reported.lock().unwrap();
+        let mut snapshot = totals.since(&reported);
+        snapshot.max = self.max.swap(0, Ordering::Relaxed);
+        *reported = totals;
+        snapshot
+    }
+}
+
+#[derive(Default)]
+pub struct MetricsRegistry {
+    counters: RwLock<HashMap<&'static str, Arc<Counter>>>,
+    histograms: RwLock<HashMap<&'static str, Arc<Histogram>>>,
+}
+
+impl MetricsRegistry {
+    /// The counter `name`, registered on first use.
+    pub fn counter(&self, name: &'static str) -> Arc<Counter> {
+        if let Some(counter) = self.counters.read().unwrap().get(name) {
+            return counter.clone();
+        }
+        self.counters
+            .write()
+            .unwrap()
+            .entry(name)
+            .or_default()
+            .clone()
+    }
+
+    /// The histogram `name`, registered on first use.
+    pub fn histogram(&self, name: &'static str) -> Arc<Histogram> {
+        if let Some(histogram) = self.histograms.read().unwrap().get(name) {
+            return histogram.clone();
+        }
+        self.histograms
+            .write()
+            .unwrap()
+            .entry(name)
+            .or_default()
+            .clone()
+    }
+
+    /// The totals of the counters, by name.
+    pub fn counter_values(&self) -> Vec<(&'static str, u64)> {
+        let mut values: Vec<(&'static str, u64)> = self
+            .counters
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, counter)| (*name, counter.get()))
+            .collect();
+        values.sort_unstable();
+        values
+    }
+
+    /// The totals of the histograms, by name.
+    pub fn histogram_totals(&self) -> Vec<(&'static str, HistogramSnapshot)> {
+        let mut totals: Vec<(&'static str, HistogramSnapshot)> = self
+            .histograms
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(name, histogram)| (*name, histogram.totals()))
+            .collect();
+        totals.sort_unstable_by_key(|(name, _)| *name);
+        totals
+    }
+
+    /// Reports the registry to solana_metrics and starts a new period.
+    pub fn report(&self) {
+        for (name, counter) in self.counters.read().unwrap().iter() {
+            let delta = counter.take_delta();
+            if delta > 0 {
+                submit(
+                    DataPoint::new(*name).add_field_i64("count", delta as i64),
+                    Level::Info,
+                );
+            }
+        }
+        for (name, histogram) in self.histograms.read().unwrap().iter() {
+            let snapshot = histogram.take_snapshot();
+            if snapshot.count == 0 {
+                continue;
+            }
+            submit(
+                DataPoint::new(*name)
+                    .add_field_i64("samples", snapshot.count as i64)
+                    .add_field_i64("mean", snapshot.mean() as i64)
+                    .add_field_i64("p50", snapshot.percentile(50) as i64)
+                    .add_field_i64("p90", snapshot.percentile(90) as i64)
+                    .add_field_i64("p99", snapshot.percentile(99) as i64)
+                    .add_field_i64("max", snapshot.max as i64),
+                Level::Info,
+            );
+        }
+    }
+}
+
+/// The registry of the current plugin instance.
+pub fn registry() -> Arc<MetricsRegistry> {
+    instance::with_current(|instance| instance.metrics().clone())
+}
+
+/// Submits `point` to solana_metrics, tagged with the name of a named instance. The
+/// host id is shared by every instance in the validator, the tag tells them apart.
+pub fn submit(point: &mut DataPoint, level: Level) {
+    instance::with_current(|instance| {
+        if let Some(name) = instance.name() {
+            point.add_tag("instance", name);
+        }
+    });
+    solana_metrics::submit(point.to_owned(), level);
+}
+
+/// The counters of a fixed set of names, resolved once in the registry of the current
+/// instance, for hot paths that would otherwise look a counter up for every event.
+pub struct CounterHandles<const N: usize>([Arc<Counter>; N]);
+
+impl<const N: usize> CounterHandles<N> {
+    pub fn new(names: [&'static str; N]) -> Self {
+        let registry = registry();
+        Self(names.map(|name| registry.counter(name)))
+    }
+
+    /// The counter of the `index`th name.
+    pub fn get(&self, index: usize) -> &Counter {
+        &self.0[index]
+    }
+}
+
+/// Adds `value` to the counter `name`.
+pub fn inc_counter(name: &'static str, value: usize) {
+    registry().counter(name).add(value as u64);
+}
+
+/// Records `value` in the histogram `name`.
+pub fn record(name: &'static str, value: u64) {
+    registry().histogram(name).record(value);
+}
+
+/// Reports the registry periodically, and once more when joined.
+pub(crate) struct MetricsReporter {
+    task: PeriodicTask,
+}
+
+impl MetricsReporter {
+    pub fn spawn() -> Self {
+        let task = PeriodicTask::spawn_thread(
+            "mongodb-metrics",
+            FirstRun::AfterInterval,
+            DEFAULT_REPORT_INTERVAL,
+            || {
+                registry().report();
+                pending_work().report();
+            },
+        );
+        Self { task }
+    }
+
+    /// The final report reads the registry of the current instance, the reporter is
+    /// joined by the plugin instance it reports.
+    pub fn join(&mut self) {
+        self.task.join();
+        registry().report();
+        debug!("Reported the final metrics");
+    }
+}
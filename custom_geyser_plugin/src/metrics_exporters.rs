@@ -0,0 +1,286 @@
+/// Prometheus and StatsD exporters of the metrics registry.
+///
+/// Like the solana_metrics reporter, the exporters only read the registry of their
+/// instance. The Prometheus exporter serves the totals since the plugin loaded at
+/// `http://<prometheus_bind_address>/metrics` in the text exposition format: a
+/// counter as `<name>_total`, a histogram as `<name>_bucket`, `<name>_sum` and
+/// `<name>_count`, the dashes of the names replaced by underscores and a named
+/// instance given as the `instance` label. The StatsD exporter sends, every
+/// `statsd_interval_ms`, the increase of every counter as a StatsD counter and the
+/// percentiles of the values a histogram recorded since the previous send as gauges
+/// `<name>.p50`, `.p90`, `.p99` and `.max`, prefixed with `<instance_name>.` for a
+/// named instance.
+use {
+    crate::{
+        instance,
+        metrics::{self, HistogramSnapshot},
+    },
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    std::{
+        collections::HashMap,
+        fmt::Write as _,
+        io::{self, BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream, UdpSocket},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{sleep, Builder, JoinHandle},
+        time::Duration,
+    },
+};
+
+const DEFAULT_STATSD_INTERVAL_MS: u64 = 10000;
+/// Keeps a datagram within the MTU of common networks.
+const MAX_STATSD_DATAGRAM_BYTES: usize = 1432;
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MetricsExportersConfig {
+    /// Serves the metrics to Prometheus on this address, e.g. "0.0.0.0:9464".
+    pub prometheus_bind_address: Option<String>,
+    /// Sends the metrics to the StatsD server at this address over UDP, e.g.
+    /// "127.0.0.1:8125".
+    pub statsd_address: Option<String>,
+    /// Milliseconds between two sends to StatsD. The default is 10000.
+    pub statsd_interval_ms: Option<u64>,
+}
+
+/// The registry of the current instance in the Prometheus text exposition format.
+fn prometheus_text() -> String {
+    let registry = metrics::registry();
+    let instance_label = instance::current()
+        .name()
+        .map(|name| format!("instance=\"{}\"", name));
+    // The label set of a sample, with the label of a bucket when given.
+    let labels = |bucket_label: Option<String>| {
+        let labels: Vec<String> = instance_label.iter().cloned().chain(bucket_label).collect();
+        if labels.is_empty() {
+            String::default()
+        } else {
+            format!("{{{}}}", labels.join(","))
+        }
+    };
+    let mut text = String::default();
+    for (name, total) in registry.counter_values() {
+        let name = name.replace('-', "_");
+        let _ = writeln!(text, "# TYPE {} counter", name);
+        let _ = writeln!(text, "{}_total{} {}", name, labels(None), total);
+    }
+    for (name, totals) in registry.histogram_totals() {
+        let name = name.replace('-', "_");
+        let _ = writeln!(text, "# TYPE {} histogram", name);
+        let buckets = totals
+            .cumulative_buckets()
+            .into_iter()
+            .map(|(upper_bound, count)| (upper_bound.to_string(), count))
+            .chain([("+Inf".to_string(), totals.count)]);
+        for (upper_bound, count) in buckets {
+            let bucket_label = format!("le=\"{}\"", upper_bound);
+            let _ = writeln!(text, "{}_bucket{} {}", name, labels(Some(bucket_label)), count);
+        }
+        let _ = writeln!(text, "{}_sum{} {}", name, labels(None), totals.sum);
+        let _ = writeln!(text, "{}_count{} {}", name, labels(None), totals.count);
+    }
+    text
+}
+
+/// Answers one scrape. Only `GET /metrics` is served.
+fn serve_scrape(stream: TcpStream) -> io::Result<()> {
+    stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request_line = String::default();
+    reader.read_line(&mut request_line)?;
+    // The headers are read up to the blank line ending them and ignored.
+    let mut header = String::default();
+    while reader.read_line(&mut header)? > 0 && !header.trim_end().is_empty() {
+        header.clear();
+    }
+    let mut stream = stream;
+    let mut parts = request_line.split_whitespace();
+    if parts.next() != Some("GET") || parts.next() != Some("/metrics") {
+        return stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n");
+    }
+    let body = prometheus_text();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+        body.len(),
+        body
+    )
+}
+
+/// The StatsD lines of the increase since `previous`, which is updated to the totals.
+fn statsd_lines(
+    prefix: &str,
+    previous_counters: &mut HashMap<&'static str, u64>,
+    previous_histograms: &mut HashMap<&'static str, HistogramSnapshot>,
+) -> Vec<String> {
+    let registry = metrics::registry();
+    let mut lines = Vec::default();
+    for (name, total) in registry.counter_values() {
+        let previous = previous_counters.insert(name, total).unwrap_or_default();
+        if total > previous {
+            lines.push(format!("{}{}:{}|c", prefix, name, total - previous));
+        }
+    }
+    for (name, totals) in registry.histogram_totals() {
+        let period = match previous_histograms.get(name) {
+            Some(previous) => totals.since(previous),
+            None => totals.since(&HistogramSnapshot::default()),
+        };
+        previous_histograms.insert(name, totals);
+        if period.count == 0 {
+            continue;
+        }
+        for (suffix, value) in [
+            ("p50", period.percentile(50)),
+            ("p90", period.percentile(90)),
+            ("p99", period.percentile(99)),
+            ("max", period.max),
+        ] {
+            lines.push(format!("{}{}.{}:{}|g", prefix, name, suffix, value));
+        }
+    }
+    lines
+}
+
+/// Sends `lines` to `socket`, as many to a datagram as fit.
+fn send_statsd_lines(socket: &UdpSocket, lines: &[String]) -> io::Result<()> {
+    let mut datagram = String::default();
+    for line in lines {
+        if !datagram.is_empty() && datagram.len() + 1 + line.len() > MAX_STATSD_DATAGRAM_BYTES {
+            socket.send(datagram.as_bytes())?;
+            datagram.clear();
+        }
+        if !datagram.is_empty() {
+            datagram.push('\n');
+        }
+        datagram.push_str(line);
+    }
+    if !datagram.is_empty() {
+        socket.send(datagram.as_bytes())?;
+    }
+    Ok(())
+}
+
+pub(crate) struct MetricsExporters {
+    exit: Arc<AtomicBool>,
+    threads: Vec<JoinHandle<()>>,
+}
+
+impl MetricsExporters {
+    /// Starts the exporters configured in `config`. The address of either exporter is
+    /// bound before any of them starts, so a failure leaves no thread behind.
+    pub fn spawn(config: &MetricsExportersConfig) -> io::Result<Self> {
+        let listener = config
+            .prometheus_bind_address
+            .as_ref()
+            .map(|bind_address| {
+                let listener = TcpListener::bind(bind_address)?;
+                listener.set_nonblocking(true)?;
+                info!("Serving the metrics to Prometheus on {}", bind_address);
+                Ok::<_, io::Error>(listener)
+            })
+            .transpose()?;
+        let socket = config
+            .statsd_address
+            .as_ref()
+            .map(|statsd_address| {
+                let socket = UdpSocket::bind("0.0.0.0:0")?;
+                socket.connect(statsd_address)?;
+                Ok::<_, io::Error>(socket)
+            })
+            .transpose()?;
+
+        let exit = Arc::new(AtomicBool::new(false));
+        let mut threads = Vec::default();
+        if let Some(listener) = listener {
+            let exit = exit.clone();
+            threads.push(
+                Builder::new()
+                    .name("mongodb-prometheus".to_string())
+                    .spawn(instance::bind(move || {
+                        while !exit.load(Ordering::Relaxed) {
+                            match listener.accept() {
+                                Ok((stream, peer)) => {
+                                    let result = stream
+                                        .set_nonblocking(false)
+                                        .and_then(|()| serve_scrape(stream));
+                                    if let Err(err) = result {
+                                        warn!("Failed to serve the metrics to {}: {}", peer, err);
+                                    }
+                                }
+                                Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                                    sleep(EXIT_POLL_INTERVAL);
+                                }
+                                Err(err) => {
+                                    warn!("Failed to accept a Prometheus scrape: {}", err);
+                                    sleep(EXIT_POLL_INTERVAL);
+                                }
+                            }
+                        }
+                    }))?,
+            );
+        }
+        if let Some(socket) = socket {
+            let interval = Duration::from_millis(
+                config
+                    .statsd_interval_ms
+                    .unwrap_or(DEFAULT_STATSD_INTERVAL_MS)
+                    .max(1),
+            );
+            info!(
+                "Sending the metrics to StatsD at {} every {:?}",
+                socket.peer_addr()?,
+                interval
+            );
+            let prefix = instance::current()
+                .name()
+                .map(|name| format!("{}.", name))
+                .unwrap_or_default();
+            let exit = exit.clone();
+            threads.push(
+                Builder::new()
+                    .name("mongodb-statsd".to_string())
+                    .spawn(instance::bind(move || {
+                        let (mut counters, mut histograms) = Default::default();
+                        let mut failed = false;
+                        let mut elapsed = Duration::ZERO;
+                        loop {
+                            let exiting = exit.load(Ordering::Relaxed);
+                            if exiting || elapsed >= interval {
+                                elapsed = Duration::ZERO;
+                                let lines = statsd_lines(&prefix, &mut counters, &mut histograms);
+                                match send_statsd_lines(&socket, &lines) {
+                                    Ok(()) => failed = false,
+                                    // Logged once per outage rather than every interval.
+                                    Err(err) if !failed => {
+                                        failed = true;
+                                        warn!("Failed to send the metrics to StatsD: {}", err);
+                                    }
+                                    Err(_) => {}
+                                }
+                            }
+                            if exiting {
+                                break;
+                            }
+                            let step = EXIT_POLL_INTERVAL.min(interval);
+                            sleep(step);
+                            elapsed += step;
+                        }
+                    }))?,
+            );
+        }
+        Ok(Self { exit, threads })
+    }
+
+    pub fn join(&mut self) {
+        self.exit.store(true, Ordering::Relaxed);
+        for thread in self.threads.drain(..) {
+            let _ = thread.join();
+        }
+    }
+}
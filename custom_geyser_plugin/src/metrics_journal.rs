@@ -0,0 +1,162 @@
+/// A local journal of the pipeline metrics, for reconstructing an incident after the
+/// fact when the metrics backend was affected as well.
+///
+/// Every `interval_ms` one JSON line is appended to `path` and synced to disk: the
+/// sequence number, the time, the length of the work queue and the counters that
+/// moved since the previous line with their increase, e.g. the updates received by
+/// the selectors, the work items taken by the workers and the failed writes. The
+/// journal is a ring of two files: once `path` reaches `max_bytes` it is moved to
+/// `<path>.1`, replacing the previous one, and a new `path` is started.
+use {
+    crate::{instance, metrics},
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    serde_json::{json, Map, Value},
+    std::{
+        collections::HashMap,
+        fs::{self, File, OpenOptions},
+        io::{self, Write},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{sleep, Builder, JoinHandle},
+        time::{Duration, SystemTime, UNIX_EPOCH},
+    },
+};
+
+const DEFAULT_JOURNAL_INTERVAL_MS: u64 = 1000;
+const DEFAULT_JOURNAL_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct MetricsJournalConfig {
+    /// The journal file, the previous one is kept as `<path>.1`.
+    pub path: String,
+    /// Milliseconds between two lines. The default is 1000.
+    pub interval_ms: Option<u64>,
+    /// The size a journal file is rotated at. The default is 64MB.
+    pub max_bytes: Option<u64>,
+}
+
+struct JournalWriter {
+    path: String,
+    max_bytes: u64,
+    file: File,
+    bytes: u64,
+    sequence: u64,
+    totals: HashMap<&'static str, u64>,
+}
+
+impl JournalWriter {
+    fn open(config: &MetricsJournalConfig) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)?;
+        Ok(Self {
+            path: config.path.clone(),
+            max_bytes: config.max_bytes.unwrap_or(DEFAULT_JOURNAL_MAX_BYTES),
+            bytes: file.metadata()?.len(),
+            file,
+            sequence: 0,
+            // The first line holds the totals since the plugin loaded.
+            totals: HashMap::default(),
+        })
+    }
+
+    fn append(&mut self, queue_len: usize) -> io::Result<()> {
+        let mut counters = Map::new();
+        for (name, total) in metrics::registry().counter_values() {
+            let previous = self.totals.insert(name, total).unwrap_or_default();
+            if total > previous {
+                counters.insert(name.to_string(), Value::from(total - previous));
+            }
+        }
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let mut line = json!({
+            "seq": self.sequence,
+            "ts_ms": timestamp_ms,
+            "queue_len": queue_len,
+            "counters": counters,
+        })
+        .to_string();
+        line.push('\n');
+        self.sequence += 1;
+
+        if self.bytes > 0 && self.bytes + line.len() as u64 > self.max_bytes {
+            fs::rename(&self.path, format!("{}.1", self.path))?;
+            self.file = File::create(&self.path)?;
+            self.bytes = 0;
+        }
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()?;
+        self.bytes += line.len() as u64;
+        Ok(())
+    }
+}
+
+pub(crate) struct MetricsJournal {
+    exit: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl MetricsJournal {
+    /// Starts journaling, `queue_len` reading the length of the work queue.
+    pub fn spawn(
+        config: &MetricsJournalConfig,
+        queue_len: impl Fn() -> usize + Send + 'static,
+    ) -> io::Result<Self> {
+        let mut writer = JournalWriter::open(config)?;
+        let interval = Duration::from_millis(
+            config
+                .interval_ms
+                .unwrap_or(DEFAULT_JOURNAL_INTERVAL_MS)
+                .max(1),
+        );
+        info!("Journaling the metrics to {} every {:?}", config.path, interval);
+        let exit = Arc::new(AtomicBool::new(false));
+        let exit_clone = exit.clone();
+        let thread = Builder::new()
+            .name("mongodb-metrics-journal".to_string())
+            .spawn(instance::bind(move || {
+                let mut failed = false;
+                let mut elapsed = Duration::ZERO;
+                while !exit_clone.load(Ordering::Relaxed) {
+                    let step = Duration::from_millis(100).min(interval);
+                    sleep(step);
+                    elapsed += step;
+                    if elapsed < interval {
+                        continue;
+                    }
+                    elapsed = Duration::ZERO;
+                    match writer.append(queue_len()) {
+                        Ok(()) => failed = false,
+                        // Logged once per outage rather than every interval.
+                        Err(err) if !failed => {
+                            failed = true;
+                            error!("Failed to write the metrics journal: {}", err);
+                        }
+                        Err(_) => {}
+                    }
+                }
+                if let Err(err) = writer.append(queue_len()) {
+                    error!("Failed to write the metrics journal: {}", err);
+                }
+            }))
+            .unwrap();
+        Ok(Self {
+            exit,
+            thread: Some(thread),
+        })
+    }
+
+    pub fn join(&mut self) {
+        self.exit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
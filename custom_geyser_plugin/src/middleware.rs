@@ -0,0 +1,212 @@
+/// Transforms applied to documents before they are persisted.
+///
+/// A middleware takes a document about to be written and returns the document to
+/// write instead, or None to drop it. The chain runs its middlewares in order, each
+/// one receiving the output of the previous one, and stops at the first that drops
+/// the document. The built-in transforms are listed in `middleware` in the config in
+/// the order they should run; library users can push their own functions.
+use {
+    crate::{
+        address_labels::{AddressLabels, DEFAULT_LABELS_FIELD},
+        geyser_plugin_mongodb::{GeyserPluginMongoDBConfig, GeyserPluginMongoDbError},
+        metrics,
+    },
+    mongodb::bson::{doc, Bson, Document},
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_sdk::hash::hash,
+    std::collections::HashMap,
+};
+
+/// Fields tried in order for the sampling key when none is configured.
+const DEFAULT_SAMPLE_KEYS: [&str; 3] = ["signature", "pubkey", "slot"];
+
+/// What is being written.
+#[derive(Clone, Copy, Debug)]
+pub struct MiddlewareContext<'a> {
+    pub collection: &'a str,
+    pub slot: Option<u64>,
+}
+
+pub type Middleware =
+    Box<dyn Fn(Document, &MiddlewareContext) -> Option<Document> + Send + Sync>;
+
+/// A built-in middleware. Each one applies to the listed collections, or to all
+/// collections when `collections` is left out.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MiddlewareConfig {
+    /// Removes top level fields.
+    DropFields {
+        collections: Option<Vec<String>>,
+        fields: Vec<String>,
+    },
+    /// Sets `field` to a fixed string.
+    Tag {
+        collections: Option<Vec<String>>,
+        field: String,
+        value: String,
+    },
+    /// Sets `field` to the instance name and plugin version that wrote the document.
+    Enrich {
+        collections: Option<Vec<String>>,
+        field: String,
+    },
+    /// Keeps `percent` percent of the documents. The decision is a hash of the
+    /// `key` field, so all documents sharing a key, e.g. every version of an
+    /// account, are kept or dropped together.
+    Sample {
+        collections: Option<Vec<String>>,
+        percent: u8,
+        key: Option<String>,
+    },
+    /// Sets `field`, `labels` by default, to the labels of the addresses of the
+    /// document. Labels are listed in `labels` and read from `collection` when the
+    /// plugin loads.
+    Label {
+        collections: Option<Vec<String>>,
+        field: Option<String>,
+        #[serde(default)]
+        labels: HashMap<String, String>,
+        collection: Option<String>,
+    },
+}
+
+fn applies_to(collections: &Option<Vec<String>>, context: &MiddlewareContext) -> bool {
+    collections
+        .as_ref()
+        .map(|collections| collections.iter().any(|name| name == context.collection))
+        .unwrap_or(true)
+}
+
+fn sample_bucket(document: &Document, key: &Option<String>) -> Option<u8> {
+    let value = match key {
+        Some(key) => document.get(key),
+        None => DEFAULT_SAMPLE_KEYS
+            .iter()
+            .find_map(|key| document.get(*key)),
+    }?;
+    let bytes = match value {
+        Bson::Binary(binary) => binary.bytes.clone(),
+        value => value.to_string().into_bytes(),
+    };
+    Some((u64::from_le_bytes(hash(&bytes).to_bytes()[..8].try_into().unwrap()) % 100) as u8)
+}
+
+impl MiddlewareConfig {
+    fn build(self, config: &GeyserPluginMongoDBConfig) -> Result<Middleware, GeyserPluginError> {
+        Ok(match self {
+            MiddlewareConfig::DropFields {
+                collections,
+                fields,
+            } => Box::new(move |mut document, context| {
+                if applies_to(&collections, context) {
+                    for field in &fields {
+                        document.remove(field);
+                    }
+                }
+                Some(document)
+            }),
+            MiddlewareConfig::Tag {
+                collections,
+                field,
+                value,
+            } => Box::new(move |mut document, context| {
+                if applies_to(&collections, context) {
+                    document.insert(field.clone(), value.clone());
+                }
+                Some(document)
+            }),
+            MiddlewareConfig::Enrich { collections, field } => {
+                let enrichment = doc! {
+                    "instance": config.instance_name.clone().unwrap_or_default(),
+                    "plugin_version": env!("CARGO_PKG_VERSION"),
+                };
+                Box::new(move |mut document, context| {
+                    if applies_to(&collections, context) {
+                        document.insert(field.clone(), enrichment.clone());
+                    }
+                    Some(document)
+                })
+            }
+            MiddlewareConfig::Sample {
+                collections,
+                percent,
+                key,
+            } => {
+                if percent > 100 {
+                    return Err(GeyserPluginError::Custom(Box::new(
+                        GeyserPluginMongoDbError::ConfigurationError {
+                            msg: format!(
+                                "The \"sample\" middleware percent must be at most 100, got {}",
+                                percent
+                            ),
+                        },
+                    )));
+                }
+                Box::new(move |document, context| {
+                    if !applies_to(&collections, context) {
+                        return Some(document);
+                    }
+                    // Documents without the key are kept, sampling them would be arbitrary.
+                    match sample_bucket(&document, &key) {
+                        Some(bucket) if bucket >= percent => None,
+                        _ => Some(document),
+                    }
+                })
+            }
+            MiddlewareConfig::Label {
+                collections,
+                field,
+                labels,
+                collection,
+            } => {
+                let address_labels = AddressLabels::load(config, &labels, collection.as_deref())?;
+                let field = field.unwrap_or_else(|| DEFAULT_LABELS_FIELD.to_string());
+                Box::new(move |mut document, context| {
+                    if applies_to(&collections, context) {
+                        address_labels.stamp(&mut document, &field);
+                    }
+                    Some(document)
+                })
+            }
+        })
+    }
+}
+
+/// The middlewares applied, in order, to every document before it is written.
+#[derive(Default)]
+pub struct MiddlewareChain {
+    middlewares: Vec<Middleware>,
+}
+
+impl MiddlewareChain {
+    pub fn from_config(config: &GeyserPluginMongoDBConfig) -> Result<Self, GeyserPluginError> {
+        let mut chain = Self::default();
+        for middleware in config.middleware.clone().unwrap_or_default() {
+            chain.push(middleware.build(config)?);
+        }
+        Ok(chain)
+    }
+
+    /// Appends `middleware` to the end of the chain.
+    pub fn push(&mut self, middleware: Middleware) {
+        self.middlewares.push(middleware);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.middlewares.is_empty()
+    }
+
+    /// The document to write in place of `document`, None when it is dropped.
+    pub fn apply(&self, document: Document, context: &MiddlewareContext) -> Option<Document> {
+        let document = self
+            .middlewares
+            .iter()
+            .try_fold(document, |document, middleware| middleware(document, context));
+        if document.is_none() {
+            metrics::inc_counter("geyser-plugin-mongodb-middleware-dropped", 1);
+        }
+        document
+    }
+}
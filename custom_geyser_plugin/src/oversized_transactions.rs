@@ -0,0 +1,195 @@
+/// Detects transaction documents exceeding a size threshold, e.g. because of huge
+/// logs or many inner instructions, and shrinks them with a configured chain of
+/// downgrades, so one transaction never fails a write with a document too large.
+///
+/// The document is encoded to measure it. While it exceeds `threshold_bytes`
+/// the next downgrade of `downgrades` is applied, and the names of those applied are
+/// stored in `downgraded`. `skip` drops the transaction. A document still exceeding
+/// the 16MB BSON limit at the end of the chain is dropped as well. Oversized
+/// transactions are counted in total and per program invoked, the latter reported
+/// with the write statistics.
+use {
+    crate::{geyser_plugin_mongodb::GeyserPluginMongoDbError, metrics},
+    log::*,
+    mongodb::bson::{self, Bson, Document},
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_metrics::datapoint::DataPoint,
+    std::collections::HashMap,
+};
+
+const DEFAULT_THRESHOLD_BYTES: usize = 1024 * 1024;
+const DEFAULT_MAX_LOG_MESSAGES: usize = 100;
+/// The BSON document limit of the server.
+const MAX_BSON_DOCUMENT_BYTES: usize = 16 * 1024 * 1024;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransactionDowngrade {
+    /// Keeps the first `max_log_messages` log messages.
+    TruncateLogs,
+    DropLogs,
+    DropInnerInstructions,
+    DropDecodedInstructions,
+    DropTokenBalances,
+    /// Does not store the transaction.
+    Skip,
+}
+
+impl TransactionDowngrade {
+    fn as_str(&self) -> &'static str {
+        match self {
+            TransactionDowngrade::TruncateLogs => "truncate_logs",
+            TransactionDowngrade::DropLogs => "drop_logs",
+            TransactionDowngrade::DropInnerInstructions => "drop_inner_instructions",
+            TransactionDowngrade::DropDecodedInstructions => "drop_decoded_instructions",
+            TransactionDowngrade::DropTokenBalances => "drop_token_balances",
+            TransactionDowngrade::Skip => "skip",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct OversizedTransactionConfig {
+    /// The encoded document size from which a transaction is oversized. The default
+    /// is 1MB.
+    pub threshold_bytes: Option<usize>,
+    /// The downgrades applied in order until the document fits. The default is
+    /// ["truncate_logs", "drop_inner_instructions", "drop_logs"].
+    pub downgrades: Option<Vec<TransactionDowngrade>>,
+    /// The log messages kept by "truncate_logs". The default is 100.
+    pub max_log_messages: Option<usize>,
+}
+
+fn encoded_len(document: &Document) -> usize {
+    bson::to_vec(document).map_or(usize::MAX, |encoded| encoded.len())
+}
+
+pub struct OversizedTransactions {
+    threshold_bytes: usize,
+    downgrades: Vec<TransactionDowngrade>,
+    max_log_messages: usize,
+    /// Oversized transactions per program invoked since the last report.
+    by_program: HashMap<String, usize>,
+}
+
+impl OversizedTransactions {
+    pub fn new(config: &OversizedTransactionConfig) -> Result<Self, GeyserPluginError> {
+        let threshold_bytes = config.threshold_bytes.unwrap_or(DEFAULT_THRESHOLD_BYTES);
+        if threshold_bytes == 0 || threshold_bytes > MAX_BSON_DOCUMENT_BYTES {
+            return Err(GeyserPluginError::Custom(Box::new(
+                GeyserPluginMongoDbError::ConfigurationError {
+                    msg: format!(
+                        "\"oversized_transactions\": threshold_bytes must be positive and at \
+                         most {}",
+                        MAX_BSON_DOCUMENT_BYTES
+                    ),
+                },
+            )));
+        }
+        Ok(Self {
+            threshold_bytes,
+            downgrades: config.downgrades.clone().unwrap_or_else(|| {
+                vec![
+                    TransactionDowngrade::TruncateLogs,
+                    TransactionDowngrade::DropInnerInstructions,
+                    TransactionDowngrade::DropLogs,
+                ]
+            }),
+            max_log_messages: config.max_log_messages.unwrap_or(DEFAULT_MAX_LOG_MESSAGES),
+            by_program: HashMap::default(),
+        })
+    }
+
+    /// Applies the downgrades `document` needs to fit. Returns false when the
+    /// transaction is not to be stored.
+    pub fn shrink(&mut self, document: &mut Document) -> bool {
+        let mut len = encoded_len(document);
+        if len <= self.threshold_bytes {
+            return true;
+        }
+        metrics::inc_counter("geyser-plugin-mongodb-oversized-transactions", 1);
+        for program_id in document
+            .get_array("program_ids")
+            .into_iter()
+            .flatten()
+            .filter_map(Bson::as_str)
+        {
+            *self.by_program.entry(program_id.to_string()).or_default() += 1;
+        }
+        let signature = document.get_str("signature").unwrap_or_default().to_string();
+        let mut applied = Vec::default();
+        for downgrade in &self.downgrades {
+            if len <= self.threshold_bytes {
+                break;
+            }
+            if !self.apply(*downgrade, document) {
+                continue;
+            }
+            if *downgrade == TransactionDowngrade::Skip {
+                warn!("Skipping the oversized transaction {} of {} bytes", signature, len);
+                metrics::inc_counter("geyser-plugin-mongodb-oversized-transactions-skipped", 1);
+                return false;
+            }
+            applied.push(downgrade.as_str());
+            len = encoded_len(document);
+        }
+        if len > MAX_BSON_DOCUMENT_BYTES {
+            warn!(
+                "Skipping the transaction {}, {} bytes after the downgrades {:?}",
+                signature, len, applied
+            );
+            metrics::inc_counter("geyser-plugin-mongodb-oversized-transactions-skipped", 1);
+            return false;
+        }
+        debug!("Downgraded the transaction {} with {:?}", signature, applied);
+        if !applied.is_empty() {
+            document.insert("downgraded", applied);
+        }
+        true
+    }
+
+    /// Applies `downgrade` to `document`, false when it has nothing to remove.
+    fn apply(&self, downgrade: TransactionDowngrade, document: &mut Document) -> bool {
+        match downgrade {
+            TransactionDowngrade::Skip => return true,
+            TransactionDowngrade::DropDecodedInstructions => {
+                return document.remove("decoded_instructions").is_some()
+            }
+            _ => {}
+        }
+        let Ok(meta) = document.get_document_mut("meta") else {
+            return false;
+        };
+        match downgrade {
+            TransactionDowngrade::TruncateLogs => match meta.get_array_mut("log_messages") {
+                Ok(logs) if logs.len() > self.max_log_messages => {
+                    logs.truncate(self.max_log_messages);
+                    true
+                }
+                _ => false,
+            },
+            TransactionDowngrade::DropLogs => meta.remove("log_messages").is_some(),
+            TransactionDowngrade::DropInnerInstructions => {
+                meta.remove("inner_instructions").is_some()
+            }
+            TransactionDowngrade::DropTokenBalances => {
+                let pre = meta.remove("pre_token_balances").is_some();
+                meta.remove("post_token_balances").is_some() || pre
+            }
+            TransactionDowngrade::Skip | TransactionDowngrade::DropDecodedInstructions => false,
+        }
+    }
+
+    /// Reports the oversized transactions per program and starts a new period.
+    pub fn report(&mut self) {
+        for (program, transactions) in self.by_program.drain() {
+            metrics::submit(
+                DataPoint::new("geyser-plugin-mongodb-oversized-transactions-by-program")
+                    .add_field_str("program", &program)
+                    .add_field_i64("transactions", transactions as i64),
+                Level::Info,
+            );
+        }
+    }
+}
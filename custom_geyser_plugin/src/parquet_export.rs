@@ -0,0 +1,157 @@
+/// Parquet export of the current account state, built with the `parquet` feature.
+///
+/// The accounts are read like the BSON export, through a snapshot session where the
+/// server supports one, and written as one row per account with the standard field
+/// names: `pubkey`, `owner`, `lamports`, `slot`, `write_version`, `executable`,
+/// `rent_epoch`, `data`, `data_hash` and `txn_signature`. The last three are null when
+/// unset, e.g. `data` when it was omitted, redacted or moved to GridFS. Row groups
+/// hold up to `BATCH_ROWS` accounts and are compressed with Snappy.
+use {
+    crate::{
+        export::{read_current_accounts, ExportSummary},
+        queries::StoredAccount,
+        schema::{expand_account_document, SchemaProfile},
+    },
+    arrow_array::{
+        builder::{BinaryBuilder, BooleanBuilder, Int64Builder, StringBuilder},
+        ArrayRef, RecordBatch,
+    },
+    arrow_schema::{DataType, Field, Schema, SchemaRef},
+    mongodb::{bson, error::Result, Client, Database},
+    parquet::{arrow::ArrowWriter, basic::Compression, file::properties::WriterProperties},
+    std::{
+        io::{self, Write},
+        sync::Arc,
+    },
+};
+
+const BATCH_ROWS: usize = 8192;
+
+fn account_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("pubkey", DataType::Utf8, false),
+        Field::new("owner", DataType::Utf8, false),
+        Field::new("lamports", DataType::Int64, false),
+        Field::new("slot", DataType::Int64, false),
+        Field::new("write_version", DataType::Int64, false),
+        Field::new("executable", DataType::Boolean, false),
+        Field::new("rent_epoch", DataType::Int64, false),
+        Field::new("data", DataType::Binary, true),
+        Field::new("data_hash", DataType::Utf8, true),
+        Field::new("txn_signature", DataType::Utf8, true),
+    ]))
+}
+
+fn to_error(err: impl std::error::Error + Send + Sync + 'static) -> mongodb::error::Error {
+    io::Error::new(io::ErrorKind::Other, err).into()
+}
+
+/// The columns of the accounts not written yet.
+#[derive(Default)]
+struct AccountColumns {
+    pubkey: StringBuilder,
+    owner: StringBuilder,
+    lamports: Int64Builder,
+    slot: Int64Builder,
+    write_version: Int64Builder,
+    executable: BooleanBuilder,
+    rent_epoch: Int64Builder,
+    data: BinaryBuilder,
+    data_hash: StringBuilder,
+    txn_signature: StringBuilder,
+    rows: usize,
+}
+
+impl AccountColumns {
+    fn append(&mut self, account: &StoredAccount) {
+        self.pubkey.append_value(&account.pubkey);
+        self.owner.append_value(&account.owner);
+        self.lamports.append_value(account.lamports);
+        self.slot.append_value(account.slot);
+        self.write_version.append_value(account.write_version);
+        self.executable.append_value(account.executable);
+        self.rent_epoch.append_value(account.rent_epoch);
+        self.data.append_option(account.data.as_ref().map(|data| &data.bytes));
+        self.data_hash.append_option(account.data_hash.as_ref());
+        self.txn_signature.append_option(account.txn_signature.as_ref());
+        self.rows += 1;
+    }
+
+    /// The accounts appended since the previous call.
+    fn finish(&mut self) -> Result<RecordBatch> {
+        self.rows = 0;
+        let columns: Vec<ArrayRef> = vec![
+            Arc::new(self.pubkey.finish()),
+            Arc::new(self.owner.finish()),
+            Arc::new(self.lamports.finish()),
+            Arc::new(self.slot.finish()),
+            Arc::new(self.write_version.finish()),
+            Arc::new(self.executable.finish()),
+            Arc::new(self.rent_epoch.finish()),
+            Arc::new(self.data.finish()),
+            Arc::new(self.data_hash.finish()),
+            Arc::new(self.txn_signature.finish()),
+        ];
+        RecordBatch::try_new(account_schema(), columns).map_err(to_error)
+    }
+}
+
+/// Counts the bytes of the Parquet file.
+struct CountingWriter<W> {
+    inner: W,
+    bytes: u64,
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Writes the current state of every account, or of the accounts owned by `owners`,
+/// to `writer` as a Parquet file. The accounts stored with `profile` are written with
+/// the standard field names. The summary counts the bytes of the file.
+pub async fn export_current_accounts_parquet<W: Write + Send>(
+    client: &Client,
+    database: &Database,
+    profile: SchemaProfile,
+    owners: &[String],
+    writer: W,
+) -> Result<ExportSummary> {
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::SNAPPY)
+        .set_max_row_group_size(BATCH_ROWS)
+        .build();
+    let mut writer = ArrowWriter::try_new(
+        CountingWriter {
+            inner: writer,
+            bytes: 0,
+        },
+        account_schema(),
+        Some(properties),
+    )
+    .map_err(to_error)?;
+    let mut columns = AccountColumns::default();
+    let mut summary = read_current_accounts(client, database, profile, owners, |account| {
+        let account: StoredAccount = bson::from_document(expand_account_document(account))?;
+        columns.append(&account);
+        if columns.rows >= BATCH_ROWS {
+            writer.write(&columns.finish()?).map_err(to_error)?;
+        }
+        Ok(0)
+    })
+    .await?;
+    if columns.rows > 0 {
+        writer.write(&columns.finish()?).map_err(to_error)?;
+    }
+    let mut file = writer.into_inner().map_err(to_error)?;
+    file.flush()?;
+    summary.bytes = file.bytes;
+    Ok(summary)
+}
@@ -0,0 +1,76 @@
+/// The documents buffered by the plugin but not written yet, by slot and collection,
+/// so operators can see which slot holds the writes back.
+///
+/// Every buffer publishes its own breakdown under a source name: each worker its
+/// startup accounts and token index entries, `slot_batching` the accounts of the
+/// slots it buffers. A source replaces its previous breakdown, and the breakdown of
+/// the plugin is the sum over the sources. The metrics reporter submits a data point
+/// per slot and collection pending, plus the oldest slot pending, with the other
+/// metrics. Work items still in the queue are not broken down, the queue length is
+/// reported on its own. Each plugin instance keeps its own breakdown.
+use {
+    crate::{instance, metrics},
+    log::Level,
+    solana_metrics::datapoint::DataPoint,
+    std::{
+        collections::{BTreeMap, HashMap},
+        sync::{Arc, Mutex},
+    },
+};
+
+/// Pending documents by slot and collection.
+pub type PendingCounts = BTreeMap<(u64, &'static str), u64>;
+
+#[derive(Default)]
+pub struct PendingWork {
+    sources: Mutex<HashMap<String, PendingCounts>>,
+}
+
+impl PendingWork {
+    /// Replaces the breakdown of `source`.
+    pub fn publish(&self, source: &str, counts: PendingCounts) {
+        let mut sources = self.sources.lock().unwrap();
+        if counts.is_empty() {
+            sources.remove(source);
+        } else {
+            sources.insert(source.to_string(), counts);
+        }
+    }
+
+    /// The pending documents of every source, by slot and collection.
+    pub fn snapshot(&self) -> PendingCounts {
+        let mut total = PendingCounts::default();
+        for counts in self.sources.lock().unwrap().values() {
+            for (key, documents) in counts {
+                *total.entry(*key).or_default() += documents;
+            }
+        }
+        total
+    }
+
+    /// Submits the breakdown to solana_metrics.
+    pub fn report(&self) {
+        let snapshot = self.snapshot();
+        for ((slot, collection), documents) in &snapshot {
+            metrics::submit(
+                DataPoint::new("geyser-plugin-mongodb-pending-work")
+                    .add_field_i64("slot", *slot as i64)
+                    .add_field_str("collection", collection)
+                    .add_field_i64("documents", *documents as i64),
+                Level::Info,
+            );
+        }
+        let oldest_slot = snapshot.keys().next().map_or(0, |(slot, _)| *slot as i64);
+        metrics::submit(
+            DataPoint::new("geyser-plugin-mongodb-oldest-pending-slot")
+                .add_field_i64("slot", oldest_slot)
+                .add_field_i64("documents", snapshot.values().sum::<u64>() as i64),
+            Level::Info,
+        );
+    }
+}
+
+/// The breakdown of the current plugin instance.
+pub fn pending_work() -> Arc<PendingWork> {
+    instance::with_current(|instance| instance.pending_work().clone())
+}
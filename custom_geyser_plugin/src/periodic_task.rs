@@ -0,0 +1,144 @@
+/// The thread running a background task every interval, e.g. the dead slot collector,
+/// the history pruners or the metrics reporter.
+///
+/// A task against the plugin database owns a current-thread runtime and one client,
+/// connected before the first run and reused by the next ones. A failed run drops the
+/// client, the next run connects again. The exit flag is polled every 100ms so
+/// unloading the plugin does not wait for the interval to pass.
+use {
+    crate::{
+        geyser_plugin_mongodb::GeyserPluginMongoDBConfig,
+        instance,
+        mongodb_client::{plugin_database, SimpleMongoDbClient},
+    },
+    log::*,
+    mongodb::{Client, Database},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::{sleep, Builder, JoinHandle},
+        time::Duration,
+    },
+    tokio::runtime::Runtime,
+};
+
+const EXIT_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// When the first run of a task is due, the next ones follow every interval.
+pub(crate) enum FirstRun {
+    /// Once the interval passed.
+    AfterInterval,
+    /// As soon as the condition holds, e.g. once the startup is done.
+    When(Box<dyn Fn() -> bool + Send>),
+}
+
+pub(crate) struct PeriodicTask {
+    exit: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl PeriodicTask {
+    /// Starts the thread `name`, calling `run` with the runtime and the plugin
+    /// database every `interval`, the first time once `interval` passed. Failures are
+    /// logged as "Failed to `description`".
+    pub fn spawn<F>(
+        name: &str,
+        description: &'static str,
+        interval: Duration,
+        config: &GeyserPluginMongoDBConfig,
+        run: F,
+    ) -> Self
+    where
+        F: FnMut(&Runtime, &Database) -> Result<(), GeyserPluginError> + Send + 'static,
+    {
+        Self::spawn_when(name, description, FirstRun::AfterInterval, interval, config, run)
+    }
+
+    /// Like `spawn`, with the first run due at `first_run`.
+    pub fn spawn_when<F>(
+        name: &str,
+        description: &'static str,
+        first_run: FirstRun,
+        interval: Duration,
+        config: &GeyserPluginMongoDBConfig,
+        mut run: F,
+    ) -> Self
+    where
+        F: FnMut(&Runtime, &Database) -> Result<(), GeyserPluginError> + Send + 'static,
+    {
+        let config = config.clone();
+        let mut runtime: Option<Runtime> = None;
+        let mut client: Option<Client> = None;
+        Self::spawn_thread(name, first_run, interval, move || {
+            if runtime.is_none() {
+                match tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()
+                {
+                    Ok(built) => runtime = Some(built),
+                    Err(err) => {
+                        error!("Failed to create the runtime to {}: {}", description, err);
+                        return;
+                    }
+                }
+            }
+            let runtime = runtime.as_ref().unwrap();
+            let result = match &client {
+                Some(client) => Ok(client.clone()),
+                None => runtime.block_on(SimpleMongoDbClient::connect_to_db(&config)),
+            }
+            .and_then(|connected| {
+                let result = run(runtime, &plugin_database(&connected, &config));
+                client = Some(connected);
+                result
+            });
+            if let Err(err) = result {
+                error!("Failed to {}: {}", description, err);
+                client = None;
+            }
+        })
+    }
+
+    /// Starts the thread `name`, calling `run` every `interval` from `first_run` on.
+    pub fn spawn_thread<F>(name: &str, first_run: FirstRun, interval: Duration, mut run: F) -> Self
+    where
+        F: FnMut() + Send + 'static,
+    {
+        let exit = Arc::new(AtomicBool::new(false));
+        let exit_clone = exit.clone();
+        let thread = Builder::new()
+            .name(name.to_string())
+            .spawn(instance::bind(move || {
+                let mut first_run = Some(first_run);
+                let mut elapsed = Duration::ZERO;
+                while !exit_clone.load(Ordering::Relaxed) {
+                    let due = match &first_run {
+                        Some(FirstRun::When(ready)) => ready(),
+                        Some(FirstRun::AfterInterval) | None => elapsed >= interval,
+                    };
+                    if due {
+                        first_run = None;
+                        elapsed = Duration::ZERO;
+                        run();
+                    }
+                    sleep(EXIT_POLL_INTERVAL);
+                    elapsed += EXIT_POLL_INTERVAL;
+                }
+            }))
+            .unwrap();
+        Self {
+            exit,
+            thread: Some(thread),
+        }
+    }
+
+    pub fn join(&mut self) {
+        self.exit.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,128 @@
+/// Named presets of the config for common setups, selected with `profile`.
+///
+/// A profile presets the selectors, the fields stored, the batching and the
+/// retention of one use case. The config file overrides it key by key: a key set in
+/// the file replaces the preset, objects are merged key by key the same way, and
+/// `null` removes a preset key. The selector sections are the exception, one set in
+/// the file replaces the preset's as a whole.
+use {
+    serde_derive::{Deserialize, Serialize},
+    serde_json::{json, Map, Value},
+};
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IngestionProfile {
+    /// Every account and non-vote transaction, with the indexes and denormalized
+    /// fields of a block explorer.
+    Explorer,
+    /// Token accounts and the transactions of the token programs, with every token
+    /// index.
+    TokenIndexer,
+    /// The transactions of the programs selected, failed ones included, and a short
+    /// account history, written with small batches for low latency.
+    ProgramDebugger,
+    /// The current state of the selected accounts only.
+    Minimal,
+}
+
+impl IngestionProfile {
+    fn preset(&self) -> Value {
+        match self {
+            IngestionProfile::Explorer => json!({
+                "accounts_selector": { "accounts": ["*"] },
+                "transaction_selector": { "mentions": ["*"] },
+                "skip_vote_transactions": true,
+                "decode_accounts": true,
+                "index_token_owner": true,
+                "index_token_mint": true,
+                "backfill_block_time": true,
+                "transaction_enrichment": {},
+                "program_accounts": "hash_only",
+                "batch_size": 100,
+            }),
+            IngestionProfile::TokenIndexer => json!({
+                "accounts_selector": { "owners": ["spl-token", "token-2022"] },
+                "transaction_selector": {
+                    "mentions": [
+                        "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+                        "TokenzQdBNbLqP5VEhdkAS6EPFLZ1VE8VMefKXY1xM",
+                    ],
+                },
+                "skip_vote_transactions": true,
+                "decode_accounts": true,
+                "index_token_owner": true,
+                "index_token_mint": true,
+                "index_token_mint_transactions": true,
+                "index_associated_token_accounts": true,
+                "batch_size": 500,
+            }),
+            IngestionProfile::ProgramDebugger => json!({
+                "skip_vote_transactions": true,
+                "store_failed_transactions": true,
+                "store_account_historical_data": true,
+                "history_retention": { "default_retain_days": 3 },
+                "batch_size": 1,
+                "batch_flush_interval_ms": 50,
+            }),
+            IngestionProfile::Minimal => json!({
+                "skip_vote_transactions": true,
+                "store_failed_transactions": false,
+                "store_account_historical_data": false,
+                "account_storage": "current_state",
+                "program_accounts": "skip_data",
+            }),
+        }
+    }
+}
+
+/// The sections replaced as a whole: merging them would keep the preset's entries
+/// next to the file's, e.g. `"accounts": ["*"]` next to the owners selected.
+const REPLACED_SECTIONS: [&str; 2] = ["accounts_selector", "transaction_selector"];
+
+/// `overrides` merged over `base`, objects key by key.
+fn merge(base: Value, overrides: Value) -> Value {
+    match (base, overrides) {
+        (Value::Object(mut base), Value::Object(overrides)) => {
+            for (key, value) in overrides {
+                let merged = match base.remove(&key) {
+                    Some(base_value) => merge(base_value, value),
+                    None => value,
+                };
+                base.insert(key, merged);
+            }
+            Value::Object(base)
+        }
+        (_, overrides) => overrides,
+    }
+}
+
+fn remove_nulls(value: &mut Value) {
+    if let Value::Object(map) = value {
+        map.retain(|_, value| !value.is_null());
+        map.values_mut().for_each(remove_nulls);
+    }
+}
+
+/// The config file `config` with the preset of its `profile`, if any, underneath.
+pub fn apply_profile(config: Value) -> Result<Value, String> {
+    let profile = match config.get("profile") {
+        None | Some(Value::Null) => return Ok(config),
+        Some(profile) => serde_json::from_value::<IngestionProfile>(profile.clone())
+            .map_err(|err| format!("Unknown \"profile\" {}: {}", profile, err))?,
+    };
+    let mut preset = profile.preset();
+    if let (Value::Object(preset), Value::Object(config)) = (&mut preset, &config) {
+        for section in REPLACED_SECTIONS {
+            if config.contains_key(section) {
+                preset.remove(section);
+            }
+        }
+    }
+    let mut merged = merge(preset, config);
+    remove_nulls(&mut merged);
+    if !merged.is_object() {
+        merged = Value::Object(Map::default());
+    }
+    Ok(merged)
+}
@@ -0,0 +1,808 @@
+/// The common consumer queries as typed functions.
+///
+/// Every query names the index it relies on with a hint, so a query whose index is
+/// missing or was changed fails instead of silently scanning a collection. Create the
+/// indexes with `ensure_query_indexes`. Results are deserialized into the structs
+/// below, so a change of the stored schema breaks these functions rather than the
+/// consumers' hand-written queries. The account queries take the `schema_profile` the
+/// accounts were stored with and return them with the standard field names.
+use {
+    crate::{
+        mongodb_client::{
+            ACCOUNTS_COLLECTION, ACCOUNT_HISTORY_COLLECTION,
+            ASSOCIATED_TOKEN_ACCOUNTS_COLLECTION, PDA_REGISTRY_COLLECTION, SLOTS_COLLECTION,
+            TOKEN_BALANCE_CHANGES_COLLECTION, TOKEN_MINT_TRANSACTIONS_COLLECTION,
+            TRANSACTIONS_COLLECTION,
+        },
+        schema::{expand_account_document, SchemaProfile},
+        server_support::{ServerFeature, ServerSupport},
+    },
+    futures::{AsyncReadExt, TryStreamExt},
+    mongodb::{
+        bson::{self, doc, spec::BinarySubtype, Binary, Bson, DateTime, Document},
+        error::{ErrorKind, GridFsErrorKind, Result},
+        options::{GridFsBucketOptions, Hint, SessionOptions},
+        Client, ClientSession, Database, IndexModel,
+    },
+    serde::de::DeserializeOwned,
+    serde_derive::Deserialize,
+    std::collections::HashMap,
+};
+
+/// The current state of an account in the accounts collection.
+#[derive(Clone, Debug, Deserialize)]
+pub struct StoredAccount {
+    pub pubkey: String,
+    pub slot: i64,
+    pub owner: String,
+    pub lamports: i64,
+    pub executable: bool,
+    pub rent_epoch: i64,
+    /// Unset when the data was omitted, redacted or moved to GridFS.
+    pub data: Option<Binary>,
+    pub write_version: i64,
+    pub txn_signature: Option<String>,
+    pub data_hash: Option<String>,
+    pub decoded: Option<Document>,
+    /// Set when the data was moved to GridFS, see `overflow_account_data`.
+    pub data_overflow: Option<DataOverflow>,
+}
+
+/// Where the data of an oversized account is stored in GridFS.
+#[derive(Clone, Debug, Deserialize)]
+pub struct DataOverflow {
+    pub bucket: String,
+    pub file_id: Bson,
+}
+
+/// The newest version of an account without its data, see `account_changed_since`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AccountDataHash {
+    pub pubkey: String,
+    pub slot: i64,
+    pub write_version: i64,
+    /// Unset on accounts stored without `hash_account_data`.
+    pub data_hash: Option<String>,
+}
+
+/// One version of an account in the account history collection.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AccountVersion {
+    pub slot: i64,
+    pub write_version: i64,
+    pub owner: String,
+    pub lamports: i64,
+    pub executable: bool,
+    pub rent_epoch: i64,
+    pub data: Option<Binary>,
+    pub txn_signature: Option<String>,
+    /// "sample", "keyframe" or "delta" on versions compacted by `history_compaction`.
+    pub compacted: Option<String>,
+}
+
+/// The fields of a transaction most consumers list.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TransactionSummary {
+    pub signature: String,
+    pub slot: i64,
+    pub index: i64,
+    pub is_vote: bool,
+    pub fee: i64,
+    /// The transaction error, null when the transaction succeeded.
+    pub error: Option<Bson>,
+    #[serde(default)]
+    pub account_keys: Vec<String>,
+    #[serde(default)]
+    pub program_ids: Vec<String>,
+    /// Empty on transactions stored before `accounts` was added.
+    #[serde(default)]
+    pub accounts: Vec<TransactionAccount>,
+}
+
+/// An account of a transaction, an entry of its `accounts`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TransactionAccount {
+    pub pubkey: String,
+    /// The position of the account in `account_keys`.
+    pub index: i64,
+    pub writable: bool,
+    pub signer: bool,
+    /// `static` for the keys of the message, `lookup` for the addresses loaded from
+    /// lookup tables.
+    pub source: String,
+}
+
+/// An entry of the token mint index, see `index_token_mint_transactions`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MintTransaction {
+    pub mint: String,
+    pub signature: String,
+    pub slot: i64,
+    pub index: i64,
+}
+
+/// A change of a token balance by a transaction, see `index_token_balance_changes`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TokenBalanceChange {
+    pub owner: String,
+    pub mint: String,
+    pub account: Option<String>,
+    /// The change in base units, as a decimal string.
+    pub delta: String,
+    pub ui_delta: f64,
+    pub decimals: i32,
+    pub signature: String,
+    pub slot: i64,
+    pub index: i64,
+}
+
+/// An entry of the associated token account index, see
+/// `index_associated_token_accounts`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AssociatedTokenAccount {
+    pub token_account: String,
+    pub owner: String,
+    pub mint: String,
+    pub token_program: String,
+    pub slot: i64,
+}
+
+/// An entry of the PDA registry, see `index_program_derived_addresses`.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProgramDerivedAddress {
+    pub pubkey: String,
+    pub program: String,
+    pub first_seen_slot: i64,
+    pub last_updated_slot: i64,
+    pub size: i64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SlotInfo {
+    pub slot: i64,
+    pub parent: Option<i64>,
+    pub status: String,
+    pub block_time: Option<DateTime>,
+    /// Unset on slots only created when a transaction of theirs was capped.
+    pub updated_on: Option<DateTime>,
+    /// Set on rooted slots with `track_slot_account_writes` enabled.
+    pub account_writes: Option<i64>,
+    pub account_write_bytes: Option<i64>,
+    /// Set on dead and abandoned slots with `track_forks` enabled.
+    pub competing_root: Option<i64>,
+    /// Set on rooted slots with `slot_ancestors_depth` set, parent first.
+    pub ancestors: Option<Vec<i64>>,
+}
+
+fn latest_account_index(profile: SchemaProfile) -> Document {
+    doc! {
+        profile.account_field("pubkey"): 1,
+        profile.account_field("slot"): -1,
+        profile.account_field("write_version"): -1,
+    }
+}
+
+/// The sort of `latest_account_index` picking the newest version of an account.
+fn latest_account_sort(profile: SchemaProfile) -> Document {
+    doc! { profile.account_field("slot"): -1, profile.account_field("write_version"): -1 }
+}
+
+fn account_history_index() -> Document {
+    doc! { "pubkey": 1, "first_slot": 1 }
+}
+
+fn account_versions_index() -> Document {
+    doc! { "pubkey": 1, "slot": 1, "write_version": 1 }
+}
+
+fn transactions_by_address_index() -> Document {
+    doc! { "account_keys": 1, "slot": -1, "index": -1 }
+}
+
+fn transactions_by_account_index() -> Document {
+    doc! { "accounts.pubkey": 1, "slot": -1, "index": -1 }
+}
+
+fn transactions_by_program_index() -> Document {
+    doc! { "program_ids": 1, "slot": -1, "index": -1 }
+}
+
+fn transactions_by_mint_index() -> Document {
+    doc! { "mint": 1, "slot": -1, "index": -1 }
+}
+
+fn token_balance_changes_index() -> Document {
+    doc! { "owner": 1, "slot": -1, "index": -1 }
+}
+
+fn associated_token_accounts_index() -> Document {
+    doc! { "owner": 1, "mint": 1 }
+}
+
+fn pda_registry_index() -> Document {
+    doc! { "program": 1, "pubkey": 1 }
+}
+
+fn slot_index() -> Document {
+    doc! { "slot": 1 }
+}
+
+/// The collections and keys of the indexes the queries of this module are hinted to,
+/// the accounts ones with the field names of `profile`.
+pub fn query_indexes(profile: SchemaProfile) -> Vec<(&'static str, Document)> {
+    vec![
+        (ACCOUNTS_COLLECTION, latest_account_index(profile)),
+        (ACCOUNT_HISTORY_COLLECTION, account_history_index()),
+        (ACCOUNT_HISTORY_COLLECTION, account_versions_index()),
+        (TRANSACTIONS_COLLECTION, transactions_by_address_index()),
+        (TRANSACTIONS_COLLECTION, transactions_by_account_index()),
+        (TRANSACTIONS_COLLECTION, transactions_by_program_index()),
+        (TOKEN_MINT_TRANSACTIONS_COLLECTION, transactions_by_mint_index()),
+        (TOKEN_BALANCE_CHANGES_COLLECTION, token_balance_changes_index()),
+        (
+            ASSOCIATED_TOKEN_ACCOUNTS_COLLECTION,
+            associated_token_accounts_index(),
+        ),
+        (PDA_REGISTRY_COLLECTION, pda_registry_index()),
+        (SLOTS_COLLECTION, slot_index()),
+    ]
+}
+
+/// Creates the indexes the queries of this module are hinted to. Existing indexes
+/// with the same keys are left as they are.
+pub async fn ensure_query_indexes(database: &Database, profile: SchemaProfile) -> Result<()> {
+    for (collection, keys) in query_indexes(profile) {
+        database
+            .collection::<Document>(collection)
+            .create_index(IndexModel::builder().keys(keys).build())
+            .await?;
+    }
+    Ok(())
+}
+
+fn deserialize<T: DeserializeOwned>(document: Document) -> Result<T> {
+    Ok(bson::from_document(document)?)
+}
+
+fn deserialize_account<T: DeserializeOwned>(document: Document) -> Result<T> {
+    deserialize(expand_account_document(document))
+}
+
+fn transaction_summary_projection() -> Document {
+    doc! {
+        "_id": 0,
+        "signature": 1,
+        "slot": 1,
+        "index": 1,
+        "is_vote": 1,
+        "fee": "$meta.fee",
+        "error": "$meta.error",
+        "account_keys": 1,
+        "program_ids": 1,
+        "accounts": 1,
+    }
+}
+
+/// The newest stored version of an account.
+pub async fn latest_account(
+    database: &Database,
+    profile: SchemaProfile,
+    pubkey: &str,
+) -> Result<Option<StoredAccount>> {
+    database
+        .collection::<Document>(ACCOUNTS_COLLECTION)
+        .find_one(doc! { profile.account_field("pubkey"): pubkey })
+        .sort(latest_account_sort(profile))
+        .hint(Hint::Keys(latest_account_index(profile)))
+        .await?
+        .map(deserialize_account)
+        .transpose()
+}
+
+/// The data of an account moved to GridFS by `account_data_overflow`, None when the
+/// data is stored inline or its file was replaced by a newer version.
+pub async fn overflow_account_data(
+    database: &Database,
+    account: &StoredAccount,
+) -> Result<Option<Vec<u8>>> {
+    let Some(overflow) = &account.data_overflow else {
+        return Ok(None);
+    };
+    let bucket = database.gridfs_bucket(
+        GridFsBucketOptions::builder()
+            .bucket_name(overflow.bucket.clone())
+            .build(),
+    );
+    let mut download = match bucket.open_download_stream(overflow.file_id.clone()).await {
+        Ok(download) => download,
+        Err(err)
+            if matches!(*err.kind, ErrorKind::GridFs(GridFsErrorKind::FileNotFound { .. })) =>
+        {
+            return Ok(None)
+        }
+        Err(err) => return Err(err),
+    };
+    let mut data = Vec::default();
+    download.read_to_end(&mut data).await?;
+    Ok(Some(data))
+}
+
+/// The newest version of an account if its `data_hash` is no longer `data_hash`, None
+/// when it is unchanged or not stored. Only the hash is read, not the data, so pollers
+/// can check often. Accounts stored without a hash always count as changed.
+pub async fn account_changed_since(
+    database: &Database,
+    profile: SchemaProfile,
+    pubkey: &str,
+    data_hash: &str,
+) -> Result<Option<AccountDataHash>> {
+    let mut projection = doc! { "_id": 0 };
+    for field in ["pubkey", "slot", "write_version", "data_hash"] {
+        projection.insert(profile.account_field(field), 1);
+    }
+    let latest: Option<AccountDataHash> = database
+        .collection::<Document>(ACCOUNTS_COLLECTION)
+        .find_one(doc! { profile.account_field("pubkey"): pubkey })
+        .sort(latest_account_sort(profile))
+        .projection(projection)
+        .hint(Hint::Keys(latest_account_index(profile)))
+        .await?
+        .map(deserialize_account)
+        .transpose()?;
+    Ok(latest.filter(|latest| latest.data_hash.as_deref() != Some(data_hash)))
+}
+
+/// The versions of an account stored between `start_slot` and `end_slot` (inclusive),
+/// ordered by slot and write_version, whether the history stores one document per
+/// version or buckets of versions.
+pub async fn account_history(
+    database: &Database,
+    pubkey: &str,
+    start_slot: u64,
+    end_slot: u64,
+) -> Result<Vec<AccountVersion>> {
+    let history = database.collection::<Document>(ACCOUNT_HISTORY_COLLECTION);
+    let slot_range = doc! { "$gte": start_slot as i64, "$lte": end_slot as i64 };
+    let mut versions: Vec<Document> = history
+        .find(doc! { "pubkey": pubkey, "slot": slot_range.clone() })
+        .sort(doc! { "slot": 1, "write_version": 1 })
+        .hint(Hint::Keys(account_versions_index()))
+        .await?
+        .try_collect()
+        .await?;
+    let pipeline = vec![
+        doc! { "$match": {
+            "pubkey": pubkey,
+            "first_slot": { "$lte": end_slot as i64 },
+            "last_slot": { "$gte": start_slot as i64 },
+        }},
+        doc! { "$unwind": "$versions" },
+        doc! { "$replaceRoot": { "newRoot": "$versions" } },
+        doc! { "$match": { "slot": slot_range } },
+    ];
+    let bucketed: Vec<Document> = history
+        .aggregate(pipeline)
+        .hint(Hint::Keys(account_history_index()))
+        .await?
+        .try_collect()
+        .await?;
+    rebuild_delta_versions(&history, pubkey, &mut versions).await?;
+    if !bucketed.is_empty() {
+        versions.extend(bucketed);
+        versions.sort_by_key(|version| {
+            (
+                version.get_i64("slot").unwrap_or_default(),
+                version.get_i64("write_version").unwrap_or_default(),
+            )
+        });
+    }
+    versions.into_iter().map(deserialize).collect()
+}
+
+/// Rebuilds the data of the versions delta compressed by `history_compaction` from
+/// their keyframes, read from `versions` or else from the history. Versions whose
+/// keyframe was deleted, e.g. by `history_retention`, are left without data.
+async fn rebuild_delta_versions(
+    history: &mongodb::Collection<Document>,
+    pubkey: &str,
+    versions: &mut [Document],
+) -> Result<()> {
+    let mut keyframes: HashMap<(i64, i64), Vec<u8>> = versions
+        .iter()
+        .filter(|version| version.get_str("compacted") == Ok("keyframe"))
+        .filter_map(|version| {
+            let key = (version.get_i64("slot").ok()?, version.get_i64("write_version").ok()?);
+            Some((key, version.get_binary_generic("data").ok()?.clone()))
+        })
+        .collect();
+    for version in versions.iter_mut() {
+        let Ok(delta) = version.get_array("data_delta").cloned() else {
+            continue;
+        };
+        let (Ok(slot), Ok(write_version)) = (
+            version.get_i64("keyframe_slot"),
+            version.get_i64("keyframe_write_version"),
+        ) else {
+            continue;
+        };
+        if !keyframes.contains_key(&(slot, write_version)) {
+            let keyframe = history
+                .find_one(doc! { "pubkey": pubkey, "slot": slot, "write_version": write_version })
+                .hint(Hint::Keys(account_versions_index()))
+                .await?;
+            let Some(data) = keyframe
+                .as_ref()
+                .and_then(|keyframe| keyframe.get_binary_generic("data").ok())
+            else {
+                continue;
+            };
+            keyframes.insert((slot, write_version), data.clone());
+        }
+        let mut data = keyframes[&(slot, write_version)].clone();
+        for run in delta.iter().filter_map(Bson::as_document) {
+            let (Ok(offset), Ok(bytes)) = (run.get_i64("offset"), run.get_binary_generic("bytes"))
+            else {
+                continue;
+            };
+            let offset = offset as usize;
+            if let Some(target) = data.get_mut(offset..offset + bytes.len()) {
+                target.copy_from_slice(bytes);
+            }
+        }
+        version.insert(
+            "data",
+            Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: data,
+            },
+        );
+    }
+    Ok(())
+}
+
+async fn find_transactions(
+    database: &Database,
+    mut filter: Document,
+    hint: Document,
+    before_slot: Option<u64>,
+    limit: i64,
+) -> Result<Vec<TransactionSummary>> {
+    if let Some(before_slot) = before_slot {
+        filter.insert("slot", doc! { "$lt": before_slot as i64 });
+    }
+    let transactions: Vec<Document> = database
+        .collection::<Document>(TRANSACTIONS_COLLECTION)
+        .find(filter)
+        .sort(doc! { "slot": -1, "index": -1 })
+        .projection(transaction_summary_projection())
+        .limit(limit)
+        .hint(Hint::Keys(hint))
+        .await?
+        .try_collect()
+        .await?;
+    transactions.into_iter().map(deserialize).collect()
+}
+
+/// The newest transactions referencing `address`, at most `limit` of them, only
+/// those of slots before `before_slot` when given.
+pub async fn transactions_by_address(
+    database: &Database,
+    address: &str,
+    before_slot: Option<u64>,
+    limit: i64,
+) -> Result<Vec<TransactionSummary>> {
+    find_transactions(
+        database,
+        doc! { "account_keys": address },
+        transactions_by_address_index(),
+        before_slot,
+        limit,
+    )
+    .await
+}
+
+/// The newest transactions in which `address` is writable, when `writable` is true,
+/// and a signer, when `signer` is true, at most `limit` of them, paged like
+/// `transactions_by_address`. With neither, any transaction referencing `address`.
+pub async fn transactions_by_account(
+    database: &Database,
+    address: &str,
+    writable: bool,
+    signer: bool,
+    before_slot: Option<u64>,
+    limit: i64,
+) -> Result<Vec<TransactionSummary>> {
+    let mut account = doc! { "pubkey": address };
+    if writable {
+        account.insert("writable", true);
+    }
+    if signer {
+        account.insert("signer", true);
+    }
+    find_transactions(
+        database,
+        doc! { "accounts": { "$elemMatch": account } },
+        transactions_by_account_index(),
+        before_slot,
+        limit,
+    )
+    .await
+}
+
+/// The newest transactions invoking `program_id` in a top level instruction, at most
+/// `limit` of them, paged like `transactions_by_address`.
+pub async fn transactions_by_program(
+    database: &Database,
+    program_id: &str,
+    before_slot: Option<u64>,
+    limit: i64,
+) -> Result<Vec<TransactionSummary>> {
+    find_transactions(
+        database,
+        doc! { "program_ids": program_id },
+        transactions_by_program_index(),
+        before_slot,
+        limit,
+    )
+    .await
+}
+
+/// The newest transactions touching the token `mint`, at most `limit` of them, paged
+/// like `transactions_by_address`. Only transactions logged with
+/// `index_token_mint_transactions` enabled are found.
+pub async fn transactions_by_mint(
+    database: &Database,
+    mint: &str,
+    before_slot: Option<u64>,
+    limit: i64,
+) -> Result<Vec<MintTransaction>> {
+    let mut filter = doc! { "mint": mint };
+    if let Some(before_slot) = before_slot {
+        filter.insert("slot", doc! { "$lt": before_slot as i64 });
+    }
+    let entries: Vec<Document> = database
+        .collection::<Document>(TOKEN_MINT_TRANSACTIONS_COLLECTION)
+        .find(filter)
+        .sort(doc! { "slot": -1, "index": -1 })
+        .projection(doc! { "_id": 0 })
+        .limit(limit)
+        .hint(Hint::Keys(transactions_by_mint_index()))
+        .await?
+        .try_collect()
+        .await?;
+    entries.into_iter().map(deserialize).collect()
+}
+
+/// The newest token balance changes of the wallet `owner`, at most `limit` of them,
+/// paged like `transactions_by_address`. Only transactions logged with
+/// `index_token_balance_changes` enabled are found.
+pub async fn token_balance_changes(
+    database: &Database,
+    owner: &str,
+    before_slot: Option<u64>,
+    limit: i64,
+) -> Result<Vec<TokenBalanceChange>> {
+    let mut filter = doc! { "owner": owner };
+    if let Some(before_slot) = before_slot {
+        filter.insert("slot", doc! { "$lt": before_slot as i64 });
+    }
+    let changes: Vec<Document> = database
+        .collection::<Document>(TOKEN_BALANCE_CHANGES_COLLECTION)
+        .find(filter)
+        .sort(doc! { "slot": -1, "index": -1 })
+        .projection(doc! { "_id": 0 })
+        .limit(limit)
+        .hint(Hint::Keys(token_balance_changes_index()))
+        .await?
+        .try_collect()
+        .await?;
+    changes.into_iter().map(deserialize).collect()
+}
+
+/// The associated token accounts of the wallet `owner`, ordered by mint. Only
+/// accounts observed with `index_associated_token_accounts` enabled are found.
+pub async fn associated_token_accounts(
+    database: &Database,
+    owner: &str,
+) -> Result<Vec<AssociatedTokenAccount>> {
+    let entries: Vec<Document> = database
+        .collection::<Document>(ASSOCIATED_TOKEN_ACCOUNTS_COLLECTION)
+        .find(doc! { "owner": owner })
+        .sort(doc! { "mint": 1 })
+        .projection(doc! { "_id": 0 })
+        .hint(Hint::Keys(associated_token_accounts_index()))
+        .await?
+        .try_collect()
+        .await?;
+    entries.into_iter().map(deserialize).collect()
+}
+
+/// The live program derived addresses owned by `program`, ordered by address, at most
+/// `limit` after the address `after`, for paging through large programs. Only accounts
+/// observed with `index_program_derived_addresses` enabled are found.
+pub async fn program_derived_addresses(
+    database: &Database,
+    program: &str,
+    after: Option<&str>,
+    limit: i64,
+) -> Result<Vec<ProgramDerivedAddress>> {
+    let mut filter = doc! { "program": program };
+    if let Some(after) = after {
+        filter.insert("pubkey", doc! { "$gt": after });
+    }
+    let entries: Vec<Document> = database
+        .collection::<Document>(PDA_REGISTRY_COLLECTION)
+        .find(filter)
+        .sort(doc! { "pubkey": 1 })
+        .limit(limit)
+        .projection(doc! { "_id": 0 })
+        .hint(Hint::Keys(pda_registry_index()))
+        .await?
+        .try_collect()
+        .await?;
+    entries.into_iter().map(deserialize).collect()
+}
+
+/// The status of a slot.
+pub async fn slot_status(database: &Database, slot: u64) -> Result<Option<SlotInfo>> {
+    database
+        .collection::<Document>(SLOTS_COLLECTION)
+        .find_one(doc! { "slot": slot as i64 })
+        .hint(Hint::Keys(slot_index()))
+        .await?
+        .map(deserialize)
+        .transpose()
+}
+
+/// The stored ancestors of the rooted slot `slot`, parent first, None when the slot
+/// has none stored, see `slot_ancestors_depth`.
+pub async fn slot_ancestors(database: &Database, slot: u64) -> Result<Option<Vec<i64>>> {
+    let slot = database
+        .collection::<Document>(SLOTS_COLLECTION)
+        .find_one(doc! { "slot": slot as i64 })
+        .projection(doc! { "_id": 0, "ancestors": 1 })
+        .hint(Hint::Keys(slot_index()))
+        .await?;
+    Ok(slot.and_then(|slot| {
+        let ancestors = slot.get_array("ancestors").ok()?;
+        Some(ancestors.iter().filter_map(Bson::as_i64).collect())
+    }))
+}
+
+/// Whether `ancestor` is an ancestor of the rooted slot `slot`, from its stored
+/// ancestors. None when that cannot be told: `slot` has no ancestors stored or
+/// `ancestor` is older than the oldest of them.
+pub async fn is_slot_ancestor(
+    database: &Database,
+    ancestor: u64,
+    slot: u64,
+) -> Result<Option<bool>> {
+    let Some(ancestors) = slot_ancestors(database, slot).await? else {
+        return Ok(None);
+    };
+    let ancestor = ancestor as i64;
+    if ancestors.contains(&ancestor) {
+        return Ok(Some(true));
+    }
+    // The stored ancestors are every root in their range, a slot in that range
+    // missing from them is on another fork or was skipped.
+    Ok(match ancestors.last() {
+        Some(oldest) if ancestor >= *oldest => Some(false),
+        _ => None,
+    })
+}
+
+/// Reads of several collections observing one snapshot of the database.
+///
+/// The reads share a snapshot session, i.e. they are all made at the cluster time of
+/// the first one, whatever the plugin writes meanwhile. The snapshot is pinned to a
+/// slot: the newest slot with the requested status when the session opens, and
+/// documents of later slots are ignored, so an account and its transactions are read
+/// as of the same slot. Snapshot sessions need a replica set or sharded cluster of
+/// MongoDB 5.0 or later.
+pub struct SnapshotReader {
+    session: ClientSession,
+    database: Database,
+    profile: SchemaProfile,
+    slot: u64,
+}
+
+impl SnapshotReader {
+    /// Opens a snapshot pinned to the newest slot whose status is `status`, e.g.
+    /// "rooted" or "confirmed". None before any slot has the status. Fails on servers
+    /// older than MongoDB 5.0.
+    pub async fn open(
+        client: &Client,
+        database: &Database,
+        profile: SchemaProfile,
+        status: &str,
+    ) -> Result<Option<Self>> {
+        let support = ServerSupport::read(client).await?;
+        if !support.supports(ServerFeature::SnapshotReads) {
+            return Err(ErrorKind::IncompatibleServer {
+                message: format!(
+                    "snapshot reads need MongoDB {}, the server runs {}",
+                    ServerFeature::SnapshotReads.min_version(),
+                    support.version
+                ),
+            }
+            .into());
+        }
+        let mut session = client
+            .start_session()
+            .with_options(SessionOptions::builder().snapshot(true).build())
+            .await?;
+        let marker = database
+            .collection::<Document>(SLOTS_COLLECTION)
+            .find_one(doc! { "status": status })
+            .sort(doc! { "slot": -1 })
+            .projection(doc! { "_id": 0, "slot": 1 })
+            .session(&mut session)
+            .await?;
+        Ok(marker
+            .and_then(|marker| marker.get_i64("slot").ok())
+            .map(|slot| Self {
+                session,
+                database: database.clone(),
+                profile,
+                slot: slot as u64,
+            }))
+    }
+
+    /// The slot the snapshot is pinned to.
+    pub fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    /// The newest version of an account as of the pinned slot.
+    pub async fn latest_account(&mut self, pubkey: &str) -> Result<Option<StoredAccount>> {
+        let profile = self.profile;
+        self.database
+            .collection::<Document>(ACCOUNTS_COLLECTION)
+            .find_one(doc! {
+                profile.account_field("pubkey"): pubkey,
+                profile.account_field("slot"): { "$lte": self.slot as i64 },
+            })
+            .sort(latest_account_sort(profile))
+            .hint(Hint::Keys(latest_account_index(profile)))
+            .session(&mut self.session)
+            .await?
+            .map(deserialize_account)
+            .transpose()
+    }
+
+    /// The newest transactions referencing `address` as of the pinned slot, at most
+    /// `limit` of them.
+    pub async fn transactions_by_address(
+        &mut self,
+        address: &str,
+        limit: i64,
+    ) -> Result<Vec<TransactionSummary>> {
+        let transactions: Vec<Document> = self
+            .database
+            .collection::<Document>(TRANSACTIONS_COLLECTION)
+            .find(doc! { "account_keys": address, "slot": { "$lte": self.slot as i64 } })
+            .sort(doc! { "slot": -1, "index": -1 })
+            .projection(transaction_summary_projection())
+            .limit(limit)
+            .hint(Hint::Keys(transactions_by_address_index()))
+            .session(&mut self.session)
+            .await?
+            .stream(&mut self.session)
+            .try_collect()
+            .await?;
+        transactions.into_iter().map(deserialize).collect()
+    }
+
+    /// The status of a slot as of the snapshot.
+    pub async fn slot_status(&mut self, slot: u64) -> Result<Option<SlotInfo>> {
+        self.database
+            .collection::<Document>(SLOTS_COLLECTION)
+            .find_one(doc! { "slot": slot as i64 })
+            .hint(Hint::Keys(slot_index()))
+            .session(&mut self.session)
+            .await?
+            .map(deserialize)
+            .transpose()
+    }
+}
@@ -0,0 +1,87 @@
+/// Replaces the data of selected accounts with its hash before anything is persisted
+/// or published.
+///
+/// Accounts are selected by pubkey or by owner program. The balance, owner and the
+/// other account fields are kept, so redacted accounts can still be tracked; only the
+/// data is dropped, leaving its hash and length to tell whether it changed.
+use {
+    crate::{
+        geyser_plugin_mongodb::GeyserPluginMongoDbError,
+        metrics,
+        mongodb_client::{account_data_hash, DbAccountInfo},
+    },
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::collections::HashSet,
+};
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct RedactionConfig {
+    /// Accounts whose data is redacted.
+    #[serde(default)]
+    pub accounts: Vec<String>,
+
+    /// Owner programs whose accounts' data is redacted.
+    #[serde(default)]
+    pub owners: Vec<String>,
+}
+
+/// What is kept of redacted account data.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RedactedData {
+    /// The blake3 hash of the data, see `account_data_hash`.
+    pub hash: String,
+    pub len: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct AccountRedactor {
+    accounts: HashSet<Vec<u8>>,
+    owners: HashSet<Vec<u8>>,
+}
+
+impl AccountRedactor {
+    pub fn new(config: &RedactionConfig) -> Result<Self, GeyserPluginError> {
+        let decode = |key: &String| {
+            bs58::decode(key).into_vec().map_err(|err| {
+                GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::ConfigurationError {
+                    msg: format!("Invalid key {} in \"redaction\": {}", key, err),
+                }))
+            })
+        };
+        let redactor = Self {
+            accounts: config.accounts.iter().map(decode).collect::<Result<_, _>>()?,
+            owners: config.owners.iter().map(decode).collect::<Result<_, _>>()?,
+        };
+        if redactor.is_enabled() {
+            info!(
+                "Redacting the data of {} accounts and of the accounts of {} owners",
+                redactor.accounts.len(),
+                redactor.owners.len()
+            );
+        }
+        Ok(redactor)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        !self.accounts.is_empty() || !self.owners.is_empty()
+    }
+
+    pub fn is_redacted(&self, pubkey: &[u8], owner: &[u8]) -> bool {
+        self.accounts.contains(pubkey) || self.owners.contains(owner)
+    }
+
+    /// Drops the data of `account` if it is selected, keeping its hash and length.
+    pub fn redact(&self, account: &mut DbAccountInfo) {
+        if !self.is_redacted(&account.pubkey, &account.owner) {
+            return;
+        }
+        let data = std::mem::take(&mut account.data);
+        account.redacted_data = Some(RedactedData {
+            hash: account_data_hash(&data),
+            len: data.len(),
+        });
+        metrics::inc_counter("geyser-plugin-mongodb-account-data-redacted", 1);
+    }
+}
@@ -0,0 +1,168 @@
+/// Thins out the updates of accounts that change every slot, e.g. orderbooks and
+/// clocks.
+///
+/// A rule applies to the listed accounts and to the accounts of the listed owners,
+/// and keeps either at most one update per `every_n_slots` slots or one in
+/// `one_in_n_updates` updates of each account. Rules naming an account take
+/// precedence over rules naming its owner. The decision is made before the update is
+/// queued, so sampled-out updates cost no database work. Startup updates and the
+/// closing update of an account are never sampled, the snapshot must be complete and
+/// a closed account must not stay live. The state of an account is dropped once it
+/// would no longer change the decision, or after `IDLE_SLOTS` slots without updates.
+use {
+    crate::{geyser_plugin_mongodb::to_config_error, metrics},
+    log::*,
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::{collections::HashMap, sync::Mutex},
+};
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SamplingRuleConfig {
+    /// The accounts sampled.
+    pub accounts: Option<Vec<String>>,
+    /// The owners whose accounts are sampled.
+    pub owners: Option<Vec<String>>,
+    /// At most one update of an account is kept per this many slots.
+    pub every_n_slots: Option<u64>,
+    /// One in this many updates of an account is kept.
+    pub one_in_n_updates: Option<u64>,
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum SamplingRate {
+    EveryNSlots(u64),
+    OneInNUpdates(u64),
+}
+
+/// The slots between two sweeps of the sampled accounts, and the slots without an
+/// update after which an account sampled by `one_in_n_updates` is forgotten.
+const IDLE_SLOTS: u64 = 150;
+
+struct SampledAccount {
+    rate: SamplingRate,
+    last_kept_slot: Option<u64>,
+    last_seen_slot: u64,
+    updates: u64,
+}
+
+impl SampledAccount {
+    /// Whether forgetting the account at `slot` could change a later decision.
+    fn is_live(&self, slot: u64) -> bool {
+        match self.rate {
+            SamplingRate::EveryNSlots(slots) => self
+                .last_kept_slot
+                .map(|last_kept_slot| slot < last_kept_slot.saturating_add(slots))
+                .unwrap_or(false),
+            SamplingRate::OneInNUpdates(_) => slot < self.last_seen_slot.saturating_add(IDLE_SLOTS),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SampledAccounts {
+    accounts: HashMap<Vec<u8>, SampledAccount>,
+    swept_slot: u64,
+}
+
+fn decode_keys(keys: &Option<Vec<String>>) -> Result<Vec<Vec<u8>>, GeyserPluginError> {
+    keys.iter()
+        .flatten()
+        .map(|key| {
+            bs58::decode(key).into_vec().map_err(|err| {
+                to_config_error(format!("Invalid key {} in \"sampling\": {}", key, err))
+            })
+        })
+        .collect()
+}
+
+pub struct UpdateSampler {
+    accounts: HashMap<Vec<u8>, SamplingRate>,
+    owners: HashMap<Vec<u8>, SamplingRate>,
+    sampled: Mutex<SampledAccounts>,
+}
+
+impl UpdateSampler {
+    pub fn new(rules: &[SamplingRuleConfig]) -> Result<Self, GeyserPluginError> {
+        let mut accounts = HashMap::default();
+        let mut owners = HashMap::default();
+        for rule in rules {
+            let rate = match (rule.every_n_slots, rule.one_in_n_updates) {
+                (Some(slots), None) if slots > 0 => SamplingRate::EveryNSlots(slots),
+                (None, Some(updates)) if updates > 0 => SamplingRate::OneInNUpdates(updates),
+                _ => {
+                    return Err(to_config_error(
+                        "A sampling rule takes either a positive \"every_n_slots\" or a \
+                         positive \"one_in_n_updates\""
+                            .to_string(),
+                    ))
+                }
+            };
+            let (rule_accounts, rule_owners) =
+                (decode_keys(&rule.accounts)?, decode_keys(&rule.owners)?);
+            if rule_accounts.is_empty() && rule_owners.is_empty() {
+                return Err(to_config_error(
+                    "A sampling rule needs \"accounts\" or \"owners\"".to_string(),
+                ));
+            }
+            accounts.extend(rule_accounts.into_iter().map(|key| (key, rate)));
+            owners.extend(rule_owners.into_iter().map(|key| (key, rate)));
+        }
+        info!(
+            "Sampling the updates of {} accounts and of the accounts of {} owners",
+            accounts.len(),
+            owners.len()
+        );
+        Ok(Self {
+            accounts,
+            owners,
+            sampled: Mutex::default(),
+        })
+    }
+
+    /// Whether the update of `pubkey` at `slot` is dropped. An update leaving the
+    /// account with no lamports closes it and is always kept.
+    pub fn is_sampled_out(&self, pubkey: &[u8], owner: &[u8], lamports: u64, slot: u64) -> bool {
+        let rate = match self
+            .accounts
+            .get(pubkey)
+            .or_else(|| self.owners.get(owner))
+        {
+            Some(rate) => *rate,
+            None => return false,
+        };
+        let mut sampled = self.sampled.lock().unwrap();
+        if slot >= sampled.swept_slot.saturating_add(IDLE_SLOTS) {
+            sampled.accounts.retain(|_, account| account.is_live(slot));
+            sampled.swept_slot = slot;
+        }
+        if lamports == 0 {
+            sampled.accounts.remove(pubkey);
+            return false;
+        }
+        let account = sampled
+            .accounts
+            .entry(pubkey.to_vec())
+            .or_insert_with(|| SampledAccount {
+                rate,
+                last_kept_slot: None,
+                last_seen_slot: slot,
+                updates: 0,
+            });
+        account.last_seen_slot = slot;
+        account.updates += 1;
+        let keep = match rate {
+            SamplingRate::EveryNSlots(slots) => account
+                .last_kept_slot
+                .map(|last_kept_slot| slot >= last_kept_slot.saturating_add(slots))
+                .unwrap_or(true),
+            SamplingRate::OneInNUpdates(updates) => (account.updates - 1) % updates == 0,
+        };
+        if keep {
+            account.last_kept_slot = Some(slot);
+        } else {
+            metrics::inc_counter("geyser-plugin-mongodb-sampled-out-updates", 1);
+        }
+        !keep
+    }
+}
@@ -0,0 +1,145 @@
+/// Field name profiles of the account documents.
+///
+/// Every BSON document repeats its field names, so with billions of account
+/// documents the names alone take a noticeable share of the storage. The compact
+/// profile stores the account fields under the short names below; readers turn such
+/// documents back into the standard field names with `expand_account_document`.
+///
+/// | standard        | compact |
+/// |-----------------|---------|
+/// | pubkey          | p       |
+/// | owner           | o       |
+/// | lamports        | l       |
+/// | data            | d       |
+/// | slot            | s       |
+/// | write_version   | wv      |
+/// | executable      | x       |
+/// | rent_epoch      | re      |
+/// | txn_signature   | ts      |
+/// | updated_on      | u       |
+/// | notified_at     | na      |
+/// | ingested_at     | ia      |
+/// | block_time      | bt      |
+/// | data_hash       | dh      |
+/// | data_len        | dl      |
+/// | data_redacted   | dr      |
+/// | data_omitted    | do      |
+/// | decoded         | dc      |
+/// | closed          | c       |
+/// | rent_exempt     | rx      |
+/// | on_curve        | oc      |
+/// | pda_likely      | pl      |
+/// | data_overflow   | df      |
+use {
+    crate::clock,
+    mongodb::bson::{doc, Document},
+    serde_derive::{Deserialize, Serialize},
+};
+
+/// Bumped whenever the layout of the stored documents changes incompatibly.
+pub const DOCUMENT_SCHEMA_VERSION: i32 = 1;
+/// The field holding the envelope of a document.
+pub const ENVELOPE_FIELD: &str = "_meta";
+
+/// The standard and compact names of the account fields.
+pub const COMPACT_ACCOUNT_FIELDS: [(&str, &str); 23] = [
+    ("pubkey", "p"),
+    ("owner", "o"),
+    ("lamports", "l"),
+    ("data", "d"),
+    ("slot", "s"),
+    ("write_version", "wv"),
+    ("executable", "x"),
+    ("rent_epoch", "re"),
+    ("txn_signature", "ts"),
+    ("updated_on", "u"),
+    ("notified_at", "na"),
+    ("ingested_at", "ia"),
+    ("block_time", "bt"),
+    ("data_hash", "dh"),
+    ("data_len", "dl"),
+    ("data_redacted", "dr"),
+    ("data_omitted", "do"),
+    ("decoded", "dc"),
+    ("closed", "c"),
+    ("rent_exempt", "rx"),
+    ("on_curve", "oc"),
+    ("pda_likely", "pl"),
+    ("data_overflow", "df"),
+];
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaProfile {
+    /// The descriptive field names.
+    #[default]
+    Standard,
+    /// The short field names of `COMPACT_ACCOUNT_FIELDS`.
+    Compact,
+}
+
+impl SchemaProfile {
+    /// The stored name of the standard account field `name`, for building filters
+    /// and updates.
+    pub fn account_field<'a>(&self, name: &'a str) -> &'a str {
+        match self {
+            SchemaProfile::Standard => name,
+            SchemaProfile::Compact => COMPACT_ACCOUNT_FIELDS
+                .iter()
+                .find(|(standard, _)| *standard == name)
+                .map(|(_, compact)| *compact)
+                .unwrap_or(name),
+        }
+    }
+
+    /// Renames the fields of a standard account document to this profile. Fields
+    /// without a compact name are kept as they are.
+    pub fn account_document(&self, document: Document) -> Document {
+        match self {
+            SchemaProfile::Standard => document,
+            SchemaProfile::Compact => document
+                .into_iter()
+                .map(|(name, value)| (self.account_field(&name).to_string(), value))
+                .collect(),
+        }
+    }
+}
+
+/// Provenance added to every persisted document under `_meta`, by the write layer
+/// rather than by each document builder:
+/// `{ schema_version, source_validator, plugin_version, ingest_ts }`.
+#[derive(Clone, Debug)]
+pub struct DocumentEnvelope {
+    pub source_validator: String,
+}
+
+impl DocumentEnvelope {
+    pub fn wrap(&self, mut document: Document) -> Document {
+        document.insert(
+            ENVELOPE_FIELD,
+            doc! {
+                "schema_version": DOCUMENT_SCHEMA_VERSION,
+                "source_validator": &self.source_validator,
+                "plugin_version": env!("CARGO_PKG_VERSION"),
+                "ingest_ts": clock::timestamp_now(),
+            },
+        );
+        document
+    }
+}
+
+/// The account document with its standard field names, whichever profile it was
+/// stored with.
+pub fn expand_account_document(document: Document) -> Document {
+    document
+        .into_iter()
+        .map(|(name, value)| {
+            let name = COMPACT_ACCOUNT_FIELDS
+                .iter()
+                .find(|(_, compact)| *compact == name)
+                .map(|(standard, _)| standard.to_string())
+                .unwrap_or(name);
+            (name, value)
+        })
+        .collect()
+}
@@ -0,0 +1,238 @@
+/// `$jsonSchema` validators on the accounts, transactions and slots collections, so
+/// a corrupt or partially written document is rejected by the server instead of
+/// being found later by a consumer.
+///
+/// The validators require the fields every document of the collection is written
+/// with and constrain their types: the pubkeys and signatures are base58 strings,
+/// the slots, write versions and lamports integers. The other fields, e.g. those of
+/// optional features, are not constrained. Account field names follow
+/// `schema_profile`. The validators are installed when the plugin loads, with
+/// `collMod` on existing collections, replacing any validator they had, and on
+/// creation otherwise.
+///
+/// With the `strict` level every insert and update is validated, with `moderate`
+/// updates of documents that were already invalid are not. With the `error` action a
+/// rejected write fails like any other and ends up in the dead letters, with `warn`
+/// it is written and the server logs the violation.
+use {
+    crate::{
+        geyser_plugin_mongodb::{GeyserPluginMongoDBConfig, GeyserPluginMongoDbError},
+        mongodb_client::{
+            plugin_database, SimpleMongoDbClient, ACCOUNTS_COLLECTION, SLOTS_COLLECTION,
+            TRANSACTIONS_COLLECTION,
+        },
+        schema::SchemaProfile,
+    },
+    log::*,
+    mongodb::{
+        bson::{doc, Document},
+        options::{ValidationAction, ValidationLevel},
+        Database,
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaValidationLevel {
+    /// Every insert and update is validated.
+    #[default]
+    Strict,
+    /// Updates of documents that are already invalid are not validated.
+    Moderate,
+}
+
+impl SchemaValidationLevel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SchemaValidationLevel::Strict => "strict",
+            SchemaValidationLevel::Moderate => "moderate",
+        }
+    }
+
+    fn to_validation_level(self) -> ValidationLevel {
+        match self {
+            SchemaValidationLevel::Strict => ValidationLevel::Strict,
+            SchemaValidationLevel::Moderate => ValidationLevel::Moderate,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaValidationAction {
+    /// Invalid documents are rejected.
+    #[default]
+    Error,
+    /// Invalid documents are written and logged by the server.
+    Warn,
+}
+
+impl SchemaValidationAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SchemaValidationAction::Error => "error",
+            SchemaValidationAction::Warn => "warn",
+        }
+    }
+
+    fn to_validation_action(self) -> ValidationAction {
+        match self {
+            SchemaValidationAction::Error => ValidationAction::Error,
+            SchemaValidationAction::Warn => ValidationAction::Warn,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SchemaValidationConfig {
+    /// "strict" or "moderate". The default is strict.
+    pub level: Option<SchemaValidationLevel>,
+    /// "error" or "warn". The default is error.
+    pub action: Option<SchemaValidationAction>,
+}
+
+fn validation_error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::DataSchemaError { msg }))
+}
+
+/// A base58 pubkey or signature.
+fn base58(max_length: i32) -> Document {
+    doc! { "bsonType": "string", "minLength": 32, "maxLength": max_length }
+}
+
+fn integer() -> Document {
+    doc! { "bsonType": ["int", "long"] }
+}
+
+fn account_validator(profile: SchemaProfile) -> Document {
+    let field = |name| profile.account_field(name);
+    doc! { "$jsonSchema": {
+        "bsonType": "object",
+        "required": [
+            field("pubkey"),
+            field("owner"),
+            field("lamports"),
+            field("slot"),
+            field("write_version"),
+        ],
+        "properties": {
+            field("pubkey"): base58(44),
+            field("owner"): base58(44),
+            field("lamports"): { "bsonType": ["int", "long"], "minimum": 0 },
+            field("slot"): { "bsonType": ["int", "long"], "minimum": 0 },
+            field("write_version"): integer(),
+            field("executable"): { "bsonType": "bool" },
+            field("rent_epoch"): integer(),
+            field("data"): { "bsonType": "binData" },
+        },
+    }}
+}
+
+fn transaction_validator() -> Document {
+    doc! { "$jsonSchema": {
+        "bsonType": "object",
+        "required": ["signature", "slot", "index", "is_vote"],
+        "properties": {
+            "signature": base58(88),
+            "slot": { "bsonType": ["int", "long"], "minimum": 0 },
+            "index": integer(),
+            "is_vote": { "bsonType": "bool" },
+            "meta": { "bsonType": "object" },
+        },
+    }}
+}
+
+fn slot_validator() -> Document {
+    doc! { "$jsonSchema": {
+        "bsonType": "object",
+        "required": ["slot", "status"],
+        "properties": {
+            "slot": { "bsonType": ["int", "long"], "minimum": 0 },
+            "status": { "bsonType": "string" },
+            "parent": integer(),
+        },
+    }}
+}
+
+async fn install_validator(
+    database: &Database,
+    collection: &str,
+    validator: Document,
+    level: SchemaValidationLevel,
+    action: SchemaValidationAction,
+) -> Result<(), GeyserPluginError> {
+    let exists = database
+        .list_collection_names()
+        .filter(doc! { "name": collection })
+        .await
+        .map_err(|err| validation_error(format!("Failed to list the collections: {}", err)))?
+        .iter()
+        .any(|name| name == collection);
+    let result = if exists {
+        database
+            .run_command(doc! {
+                "collMod": collection,
+                "validator": validator,
+                "validationLevel": level.as_str(),
+                "validationAction": action.as_str(),
+            })
+            .await
+            .map(|_| ())
+    } else {
+        database
+            .create_collection(collection)
+            .validator(validator)
+            .validation_level(level.to_validation_level())
+            .validation_action(action.to_validation_action())
+            .await
+    };
+    result.map_err(|err| {
+        validation_error(format!(
+            "Failed to install the validator of {}: {}",
+            collection, err
+        ))
+    })?;
+    info!(
+        "Installed the validator of {}, level {}, action {}",
+        collection,
+        level.as_str(),
+        action.as_str()
+    );
+    Ok(())
+}
+
+/// Installs the validators configured by `schema_validation`, once on load rather
+/// than by every worker.
+pub fn prepare_schema_validation(
+    config: &GeyserPluginMongoDBConfig,
+) -> Result<(), GeyserPluginError> {
+    let Some(validation) = &config.schema_validation else {
+        return Ok(());
+    };
+    let level = validation.level.unwrap_or_default();
+    let action = validation.action.unwrap_or_default();
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| {
+            validation_error(format!("Failed to create the validation runtime: {}", err))
+        })?;
+    runtime.block_on(async {
+        let client = SimpleMongoDbClient::connect_to_db(config).await?;
+        let database = plugin_database(&client, config);
+        let validators = [
+            (
+                ACCOUNTS_COLLECTION,
+                account_validator(config.schema_profile.unwrap_or_default()),
+            ),
+            (TRANSACTIONS_COLLECTION, transaction_validator()),
+            (SLOTS_COLLECTION, slot_validator()),
+        ];
+        for (collection, validator) in validators {
+            install_validator(&database, collection, validator, level, action).await?;
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,224 @@
+/// Verifies that the indexes the plugin relies on still exist.
+///
+/// Indexes dropped by an external writer, e.g. a DBA cleaning up, make MongoDB fall
+/// back to collection scans without any error, the plugin and its readers just get
+/// slower. The check lists the indexes of every collection the plugin writes and
+/// compares them, keys and unique option, to the ones it creates. A missing index is
+/// recreated with `recreate`, or only reported with `alert`; both log it, count it
+/// and store an alert in the alerts collection. Collections that do not exist are
+/// skipped, they belong to features that are turned off. The first check runs when
+/// the plugin loads, or after the end of startup when the index builds are
+/// deferred, then every `interval_secs`.
+use {
+    crate::{
+        geyser_plugin_mongodb::{GeyserPluginMongoDBConfig, GeyserPluginMongoDbError},
+        index_builds::{core_indexes, StartupIndexBuild},
+        metrics,
+        mongodb_client::{
+            associated_token_account_indexes, pda_registry_indexes, ALERTS_COLLECTION,
+            ASSOCIATED_TOKEN_ACCOUNTS_COLLECTION, PDA_REGISTRY_COLLECTION,
+        },
+        periodic_task::{FirstRun, PeriodicTask},
+        queries::query_indexes,
+    },
+    futures::TryStreamExt,
+    log::*,
+    mongodb::{
+        bson::{self, doc, Document},
+        Database, IndexModel,
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    },
+};
+
+const DEFAULT_SCHEMA_VERIFICATION_INTERVAL_SECS: u64 = 600;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SchemaDriftAction {
+    /// Missing indexes are reported.
+    #[default]
+    Alert,
+    /// Missing indexes are reported and created again.
+    Recreate,
+}
+
+impl SchemaDriftAction {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SchemaDriftAction::Alert => "alert",
+            SchemaDriftAction::Recreate => "recreate",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SchemaVerificationConfig {
+    /// What is done about a missing index. The default is alert.
+    pub action: Option<SchemaDriftAction>,
+    /// Seconds between two checks. The default is 600.
+    pub interval_secs: Option<u64>,
+}
+
+/// The indexes the plugin creates, by collection.
+fn required_indexes(config: &GeyserPluginMongoDBConfig) -> Vec<(&'static str, IndexModel)> {
+    let query_indexes = query_indexes(config.schema_profile.unwrap_or_default());
+    let core_indexes = core_indexes(config)
+        .into_iter()
+        .filter(|index| !query_indexes.contains(index));
+    query_indexes
+        .into_iter()
+        .chain(core_indexes)
+        .map(|(collection, keys)| (collection, IndexModel::builder().keys(keys).build()))
+        .chain(
+            associated_token_account_indexes()
+                .into_iter()
+                .map(|index| (ASSOCIATED_TOKEN_ACCOUNTS_COLLECTION, index)),
+        )
+        .chain(
+            pda_registry_indexes()
+                .into_iter()
+                .map(|index| (PDA_REGISTRY_COLLECTION, index)),
+        )
+        .collect()
+}
+
+fn is_unique(index: &IndexModel) -> bool {
+    index
+        .options
+        .as_ref()
+        .and_then(|options| options.unique)
+        .unwrap_or(false)
+}
+
+fn drift_error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::DataSchemaError { msg }))
+}
+
+/// Checks the required indexes once, returns how many were missing.
+async fn verify_indexes(
+    database: &Database,
+    config: &GeyserPluginMongoDBConfig,
+    action: SchemaDriftAction,
+) -> Result<usize, GeyserPluginError> {
+    let collections = database
+        .list_collection_names()
+        .await
+        .map_err(|err| drift_error(format!("Failed to list the collections: {}", err)))?;
+    let mut missing = 0;
+    for (collection_name, required) in required_indexes(config) {
+        if !collections.iter().any(|name| name == collection_name) {
+            continue;
+        }
+        let collection = database.collection::<Document>(collection_name);
+        let existing: Vec<IndexModel> = match collection.list_indexes().await {
+            Ok(indexes) => indexes.try_collect().await,
+            Err(err) => Err(err),
+        }
+        .map_err(|err| {
+            drift_error(format!(
+                "Failed to list the indexes of {}: {}",
+                collection_name, err
+            ))
+        })?;
+        if existing
+            .iter()
+            .any(|index| index.keys == required.keys && is_unique(index) == is_unique(&required))
+        {
+            continue;
+        }
+        missing += 1;
+        warn!(
+            "Index {} of {} is missing, action: {}",
+            required.keys,
+            collection_name,
+            action.as_str()
+        );
+        metrics::inc_counter("geyser-plugin-mongodb-schema-drift-missing-indexes", 1);
+        let alert = doc! {
+            "source": "schema_verification",
+            "kind": "missing_index",
+            "collection": collection_name,
+            "keys": required.keys.clone(),
+            "unique": is_unique(&required),
+            "action": action.as_str(),
+            "detected_at": bson::DateTime::now(),
+        };
+        if let Err(err) = database
+            .collection::<Document>(ALERTS_COLLECTION)
+            .insert_one(alert)
+            .await
+        {
+            error!("Failed to insert the missing index alert: {}", err);
+        }
+        if action == SchemaDriftAction::Recreate {
+            collection.create_index(required).await.map_err(|err| {
+                drift_error(format!(
+                    "Failed to recreate an index of {}: {}",
+                    collection_name, err
+                ))
+            })?;
+            metrics::inc_counter("geyser-plugin-mongodb-schema-drift-recreated-indexes", 1);
+        }
+    }
+    Ok(missing)
+}
+
+pub(crate) struct SchemaVerifier {
+    task: PeriodicTask,
+}
+
+impl SchemaVerifier {
+    /// Starts the checks configured by `schema_verification`, None when unset.
+    /// `is_startup_done` holds back the first check while the index builds are
+    /// deferred to the end of startup.
+    pub fn spawn(
+        config: &GeyserPluginMongoDBConfig,
+        is_startup_done: Arc<AtomicBool>,
+    ) -> Option<Self> {
+        let verification = config.schema_verification.clone()?;
+        let action = verification.action.unwrap_or_default();
+        let interval = Duration::from_secs(
+            verification
+                .interval_secs
+                .unwrap_or(DEFAULT_SCHEMA_VERIFICATION_INTERVAL_SECS),
+        );
+        let deferred = matches!(
+            config.startup_index_build,
+            Some(StartupIndexBuild::Deferred) | Some(StartupIndexBuild::Rebuild)
+        );
+        // The first check is due as soon as the indexes should exist.
+        let first_run = if deferred {
+            FirstRun::When(Box::new(move || is_startup_done.load(Ordering::Relaxed)))
+        } else {
+            FirstRun::When(Box::new(|| true))
+        };
+        let verified_config = config.clone();
+        let task = PeriodicTask::spawn_when(
+            "mongodb-schema-verification",
+            "verify the indexes",
+            first_run,
+            interval,
+            config,
+            move |runtime, database| {
+                match runtime.block_on(verify_indexes(database, &verified_config, action))? {
+                    0 => debug!("All the required indexes exist"),
+                    missing => warn!("{} required indexes were missing", missing),
+                }
+                Ok(())
+            },
+        );
+        Some(Self { task })
+    }
+
+    pub fn join(&mut self) {
+        self.task.join();
+    }
+}
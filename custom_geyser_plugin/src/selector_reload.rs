@@ -0,0 +1,163 @@
+/// Reloads the account and transaction selectors when the config file changes.
+///
+/// The file's modification time is polled; on a change the selector sections are
+/// parsed again and swapped in under a write lock, so the notification path always
+/// sees either the old or the new selectors as a whole. A file that fails to parse
+/// leaves the current selectors in place. The selectors preset by the `profile` of
+/// the file apply as they do on load. `skip_startup_accounts` is reloaded too, so the
+/// startup snapshot can be skipped or resumed while it is loading. The other settings
+/// of the file are only read when the plugin loads.
+use {
+    crate::{
+        accounts_selector::SelectorSet,
+        geyser_plugin_mongodb::{to_config_error, GeyserPluginMongodb},
+        metrics,
+        periodic_task::{FirstRun, PeriodicTask},
+        profiles::apply_profile,
+        transaction_selector::TransactionSelector,
+    },
+    log::*,
+    solana_geyser_plugin_interface::geyser_plugin_interface::{GeyserPluginError, Result},
+    std::{
+        fs,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, RwLock,
+        },
+        time::{Duration, SystemTime},
+    },
+};
+
+pub type SharedAccountsSelector = Arc<RwLock<SelectorSet>>;
+pub(crate) type SharedTransactionSelector = Arc<RwLock<TransactionSelector>>;
+
+fn modified_at(config_file: &str) -> Option<SystemTime> {
+    fs::metadata(config_file)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Parses the selectors of `config_file` and swaps them in. Any error leaves the
+/// current selectors and `skip_startup_accounts` as they are.
+fn reload(
+    config_file: &str,
+    accounts_selector: Option<&SharedAccountsSelector>,
+    transaction_selector: &SharedTransactionSelector,
+    skip_startup_accounts: &AtomicBool,
+) -> Result<()> {
+    let contents =
+        fs::read_to_string(config_file).map_err(|err| to_config_error(err.to_string()))?;
+    let config: serde_json::Value =
+        serde_json::from_str(&contents).map_err(|err| to_config_error(err.to_string()))?;
+    let config = apply_profile(config).map_err(to_config_error)?;
+    // Both are parsed before either is swapped, a bad section changes nothing.
+    let accounts = GeyserPluginMongodb::create_accounts_selector_from_config(&config)?;
+    let transactions = GeyserPluginMongodb::create_transaction_selector_from_config(&config)?;
+    if let Some(accounts_selector) = accounts_selector {
+        *accounts_selector.write().unwrap() = accounts;
+    }
+    *transaction_selector.write().unwrap() = transactions;
+    let skip = config["skip_startup_accounts"].as_bool().unwrap_or_default();
+    if skip_startup_accounts.swap(skip, Ordering::Relaxed) != skip {
+        info!("Set skipping the accounts of the startup snapshot to {}", skip);
+    }
+    Ok(())
+}
+
+pub(crate) struct SelectorReloader {
+    task: PeriodicTask,
+}
+
+impl SelectorReloader {
+    /// Without `accounts_selector` only the transaction selector is reloaded, for
+    /// account selectors built from `selector_rules`.
+    pub fn spawn(
+        config_file: String,
+        accounts_selector: Option<SharedAccountsSelector>,
+        transaction_selector: SharedTransactionSelector,
+        skip_startup_accounts: Arc<AtomicBool>,
+        interval: Duration,
+    ) -> Self {
+        info!(
+            "Reloading the selectors when {} changes, checked every {:?}",
+            config_file, interval
+        );
+        let mut last_modified = modified_at(&config_file);
+        let task = PeriodicTask::spawn_thread(
+            "mongodb-selector-reload",
+            FirstRun::AfterInterval,
+            interval,
+            move || {
+                let modified = modified_at(&config_file);
+                if modified == last_modified {
+                    return;
+                }
+                last_modified = modified;
+                let reloaded = reload(
+                    &config_file,
+                    accounts_selector.as_ref(),
+                    &transaction_selector,
+                    &skip_startup_accounts,
+                );
+                match reloaded {
+                    Ok(()) => {
+                        info!("Reloaded the selectors from {}", config_file);
+                        metrics::inc_counter("geyser-plugin-mongodb-selector-reloads", 1);
+                    }
+                    Err(err) => {
+                        error!(
+                            "Failed to reload the selectors from {}, keeping the current ones: {}",
+                            config_file, err
+                        );
+                        metrics::inc_counter("geyser-plugin-mongodb-selector-reload-failures", 1);
+                    }
+                }
+            },
+        );
+        Self { task }
+    }
+
+    pub fn join(&mut self) {
+        self.task.join();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::io::Write, tempfile::NamedTempFile};
+
+    fn write_config(file: &mut NamedTempFile, contents: &str) {
+        let file = file.as_file_mut();
+        file.set_len(0).unwrap();
+        file.write_all(contents.as_bytes()).unwrap();
+        file.sync_all().unwrap();
+    }
+
+    #[test]
+    fn test_reload_keeps_the_selectors_of_an_invalid_file() {
+        let mut file = NamedTempFile::new().unwrap();
+        let path = file.path().to_str().unwrap().to_string();
+        let transaction_selector: SharedTransactionSelector =
+            Arc::new(RwLock::new(TransactionSelector::default()));
+        let skip_startup_accounts = AtomicBool::new(false);
+
+        write_config(
+            &mut file,
+            r#"{"transaction_selector": {"mentions": ["*"]}, "skip_startup_accounts": true}"#,
+        );
+        reload(&path, None, &transaction_selector, &skip_startup_accounts).unwrap();
+        assert!(transaction_selector.read().unwrap().select_all_transactions);
+        assert!(skip_startup_accounts.load(Ordering::Relaxed));
+
+        // A non-string entry, then a file that is not JSON.
+        for contents in [
+            r#"{"transaction_selector": {"programs": [1]}, "skip_startup_accounts": false}"#,
+            "{",
+        ] {
+            write_config(&mut file, contents);
+            assert!(reload(&path, None, &transaction_selector, &skip_startup_accounts).is_err());
+            assert!(transaction_selector.read().unwrap().select_all_transactions);
+            assert!(skip_startup_accounts.load(Ordering::Relaxed));
+        }
+    }
+}
@@ -0,0 +1,158 @@
+/// Builds the account selector from the rules stored in a MongoDB collection.
+///
+/// Every document of the collection is a selector in the format of the
+/// `accounts_selector` config section, e.g. `{ "owners": ["<program>"] }`, and the
+/// account selector is their union. Documents with `enabled: false` are left out. The
+/// collection is read when the plugin loads and then polled; when its documents
+/// changed the selector is rebuilt and swapped in like a reload of the config file.
+/// An invalid rule or a failed poll leaves the current selector in place. An empty
+/// collection selects no account.
+use {
+    crate::{
+        accounts_selector::SelectorSet,
+        geyser_plugin_mongodb::{to_config_error, GeyserPluginMongoDBConfig},
+        metrics,
+        mongodb_client::{plugin_database, SimpleMongoDbClient},
+        periodic_task::PeriodicTask,
+        selector_reload::SharedAccountsSelector,
+    },
+    futures::TryStreamExt,
+    log::*,
+    mongodb::{
+        bson::{doc, Bson, Document},
+        Database,
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::time::Duration,
+};
+
+const DEFAULT_SELECTOR_RULES_COLLECTION: &str = "selector_rules";
+const DEFAULT_SELECTOR_RULES_POLL_INTERVAL_SECS: u64 = 30;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SelectorRulesConfig {
+    /// The collection holding the rules. The default is selector_rules.
+    pub collection: Option<String>,
+    /// Seconds between two polls of the collection. The default is 30.
+    pub poll_interval_secs: Option<u64>,
+}
+
+async fn read_rules(
+    database: &Database,
+    collection: &str,
+) -> Result<Vec<Document>, GeyserPluginError> {
+    let to_error = |err: mongodb::error::Error| {
+        to_config_error(format!("Failed to read the selector rules: {}", err))
+    };
+    database
+        .collection::<Document>(collection)
+        .find(doc! { "enabled": { "$ne": false } })
+        .sort(doc! { "_id": 1 })
+        .await
+        .map_err(to_error)?
+        .try_collect()
+        .await
+        .map_err(to_error)
+}
+
+/// The union of the selectors of `rules`.
+fn build_selector(rules: &[Document]) -> Result<SelectorSet, GeyserPluginError> {
+    let mut selectors = Vec::with_capacity(rules.len());
+    for rule in rules {
+        let id = rule.get("_id").map(Bson::to_string).unwrap_or_default();
+        let mut rule = rule.clone();
+        rule.remove("_id");
+        rule.remove("enabled");
+        let value: serde_json::Value = Bson::Document(rule).into_relaxed_extjson();
+        let selector = SelectorSet::from_config(&value)
+            .map_err(|err| to_config_error(format!("Invalid selector rule {}: {}", id, err)))?;
+        selectors.push(selector);
+    }
+    Ok(match selectors.len() {
+        0 => SelectorSet::default(),
+        1 => selectors.pop().unwrap(),
+        _ => SelectorSet::Union(selectors),
+    })
+}
+
+pub(crate) struct SelectorRulesPoller {
+    task: PeriodicTask,
+}
+
+impl SelectorRulesPoller {
+    /// Reads the rules into `accounts_selector` and starts polling them. Fails when
+    /// the rules cannot be read or are invalid when the plugin loads.
+    pub fn spawn(
+        config: &GeyserPluginMongoDBConfig,
+        rules_config: &SelectorRulesConfig,
+        accounts_selector: SharedAccountsSelector,
+    ) -> Result<Self, GeyserPluginError> {
+        let collection = rules_config
+            .collection
+            .clone()
+            .unwrap_or_else(|| DEFAULT_SELECTOR_RULES_COLLECTION.to_string());
+        let interval = Duration::from_secs(
+            rules_config
+                .poll_interval_secs
+                .unwrap_or(DEFAULT_SELECTOR_RULES_POLL_INTERVAL_SECS)
+                .max(1),
+        );
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| {
+                to_config_error(format!("Failed to create the selector rules runtime: {}", err))
+            })?;
+        let mut rules = runtime.block_on(async {
+            let client = SimpleMongoDbClient::connect_to_db(config).await?;
+            read_rules(&plugin_database(&client, config), &collection).await
+        })?;
+        *accounts_selector.write().unwrap() = build_selector(&rules)?;
+        info!(
+            "Selecting accounts by the {} rules of {}, polled every {:?}",
+            rules.len(),
+            collection,
+            interval
+        );
+
+        let task = PeriodicTask::spawn(
+            "mongodb-selector-rules",
+            "poll the selector rules",
+            interval,
+            config,
+            move |runtime, database| {
+                let polled = runtime.block_on(read_rules(database, &collection));
+                let polled = match polled {
+                    Ok(polled) => polled,
+                    Err(err) => {
+                        metrics::inc_counter("geyser-plugin-mongodb-selector-rules-failures", 1);
+                        return Err(err);
+                    }
+                };
+                if polled == rules {
+                    return Ok(());
+                }
+                match build_selector(&polled) {
+                    Ok(selector) => {
+                        *accounts_selector.write().unwrap() = selector;
+                        info!("Rebuilt the account selector from {} rules", polled.len());
+                        metrics::inc_counter("geyser-plugin-mongodb-selector-rules-reloads", 1);
+                    }
+                    Err(err) => {
+                        error!("{}, keeping the current selector", err);
+                        metrics::inc_counter("geyser-plugin-mongodb-selector-rules-failures", 1);
+                    }
+                }
+                // An invalid rule is reported once, not at every poll.
+                rules = polled;
+                Ok(())
+            },
+        );
+        Ok(Self { task })
+    }
+
+    pub fn join(&mut self) {
+        self.task.join();
+    }
+}
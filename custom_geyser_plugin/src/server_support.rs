@@ -0,0 +1,197 @@
+/// The MongoDB server versions the plugin supports and the optional server features
+/// it relies on.
+///
+/// The server version is read with `buildInfo` when the plugin loads; servers older
+/// than `MIN_SERVER_VERSION` are refused. Each optional feature needs a minimum
+/// version:
+///
+/// | Feature                                   | Server |
+/// |-------------------------------------------|--------|
+/// | Time-series collections                   | 5.0    |
+/// | Snapshot reads                            | 5.0    |
+/// | Client bulk writes (`bulkWrite` command)  | 8.0    |
+///
+/// A feature the plugin would only use implicitly is turned off on older servers, e.g.
+/// the token indexes fall back to `update` commands without client bulk writes. A
+/// feature the config asks for explicitly fails the load with a configuration error
+/// naming the config key and the server version it needs. The readers check the
+/// server with `ServerSupport::read` before opening a snapshot session.
+use {
+    crate::{
+        geyser_plugin_mongodb::{
+            to_config_error, GeyserPluginMongoDBConfig, GeyserPluginMongoDbError,
+        },
+        mongodb_client::SimpleMongoDbClient,
+    },
+    log::*,
+    mongodb::{bson::doc, Client},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::fmt,
+};
+
+pub const MIN_SERVER_VERSION: ServerVersion = ServerVersion::new(4, 2, 0);
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct ServerVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+impl ServerVersion {
+    pub const fn new(major: u32, minor: u32, patch: u32) -> Self {
+        Self {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    /// Parses versions like `7.0.12` or `8.0.0-rc4`, a missing minor or patch is 0.
+    pub fn parse(version: &str) -> Option<Self> {
+        let mut parts = version.split(|c| c == '.' || c == '-').map(str::parse::<u32>);
+        let major = parts.next()?.ok()?;
+        let minor = parts.next().and_then(Result::ok).unwrap_or_default();
+        let patch = parts.next().and_then(Result::ok).unwrap_or_default();
+        Some(Self::new(major, minor, patch))
+    }
+}
+
+impl fmt::Display for ServerVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ServerFeature {
+    TimeSeriesCollections,
+    SnapshotReads,
+    ClientBulkWrite,
+}
+
+impl ServerFeature {
+    pub fn min_version(&self) -> ServerVersion {
+        match self {
+            ServerFeature::TimeSeriesCollections => ServerVersion::new(5, 0, 0),
+            ServerFeature::SnapshotReads => ServerVersion::new(5, 0, 0),
+            ServerFeature::ClientBulkWrite => ServerVersion::new(8, 0, 0),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ServerFeature::TimeSeriesCollections => "time-series collections",
+            ServerFeature::SnapshotReads => "snapshot reads",
+            ServerFeature::ClientBulkWrite => "client bulk writes",
+        }
+    }
+}
+
+/// What the connected server supports.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ServerSupport {
+    pub version: ServerVersion,
+}
+
+impl ServerSupport {
+    pub fn new(version: ServerVersion) -> Self {
+        Self { version }
+    }
+
+    /// Reads the server version, failing on unsupported servers.
+    pub fn detect(config: &GeyserPluginMongoDBConfig) -> Result<Self, GeyserPluginError> {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(|err| {
+                GeyserPluginError::Custom(Box::new(
+                    GeyserPluginMongoDbError::DataStoreConnectionError {
+                        msg: format!("Failed to create the tokio runtime: {}", err),
+                    },
+                ))
+            })?;
+        let support = runtime.block_on(async {
+            let client = SimpleMongoDbClient::connect_to_db(config).await?;
+            Self::read(&client).await.map_err(|err| {
+                GeyserPluginError::Custom(Box::new(
+                    GeyserPluginMongoDbError::DataStoreConnectionError {
+                        msg: format!("Failed to read the server version: {}", err),
+                    },
+                ))
+            })
+        })?;
+        let version = support.version;
+        support.check_version()?;
+        let unsupported = support.unsupported_features();
+        if unsupported.is_empty() {
+            info!("Connected to MongoDB {}", version);
+        } else {
+            info!(
+                "Connected to MongoDB {}, turning off {}",
+                version,
+                unsupported.join(", ")
+            );
+        }
+        Ok(support)
+    }
+
+    /// Reads the version of the server `client` is connected to. A version missing
+    /// from the `buildInfo` reply reads as 0.0.0, which supports no optional feature.
+    pub async fn read(client: &Client) -> mongodb::error::Result<Self> {
+        let build_info = client
+            .database("admin")
+            .run_command(doc! { "buildInfo": 1 })
+            .await?;
+        let version = build_info
+            .get_str("version")
+            .ok()
+            .and_then(ServerVersion::parse)
+            .unwrap_or_default();
+        Ok(Self::new(version))
+    }
+
+    pub fn supports(&self, feature: ServerFeature) -> bool {
+        self.version >= feature.min_version()
+    }
+
+    fn unsupported_features(&self) -> Vec<&'static str> {
+        [
+            ServerFeature::TimeSeriesCollections,
+            ServerFeature::ClientBulkWrite,
+        ]
+        .into_iter()
+        .filter(|feature| !self.supports(*feature))
+        .map(|feature| feature.as_str())
+        .collect()
+    }
+
+    /// Fails when `config_key` asks for `feature` and the server lacks it.
+    pub fn require(
+        &self,
+        feature: ServerFeature,
+        config_key: &str,
+    ) -> Result<(), GeyserPluginError> {
+        if self.supports(feature) {
+            return Ok(());
+        }
+        Err(to_config_error(format!(
+            "\"{}\" needs {}, available from MongoDB {}, but the server runs {}",
+            config_key,
+            feature.as_str(),
+            feature.min_version(),
+            self.version
+        )))
+    }
+
+    /// Fails on servers older than `MIN_SERVER_VERSION`.
+    fn check_version(&self) -> Result<(), GeyserPluginError> {
+        if self.version < MIN_SERVER_VERSION {
+            return Err(to_config_error(format!(
+                "MongoDB {} is not supported, the plugin needs {} or later",
+                self.version, MIN_SERVER_VERSION
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,294 @@
+/// Shards the large collections when the plugin is connected to a mongos.
+///
+/// When the plugin loads it enables sharding on its database and shards the
+/// accounts, the account history, when stored, and the transactions collections on
+/// the configured keys, creating the index of each key first. Collections already
+/// sharded are kept as they are, with a warning when their key differs. With
+/// `initial_chunks` an empty collection with a hashed key is pre-split into that many
+/// chunks, spreading the first writes over the shards. Against a replica set or a
+/// standalone server nothing is done.
+///
+/// Writes are targeted by their shard key: accounts and history versions are
+/// inserted in unordered batches, which mongos splits by shard, and with a ranged key
+/// the documents of a batch are ordered by the key first so each chunk gets one
+/// contiguous run. Updates filtering on another field, such as the block time
+/// backfill by slot on accounts sharded by pubkey, are broadcast to every shard.
+use {
+    crate::{
+        geyser_plugin_mongodb::{
+            to_config_error, GeyserPluginMongoDBConfig, GeyserPluginMongoDbError,
+        },
+        mongodb_client::{
+            plugin_database, AccountStorageMode, SimpleMongoDbClient, ACCOUNTS_COLLECTION,
+            ACCOUNT_HISTORY_COLLECTION, TRANSACTIONS_COLLECTION,
+        },
+    },
+    log::*,
+    mongodb::{
+        bson::{doc, Bson, Document},
+        Client, IndexModel,
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+};
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ShardKeyKind {
+    /// Documents are spread by the hash of the field.
+    #[default]
+    Hashed,
+    /// Documents are split in ranges of the field.
+    Ranged,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ShardKeyConfig {
+    /// The field, by its standard name.
+    pub field: String,
+    /// "hashed" or "ranged". The default is hashed.
+    pub kind: Option<ShardKeyKind>,
+}
+
+impl ShardKeyConfig {
+    fn new(field: &str) -> Self {
+        Self {
+            field: field.to_string(),
+            kind: None,
+        }
+    }
+
+    fn key(&self, field: &str) -> Document {
+        match self.kind.unwrap_or_default() {
+            ShardKeyKind::Hashed => doc! { field: "hashed" },
+            ShardKeyKind::Ranged => doc! { field: 1 },
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ShardingConfig {
+    /// The shard key of the accounts. The default is the hashed pubkey.
+    pub accounts_key: Option<ShardKeyConfig>,
+    /// The shard key of the account history. The default is the hashed pubkey.
+    pub account_history_key: Option<ShardKeyConfig>,
+    /// The shard key of the transactions. The default is the hashed slot, which keeps
+    /// the transactions of a slot together without making the newest chunk a hot spot.
+    pub transactions_key: Option<ShardKeyConfig>,
+    /// Chunks an empty collection with a hashed key is pre-split into. Left to the
+    /// server when unset.
+    pub initial_chunks: Option<u32>,
+}
+
+impl ShardingConfig {
+    pub fn accounts_key(&self) -> ShardKeyConfig {
+        self.accounts_key
+            .clone()
+            .unwrap_or_else(|| ShardKeyConfig::new("pubkey"))
+    }
+
+    pub fn account_history_key(&self) -> ShardKeyConfig {
+        self.account_history_key
+            .clone()
+            .unwrap_or_else(|| ShardKeyConfig::new("pubkey"))
+    }
+
+    pub fn transactions_key(&self) -> ShardKeyConfig {
+        self.transactions_key
+            .clone()
+            .unwrap_or_else(|| ShardKeyConfig::new("slot"))
+    }
+}
+
+/// The stored field batches of `collection` are ordered by, set for ranged keys.
+pub(crate) fn batch_order_field(
+    config: &GeyserPluginMongoDBConfig,
+    collection: &str,
+) -> Option<String> {
+    let sharding = config.sharding.as_ref()?;
+    let (key, field) = match collection {
+        ACCOUNTS_COLLECTION => {
+            let key = sharding.accounts_key();
+            let field = config
+                .schema_profile
+                .unwrap_or_default()
+                .account_field(&key.field)
+                .to_string();
+            (key, field)
+        }
+        ACCOUNT_HISTORY_COLLECTION => {
+            let key = sharding.account_history_key();
+            let field = key.field.clone();
+            (key, field)
+        }
+        _ => return None,
+    };
+    (key.kind.unwrap_or_default() == ShardKeyKind::Ranged).then_some(field)
+}
+
+/// The value documents are ordered by within a batch, numbers in numeric order.
+fn batch_order(value: Option<&Bson>) -> (Option<i64>, String) {
+    match value {
+        Some(Bson::Int32(value)) => (Some(*value as i64), String::default()),
+        Some(Bson::Int64(value)) => (Some(*value), String::default()),
+        Some(Bson::String(value)) => (None, value.clone()),
+        Some(value) => (None, value.to_string()),
+        None => (None, String::default()),
+    }
+}
+
+/// Orders `documents` by `field`, grouping them by chunk for a ranged shard key.
+pub(crate) fn order_by_shard_key(documents: &mut [Document], field: &str) {
+    documents.sort_by_cached_key(|document| batch_order(document.get(field)));
+}
+
+fn sharding_error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::DataSchemaError { msg }))
+}
+
+async fn is_mongos(client: &Client) -> Result<bool, GeyserPluginError> {
+    let hello = client
+        .database("admin")
+        .run_command(doc! { "hello": 1 })
+        .await
+        .map_err(|err| sharding_error(format!("Failed to run hello: {}", err)))?;
+    Ok(hello.get_str("msg").is_ok_and(|msg| msg == "isdbgrid"))
+}
+
+/// Shards `collection` on `key` unless it is sharded already.
+async fn shard_collection(
+    client: &Client,
+    database_name: &str,
+    collection: &str,
+    key: Document,
+    initial_chunks: Option<u32>,
+) -> Result<(), GeyserPluginError> {
+    let namespace = format!("{}.{}", database_name, collection);
+    let sharded = client
+        .database("config")
+        .collection::<Document>("collections")
+        .find_one(doc! { "_id": &namespace, "dropped": { "$ne": true } })
+        .await
+        .map_err(|err| {
+            sharding_error(format!("Failed to read the sharding of {}: {}", namespace, err))
+        })?;
+    if let Some(sharded) = sharded {
+        match sharded.get_document("key") {
+            Ok(existing) if *existing == key => {}
+            existing => warn!(
+                "{} is sharded on {:?} rather than {}, it is kept",
+                namespace, existing, key
+            ),
+        }
+        return Ok(());
+    }
+    client
+        .database(database_name)
+        .collection::<Document>(collection)
+        .create_index(IndexModel::builder().keys(key.clone()).build())
+        .await
+        .map_err(|err| {
+            sharding_error(format!(
+                "Failed to create the shard key index of {}: {}",
+                namespace, err
+            ))
+        })?;
+    let is_hashed = key.values().any(|value| value.as_str() == Some("hashed"));
+    let mut command = doc! { "shardCollection": &namespace, "key": key.clone() };
+    if let (true, Some(initial_chunks)) = (is_hashed, initial_chunks) {
+        command.insert("numInitialChunks", initial_chunks as i64);
+    }
+    client
+        .database("admin")
+        .run_command(command)
+        .await
+        .map_err(|err| sharding_error(format!("Failed to shard {}: {}", namespace, err)))?;
+    info!("Sharded {} on {}", namespace, key);
+    Ok(())
+}
+
+/// Shards the collections configured by `sharding`, once on load rather than by every
+/// worker.
+pub fn prepare_sharding(config: &GeyserPluginMongoDBConfig) -> Result<(), GeyserPluginError> {
+    let Some(sharding) = &config.sharding else {
+        return Ok(());
+    };
+    if sharding.initial_chunks == Some(0) {
+        return Err(to_config_error(
+            "\"sharding\": initial_chunks must be positive".to_string(),
+        ));
+    }
+    if config.transactions_capped_size_bytes.is_some() {
+        return Err(to_config_error(
+            "\"sharding\" is not supported with \"transactions_capped_size_bytes\", capped \
+             collections cannot be sharded"
+                .to_string(),
+        ));
+    }
+    let accounts_key = sharding.accounts_key();
+    if config.account_storage.unwrap_or_default() == AccountStorageMode::CurrentState
+        && accounts_key.field != "pubkey"
+    {
+        return Err(to_config_error(
+            "\"sharding\": with \"account_storage\": \"current_state\" the accounts must be \
+             sharded on pubkey, the upserts of an account are targeted by it"
+                .to_string(),
+        ));
+    }
+    if config.account_history_bucket_size.is_some()
+        && sharding.account_history_key().field != "pubkey"
+    {
+        return Err(to_config_error(
+            "\"sharding\": with \"account_history_bucket_size\" the account history must \
+             be sharded on pubkey, the bucket upserts of an account are targeted by it"
+                .to_string(),
+        ));
+    }
+    let profile = config.schema_profile.unwrap_or_default();
+    let mut collections = vec![(
+        ACCOUNTS_COLLECTION,
+        accounts_key.key(profile.account_field(&accounts_key.field)),
+    )];
+    if config.store_account_historical_data.unwrap_or_default() {
+        let key = sharding.account_history_key();
+        collections.push((ACCOUNT_HISTORY_COLLECTION, key.key(&key.field)));
+    }
+    let transactions_key = sharding.transactions_key();
+    collections.push((
+        TRANSACTIONS_COLLECTION,
+        transactions_key.key(&transactions_key.field),
+    ));
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| to_config_error(format!("Failed to create the sharding runtime: {}", err)))?;
+    runtime.block_on(async {
+        let client = SimpleMongoDbClient::connect_to_db(config).await?;
+        if !is_mongos(&client).await? {
+            info!("Not connected to a mongos, the collections are not sharded");
+            return Ok(());
+        }
+        let database_name = plugin_database(&client, config).name().to_string();
+        client
+            .database("admin")
+            .run_command(doc! { "enableSharding": &database_name })
+            .await
+            .map_err(|err| {
+                sharding_error(format!(
+                    "Failed to enable sharding on {}: {}",
+                    database_name, err
+                ))
+            })?;
+        for (collection, key) in collections {
+            shard_collection(
+                &client,
+                &database_name,
+                collection,
+                key,
+                sharding.initial_chunks,
+            )
+            .await?;
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,275 @@
+/// Batches the account updates of a slot and writes them at a slot status.
+///
+/// Steady-state updates are buffered per slot instead of being queued one by one. When
+/// a slot reaches the `flush_on` status, the updates of that slot and of every older
+/// slot still buffered are queued as batches of at most `max_batch_size` accounts,
+/// each written with one insert. Only the latest update of an account in a slot is
+/// kept, so accounts updated many times in a slot are written once and readers see
+/// the accounts of a slot land together. The versions of an account in different
+/// slots of a flush are all written, the slots may be on different forks. Beyond
+/// `max_buffered_slots` the oldest slot is flushed whatever its status, slots of
+/// abandoned forks never reach it. Startup updates are not buffered. The buffered
+/// accounts are published as pending work at every slot status.
+use {
+    crate::{
+        metrics,
+        mongodb_client::{DbAccountInfo, ACCOUNTS_COLLECTION},
+        pending_work::{pending_work, PendingCounts},
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::SlotStatus,
+    std::{
+        collections::{BTreeMap, HashMap},
+        sync::Mutex,
+    },
+};
+
+const DEFAULT_SLOT_BATCH_MAX_BATCH_SIZE: usize = 1000;
+const DEFAULT_SLOT_BATCH_MAX_BUFFERED_SLOTS: usize = 32;
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SlotBatchFlush {
+    /// The slot was replayed, the lowest latency.
+    #[default]
+    Processed,
+    /// The slot was confirmed by the cluster.
+    Confirmed,
+    /// The slot was rooted, updates of abandoned forks are only flushed by the slot
+    /// limit.
+    Rooted,
+}
+
+impl SlotBatchFlush {
+    fn is_reached(&self, status: &SlotStatus) -> bool {
+        matches!(
+            (self, status),
+            (SlotBatchFlush::Processed, _)
+                | (SlotBatchFlush::Confirmed, SlotStatus::Confirmed)
+                | (SlotBatchFlush::Confirmed, SlotStatus::Rooted)
+                | (SlotBatchFlush::Rooted, SlotStatus::Rooted)
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct SlotBatchingConfig {
+    /// The slot status the updates of a slot are written at. The default is processed.
+    pub flush_on: Option<SlotBatchFlush>,
+    /// Accounts written per batch at most. The default is 1000.
+    pub max_batch_size: Option<usize>,
+    /// Slots buffered at most. The default is 32.
+    pub max_buffered_slots: Option<usize>,
+}
+
+pub struct SlotAccountBatches {
+    flush_on: SlotBatchFlush,
+    max_batch_size: usize,
+    max_buffered_slots: usize,
+    slots: Mutex<BTreeMap<u64, HashMap<Vec<u8>, DbAccountInfo>>>,
+}
+
+impl SlotAccountBatches {
+    pub fn new(config: &SlotBatchingConfig) -> Self {
+        Self {
+            flush_on: config.flush_on.unwrap_or_default(),
+            max_batch_size: config
+                .max_batch_size
+                .unwrap_or(DEFAULT_SLOT_BATCH_MAX_BATCH_SIZE)
+                .max(1),
+            max_buffered_slots: config
+                .max_buffered_slots
+                .unwrap_or(DEFAULT_SLOT_BATCH_MAX_BUFFERED_SLOTS)
+                .max(1),
+            slots: Mutex::default(),
+        }
+    }
+
+    /// Buffers `account` until its slot is flushed. Returns the batches flushed to
+    /// stay within the buffered slot limit.
+    pub fn push(&self, account: DbAccountInfo) -> Vec<Vec<DbAccountInfo>> {
+        let mut slots = self.slots.lock().unwrap();
+        let accounts = slots.entry(account.slot as u64).or_default();
+        let is_latest = accounts
+            .get(&account.pubkey)
+            .map(|buffered| buffered.write_version <= account.write_version);
+        if is_latest.is_some() {
+            metrics::inc_counter("geyser-plugin-mongodb-slot-batch-superseded-updates", 1);
+        }
+        if is_latest.unwrap_or(true) {
+            accounts.insert(account.pubkey.clone(), account);
+        }
+        let mut flushed = BTreeMap::default();
+        while slots.len() > self.max_buffered_slots {
+            let (slot, accounts) = slots.pop_first().unwrap();
+            flushed.insert(slot, accounts);
+        }
+        drop(slots);
+        self.batches(flushed)
+    }
+
+    /// Flushes `slot` and every older slot when `status` is the configured one.
+    pub fn flush(&self, slot: u64, status: &SlotStatus) -> Vec<Vec<DbAccountInfo>> {
+        if !self.flush_on.is_reached(status) {
+            return Vec::default();
+        }
+        self.flush_through(slot)
+    }
+
+    /// Flushes `slot` and every older slot whatever their status.
+    pub fn flush_through(&self, slot: u64) -> Vec<Vec<DbAccountInfo>> {
+        let mut slots = self.slots.lock().unwrap();
+        let newer = slots.split_off(&slot.saturating_add(1));
+        let flushed = std::mem::replace(&mut *slots, newer);
+        drop(slots);
+        self.batches(flushed)
+    }
+
+    /// Publishes the accounts buffered per slot as pending work.
+    pub fn publish_pending_work(&self) {
+        let counts: PendingCounts = self
+            .slots
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(slot, accounts)| ((*slot, ACCOUNTS_COLLECTION), accounts.len() as u64))
+            .collect();
+        pending_work().publish("slot_batching", counts);
+    }
+
+    /// The latest update of every account in each of `slots`, in batches.
+    fn batches(
+        &self,
+        slots: BTreeMap<u64, HashMap<Vec<u8>, DbAccountInfo>>,
+    ) -> Vec<Vec<DbAccountInfo>> {
+        if slots.is_empty() {
+            return Vec::default();
+        }
+        let mut accounts: Vec<DbAccountInfo> = slots
+            .into_values()
+            .flat_map(|accounts| accounts.into_values())
+            .collect();
+        accounts.sort_unstable_by_key(|account| (account.slot, account.write_version));
+        metrics::inc_counter("geyser-plugin-mongodb-slot-batch-accounts", accounts.len());
+        let mut batches = Vec::default();
+        while accounts.len() > self.max_batch_size {
+            let rest = accounts.split_off(self.max_batch_size);
+            batches.push(std::mem::replace(&mut accounts, rest));
+        }
+        batches.push(accounts);
+        batches
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, mongodb::bson};
+
+    fn account(pubkey: u8, slot: i64, write_version: i64) -> DbAccountInfo {
+        DbAccountInfo {
+            pubkey: vec![pubkey; 32],
+            lamports: 1,
+            owner: vec![0; 32],
+            executable: false,
+            rent_epoch: 0,
+            data: Vec::default(),
+            slot,
+            write_version,
+            txn_signature: None,
+            notified_at: bson::DateTime::now(),
+            redacted_data: None,
+        }
+    }
+
+    fn slot_batches(
+        flush_on: SlotBatchFlush,
+        max_batch_size: usize,
+        max_buffered_slots: usize,
+    ) -> SlotAccountBatches {
+        SlotAccountBatches::new(&SlotBatchingConfig {
+            flush_on: Some(flush_on),
+            max_batch_size: Some(max_batch_size),
+            max_buffered_slots: Some(max_buffered_slots),
+        })
+    }
+
+    /// The (pubkey, slot, write_version) of the accounts of `batches`, in order.
+    fn versions(batches: &[Vec<DbAccountInfo>]) -> Vec<(u8, i64, i64)> {
+        batches
+            .iter()
+            .flatten()
+            .map(|account| (account.pubkey[0], account.slot, account.write_version))
+            .collect()
+    }
+
+    #[test]
+    fn test_only_the_latest_update_of_a_slot_is_kept() {
+        let batches = slot_batches(SlotBatchFlush::Processed, 1000, 32);
+        assert!(batches.push(account(1, 10, 2)).is_empty());
+        // An older write version arriving late does not replace the newer one.
+        assert!(batches.push(account(1, 10, 1)).is_empty());
+        assert!(batches.push(account(1, 10, 3)).is_empty());
+        assert!(batches.push(account(2, 10, 4)).is_empty());
+        // The version of another slot is kept apart.
+        assert!(batches.push(account(1, 11, 5)).is_empty());
+
+        let flushed = batches.flush(11, &SlotStatus::Processed);
+        assert_eq!(versions(&flushed), vec![(1, 10, 3), (2, 10, 4), (1, 11, 5)]);
+    }
+
+    #[test]
+    fn test_flush_waits_for_the_configured_status() {
+        let statuses = [SlotStatus::Processed, SlotStatus::Confirmed, SlotStatus::Rooted];
+        for (flush_on, flushed_at) in [
+            (SlotBatchFlush::Processed, 0),
+            (SlotBatchFlush::Confirmed, 1),
+            (SlotBatchFlush::Rooted, 2),
+        ] {
+            let batches = slot_batches(flush_on, 1000, 32);
+            batches.push(account(1, 10, 1));
+            batches.push(account(2, 12, 2));
+            for status in &statuses[..flushed_at] {
+                assert!(batches.flush(12, status).is_empty(), "{:?} at {:?}", flush_on, status);
+            }
+            // The slot and the older ones are flushed, the newer ones stay buffered.
+            let status = &statuses[flushed_at];
+            assert_eq!(versions(&batches.flush(11, status)), vec![(1, 10, 1)]);
+            assert_eq!(versions(&batches.flush(12, status)), vec![(2, 12, 2)]);
+            assert!(batches.flush(12, status).is_empty());
+        }
+        // A later status than the configured one flushes too.
+        let batches = slot_batches(SlotBatchFlush::Confirmed, 1000, 32);
+        batches.push(account(1, 10, 1));
+        assert_eq!(versions(&batches.flush(10, &SlotStatus::Rooted)), vec![(1, 10, 1)]);
+    }
+
+    #[test]
+    fn test_the_oldest_slot_is_flushed_beyond_the_slot_limit() {
+        let batches = slot_batches(SlotBatchFlush::Rooted, 1000, 2);
+        assert!(batches.push(account(1, 10, 1)).is_empty());
+        assert!(batches.push(account(2, 11, 2)).is_empty());
+        assert_eq!(versions(&batches.push(account(3, 12, 3))), vec![(1, 10, 1)]);
+        // An update of a buffered slot adds no slot.
+        assert!(batches.push(account(4, 12, 4)).is_empty());
+        assert_eq!(
+            versions(&batches.flush_through(u64::MAX - 1)),
+            vec![(2, 11, 2), (3, 12, 3), (4, 12, 4)]
+        );
+    }
+
+    #[test]
+    fn test_flushed_accounts_are_split_into_batches() {
+        let batches = slot_batches(SlotBatchFlush::Processed, 2, 32);
+        for pubkey in 0..5 {
+            batches.push(account(pubkey, 10 + pubkey as i64 % 2, pubkey as i64));
+        }
+        let flushed = batches.flush(11, &SlotStatus::Processed);
+        let sizes: Vec<usize> = flushed.iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 2, 1]);
+        // Ordered by slot then write version across the batches.
+        assert_eq!(
+            versions(&flushed),
+            vec![(0, 10, 0), (2, 10, 2), (4, 10, 4), (1, 11, 1), (3, 11, 3)]
+        );
+    }
+}
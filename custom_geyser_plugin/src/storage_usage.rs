@@ -0,0 +1,147 @@
+/// How much storage the plugin's data takes, per collection and per account owner.
+///
+/// The collection figures are the server's own, from `collStats`. The owner figures
+/// are estimates: the BSON size of the current account documents of each owner,
+/// before compression and without their share of the indexes, which is what
+/// selecting or dropping an owner changes. Owners are summed over the whole accounts
+/// collection, so the report is meant for occasional capacity planning runs rather
+/// than for a live database under load.
+use {
+    crate::{metrics, mongodb_client::ACCOUNTS_COLLECTION, schema::SchemaProfile},
+    futures::TryStreamExt,
+    log::Level,
+    mongodb::{
+        bson::{doc, Bson, Document},
+        error::Result,
+        Database,
+    },
+    solana_metrics::datapoint::DataPoint,
+};
+
+/// The `collStats` figures of one collection.
+#[derive(Clone, Debug, Default)]
+pub struct CollectionStorage {
+    pub collection: String,
+    pub documents: i64,
+    /// The uncompressed size of the documents.
+    pub data_bytes: i64,
+    /// The bytes allocated on disk for the documents.
+    pub storage_bytes: i64,
+    pub index_bytes: i64,
+}
+
+/// The estimated storage of the accounts of one owner.
+#[derive(Clone, Debug, Default)]
+pub struct OwnerStorage {
+    pub owner: String,
+    pub accounts: i64,
+    /// The summed BSON size of the account documents.
+    pub estimated_bytes: i64,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct StorageReport {
+    /// Ordered by storage, the largest first.
+    pub collections: Vec<CollectionStorage>,
+    /// The largest owners by estimated bytes.
+    pub owners: Vec<OwnerStorage>,
+}
+
+/// `collStats` reports sizes as int32, int64 or double depending on their magnitude.
+fn number(document: &Document, field: &str) -> i64 {
+    match document.get(field) {
+        Some(Bson::Int32(value)) => *value as i64,
+        Some(Bson::Int64(value)) => *value,
+        Some(Bson::Double(value)) => *value as i64,
+        _ => 0,
+    }
+}
+
+/// The storage figures of every collection of `database`.
+pub async fn collection_storage(database: &Database) -> Result<Vec<CollectionStorage>> {
+    let mut collections = Vec::default();
+    for collection in database.list_collection_names().await? {
+        let stats = database.run_command(doc! { "collStats": &collection }).await?;
+        collections.push(CollectionStorage {
+            documents: number(&stats, "count"),
+            data_bytes: number(&stats, "size"),
+            storage_bytes: number(&stats, "storageSize"),
+            index_bytes: number(&stats, "totalIndexSize"),
+            collection,
+        });
+    }
+    collections.sort_by(|a, b| {
+        (b.storage_bytes + b.index_bytes).cmp(&(a.storage_bytes + a.index_bytes))
+    });
+    Ok(collections)
+}
+
+/// The `limit` owners whose account documents are the largest in total.
+pub async fn owner_storage(
+    database: &Database,
+    schema_profile: SchemaProfile,
+    limit: i64,
+) -> Result<Vec<OwnerStorage>> {
+    let pipeline = vec![
+        doc! { "$group": {
+            "_id": format!("${}", schema_profile.account_field("owner")),
+            "accounts": { "$sum": 1 },
+            "estimated_bytes": { "$sum": { "$bsonSize": "$$ROOT" } },
+        }},
+        doc! { "$sort": { "estimated_bytes": -1 } },
+        doc! { "$limit": limit },
+    ];
+    let owners: Vec<Document> = database
+        .collection::<Document>(ACCOUNTS_COLLECTION)
+        .aggregate(pipeline)
+        .allow_disk_use(true)
+        .await?
+        .try_collect()
+        .await?;
+    Ok(owners
+        .iter()
+        .map(|owner| OwnerStorage {
+            owner: owner.get_str("_id").unwrap_or_default().to_string(),
+            accounts: number(owner, "accounts"),
+            estimated_bytes: number(owner, "estimated_bytes"),
+        })
+        .collect())
+}
+
+/// The storage of every collection and of the `owner_limit` largest owners.
+pub async fn storage_usage(
+    database: &Database,
+    schema_profile: SchemaProfile,
+    owner_limit: i64,
+) -> Result<StorageReport> {
+    Ok(StorageReport {
+        collections: collection_storage(database).await?,
+        owners: owner_storage(database, schema_profile, owner_limit).await?,
+    })
+}
+
+impl StorageReport {
+    /// Reports one data point per collection and per owner to solana_metrics.
+    pub fn submit(&self) {
+        for collection in &self.collections {
+            metrics::submit(
+                DataPoint::new("geyser-plugin-mongodb-collection-storage")
+                    .add_field_str("collection", &collection.collection)
+                    .add_field_i64("documents", collection.documents)
+                    .add_field_i64("data_bytes", collection.data_bytes)
+                    .add_field_i64("storage_bytes", collection.storage_bytes)
+                    .add_field_i64("index_bytes", collection.index_bytes),
+                Level::Info,
+            );
+        }
+        for owner in &self.owners {
+            metrics::submit(
+                DataPoint::new("geyser-plugin-mongodb-owner-storage")
+                    .add_field_str("owner", &owner.owner)
+                    .add_field_i64("accounts", owner.accounts)
+                    .add_field_i64("estimated_bytes", owner.estimated_bytes),
+                Level::Info,
+            );
+        }
+    }
+}
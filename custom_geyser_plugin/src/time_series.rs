@@ -0,0 +1,220 @@
+/// Time-series collections for the slot and metrics data charted over time, which
+/// MongoDB stores in compressed buckets of measurements and expires by bucket.
+///
+/// The `slots` collection itself stays a regular collection: its document of a slot
+/// is upserted as the statuses, the block time and the leader of the slot arrive,
+/// and time-series collections do not take such updates. With `slot_events` every
+/// slot status notification is also appended to the `slot_events` time-series
+/// collection, with the status as its meta field. With `metrics_interval_secs` a
+/// snapshot of the pipeline metrics is appended every interval to `plugin_metrics`:
+/// the length of the work queue and the counters that moved since the previous
+/// snapshot with their increase, under the hostname or `source_validator` of the
+/// validator. Both collections are created on load when missing, with `granularity`
+/// and `expire_after_secs`, and need MongoDB 5.0.
+use {
+    crate::{
+        geyser_plugin_mongodb::{
+            metrics_hostname, to_config_error, GeyserPluginMongoDBConfig, GeyserPluginMongoDbError,
+        },
+        metrics,
+        mongodb_client::{plugin_database, SimpleMongoDbClient, SLOT_EVENTS_COLLECTION},
+        periodic_task::PeriodicTask,
+        server_support::{ServerFeature, ServerSupport},
+    },
+    futures::TryStreamExt,
+    log::*,
+    mongodb::{
+        bson::{self, doc, Document},
+        options::{TimeseriesGranularity, TimeseriesOptions},
+        Database,
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::{collections::HashMap, time::Duration},
+};
+
+pub const METRICS_COLLECTION: &str = "plugin_metrics";
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TimeSeriesGranularityConfig {
+    #[default]
+    Seconds,
+    Minutes,
+    Hours,
+}
+
+impl TimeSeriesGranularityConfig {
+    fn to_granularity(self) -> TimeseriesGranularity {
+        match self {
+            TimeSeriesGranularityConfig::Seconds => TimeseriesGranularity::Seconds,
+            TimeSeriesGranularityConfig::Minutes => TimeseriesGranularity::Minutes,
+            TimeSeriesGranularityConfig::Hours => TimeseriesGranularity::Hours,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TimeSeriesConfig {
+    /// Whether slot status notifications are appended to `slot_events`. The default
+    /// is false.
+    pub slot_events: Option<bool>,
+    /// Seconds between two snapshots of the metrics in `plugin_metrics`, none are
+    /// taken when unset.
+    pub metrics_interval_secs: Option<u64>,
+    /// The interval between measurements the buckets are sized for: "seconds",
+    /// "minutes" or "hours". The default is "seconds".
+    pub granularity: Option<TimeSeriesGranularityConfig>,
+    /// Seconds after which measurements expire, kept forever when unset.
+    pub expire_after_secs: Option<u64>,
+}
+
+fn time_series_error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::DataSchemaError { msg }))
+}
+
+/// Creates `name` as a time-series collection unless it exists, failing when it
+/// exists as a regular collection.
+async fn ensure_time_series_collection(
+    database: &Database,
+    name: &str,
+    time_field: &str,
+    meta_field: &str,
+    config: &TimeSeriesConfig,
+) -> Result<(), GeyserPluginError> {
+    let specification = database
+        .list_collections()
+        .filter(doc! { "name": name })
+        .await
+        .map_err(|err| time_series_error(format!("Failed to list the collections: {}", err)))?
+        .try_next()
+        .await
+        .map_err(|err| time_series_error(format!("Failed to list the collections: {}", err)))?;
+    if let Some(specification) = specification {
+        if specification.options.timeseries.is_none() {
+            return Err(to_config_error(format!(
+                "\"time_series\": {} exists as a regular collection, drop or rename it",
+                name
+            )));
+        }
+        return Ok(());
+    }
+    let options = TimeseriesOptions::builder()
+        .time_field(time_field.to_string())
+        .meta_field(Some(meta_field.to_string()))
+        .granularity(Some(config.granularity.unwrap_or_default().to_granularity()))
+        .build();
+    let mut create = database.create_collection(name).timeseries(options);
+    if let Some(expire_after_secs) = config.expire_after_secs {
+        create = create.expire_after_seconds(Duration::from_secs(expire_after_secs));
+    }
+    create.await.map_err(|err| {
+        time_series_error(format!("Failed to create the time-series {}: {}", name, err))
+    })?;
+    info!("Created the time-series collection {}", name);
+    Ok(())
+}
+
+/// Creates the time-series collections enabled by `time_series`, once on load rather
+/// than by every worker.
+pub fn prepare_time_series_collections(
+    config: &GeyserPluginMongoDBConfig,
+    server_support: &ServerSupport,
+) -> Result<(), GeyserPluginError> {
+    let Some(time_series) = &config.time_series else {
+        return Ok(());
+    };
+    let slot_events = time_series.slot_events.unwrap_or_default();
+    if !slot_events && time_series.metrics_interval_secs.is_none() {
+        return Ok(());
+    }
+    server_support.require(ServerFeature::TimeSeriesCollections, "time_series")?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| {
+            to_config_error(format!("Failed to create the time-series runtime: {}", err))
+        })?;
+    runtime.block_on(async {
+        let client = SimpleMongoDbClient::connect_to_db(config).await?;
+        let database = plugin_database(&client, config);
+        if slot_events {
+            ensure_time_series_collection(
+                &database,
+                SLOT_EVENTS_COLLECTION,
+                "notified_at",
+                "status",
+                time_series,
+            )
+            .await?;
+        }
+        if time_series.metrics_interval_secs.is_some() {
+            ensure_time_series_collection(&database, METRICS_COLLECTION, "ts", "host", time_series)
+                .await?;
+        }
+        Ok(())
+    })
+}
+
+/// The counters that moved since the previous snapshot, by name, with their increase.
+fn counter_increases(totals: &mut HashMap<&'static str, u64>) -> Document {
+    let mut counters = Document::new();
+    for (name, total) in metrics::registry().counter_values() {
+        let previous = totals.insert(name, total).unwrap_or_default();
+        if total > previous {
+            counters.insert(name, (total - previous) as i64);
+        }
+    }
+    counters
+}
+
+/// Appends a snapshot of the metrics to `plugin_metrics` every interval.
+pub(crate) struct TimeSeriesMetricsWriter {
+    task: PeriodicTask,
+}
+
+impl TimeSeriesMetricsWriter {
+    /// Starts the writer configured by `time_series`, None when no interval is set.
+    /// `queue_len` reads the length of the work queue.
+    pub fn spawn(
+        config: &GeyserPluginMongoDBConfig,
+        queue_len: impl Fn() -> usize + Send + 'static,
+    ) -> Option<Self> {
+        let interval_secs = config.time_series.as_ref()?.metrics_interval_secs?;
+        let interval = Duration::from_secs(interval_secs.max(1));
+        let host = config.source_validator.clone().unwrap_or_else(metrics_hostname);
+        // The first snapshot holds the totals since the plugin loaded.
+        let mut totals = HashMap::default();
+        let mut failed = false;
+        let task = PeriodicTask::spawn(
+            "mongodb-metrics-time-series",
+            "write the metrics time series",
+            interval,
+            config,
+            move |runtime, database| {
+                let snapshot = doc! {
+                    "ts": bson::DateTime::now(),
+                    "host": &host,
+                    "queue_len": queue_len() as i64,
+                    "counters": counter_increases(&mut totals),
+                };
+                let collection = database.collection::<Document>(METRICS_COLLECTION);
+                match runtime.block_on(collection.insert_one(snapshot)) {
+                    Ok(_) => failed = false,
+                    // Logged once per outage rather than every interval.
+                    Err(err) if !failed => {
+                        failed = true;
+                        error!("Failed to write the metrics to {}: {}", METRICS_COLLECTION, err);
+                    }
+                    Err(_) => {}
+                }
+                Ok(())
+            },
+        );
+        Some(Self { task })
+    }
+
+    pub fn join(&mut self) {
+        self.task.join();
+    }
+}
@@ -0,0 +1,323 @@
+/// Caps the transactions stored per slot by count and by bytes.
+///
+/// The transactions of a slot are held back until the block metadata of the slot is
+/// notified, or a status of the slot or a later one is, and are then released in a
+/// deterministic priority order: non-vote before vote, succeeded before failed, and
+/// within those by the hash of the signature, which samples them uniformly. The
+/// transactions that do not fit under the caps are dropped and counted on the slot
+/// document.
+use {
+    crate::{metrics, mongodb_client::{DbTransaction, LogTransactionRequest}},
+    serde_derive::{Deserialize, Serialize},
+    solana_sdk::hash::hash,
+    std::{collections::BTreeMap, sync::Mutex},
+};
+
+/// Slots held back at most, the oldest are released beyond that.
+const MAX_BUFFERED_SLOTS: usize = 64;
+/// Rough encoded size of a transaction document without its variable parts.
+const TRANSACTION_DOCUMENT_OVERHEAD_BYTES: usize = 512;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TransactionCapConfig {
+    /// The number of transactions stored per slot at most.
+    pub max_transactions_per_slot: Option<usize>,
+    /// The estimated document bytes stored per slot at most.
+    pub max_bytes_per_slot: Option<usize>,
+}
+
+/// What was dropped of a capped slot.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct CappedSlot {
+    pub slot: u64,
+    pub stored: usize,
+    pub dropped: usize,
+    pub dropped_bytes: usize,
+}
+
+/// The estimated size of the stored transaction document.
+fn estimate_transaction_document_bytes(transaction: &DbTransaction) -> usize {
+    let (account_keys, instructions) =
+        match (&transaction.legacy_message, &transaction.v0_loaded_message) {
+            (Some(message), _) => (message.account_keys.len(), &message.instructions),
+            (None, Some(loaded_message)) => (
+                loaded_message.message.account_keys.len()
+                    + loaded_message.loaded_addresses.writable.len()
+                    + loaded_message.loaded_addresses.readonly.len(),
+                &loaded_message.message.instructions,
+            ),
+            (None, None) => return TRANSACTION_DOCUMENT_OVERHEAD_BYTES,
+        };
+    let inner_instructions = transaction
+        .meta
+        .inner_instructions
+        .iter()
+        .flatten()
+        .flat_map(|inner_instructions| inner_instructions.instructions.iter());
+    let instruction_bytes: usize = instructions
+        .iter()
+        .chain(inner_instructions)
+        .map(|instruction| instruction.data.len() + instruction.accounts.len() * 4)
+        .sum();
+    let log_bytes: usize = transaction
+        .meta
+        .log_messages
+        .iter()
+        .flatten()
+        .map(String::len)
+        .sum();
+    TRANSACTION_DOCUMENT_OVERHEAD_BYTES
+        + transaction.signatures.len() * 88
+        // Each key in `account_keys` and as an entry of `accounts` with its flags.
+        + account_keys * (44 + 110)
+        + instruction_bytes
+        + log_bytes
+}
+
+struct BufferedTransaction {
+    request: LogTransactionRequest,
+    bytes: usize,
+}
+
+impl BufferedTransaction {
+    /// Lower sorts first: non-vote, succeeded, then by the signature hash.
+    fn priority(&self) -> (bool, bool, [u8; 32]) {
+        let transaction = &self.request.transaction_info;
+        (
+            transaction.is_vote,
+            transaction.meta.error.is_some(),
+            hash(&transaction.signature).to_bytes(),
+        )
+    }
+}
+
+pub struct SlotTransactionCap {
+    config: TransactionCapConfig,
+    slots: Mutex<BTreeMap<u64, Vec<BufferedTransaction>>>,
+}
+
+impl SlotTransactionCap {
+    pub fn new(config: TransactionCapConfig) -> Self {
+        Self {
+            config,
+            slots: Mutex::default(),
+        }
+    }
+
+    /// Holds `request` back until its slot is released. Returns the slots released
+    /// to stay within the buffered slot limit.
+    pub fn push(
+        &self,
+        request: LogTransactionRequest,
+    ) -> Vec<(Vec<LogTransactionRequest>, CappedSlot)> {
+        let slot = request.transaction_info.slot as u64;
+        let bytes = estimate_transaction_document_bytes(&request.transaction_info);
+        let mut slots = self.slots.lock().unwrap();
+        slots
+            .entry(slot)
+            .or_default()
+            .push(BufferedTransaction { request, bytes });
+        let mut released = Vec::default();
+        while slots.len() > MAX_BUFFERED_SLOTS {
+            let (slot, transactions) = slots.pop_first().unwrap();
+            released.push(self.cap(slot, transactions));
+        }
+        released
+    }
+
+    /// Releases `slot` and every older slot still held back.
+    pub fn release(&self, slot: u64) -> Vec<(Vec<LogTransactionRequest>, CappedSlot)> {
+        let mut slots = self.slots.lock().unwrap();
+        let newer = slots.split_off(&(slot + 1));
+        let released = std::mem::replace(&mut *slots, newer);
+        drop(slots);
+        released
+            .into_iter()
+            .map(|(slot, transactions)| self.cap(slot, transactions))
+            .collect()
+    }
+
+    fn cap(
+        &self,
+        slot: u64,
+        mut transactions: Vec<BufferedTransaction>,
+    ) -> (Vec<LogTransactionRequest>, CappedSlot) {
+        transactions.sort_by_cached_key(BufferedTransaction::priority);
+        let max_transactions = self.config.max_transactions_per_slot.unwrap_or(usize::MAX);
+        let max_bytes = self.config.max_bytes_per_slot.unwrap_or(usize::MAX);
+        let mut summary = CappedSlot {
+            slot,
+            ..CappedSlot::default()
+        };
+        let mut stored_bytes = 0usize;
+        let mut kept = Vec::with_capacity(transactions.len().min(max_transactions));
+        for transaction in transactions {
+            if kept.len() < max_transactions
+                && stored_bytes.saturating_add(transaction.bytes) <= max_bytes
+            {
+                stored_bytes += transaction.bytes;
+                kept.push(transaction.request);
+            } else {
+                summary.dropped += 1;
+                summary.dropped_bytes += transaction.bytes;
+            }
+        }
+        summary.stored = kept.len();
+        if summary.dropped > 0 {
+            metrics::inc_counter("geyser-plugin-mongodb-capped-slots", 1);
+            metrics::inc_counter(
+                "geyser-plugin-mongodb-capped-transactions-dropped",
+                summary.dropped,
+            );
+        }
+        (kept, summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::mongodb_client::DbTransactionStatusMeta,
+        mongodb::bson,
+        solana_sdk::transaction::TransactionError,
+        solana_transaction_status::TransactionStatusMeta,
+    };
+
+    /// A transaction without a message, estimated at the document overhead.
+    fn transaction(slot: i64, signature: u8, is_vote: bool, failed: bool) -> LogTransactionRequest {
+        let meta = TransactionStatusMeta {
+            status: if failed {
+                Err(TransactionError::AccountInUse)
+            } else {
+                Ok(())
+            },
+            ..TransactionStatusMeta::default()
+        };
+        LogTransactionRequest {
+            transaction_info: DbTransaction {
+                signature: vec![signature; 64],
+                is_vote,
+                slot,
+                message_type: 0,
+                legacy_message: None,
+                v0_loaded_message: None,
+                message_hash: Vec::default(),
+                meta: DbTransactionStatusMeta::from(&meta),
+                signatures: Vec::default(),
+                write_version: signature as i64,
+                index: signature as i64,
+                notified_at: bson::DateTime::now(),
+            },
+        }
+    }
+
+    fn signatures(requests: &[LogTransactionRequest]) -> Vec<u8> {
+        requests
+            .iter()
+            .map(|request| request.transaction_info.signature[0])
+            .collect()
+    }
+
+    /// `signatures` ordered by the hash of their transaction signature.
+    fn by_hash(mut signatures: Vec<u8>) -> Vec<u8> {
+        signatures.sort_by_key(|signature| hash(&[*signature; 64]).to_bytes());
+        signatures
+    }
+
+    #[test]
+    fn test_transactions_are_released_in_priority_order() {
+        let cap = SlotTransactionCap::new(TransactionCapConfig::default());
+        for (signature, is_vote, failed) in [
+            (1, true, false),
+            (2, false, true),
+            (3, false, false),
+            (4, true, true),
+            (5, false, false),
+            (6, false, true),
+            (7, false, false),
+        ] {
+            assert!(cap.push(transaction(10, signature, is_vote, failed)).is_empty());
+        }
+        let released = cap.release(10);
+        assert_eq!(released.len(), 1);
+        let (requests, summary) = &released[0];
+        // Non-vote before vote, then succeeded before failed, then by signature hash.
+        let expected: Vec<u8> = [by_hash(vec![3, 5, 7]), by_hash(vec![2, 6]), vec![1], vec![4]]
+            .concat();
+        assert_eq!(signatures(requests), expected);
+        assert_eq!(
+            *summary,
+            CappedSlot {
+                slot: 10,
+                stored: 7,
+                dropped: 0,
+                dropped_bytes: 0,
+            }
+        );
+    }
+
+    #[test]
+    fn test_the_count_cap_drops_the_lowest_priority() {
+        let cap = SlotTransactionCap::new(TransactionCapConfig {
+            max_transactions_per_slot: Some(2),
+            max_bytes_per_slot: None,
+        });
+        cap.push(transaction(10, 1, true, false));
+        cap.push(transaction(10, 2, false, true));
+        cap.push(transaction(10, 3, false, false));
+        let (requests, summary) = cap.release(10).pop().unwrap();
+        assert_eq!(signatures(&requests), vec![3, 2]);
+        assert_eq!(
+            summary,
+            CappedSlot {
+                slot: 10,
+                stored: 2,
+                dropped: 1,
+                dropped_bytes: TRANSACTION_DOCUMENT_OVERHEAD_BYTES,
+            }
+        );
+    }
+
+    #[test]
+    fn test_the_byte_cap_drops_what_does_not_fit() {
+        let cap = SlotTransactionCap::new(TransactionCapConfig {
+            max_transactions_per_slot: None,
+            max_bytes_per_slot: Some(TRANSACTION_DOCUMENT_OVERHEAD_BYTES * 2 + 1),
+        });
+        for signature in 1..=4 {
+            cap.push(transaction(10, signature, false, false));
+        }
+        let (requests, summary) = cap.release(10).pop().unwrap();
+        assert_eq!(signatures(&requests), by_hash(vec![1, 2, 3, 4])[..2].to_vec());
+        assert_eq!(summary.stored, 2);
+        assert_eq!(summary.dropped, 2);
+        assert_eq!(summary.dropped_bytes, TRANSACTION_DOCUMENT_OVERHEAD_BYTES * 2);
+    }
+
+    #[test]
+    fn test_release_covers_the_older_slots() {
+        let cap = SlotTransactionCap::new(TransactionCapConfig::default());
+        cap.push(transaction(12, 3, false, false));
+        cap.push(transaction(10, 1, false, false));
+        cap.push(transaction(11, 2, false, false));
+
+        let released = cap.release(11);
+        let slots: Vec<u64> = released.iter().map(|(_, summary)| summary.slot).collect();
+        assert_eq!(slots, vec![10, 11]);
+        assert!(cap.release(11).is_empty());
+        let (requests, summary) = cap.release(u64::MAX - 1).pop().unwrap();
+        assert_eq!((signatures(&requests), summary.slot), (vec![3], 12));
+    }
+
+    #[test]
+    fn test_the_oldest_slot_is_released_beyond_the_slot_limit() {
+        let cap = SlotTransactionCap::new(TransactionCapConfig::default());
+        for slot in 0..MAX_BUFFERED_SLOTS as i64 {
+            assert!(cap.push(transaction(slot, 1, false, false)).is_empty());
+        }
+        let released = cap.push(transaction(MAX_BUFFERED_SLOTS as i64, 1, false, false));
+        let slots: Vec<u64> = released.iter().map(|(_, summary)| summary.slot).collect();
+        assert_eq!(slots, vec![0]);
+    }
+}
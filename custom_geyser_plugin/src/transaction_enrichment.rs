@@ -0,0 +1,71 @@
+/// Denormalizes the epoch and the leader of a slot onto its transactions, so
+/// analytics grouping transactions by either need no join against the slots.
+///
+/// The epoch follows from the slot and the epoch schedule and is stamped when the
+/// transaction is written. The leader is only known once the block metadata of the
+/// slot arrives: it is the recipient of the fee reward of the block. It is then set
+/// on the slot document and on the transactions of the slot already written. As with
+/// `backfill_block_time`, transactions of the slot still queued in other workers at
+/// that point are written without it.
+use {
+    crate::{
+        geyser_plugin_mongodb::GeyserPluginMongoDbError,
+        mongodb_client::{DbReward, DbRewardType},
+    },
+    serde_derive::{Deserialize, Serialize},
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_sdk::epoch_schedule::{EpochSchedule, MINIMUM_SLOTS_PER_EPOCH},
+};
+
+/// The slots per epoch of mainnet-beta.
+const DEFAULT_SLOTS_PER_EPOCH: u64 = 432_000;
+
+#[derive(Clone, Debug, Default, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TransactionEnrichmentConfig {
+    /// The slots per epoch of the cluster. The default is 432000, as on mainnet-beta.
+    pub slots_per_epoch: Option<u64>,
+    /// Whether the cluster started with short epochs growing to `slots_per_epoch`,
+    /// as test validators do. The default is false.
+    pub warmup: Option<bool>,
+}
+
+#[derive(Clone, Debug)]
+pub struct TransactionEnrichment {
+    epoch_schedule: EpochSchedule,
+}
+
+impl TransactionEnrichment {
+    pub fn new(config: &TransactionEnrichmentConfig) -> Result<Self, GeyserPluginError> {
+        let slots_per_epoch = config.slots_per_epoch.unwrap_or(DEFAULT_SLOTS_PER_EPOCH);
+        if slots_per_epoch < MINIMUM_SLOTS_PER_EPOCH {
+            return Err(GeyserPluginError::Custom(Box::new(
+                GeyserPluginMongoDbError::ConfigurationError {
+                    msg: format!(
+                        "\"transaction_enrichment\": slots_per_epoch must be at least {}",
+                        MINIMUM_SLOTS_PER_EPOCH
+                    ),
+                },
+            )));
+        }
+        Ok(Self {
+            epoch_schedule: EpochSchedule::custom(
+                slots_per_epoch,
+                slots_per_epoch,
+                config.warmup.unwrap_or_default(),
+            ),
+        })
+    }
+
+    pub fn epoch(&self, slot: u64) -> u64 {
+        self.epoch_schedule.get_epoch(slot)
+    }
+}
+
+/// The leader of a block, the recipient of its fee reward. None when no fees were
+/// collected or the rewards are not notified.
+pub fn block_leader(rewards: &[DbReward]) -> Option<&str> {
+    rewards
+        .iter()
+        .find(|reward| reward.reward_type == Some(DbRewardType::Fee))
+        .map(|reward| reward.pubkey.as_str())
+}
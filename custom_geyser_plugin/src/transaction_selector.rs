@@ -1,11 +1,141 @@
 /// The transaction selector is responsible for filtering transactions
 /// in the plugin framework.
-use {log::*, solana_sdk::pubkey::Pubkey, std::collections::HashSet};
+use {
+    crate::{geyser_plugin_mongodb::GeyserPluginMongoDbError, metrics::CounterHandles},
+    log::*,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    solana_sdk::{pubkey::Pubkey, transaction::SanitizedTransaction},
+    solana_transaction_status::TransactionStatusMeta,
+    std::collections::HashSet,
+};
+
+/// The counter of every `TransactionSelection`, by discriminant.
+pub type TransactionSelectionCounters = CounterHandles<{ TransactionSelection::ALL.len() }>;
+
+/// The rule that selected or rejected a transaction, counted per rule as
+/// `geyser-plugin-mongodb-transaction-selector-<matched|rejected>-<rule>`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum TransactionSelection {
+    /// A vote transaction selected by `votes: true`.
+    MatchedVotes,
+    /// Selected by `"*"` or `"all"` in `mentions`, or `"*"` in `programs`.
+    MatchedWildcard,
+    /// A vote transaction selected by `"all_votes"`.
+    MatchedAllVotes,
+    MatchedMention,
+    MatchedProgram,
+    /// A vote transaction rejected by `votes: false`.
+    RejectedVotes,
+    /// The selector selects nothing.
+    RejectedDisabled,
+    /// Neither a mentioned address nor an invoked program is selected.
+    RejectedNoMatch,
+}
+
+impl TransactionSelection {
+    /// Every selection, in the order of declaration.
+    pub const ALL: [TransactionSelection; 8] = [
+        TransactionSelection::MatchedVotes,
+        TransactionSelection::MatchedWildcard,
+        TransactionSelection::MatchedAllVotes,
+        TransactionSelection::MatchedMention,
+        TransactionSelection::MatchedProgram,
+        TransactionSelection::RejectedVotes,
+        TransactionSelection::RejectedDisabled,
+        TransactionSelection::RejectedNoMatch,
+    ];
+
+    /// Resolves the counters of every selection in the registry of the current instance.
+    pub fn counters() -> TransactionSelectionCounters {
+        CounterHandles::new(Self::ALL.map(|selection| selection.counter_name()))
+    }
+
+    pub fn is_selected(&self) -> bool {
+        matches!(
+            self,
+            TransactionSelection::MatchedVotes
+                | TransactionSelection::MatchedWildcard
+                | TransactionSelection::MatchedAllVotes
+                | TransactionSelection::MatchedMention
+                | TransactionSelection::MatchedProgram
+        )
+    }
+
+    pub fn counter_name(&self) -> &'static str {
+        match self {
+            TransactionSelection::MatchedVotes => {
+                "geyser-plugin-mongodb-transaction-selector-matched-votes"
+            }
+            TransactionSelection::MatchedWildcard => {
+                "geyser-plugin-mongodb-transaction-selector-matched-wildcard"
+            }
+            TransactionSelection::MatchedAllVotes => {
+                "geyser-plugin-mongodb-transaction-selector-matched-all-votes"
+            }
+            TransactionSelection::MatchedMention => {
+                "geyser-plugin-mongodb-transaction-selector-matched-mention"
+            }
+            TransactionSelection::MatchedProgram => {
+                "geyser-plugin-mongodb-transaction-selector-matched-program"
+            }
+            TransactionSelection::RejectedVotes => {
+                "geyser-plugin-mongodb-transaction-selector-rejected-votes"
+            }
+            TransactionSelection::RejectedDisabled => {
+                "geyser-plugin-mongodb-transaction-selector-rejected-disabled"
+            }
+            TransactionSelection::RejectedNoMatch => {
+                "geyser-plugin-mongodb-transaction-selector-rejected-no-match"
+            }
+        }
+    }
+}
 
 pub(crate) struct TransactionSelector {
     pub mentioned_addresses: HashSet<Vec<u8>>,
     pub select_all_transactions: bool,
     pub select_all_vote_transactions: bool,
+    /// Transactions invoking one of these programs are selected.
+    pub programs: HashSet<Vec<u8>>,
+    /// When set, vote transactions are always (true) or never (false) selected.
+    pub votes: Option<bool>,
+}
+
+fn decode_keys(keys: &[String], field: &str) -> Result<HashSet<Vec<u8>>, GeyserPluginError> {
+    keys.iter()
+        .map(|key| {
+            bs58::decode(key).into_vec().map_err(|err| {
+                GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::ConfigurationError {
+                    msg: format!(
+                        "Invalid key {} in \"transaction_selector\" {}: {}",
+                        key, field, err
+                    ),
+                }))
+            })
+        })
+        .collect()
+}
+
+/// The programs invoked by the transaction, by its top level and inner instructions.
+pub(crate) fn invoked_programs<'a>(
+    transaction: &'a SanitizedTransaction,
+    meta: &'a TransactionStatusMeta,
+) -> impl Iterator<Item = &'a Pubkey> + 'a {
+    let message = transaction.message();
+    let account_keys = message.account_keys();
+    let top_level = message
+        .instructions()
+        .iter()
+        .map(|instruction| instruction.program_id_index);
+    let inner = meta
+        .inner_instructions
+        .iter()
+        .flatten()
+        .flat_map(|inner_instructions| inner_instructions.instructions.iter())
+        .map(|inner_instruction| inner_instruction.instruction.program_id_index);
+    top_level
+        .chain(inner)
+        .filter_map(move |index| account_keys.get(index as usize))
 }
 
 #[allow(dead_code)]
@@ -15,96 +145,124 @@ impl TransactionSelector {
             mentioned_addresses: HashSet::default(),
             select_all_transactions: false,
             select_all_vote_transactions: false,
+            programs: HashSet::default(),
+            votes: None,
         }
     }
 
-    /// Create a selector based on the mentioned addresses
+    /// Create a selector based on the mentioned addresses and invoked programs
     /// To select all transactions use ["*"] or ["all"]
     /// To select all vote transactions, use ["all_votes"]
     /// To select transactions mentioning specific addresses use ["<pubkey1>", "<pubkey2>", ...]
-    pub fn new(mentioned_addresses: &[String]) -> Self {
+    /// To select transactions invoking specific programs use ["<program1>", ...] in `programs`,
+    /// ["*"] selects every transaction as each one invokes a program
+    /// `votes` overrides the other criteria for vote transactions when set.
+    pub fn new(
+        mentioned_addresses: &[String],
+        programs: &[String],
+        votes: Option<bool>,
+    ) -> Result<Self, GeyserPluginError> {
         info!(
-            "Creating TransactionSelector from addresses: {:?}",
-            mentioned_addresses
+            "Creating TransactionSelector from addresses: {:?}, programs: {:?}, votes: {:?}",
+            mentioned_addresses, programs, votes
         );
+        let (wildcard_programs, programs): (Vec<String>, Vec<String>) =
+            programs.iter().cloned().partition(|key| key == "*");
+        let programs = decode_keys(&programs, "programs")?;
 
         let select_all_transactions = mentioned_addresses
             .iter()
-            .any(|key| key == "*" || key == "all");
+            .any(|key| key == "*" || key == "all")
+            || !wildcard_programs.is_empty();
         if select_all_transactions {
-            return Self {
+            return Ok(Self {
                 mentioned_addresses: HashSet::default(),
                 select_all_transactions,
                 select_all_vote_transactions: true,
-            };
+                programs,
+                votes,
+            });
         }
         let select_all_vote_transactions = mentioned_addresses.iter().any(|key| key == "all_votes");
         if select_all_vote_transactions {
-            return Self {
+            return Ok(Self {
                 mentioned_addresses: HashSet::default(),
                 select_all_transactions,
                 select_all_vote_transactions: true,
-            };
+                programs,
+                votes,
+            });
         }
 
-        let mentioned_addresses = mentioned_addresses
-            .iter()
-            .map(|key| bs58::decode(key).into_vec().unwrap())
-            .collect();
+        let mentioned_addresses = decode_keys(mentioned_addresses, "mentions")?;
 
-        Self {
+        Ok(Self {
             mentioned_addresses,
             select_all_transactions: false,
             select_all_vote_transactions: false,
-        }
+            programs,
+            votes,
+        })
     }
 
-    /// Check if a transaction is of interest.
+    /// Check if a transaction is of interest, counting the rule that decided in
+    /// `counters`.
     pub fn is_transaction_selected(
         &self,
+        counters: &TransactionSelectionCounters,
         is_vote: bool,
         mentioned_addresses: Box<dyn Iterator<Item = &Pubkey> + '_>,
+        invoked_programs: Box<dyn Iterator<Item = &Pubkey> + '_>,
     ) -> bool {
+        let selection = self.transaction_selection(is_vote, mentioned_addresses, invoked_programs);
+        counters.get(selection as usize).add(1);
+        selection.is_selected()
+    }
+
+    /// The rule selecting or rejecting a transaction. The invoked programs are only
+    /// walked when no other criterion decided.
+    pub fn transaction_selection(
+        &self,
+        is_vote: bool,
+        mentioned_addresses: Box<dyn Iterator<Item = &Pubkey> + '_>,
+        invoked_programs: Box<dyn Iterator<Item = &Pubkey> + '_>,
+    ) -> TransactionSelection {
         if !self.is_enabled() {
-            return false;
+            return TransactionSelection::RejectedDisabled;
+        }
+        match (is_vote, self.votes) {
+            (true, Some(true)) => return TransactionSelection::MatchedVotes,
+            (true, Some(false)) => return TransactionSelection::RejectedVotes,
+            _ => {}
         }
 
-        if self.select_all_transactions || (self.select_all_vote_transactions && is_vote) {
-            return true;
+        if self.select_all_transactions {
+            return TransactionSelection::MatchedWildcard;
+        }
+        if self.select_all_vote_transactions && is_vote {
+            return TransactionSelection::MatchedAllVotes;
         }
         for address in mentioned_addresses {
             if self.mentioned_addresses.contains(address.as_ref()) {
-                return true;
+                return TransactionSelection::MatchedMention;
+            }
+        }
+        if !self.programs.is_empty() {
+            for program in invoked_programs {
+                if self.programs.contains(program.as_ref()) {
+                    return TransactionSelection::MatchedProgram;
+                }
             }
         }
-        false
+        TransactionSelection::RejectedNoMatch
     }
-    //I believe this fn is more optimize than the one above due to avoiding dyn memory overhead
-    // pub fn is_transaction_selected<I>(
-    //     &self,
-    //     is_vote: bool,
-    //     mentioned_addresses: I,
-    // ) -> bool
-    // where
-    //     I: IntoIterator<Item = &Pubkey>,
-    // {
-    //     if !self.is_enabled() {
-    //         return false;
-    //     }
-    
-    //     if self.select_all_transactions || (self.select_all_vote_transactions && is_vote) {
-    //         return true;
-    //     }
-    
-    //     mentioned_addresses.into_iter().any(|address| {
-    //         self.mentioned_addresses.contains(address.as_ref())
-    //     })
-    // }
 
     /// Check if any transaction is of interest at all
     pub fn is_enabled(&self) -> bool {
         self.select_all_transactions
             || self.select_all_vote_transactions
             || !self.mentioned_addresses.is_empty()
+            || !self.programs.is_empty()
+            || self.votes == Some(true)
     }
 }
@@ -0,0 +1,170 @@
+/// TTL indexes expiring the transactions and the account history after
+/// `retention_days`, so MongoDB ages out old documents without a pruning job.
+///
+/// Every transaction and account history document is written with `ingested_at`, a
+/// BSON datetime. When the plugin loads it creates a TTL index on `ingested_at` for
+/// each collection given a retention, or changes the expiry of the existing one with
+/// `collMod`, which applies to the documents already stored. The server's TTL monitor
+/// then deletes the expired documents about once a minute. A bucket of the bucketed
+/// history keeps the `ingested_at` of its newest version and expires as a whole.
+/// Collections left out of `retention_days` keep an existing TTL index as it is.
+use {
+    crate::{
+        geyser_plugin_mongodb::{
+            to_config_error, GeyserPluginMongoDBConfig, GeyserPluginMongoDbError,
+        },
+        history_compaction::HistoryCompactionMode,
+        mongodb_client::{
+            plugin_database, SimpleMongoDbClient, ACCOUNT_HISTORY_COLLECTION,
+            TRANSACTIONS_COLLECTION,
+        },
+    },
+    futures::TryStreamExt,
+    log::*,
+    mongodb::{
+        bson::{doc, Document},
+        options::IndexOptions,
+        Database, IndexModel,
+    },
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::time::Duration,
+};
+
+/// The datetime field the TTL indexes expire documents by.
+pub const TTL_FIELD: &str = "ingested_at";
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// The collections a retention can be set on.
+const RETAINED_COLLECTIONS: [&str; 2] = [TRANSACTIONS_COLLECTION, ACCOUNT_HISTORY_COLLECTION];
+
+fn retention_error(msg: String) -> GeyserPluginError {
+    GeyserPluginError::Custom(Box::new(GeyserPluginMongoDbError::DataSchemaError { msg }))
+}
+
+/// Creates the TTL index of `collection`, or sets its expiry when it exists.
+async fn install_ttl_index(
+    database: &Database,
+    collection: &str,
+    expire_after: Duration,
+) -> Result<(), GeyserPluginError> {
+    let keys = doc! { TTL_FIELD: 1 };
+    let handle = database.collection::<Document>(collection);
+    let existing = match handle.list_indexes().await {
+        Ok(indexes) => indexes
+            .try_collect::<Vec<IndexModel>>()
+            .await
+            .map_err(|err| {
+                retention_error(format!(
+                    "Failed to list the indexes of {}: {}",
+                    collection, err
+                ))
+            })?
+            .into_iter()
+            .find(|index| index.keys == keys),
+        // The collection does not exist yet, creating the index creates it.
+        Err(_) => None,
+    };
+    let result = match existing {
+        None => handle
+            .create_index(
+                IndexModel::builder()
+                    .keys(keys)
+                    .options(IndexOptions::builder().expire_after(expire_after).build())
+                    .build(),
+            )
+            .await
+            .map(|_| ()),
+        Some(index)
+            if index.options.and_then(|options| options.expire_after) == Some(expire_after) =>
+        {
+            return Ok(());
+        }
+        Some(_) => database
+            .run_command(doc! {
+                "collMod": collection,
+                "index": {
+                    "keyPattern": keys,
+                    "expireAfterSeconds": expire_after.as_secs() as i64,
+                },
+            })
+            .await
+            .map(|_| ()),
+    };
+    result.map_err(|err| {
+        retention_error(format!(
+            "Failed to set the TTL index of {}: {}",
+            collection, err
+        ))
+    })?;
+    info!(
+        "Documents of {} expire {} days after they are stored",
+        collection,
+        expire_after.as_secs() / SECONDS_PER_DAY
+    );
+    Ok(())
+}
+
+/// Installs the TTL indexes configured by `retention_days`, once on load rather than
+/// by every worker.
+pub fn prepare_ttl_retention(
+    config: &GeyserPluginMongoDBConfig,
+) -> Result<(), GeyserPluginError> {
+    let Some(retention_days) = &config.retention_days else {
+        return Ok(());
+    };
+    let mut retentions = Vec::default();
+    for (collection, days) in retention_days {
+        let Some(collection) = RETAINED_COLLECTIONS
+            .into_iter()
+            .find(|retained| *retained == collection.as_str())
+        else {
+            return Err(to_config_error(format!(
+                "\"retention_days\": unsupported collection {}, expected one of {:?}",
+                collection, RETAINED_COLLECTIONS
+            )));
+        };
+        if *days == 0 {
+            return Err(to_config_error(format!(
+                "\"retention_days\": the retention of {} must be positive",
+                collection
+            )));
+        }
+        retentions.push((collection, Duration::from_secs(days * SECONDS_PER_DAY)));
+    }
+    if config.transactions_capped_size_bytes.is_some()
+        && retention_days.contains_key(TRANSACTIONS_COLLECTION)
+    {
+        return Err(to_config_error(format!(
+            "\"retention_days\" of {} is not supported with \
+             \"transactions_capped_size_bytes\", capped collections cannot have a TTL index",
+            TRANSACTIONS_COLLECTION
+        )));
+    }
+    let delta_compaction = config
+        .history_compaction
+        .as_ref()
+        .is_some_and(|compaction| compaction.mode == Some(HistoryCompactionMode::Delta));
+    if delta_compaction && retention_days.contains_key(ACCOUNT_HISTORY_COLLECTION) {
+        return Err(to_config_error(format!(
+            "\"retention_days\" of {} is not supported with the \"delta\" \
+             \"history_compaction\": a TTL index would expire keyframes the newer deltas \
+             are rebuilt from, use \"history_retention\" instead",
+            ACCOUNT_HISTORY_COLLECTION
+        )));
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| {
+            to_config_error(format!("Failed to create the retention runtime: {}", err))
+        })?;
+    runtime.block_on(async {
+        let client = SimpleMongoDbClient::connect_to_db(config).await?;
+        let database = plugin_database(&client, config);
+        for (collection, expire_after) in retentions {
+            install_ttl_index(&database, collection, expire_after).await?;
+        }
+        Ok(())
+    })
+}
@@ -0,0 +1,283 @@
+/// Pushes account updates persisted by the plugin to WebSocket subscribers, in the
+/// spirit of the RPC `accountSubscribe` and `programSubscribe` notifications.
+///
+/// A client subscribes by sending `{"method": "subscribe", "params": {"pubkeys":
+/// [...], "owners": [...], "programs": [...]}}`, a later subscribe message replaces
+/// the previous filter. `programs` matches the updates of the program accounts
+/// themselves, including the ProgramData account an upgrade writes. Every matching
+/// update is then pushed as an `accountNotification`. With the fast lane enabled
+/// updates are pushed straight from the geyser callback, before MongoDB has stored
+/// them, and the `persisted` flag of the notification is false.
+///
+/// The server is built with the `websocket` feature, without it a configured gateway
+/// fails to start.
+#[cfg(feature = "websocket")]
+use {
+    crate::instance,
+    base64::{engine::general_purpose::STANDARD, Engine},
+    futures::{SinkExt, StreamExt},
+    serde_derive::Deserialize,
+    serde_json::json,
+    solana_sdk::{bpf_loader_upgradeable, pubkey::Pubkey},
+    std::{collections::HashSet, net::SocketAddr, str::FromStr, thread},
+    tokio::net::{TcpListener, TcpStream},
+    tokio_tungstenite::{accept_async, tungstenite::Message},
+};
+use {
+    crate::{geyser_plugin_mongodb::to_config_error, mongodb_client::DbAccountInfo},
+    log::*,
+    solana_geyser_plugin_interface::geyser_plugin_interface::GeyserPluginError,
+    std::{sync::Arc, thread::JoinHandle},
+    tokio::sync::{broadcast, oneshot},
+};
+
+pub struct AccountUpdateEvent {
+    pub account: DbAccountInfo,
+    /// Whether the update was already written to MongoDB when it was published.
+    pub persisted: bool,
+}
+
+pub type AccountUpdateSender = broadcast::Sender<Arc<AccountUpdateEvent>>;
+
+pub struct WebSocketGateway {
+    sender: AccountUpdateSender,
+    shutdown: Option<oneshot::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WebSocketGateway {
+    /// Starts the gateway on `bind_address`. Subscribers lagging further behind than
+    /// `channel_capacity` updates miss the overflow.
+    #[cfg(feature = "websocket")]
+    pub fn start(bind_address: &str, channel_capacity: usize) -> Result<Self, GeyserPluginError> {
+        if channel_capacity == 0 {
+            return Err(to_config_error(
+                "websocket_channel_capacity must be at least 1".to_string(),
+            ));
+        }
+        let current_instance = instance::current();
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(2)
+            .thread_name("mongodb-ws-gateway")
+            .on_thread_start(move || instance::set_current(current_instance.clone()))
+            .enable_all()
+            .build()
+            .map_err(|err| {
+                to_config_error(format!("Failed to create the WebSocket runtime: {}", err))
+            })?;
+        let listener = runtime
+            .block_on(TcpListener::bind(bind_address))
+            .map_err(|err| {
+                to_config_error(format!(
+                    "Failed to bind the WebSocket gateway to {}: {}",
+                    bind_address, err
+                ))
+            })?;
+
+        let (sender, _) = broadcast::channel(channel_capacity);
+        let (shutdown, shutdown_receiver) = oneshot::channel();
+        let accept_sender = sender.clone();
+        let thread = thread::Builder::new()
+            .name("mongodb-ws-gateway".to_string())
+            .spawn(instance::bind(move || {
+                runtime.block_on(async move {
+                    tokio::select! {
+                        _ = accept_subscribers(listener, accept_sender) => {}
+                        _ = shutdown_receiver => {}
+                    }
+                });
+            }))
+            .map_err(|err| {
+                to_config_error(format!("Failed to spawn the WebSocket gateway: {}", err))
+            })?;
+
+        info!("WebSocket gateway listening on {}", bind_address);
+        Ok(Self {
+            sender,
+            shutdown: Some(shutdown),
+            thread: Some(thread),
+        })
+    }
+
+    #[cfg(not(feature = "websocket"))]
+    pub fn start(_bind_address: &str, _channel_capacity: usize) -> Result<Self, GeyserPluginError> {
+        Err(to_config_error(
+            "\"websocket_bind_address\" requires the plugin to be built with the \"websocket\" \
+             feature"
+                .to_string(),
+        ))
+    }
+
+    /// The handle the write pipeline publishes persisted updates through.
+    pub fn publisher(&self) -> AccountUpdateSender {
+        self.sender.clone()
+    }
+
+    pub fn join(&mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                error!("The WebSocket gateway thread has panicked");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "websocket")]
+#[derive(Deserialize)]
+struct SubscribeRequest {
+    method: String,
+    #[serde(default)]
+    params: SubscribeParams,
+}
+
+#[cfg(feature = "websocket")]
+#[derive(Default, Deserialize)]
+struct SubscribeParams {
+    #[serde(default)]
+    pubkeys: Vec<String>,
+    #[serde(default)]
+    owners: Vec<String>,
+    #[serde(default)]
+    programs: Vec<String>,
+}
+
+#[cfg(feature = "websocket")]
+#[derive(Default)]
+struct Subscription {
+    pubkeys: HashSet<Vec<u8>>,
+    owners: HashSet<Vec<u8>>,
+    /// The program accounts of `programs` and their ProgramData accounts.
+    programs: HashSet<Vec<u8>>,
+}
+
+#[cfg(feature = "websocket")]
+impl Subscription {
+    fn parse(text: &str) -> Result<Self, String> {
+        let request: SubscribeRequest =
+            serde_json::from_str(text).map_err(|err| format!("Invalid request: {}", err))?;
+        if request.method != "subscribe" {
+            return Err(format!("Unknown method: {}", request.method));
+        }
+        let decode = |keys: &[String]| -> Result<HashSet<Vec<u8>>, String> {
+            keys.iter()
+                .map(|key| {
+                    bs58::decode(key)
+                        .into_vec()
+                        .map_err(|err| format!("Invalid pubkey {}: {}", key, err))
+                })
+                .collect()
+        };
+        let mut programs = HashSet::default();
+        for program in &request.params.programs {
+            let program = Pubkey::from_str(program)
+                .map_err(|err| format!("Invalid program {}: {}", program, err))?;
+            let (program_data, _) = Pubkey::find_program_address(
+                &[program.as_ref()],
+                &bpf_loader_upgradeable::id(),
+            );
+            programs.insert(program.to_bytes().to_vec());
+            programs.insert(program_data.to_bytes().to_vec());
+        }
+        Ok(Self {
+            pubkeys: decode(&request.params.pubkeys)?,
+            owners: decode(&request.params.owners)?,
+            programs,
+        })
+    }
+
+    fn matches(&self, account: &DbAccountInfo) -> bool {
+        self.pubkeys.contains(&account.pubkey)
+            || self.owners.contains(&account.owner)
+            || self.programs.contains(&account.pubkey)
+    }
+}
+
+#[cfg(feature = "websocket")]
+fn build_notification(event: &AccountUpdateEvent) -> String {
+    let account = &event.account;
+    json!({
+        "method": "accountNotification",
+        "params": {
+            "pubkey": bs58::encode(&account.pubkey).into_string(),
+            "slot": account.slot,
+            "write_version": account.write_version,
+            "owner": bs58::encode(&account.owner).into_string(),
+            "lamports": account.lamports,
+            "executable": account.executable,
+            "rent_epoch": account.rent_epoch,
+            "data": [STANDARD.encode(&account.data), "base64"],
+            "data_hash": account.redacted_data.as_ref().map(|redacted| &redacted.hash),
+            "persisted": event.persisted,
+        }
+    })
+    .to_string()
+}
+
+#[cfg(feature = "websocket")]
+async fn accept_subscribers(listener: TcpListener, sender: AccountUpdateSender) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                tokio::spawn(serve_subscriber(stream, peer, sender.subscribe()));
+            }
+            Err(err) => warn!("Failed to accept a WebSocket connection: {}", err),
+        }
+    }
+}
+
+#[cfg(feature = "websocket")]
+async fn serve_subscriber(
+    stream: TcpStream,
+    peer: SocketAddr,
+    mut updates: broadcast::Receiver<Arc<AccountUpdateEvent>>,
+) {
+    let websocket = match accept_async(stream).await {
+        Ok(websocket) => websocket,
+        Err(err) => {
+            debug!("WebSocket handshake with {} failed: {}", peer, err);
+            return;
+        }
+    };
+    debug!("WebSocket subscriber {} connected", peer);
+    let (mut outgoing, mut incoming) = websocket.split();
+    let mut subscription = Subscription::default();
+
+    loop {
+        let reply = tokio::select! {
+            message = incoming.next() => match message {
+                Some(Ok(Message::Text(text))) => match Subscription::parse(&text) {
+                    Ok(new_subscription) => {
+                        subscription = new_subscription;
+                        Some(json!({ "result": "subscribed" }).to_string())
+                    }
+                    Err(err) => Some(json!({ "error": err }).to_string()),
+                },
+                Some(Ok(Message::Close(_))) | None => break,
+                Some(Ok(_)) => None,
+                Some(Err(err)) => {
+                    debug!("WebSocket subscriber {} failed: {}", peer, err);
+                    break;
+                }
+            },
+            update = updates.recv() => match update {
+                Ok(event) => subscription
+                    .matches(&event.account)
+                    .then(|| build_notification(&event)),
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("WebSocket subscriber {} lagged behind, {} updates dropped", peer, skipped);
+                    None
+                }
+                Err(broadcast::error::RecvError::Closed) => break,
+            },
+        };
+        if let Some(reply) = reply {
+            if outgoing.send(Message::Text(reply)).await.is_err() {
+                break;
+            }
+        }
+    }
+    debug!("WebSocket subscriber {} disconnected", peer);
+}
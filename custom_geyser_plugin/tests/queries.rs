@@ -0,0 +1,330 @@
+//! Runs the queries of the `queries` module against a MongoDB server. The tests are
+//! skipped unless `MONGODB_TEST_URI` is set, e.g. to `mongodb://localhost:27017`.
+//! Each test works in a database of its own, dropped when the test starts.
+
+use {
+    custom_geyser_plugin::{
+        mongodb_client::{
+            ACCOUNTS_COLLECTION, ACCOUNT_HISTORY_COLLECTION, ASSOCIATED_TOKEN_ACCOUNTS_COLLECTION,
+            PDA_REGISTRY_COLLECTION, SLOTS_COLLECTION, TOKEN_BALANCE_CHANGES_COLLECTION,
+            TOKEN_MINT_TRANSACTIONS_COLLECTION, TRANSACTIONS_COLLECTION,
+        },
+        queries::{self, ensure_query_indexes},
+        schema::SchemaProfile,
+    },
+    mongodb::{
+        bson::{doc, spec::BinarySubtype, Binary, DateTime, Document},
+        Client, Database,
+    },
+    std::env,
+};
+
+const PUBKEY: &str = "9xQeWvG816bUx9EPjHmaT23yvVM2ZWbrrpZb9PusVFin";
+const OWNER: &str = "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA";
+const MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+const SIGNATURE: &str =
+    "5VERv8NMvzbJMEkV8xnrLkEaWRtSz9CosKDYjCJjBRnbJLgp8uirBgmQpjKhoR4tjF3ZpRzrFmBV6UjKdiSZkQUW";
+
+/// A fresh database on the test server, None when no server is configured.
+async fn test_database(name: &str) -> Option<Database> {
+    let Ok(uri) = env::var("MONGODB_TEST_URI") else {
+        eprintln!("MONGODB_TEST_URI is not set, skipping {}", name);
+        return None;
+    };
+    let client = Client::with_uri_str(uri).await.unwrap();
+    let database = client.database(&format!("geyser_plugin_test_{}", name));
+    database.drop().await.unwrap();
+    Some(database)
+}
+
+async fn insert(database: &Database, collection: &str, document: Document) {
+    database
+        .collection::<Document>(collection)
+        .insert_one(document)
+        .await
+        .unwrap();
+}
+
+fn account(profile: SchemaProfile, slot: i64, write_version: i64) -> Document {
+    profile.account_document(doc! {
+        "pubkey": PUBKEY,
+        "owner": OWNER,
+        "lamports": 2_039_280_i64,
+        "data": Binary { subtype: BinarySubtype::Generic, bytes: vec![1, 2, 3] },
+        "slot": slot,
+        "write_version": write_version,
+        "executable": false,
+        "rent_epoch": 0_i64,
+        "data_hash": format!("hash-{}", write_version),
+    })
+}
+
+fn account_version(slot: i64, write_version: i64) -> Document {
+    doc! {
+        "pubkey": PUBKEY,
+        "slot": slot,
+        "write_version": write_version,
+        "owner": OWNER,
+        "lamports": 2_039_280_i64,
+        "executable": false,
+        "rent_epoch": 0_i64,
+        "data": Binary { subtype: BinarySubtype::Generic, bytes: vec![1, 2, 3] },
+    }
+}
+
+fn transaction(slot: i64, index: i64) -> Document {
+    doc! {
+        "signature": format!("{}-{}", SIGNATURE, index),
+        "slot": slot,
+        "index": index,
+        "is_vote": false,
+        "meta": { "fee": 5000_i64, "error": null },
+        "account_keys": [PUBKEY, OWNER],
+        "program_ids": [OWNER],
+        "accounts": [
+            {
+                "pubkey": PUBKEY,
+                "index": 0_i64,
+                "writable": true,
+                "signer": true,
+                "source": "static",
+            },
+            {
+                "pubkey": OWNER,
+                "index": 1_i64,
+                "writable": false,
+                "signer": false,
+                "source": "static",
+            },
+        ],
+    }
+}
+
+async fn seed(database: &Database, profile: SchemaProfile) {
+    insert(database, ACCOUNTS_COLLECTION, account(profile, 10, 1)).await;
+    insert(database, ACCOUNTS_COLLECTION, account(profile, 12, 2)).await;
+    insert(database, ACCOUNT_HISTORY_COLLECTION, account_version(10, 1)).await;
+    insert(database, ACCOUNT_HISTORY_COLLECTION, account_version(12, 2)).await;
+    insert(
+        database,
+        ACCOUNT_HISTORY_COLLECTION,
+        doc! {
+            "pubkey": PUBKEY,
+            "first_slot": 11_i64,
+            "last_slot": 11_i64,
+            "count": 1_i64,
+            "versions": [account_version(11, 3)],
+        },
+    )
+    .await;
+    insert(database, TRANSACTIONS_COLLECTION, transaction(10, 0)).await;
+    insert(database, TRANSACTIONS_COLLECTION, transaction(12, 1)).await;
+    insert(
+        database,
+        TOKEN_MINT_TRANSACTIONS_COLLECTION,
+        doc! { "mint": MINT, "signature": SIGNATURE, "slot": 12_i64, "index": 1_i64 },
+    )
+    .await;
+    insert(
+        database,
+        TOKEN_BALANCE_CHANGES_COLLECTION,
+        doc! {
+            "owner": PUBKEY,
+            "mint": MINT,
+            "account": null,
+            "delta": "-1000000",
+            "ui_delta": -1.0,
+            "decimals": 6,
+            "signature": SIGNATURE,
+            "slot": 12_i64,
+            "index": 1_i64,
+        },
+    )
+    .await;
+    insert(
+        database,
+        ASSOCIATED_TOKEN_ACCOUNTS_COLLECTION,
+        doc! {
+            "token_account": PUBKEY,
+            "owner": OWNER,
+            "mint": MINT,
+            "token_program": OWNER,
+            "slot": 12_i64,
+        },
+    )
+    .await;
+    insert(
+        database,
+        PDA_REGISTRY_COLLECTION,
+        doc! {
+            "pubkey": PUBKEY,
+            "program": OWNER,
+            "first_seen_slot": 10_i64,
+            "last_updated_slot": 12_i64,
+            "size": 165_i64,
+        },
+    )
+    .await;
+    insert(
+        database,
+        SLOTS_COLLECTION,
+        doc! {
+            "slot": 12_i64,
+            "parent": 11_i64,
+            "status": "rooted",
+            "updated_on": DateTime::now(),
+            "ancestors": [11_i64, 10_i64],
+        },
+    )
+    .await;
+}
+
+#[tokio::test]
+async fn test_every_query_resolves_its_index_hint() {
+    let Some(database) = test_database("every_query").await else {
+        return;
+    };
+    let profile = SchemaProfile::Standard;
+    ensure_query_indexes(&database, profile).await.unwrap();
+    seed(&database, profile).await;
+
+    let latest = queries::latest_account(&database, profile, PUBKEY)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!((latest.slot, latest.write_version), (12, 2));
+    let changed = queries::account_changed_since(&database, profile, PUBKEY, "hash-1")
+        .await
+        .unwrap();
+    assert_eq!(changed.unwrap().data_hash.as_deref(), Some("hash-2"));
+    assert!(
+        queries::account_changed_since(&database, profile, PUBKEY, "hash-2")
+            .await
+            .unwrap()
+            .is_none()
+    );
+
+    let history = queries::account_history(&database, PUBKEY, 10, 12)
+        .await
+        .unwrap();
+    let slots: Vec<i64> = history.iter().map(|version| version.slot).collect();
+    assert_eq!(slots, vec![10, 11, 12]);
+
+    let by_address = queries::transactions_by_address(&database, PUBKEY, None, 10)
+        .await
+        .unwrap();
+    assert_eq!(by_address.len(), 2);
+    assert_eq!(by_address[0].slot, 12);
+    let before = queries::transactions_by_address(&database, PUBKEY, Some(12), 10)
+        .await
+        .unwrap();
+    assert_eq!(before.len(), 1);
+    let signed = queries::transactions_by_account(&database, PUBKEY, true, true, None, 10)
+        .await
+        .unwrap();
+    assert_eq!(signed.len(), 2);
+    let signed_by_owner = queries::transactions_by_account(&database, OWNER, false, true, None, 10)
+        .await
+        .unwrap();
+    assert!(signed_by_owner.is_empty());
+    let by_program = queries::transactions_by_program(&database, OWNER, None, 10)
+        .await
+        .unwrap();
+    assert_eq!(by_program.len(), 2);
+    let by_mint = queries::transactions_by_mint(&database, MINT, None, 10)
+        .await
+        .unwrap();
+    assert_eq!(by_mint.len(), 1);
+
+    let changes = queries::token_balance_changes(&database, PUBKEY, None, 10)
+        .await
+        .unwrap();
+    assert_eq!(changes[0].delta, "-1000000");
+    let token_accounts = queries::associated_token_accounts(&database, OWNER)
+        .await
+        .unwrap();
+    assert_eq!(token_accounts[0].mint, MINT);
+    let addresses = queries::program_derived_addresses(&database, OWNER, None, 10)
+        .await
+        .unwrap();
+    assert_eq!(addresses[0].pubkey, PUBKEY);
+
+    let slot = queries::slot_status(&database, 12).await.unwrap().unwrap();
+    assert_eq!(slot.status, "rooted");
+    assert_eq!(
+        queries::slot_ancestors(&database, 12).await.unwrap(),
+        Some(vec![11, 10])
+    );
+    assert_eq!(
+        queries::is_slot_ancestor(&database, 10, 12).await.unwrap(),
+        Some(true)
+    );
+}
+
+#[tokio::test]
+async fn test_query_without_its_index_fails() {
+    let Some(database) = test_database("missing_index").await else {
+        return;
+    };
+    seed(&database, SchemaProfile::Standard).await;
+    assert!(
+        queries::latest_account(&database, SchemaProfile::Standard, PUBKEY)
+            .await
+            .is_err()
+    );
+    assert!(
+        queries::transactions_by_address(&database, PUBKEY, None, 10)
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_account_queries_read_the_compact_profile() {
+    let Some(database) = test_database("compact_profile").await else {
+        return;
+    };
+    let profile = SchemaProfile::Compact;
+    ensure_query_indexes(&database, profile).await.unwrap();
+    seed(&database, profile).await;
+
+    let latest = queries::latest_account(&database, profile, PUBKEY)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(latest.pubkey, PUBKEY);
+    assert_eq!((latest.slot, latest.write_version), (12, 2));
+    let changed = queries::account_changed_since(&database, profile, PUBKEY, "hash-1")
+        .await
+        .unwrap();
+    assert_eq!(changed.unwrap().write_version, 2);
+}
+
+#[tokio::test]
+async fn test_slot_status_of_a_slot_created_by_the_transaction_cap() {
+    let Some(database) = test_database("capped_slot").await else {
+        return;
+    };
+    ensure_query_indexes(&database, SchemaProfile::Standard)
+        .await
+        .unwrap();
+    // The document `mark_transactions_capped` upserts before the slot status arrives.
+    insert(
+        &database,
+        SLOTS_COLLECTION,
+        doc! {
+            "slot": 20_i64,
+            "transactions_capped": true,
+            "transactions_stored": 100_i64,
+            "transactions_dropped": 5_i64,
+            "transaction_bytes_dropped": 4096_i64,
+            "status": "processed",
+            "ingested_at": DateTime::now(),
+        },
+    )
+    .await;
+
+    let slot = queries::slot_status(&database, 20).await.unwrap().unwrap();
+    assert_eq!(slot.status, "processed");
+    assert!(slot.updated_on.is_none());
+}